@@ -1,61 +1,81 @@
 use std::ffi::OsStr;
-use std::fs::File;
-use std::io::Write;
-use std::sync::Arc;
-use std::path::Path;
-use anyhow::anyhow;
-use clap::Parser as CliParser;
-use serve_md_core::generate_payload_from_path;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::sync::{mpsc, Arc};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Context};
+use glob::glob;
+use notify::{RecursiveMode, Watcher};
+use rayon::ThreadPoolBuilder;
+use rayon::prelude::*;
+use serve_md_core::bundle;
+use serve_md_core::collection::{find_markdown, within_max_file_size, Document};
+use serve_md_core::{generate_payload_from_path, Payload};
 use serve_md_core::formats::Payload as PayloadFormats;
 use serve_md_core::state::State as Cli;
 use anyhow::Result;
+use tracing::debug;
+use tracing_subscriber::EnvFilter;
 
 fn main() -> Result<()> {
-    let mut cli = Cli::parse();
-    cli.load_config();
-    cli.set_missing();
+    // Documents can be written to stdout, so logs go to stderr.
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr).init();
 
-    #[cfg(debug_assertions)]
-    dbg!(&cli);
+    let mut cli = Cli::from_command_line();
+    // An invalid config file fails, rather than rendering with the defaults.
+    cli.try_load_config()?;
+    if let Some(format) = cli.print_config {
+        println!("{}", cli.to_config(format)?.trim_end());
+        return Ok(());
+    }
+
+    cli.set_missing();
+    debug!(?cli);
 
     let state = Arc::new(cli);
 
     if let Some(p) = state.file.as_ref() {
-        let context: Option<(&Path, bool)> = state.output.as_ref()
-            .map(Path::new)
-            .map(|p| (p, p.exists()));
-        let ext = context
-            .and_then(|(p, _)| p.extension())
-            .and_then(OsStr::to_str)
-            .and_then(|s| PayloadFormats::try_from(s).ok())
-            .or(Some(PayloadFormats::Html))
-            ;
-        let input = Path::new(p);
-        
-        let res = generate_payload_from_path(input, Arc::clone(&state))
-            .and_then(|payload| payload.into_response_for(&ext.unwrap()))
-            .and_then(|payload| 
-            aquire_output(context).map(|writer| (payload, writer))
-        );
+        if state.watch {
+            return watch(p, &state);
+        }
+        if Path::new(p).is_dir() || is_glob(p) {
+            return render_tree(p, &state);
+        }
+        return render_file_to_output(p, &state);
+    }
+    Err(anyhow!("No input detected from either -i or --file."))
+}
 
-        match res {
-            Result::Ok((payload, mut writer)) => {
-                match writer.write(&payload[..]) {
-                    Result::Ok(_) => {
-                        return Result::Ok(())
-                    },
-                    Err(e) => {
-                        return Err(e.into())
-                    }
-                }
+fn render_file_to_output(p: &str, state: &Arc<Cli>) -> Result<()> {
+    let context: Option<(&Path, bool)> = state.output.as_ref()
+        .map(Path::new)
+        .map(|p| (p, p.exists()));
+    let ext = context
+        .and_then(|(p, _)| p.extension())
+        .and_then(OsStr::to_str)
+        .and_then(|s| PayloadFormats::try_from(s).ok())
+        .or(Some(PayloadFormats::Html))
+        ;
+    let input = Path::new(p);
+    
+    let res = generate_payload_from_path(input, Arc::clone(state))
+        .and_then(|payload| 
+        aquire_output(context).map(|writer| (payload, writer))
+    );
 
-            }
-            Err(e) => {
-                return Err(e)
-            }
+    match res {
+        Result::Ok((payload, writer)) => {
+            let mut writer = BufWriter::new(writer);
+            payload.write_response_with_state(&mut writer, &ext.unwrap(), state)?;
+            writer.flush()?;
+            report_lint(input, &payload)
+        }
+        Err(e) => {
+            return Err(e)
         }
     }
-    Err(anyhow!("No input detected from either -i or --file."))
 }
 
 fn aquire_output(output:Option<(&Path, bool)>) -> Result<Box<dyn Write>, anyhow::Error> {
@@ -64,7 +84,7 @@ fn aquire_output(output:Option<(&Path, bool)>) -> Result<Box<dyn Write>, anyhow:
             Result::Ok(Box::new(std::io::stdout()))
         }
         Some((path, true)) => {
-            match File::options().write(true).open(path) {
+            match File::options().write(true).truncate(true).open(path) {
                 Result::Ok(file) => {
                     Result::Ok(Box::new(file))
                 }
@@ -84,4 +104,246 @@ fn aquire_output(output:Option<(&Path, bool)>) -> Result<Box<dyn Write>, anyhow:
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Renders every markdown file in a directory or matching a glob pattern to html,
+/// mirroring the input tree under the output directory.
+fn render_tree(pattern: &str, state: &Arc<Cli>) -> Result<()> {
+    let (base, files) = find_inputs(pattern, state)?;
+    if let Some(output) = bundle_output(state) {
+        return render_bundle(&base, files, output, state);
+    }
+    render_files(&base, &files, state)
+}
+
+/// The output path, when it names a newline delimited json bundle rather than a directory.
+fn bundle_output(state: &Cli) -> Option<&Path> {
+    state.output.as_ref()
+        .map(Path::new)
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ndjson"))
+}
+
+/// Renders every file into one newline delimited json stream of payloads, each with its
+/// path relative to `base`, for bulk ingestion by search engines or databases.
+fn render_bundle(base: &Path, mut files: Vec<PathBuf>, output: &Path, state: &Arc<Cli>) -> Result<()> {
+    files.retain(|file| within_max_file_size(file, state));
+    files.sort();
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(state.jobs.unwrap_or(0))
+        .build()?;
+    let documents = pool.install(|| files
+        .par_iter()
+        .map(|file| {
+            let payload = generate_payload_from_path(file, Arc::clone(state))
+                .with_context(|| format!("Failed to render {}.", file.display()))?;
+            let path = file.strip_prefix(base).unwrap_or(file)
+                .to_string_lossy()
+                .replace('\\', "/");
+            Ok(Document { path, payload })
+        })
+        .collect::<Result<Vec<_>>>()
+    )?;
+    let documents: Vec<Document> = documents.into_iter()
+        .filter(|document| !state.is_hidden(&document.payload.front_matter))
+        .collect();
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    bundle::write(&mut writer, &documents)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn render_files(base: &Path, files: &[PathBuf], state: &Arc<Cli>) -> Result<()> {
+    let output = state.output.as_ref()
+        .map(Path::new)
+        .ok_or_else(|| anyhow!("A directory or glob pattern input needs an output directory from -o or --output."))?;
+
+    // Zero lets rayon pick, one thread per core.
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(state.jobs.unwrap_or(0))
+        .build()?;
+    let errors: Vec<anyhow::Error> = pool.install(|| files
+        .par_iter()
+        .filter_map(|file| render_file(file, base, output, state)
+            .with_context(|| format!("Failed to render {}.", file.display()))
+            .err()
+        )
+        .collect()
+    );
+
+    for error in &errors {
+        eprintln!("{error:#}");
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!("{} of {} files failed to render.", errors.len(), files.len()));
+    }
+    Ok(())
+}
+
+/// The files to render, along with the directory their output paths are
+/// relative to.
+fn find_inputs(pattern: &str, state: &Cli) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let input = Path::new(pattern);
+    if input.is_dir() {
+        let mut files = vec![];
+        find_markdown(input, &state.source_extensions(), state, &mut files)?;
+        return Ok((input.to_path_buf(), files));
+    }
+
+    // Mirror from the last directory before the first wildcard.
+    let base = input.components()
+        .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+        .collect::<PathBuf>();
+    let mut files = vec![];
+    for path in glob(pattern)? {
+        let path = path?;
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok((base, files))
+}
+
+fn render_file(file: &Path, base: &Path, output: &Path, state: &Arc<Cli>) -> Result<()> {
+    let payload = generate_payload_from_path(file, Arc::clone(state))?;
+    // Drafts are left out of the output tree, unless --show-drafts is set.
+    if state.is_hidden(&payload.front_matter) {
+        return Ok(());
+    }
+
+    let relative = file.strip_prefix(base).unwrap_or(file);
+    let target = output.join(relative).with_extension("html");
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut writer = BufWriter::new(File::create(target)?);
+    payload.write_response_with_state(&mut writer, &PayloadFormats::Html, state)?;
+    writer.flush()?;
+    report_lint(file, &payload)
+}
+
+/// Prints the `--lint` warnings of a document to stderr, as
+/// `path:line: message [rule]`, failing when there are any, after its output
+/// is written.
+fn report_lint(file: &Path, payload: &Payload) -> Result<()> {
+    for warning in &payload.lint {
+        eprintln!("{}:{}: {} [{}]", file.display(), warning.line, warning.message, warning.rule);
+    }
+    match payload.lint.len() {
+        0 => Ok(()),
+        1 => Err(anyhow!("Found 1 lint warning.")),
+        count => Err(anyhow!("Found {count} lint warnings.")),
+    }
+}
+
+/// How long the input has to be quiet before re-rendering, as editors often
+/// write a file several times in a row.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Renders the input, then re-renders whatever changes until interrupted.
+fn watch(pattern: &str, state: &Arc<Cli>) -> Result<()> {
+    let input = Path::new(pattern);
+    let tree = input.is_dir() || is_glob(pattern);
+    let render = |pattern: &str| if tree {
+        render_tree(pattern, state)
+    } else {
+        render_file_to_output(pattern, state)
+    };
+    if let Err(error) = render(pattern) {
+        eprintln!("{error:#}");
+    }
+
+    // Watcher events carry absolute paths, so watch & compare against the same.
+    let (root, mode) = if tree {
+        let (base, _) = find_inputs(pattern, state)?;
+        let base = if base.as_os_str().is_empty() { PathBuf::from(".") } else { base };
+        (base.canonicalize()?, RecursiveMode::Recursive)
+    } else {
+        // Watch the directory, as editors often replace a file rather than write to it.
+        let file = input.canonicalize()?;
+        let parent = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        (parent, RecursiveMode::NonRecursive)
+    };
+    let file = (!tree).then(|| root.join(input.file_name().unwrap_or_default()));
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+        Ok(event) if !event.kind.is_access() => {
+            let _: Result<(), _> = sender.send(event.paths);
+        }
+        Ok(_) => {}
+        Err(error) => eprintln!("{error}"),
+    })?;
+    watcher.watch(&root, mode)?;
+    eprintln!("Watching {} for changes.", root.display());
+
+    while let Ok(mut changed) = receiver.recv() {
+        while let Ok(paths) = receiver.recv_timeout(DEBOUNCE) {
+            changed.extend(paths);
+        }
+        let started = Instant::now();
+
+        if let Some(file) = &file {
+            if changed.contains(file) {
+                match render(pattern) {
+                    Result::Ok(()) => eprintln!("Rebuilt {pattern} in {}ms.", started.elapsed().as_millis()),
+                    Err(error) => eprintln!("{error:#}"),
+                }
+            }
+            continue;
+        }
+
+        // A bundle holds every input, so is always rebuilt whole, ignoring its own writes.
+        if bundle_output(state).is_some() {
+            let extensions = state.source_extensions();
+            let sources = changed.iter().any(|path| path
+                .extension()
+                .and_then(OsStr::to_str)
+                .is_some_and(|extension| extensions.contains(&extension))
+            );
+            if !sources {
+                continue;
+            }
+            match render(pattern) {
+                Result::Ok(()) => eprintln!("Rebuilt {pattern} in {}ms.", started.elapsed().as_millis()),
+                Err(error) => eprintln!("{error:#}"),
+            }
+            continue;
+        }
+
+        // Only re-render the inputs that changed, skipping any since removed.
+        let (base, files) = match find_inputs(pattern, state) {
+            Result::Ok(inputs) => inputs,
+            Err(error) => {
+                eprintln!("{error:#}");
+                continue;
+            }
+        };
+        let files: Vec<PathBuf> = files.into_iter()
+            .filter(|file| file.canonicalize().is_ok_and(|file| changed.contains(&file)))
+            .collect();
+        if files.is_empty() {
+            continue;
+        }
+        if let Err(error) = render_files(&base, &files, state) {
+            eprintln!("{error:#}");
+            continue;
+        }
+        let names: Vec<_> = files.iter()
+            .map(|file| file.strip_prefix(&base).unwrap_or(file).display().to_string())
+            .collect();
+        eprintln!(
+            "Rebuilt {} file{} in {}ms: {}",
+            files.len(),
+            if files.len() == 1 { "" } else { "s" },
+            started.elapsed().as_millis(),
+            names.join(", "),
+        );
+    }
+    Ok(())
+}
@@ -2,36 +2,324 @@ extern crate alloc;
 
 use tokio::signal;
 use alloc::sync::Arc;
-use std::net::SocketAddr;
-use serve_md_async::determine;
-use clap::Parser as CliParser;
-use axum::{ Router, routing::get, };
+use std::ffi::{OsStr, OsString};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use tracing::{debug, info, info_span, Level};
+use tracing_subscriber::EnvFilter;
+use tower_http::LatencyUnit;
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use serve_md_async::{bundle, determine, feed, list, mounted, render_markdown, robots};
+use serve_md_async::access::{parse_allowlist, restrict, RateLimiter};
+use serve_md_async::admin::{flush_head_cache, options, plugins, reindex};
+use serve_md_async::aliases::{redirect, Redirector};
+use serve_md_async::auth::authenticate;
+use serve_md_async::check::check_links;
+use serve_md_async::config::{reload, ReloadableState};
+#[cfg(unix)]
+use serve_md_async::config::reload_on_hangup;
+use serve_md_async::images::cached_image;
+use serve_md_async::pages::error_pages;
+use serve_md_async::reload::{events, Reloader};
+use serve_md_async::request_id::{self, identify};
+use serve_md_async::search::{search, Searcher};
+use axum::{ Router, routing::{delete, get, post}, body::Body, extract::ConnectInfo, http::Request, middleware::{self, Next} };
+use axum_server::Handle;
+use axum_server::tls_rustls::RustlsConfig;
 use serve_md_core::state::State as Cli;
+use serve_md_core::formats::Feed as FeedFormats;
 
-#[tokio::main]
-async fn main() {
-    let mut cli = Cli::parse();
-    cli.load_config();
-    cli.set_missing();
+/// `serve_md config dump [json|toml|yaml] [options]` is shorthand for
+/// `serve_md --print-config <format> [options]`, defaulting to toml, and
+/// `serve_md check [options]` for `serve_md --check [options]`.
+fn arguments() -> Vec<OsString> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    if args.len() > 2 && args[1] == "config" && args[2] == "dump" {
+        let named = matches!(
+            args.get(3).and_then(|arg| arg.to_str()),
+            Some("json" | "toml" | "yaml")
+        );
+        let format = if named { args.remove(3) } else { OsString::from("toml") };
+        args.splice(1..3, [OsString::from("--print-config"), format]);
+    } else if args.len() > 1 && args[1] == "check" {
+        args[1] = OsString::from("--check");
+    }
+    args
+}
+
+fn main() {
+    let mut cli = Cli::try_from_args(arguments()).unwrap_or_else(|error| error.exit());
+
+    if let Some(format) = cli.print_config {
+        match cli.try_load_config().and_then(|_| cli.to_config(format)) {
+            Ok(config) => println!("{}", config.trim_end()),
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let filter = cli.log_level.as_deref().map_or_else(
+        || EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        EnvFilter::new,
+    );
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    // Keeps stdout for the report.
+    if cli.check {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
+
+    let shared = match ReloadableState::new(cli) {
+        Ok(state) => Arc::new(state),
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = shared.load().render_threads {
+        runtime.max_blocking_threads(threads.max(1));
+    }
+    runtime
+        .build()
+        .expect("failed to start the runtime")
+        .block_on(async {
+            let state = shared.load();
+            if state.check {
+                check(state).await;
+            } else {
+                serve(shared).await;
+            }
+        });
+}
+
+/// Prints the link report of every document under root as json, exiting
+/// with 1 when a link is broken, or 2 when the check itself fails.
+async fn check(state: Arc<Cli>) {
+    let root = state.root.as_deref().unwrap_or(".");
+    match check_links(Path::new(root), Arc::clone(&state)).await {
+        Ok(report) => {
+            println!("{}", serde_json::to_string_pretty(&report).expect("failed to serialize the report"));
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+        }
+        Err(error) => {
+            eprintln!("{error:#}");
+            std::process::exit(2);
+        }
+    }
+}
+
+async fn serve(shared: Arc<ReloadableState>) {
+    // Options used on start up, e.g. the enabled endpoints, need a restart to change.
+    let state = shared.load();
 
-    #[cfg(debug_assertions)]
-    dbg!(&cli);
+    debug!(?state);
 
-    let state = Arc::new(cli);
-    
     // As far as I can tell, axum can't match paths with
     // file extensions? `:file.html` or `:file.md`.
-    let routes = Router::new()
+    let mut routes = Router::new()
+        .route("/feed.rss", get({
+            let shared_state = Arc::clone(&shared);
+            move |query| feed(FeedFormats::Rss, query, shared_state.load())
+        }))
+        .route("/feed.atom", get({
+            let shared_state = Arc::clone(&shared);
+            move |query| feed(FeedFormats::Atom, query, shared_state.load())
+        }))
+        .route("/feed.json", get({
+            let shared_state = Arc::clone(&shared);
+            move |query| feed(FeedFormats::Json, query, shared_state.load())
+        }))
+        .route("/bundle.ndjson", get({
+            let shared_state = Arc::clone(&shared);
+            move |query| bundle(query, shared_state.load())
+        }))
+        .route("/api/list", get({
+            let shared_state = Arc::clone(&shared);
+            move |query| list(query, shared_state.load())
+        }))
         .route("/:path", get({
-            let shared_state = Arc::clone(&state);
-            move |path| determine(path, shared_state)
+            let shared_state = Arc::clone(&shared);
+            move |method, path, query, headers| {
+                determine(method, path, query, headers, shared_state.load())
+            }
+        }))
+    ;
+
+    for name in state.profiles.keys() {
+        state.for_profile(name).expect("invalid profile options");
+    }
+
+    for (prefix, mount) in &state.mounts {
+        state.for_mount(mount).expect("invalid mount options");
+        let route = format!("{}/*path", prefix.trim_end_matches('/'));
+        routes = routes.route(&route, get({
+            let shared_state = Arc::clone(&shared);
+            // Looked up on every request, so reloading the config changes a mount's options.
+            let prefix = prefix.clone();
+            move |method, path, query, headers| {
+                mounted(prefix, method, path, query, headers, shared_state.load())
+            }
+        }));
+    }
+
+    if state.watch {
+        let root = state.root.as_deref().unwrap_or(".");
+        let extensions = state.source_extensions().into_iter().map(String::from).collect();
+        let reloader = Arc::new(
+            Reloader::watch(Path::new(root), extensions).expect("failed to watch root")
+        );
+        routes = routes.route("/__reload", get(move || events(reloader)));
+    }
+
+    if state.render_endpoint {
+        routes = routes.route("/render", post({
+            let shared_state = Arc::clone(&shared);
+            move |query, headers, body| render_markdown(query, headers, shared_state.load(), body)
+        }));
+    }
+
+    if state.robots {
+        routes = routes.route("/robots.txt", get({
+            let shared_state = Arc::clone(&shared);
+            move || robots(shared_state.load())
+        }));
+    }
+
+    if state.reload_endpoint {
+        routes = routes.route("/__admin/reload", post({
+            let shared_state = Arc::clone(&shared);
+            move || reload(shared_state)
+        }));
+    }
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(Arc::clone(&shared)));
+
+    let searcher = state.search.then(|| {
+        let root = state.root.as_deref().unwrap_or(".");
+        Arc::new(
+            Searcher::watch(Path::new(root), Arc::clone(&shared)).expect("failed to index root")
+        )
+    });
+    if let Some(searcher) = &searcher {
+        let searcher = Arc::clone(searcher);
+        routes = routes.route("/search", get(move |query| search(query, searcher)));
+    }
+
+    if state.admin_token.is_some() {
+        routes = routes
+            .route("/__admin/head-cache", delete(flush_head_cache))
+            .route("/__admin/search", post({
+                let shared_state = Arc::clone(&shared);
+                move || reindex(searcher, shared_state.load())
+            }))
+            .route("/__admin/plugins", get({
+                let shared_state = Arc::clone(&shared);
+                move || plugins(shared_state.load())
+            }))
+            .route("/__admin/state", get({
+                let shared_state = Arc::clone(&shared);
+                move || options(shared_state.load())
+            }));
+    }
+
+    if state.image_cache.is_some() {
+        routes = routes.route("/img-cache/:key", get({
+            let shared_state = Arc::clone(&shared);
+            move |key| cached_image(key, shared_state.load())
+        }));
+    }
+
+    if state.aliases {
+        let root = state.root.as_deref().unwrap_or(".");
+        let redirector = Arc::new(
+            Redirector::watch(Path::new(root), Arc::clone(&shared)).expect("failed to read aliases")
+        );
+        routes = routes.layer(middleware::from_fn(move |request: Request<Body>, next: Next<Body>| {
+            redirect(Arc::clone(&redirector), request, next)
+        }));
+    }
+
+    let routes = routes
+        .layer(middleware::from_fn({
+            let shared_state = Arc::clone(&shared);
+            move |request: Request<Body>, next: Next<Body>| authenticate(shared_state.load(), request, next)
         }))
+        .layer(middleware::from_fn({
+            let shared_state = Arc::clone(&shared);
+            move |request: Request<Body>, next: Next<Body>| error_pages(shared_state.load(), request, next)
+        }))
+        .layer(middleware::from_fn({
+            let shared_state = Arc::clone(&shared);
+            let limiter = Arc::new(RateLimiter::default());
+            move |peer: ConnectInfo<SocketAddr>, request: Request<Body>, next: Next<Body>| {
+                restrict(shared_state.load(), Arc::clone(&limiter), peer, request, next)
+            }
+        }))
+        .layer(TraceLayer::new_for_http()
+            .make_span_with(|request: &Request<Body>| {
+                let path = request.uri().path();
+                let format = Path::new(path)
+                    .extension()
+                    .and_then(OsStr::to_str)
+                    .unwrap_or_default();
+                let id = request
+                    .headers()
+                    .get(request_id::HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default();
+                info_span!("request", id, method = %request.method(), path, format)
+            })
+            .on_response(DefaultOnResponse::new()
+                .level(Level::INFO)
+                .latency_unit(LatencyUnit::Millis)
+            )
+        )
+        // Outermost, so the id is set before the request span is made.
+        .layer(middleware::from_fn(|request: Request<Body>, next: Next<Body>| identify(request, next)))
     ;
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], state.port));
-    println!("starting server on 127.0.0.1:{}", state.port);
+    if let Some(allow) = &state.allow {
+        parse_allowlist(allow).expect("invalid --allow entry");
+    }
+
+    let host = state.host.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    let addr = SocketAddr::from((host, state.port));
+
+    if let (Some(cert), Some(key)) = (&state.tls_cert, &state.tls_key) {
+        let config = RustlsConfig::from_pem_file(cert, key)
+            .await
+            .expect("failed to load the TLS certificate or key");
+        let handle = Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                shutdown_signal().await;
+                handle.graceful_shutdown(None);
+            }
+        });
+
+        info!("starting server on https://{addr}");
+        axum_server::bind_rustls(addr, config)
+            .handle(handle)
+            .serve(routes.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap();
+        return;
+    }
+
+    info!("starting server on {addr}");
     axum::Server::bind(&addr)
-        .serve(routes.into_make_service())
+        .serve(routes.into_make_service_with_connect_info::<SocketAddr>())
         // @see https://github.com/tokio-rs/axum/blob/main/examples/graceful-shutdown/src/main.rs
         .with_graceful_shutdown(shutdown_signal())
         .await
@@ -62,5 +350,5 @@ async fn shutdown_signal() {
         _ = terminate => {},
     }
 
-    println!("signal received, starting graceful shutdown");
+    info!("signal received, starting graceful shutdown");
 }
\ No newline at end of file
@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::anyhow;
+use axum::{
+    extract::ConnectInfo,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serve_md_core::state::State;
+
+use crate::problem;
+
+/// The most client addresses tracked before those with a full bucket are forgotten.
+const TRACKED: usize = 4096;
+
+/// An address range, e.g. `10.0.0.0/8`, or a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = value.trim().split_once('/').unwrap_or((value.trim(), ""));
+        let network = canonical(
+            address
+                .parse::<IpAddr>()
+                .map_err(|error| anyhow!("{value} isn't an address or CIDR range, {error}."))?,
+        );
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = if prefix.is_empty() {
+            max
+        } else {
+            prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| anyhow!("{value} has a prefix outside of 0..={max}."))?
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+/// IPv4 clients of a dual stack listener arrive as IPv4 mapped IPv6 addresses.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Parses every entry of `--allow`.
+///
+/// # Errors
+///
+/// Will return `Err` on the first entry that isn't an address or CIDR range.
+pub fn parse_allowlist(entries: &[String]) -> anyhow::Result<Vec<Cidr>> {
+    entries.iter().map(|entry| entry.parse()).collect()
+}
+
+/// A token bucket per client address, refilled at `rate_limit` tokens a second.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    /// Takes a token from the bucket of `ip`, returning how many seconds until
+    /// the next token when it's empty.
+    pub fn take(&self, ip: IpAddr, rate: u32) -> Result<(), u64> {
+        let rate = f64::from(rate.max(1));
+        let now = Instant::now();
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if buckets.len() >= TRACKED {
+            buckets.retain(|_, (tokens, updated)| {
+                *tokens + now.duration_since(*updated).as_secs_f64() * rate < rate
+            });
+        }
+        let (tokens, updated) = buckets.entry(ip).or_insert((rate, now));
+        *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * rate).min(rate);
+        *updated = now;
+        if *tokens < 1.0 {
+            return Err(((1.0 - *tokens) / rate).ceil() as u64);
+        }
+        *tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Middleware answering clients outside of the `allow` list with
+/// `StatusCode::FORBIDDEN`, and those over the `rate_limit` with
+/// `StatusCode::TOO_MANY_REQUESTS` and a `Retry-After` header.
+pub async fn restrict<B>(
+    state: Arc<State>,
+    limiter: Arc<RateLimiter>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let ip = canonical(peer.ip());
+    if let Some(allow) = &state.allow {
+        let allowed = allow
+            .iter()
+            .filter_map(|entry| entry.parse::<Cidr>().ok())
+            .any(|cidr| cidr.contains(ip));
+        if !allowed {
+            return Err::<Response, _>(problem(
+                StatusCode::FORBIDDEN,
+                &format!("{ip} isn't allowed."),
+            ))
+            .into_response();
+        }
+    }
+    if let Some(rate) = state.rate_limit {
+        if let Err(retry_after) = limiter.take(ip, rate) {
+            let mut response = Err::<Response, _>(problem(
+                StatusCode::TOO_MANY_REQUESTS,
+                &format!("Over the limit of {rate} requests a second."),
+            ))
+            .into_response();
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after.max(1)));
+            return response;
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_allowlist, Cidr, RateLimiter};
+    use std::net::IpAddr;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().expect("Valid address.")
+    }
+
+    #[test]
+    fn access_test_cidr_contains() {
+        let entries = ["10.0.0.0/8", "192.168.1.7", "fd00::/8", "0.0.0.0/0"]
+            .map(String::from)
+            .to_vec();
+        match parse_allowlist(&entries) {
+            Ok(cidrs) => {
+                assert!(cidrs[0].contains(ip("10.20.30.40")));
+                assert!(!cidrs[0].contains(ip("11.0.0.1")));
+                assert!(cidrs[0].contains(ip("::ffff:10.1.1.1")));
+                assert!(cidrs[1].contains(ip("192.168.1.7")));
+                assert!(!cidrs[1].contains(ip("192.168.1.8")));
+                assert!(cidrs[2].contains(ip("fd12::1")));
+                assert!(!cidrs[2].contains(ip("fe80::1")));
+                assert!(cidrs[3].contains(ip("8.8.8.8")));
+            }
+            Err(error) => {
+                dbg!(&error);
+                assert!(false, "Should NEVER return an error. Error was {error}.")
+            }
+        }
+    }
+
+    #[test]
+    fn access_test_invalid_cidr() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("localhost".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn access_test_rate_limit() {
+        let limiter = RateLimiter::default();
+        for _ in 0..3 {
+            assert!(limiter.take(ip("127.0.0.1"), 3).is_ok());
+        }
+        assert_eq!(limiter.take(ip("127.0.0.1"), 3), Err(1));
+        assert!(limiter.take(ip("127.0.0.2"), 3).is_ok());
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response, Result},
+    Json,
+};
+use serde_json::{json, Value};
+use serve_md_core::{plugin_configs, state::State};
+use tokio::task::spawn_blocking;
+use tracing::{info, warn};
+
+use crate::{head, problem, search::Searcher};
+
+/// The prefix of every admin endpoint, which need `State::admin_token` when
+/// it's set, see `auth::authenticate`.
+pub const PREFIX: &str = "/__admin/";
+
+/// Options holding credentials, left out of `GET /__admin/state`.
+const SECRETS: [&str; 3] = ["auth_token", "basic_auth", "admin_token"];
+
+/// The `DELETE /__admin/head-cache` endpoint, emptying the cache of rendered
+/// headers `HEAD` requests are answered from, see `head`, answering with how
+/// many entries it held.
+pub async fn flush_head_cache() -> Json<Value> {
+    let flushed = head::clear();
+    info!(flushed, "Flushed the HEAD cache.");
+    Json(json!({ "flushed": flushed }))
+}
+
+/// The `POST /__admin/search` endpoint, rebuilding the search index from
+/// scratch with the current options, answering with how many documents
+/// were indexed.
+///
+/// # Errors
+///
+/// Will return, with an `application/problem+json` body:
+/// - `StatusCode::NOT_FOUND` without `--search`.
+/// - `StatusCode::INTERNAL_SERVER_ERROR` if root can't be indexed.
+pub async fn reindex(searcher: Option<Arc<Searcher>>, state: Arc<State>) -> Result<Json<Value>> {
+    let Some(searcher) = searcher else {
+        return Err(problem(
+            StatusCode::NOT_FOUND,
+            "Search isn't enabled, see --search.",
+        ));
+    };
+    let documents = spawn_blocking(move || searcher.rebuild(state))
+        .await
+        .map_err(|error| problem(StatusCode::INTERNAL_SERVER_ERROR, &error.to_string()))?
+        .map_err(|error| {
+            warn!("{error}");
+            problem(StatusCode::INTERNAL_SERVER_ERROR, &error.to_string())
+        })?;
+    info!(documents, "Rebuilt the search index.");
+    Ok(Json(json!({ "documents": documents })))
+}
+
+/// The `GET /__admin/plugins` endpoint, every plugin documents are rendered
+/// with, in the order they run, along with the config each is built from.
+pub async fn plugins(state: Arc<State>) -> Json<Value> {
+    let plugins: Vec<Value> = plugin_configs(&state)
+        .into_iter()
+        .map(|(name, config)| json!({ "name": name, "config": config }))
+        .collect();
+    Json(Value::Array(plugins))
+}
+
+/// The `GET /__admin/state` endpoint, the effective options as json, as
+/// `--print-config json` prints them, with credentials redacted.
+///
+/// # Errors
+///
+/// Will return `StatusCode::INTERNAL_SERVER_ERROR` if the options can't be
+/// serialized.
+pub async fn options(state: Arc<State>) -> Result<Response> {
+    let mut options = serde_json::to_value(&*state)
+        .map_err(|error| problem(StatusCode::INTERNAL_SERVER_ERROR, &error.to_string()))?;
+    if let Some(options) = options.as_object_mut() {
+        for secret in SECRETS {
+            if options.get(secret).is_some_and(|value| !value.is_null()) {
+                options.insert(secret.to_string(), json!("[redacted]"));
+            }
+        }
+    }
+    Ok(Json(options).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn admin_test_options_redacted() {
+        let state = State::try_from_args(["serve_md", "--admin-token", "r00t", "--toc"])
+            .expect("Valid arguments.");
+        let response = options(Arc::new(state)).await.into_response();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let options: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(options["admin_token"], "[redacted]");
+        assert_eq!(options["auth_token"], Value::Null);
+        assert_eq!(options["toc"], true);
+    }
+
+    #[tokio::test]
+    async fn admin_test_plugins() {
+        let state = State::try_from_args(["serve_md", "--emoji-shortcodes", "--external-links"])
+            .expect("Valid arguments.");
+        let Json(plugins) = plugins(Arc::new(state)).await;
+        let names: Vec<_> = plugins
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|plugin| plugin["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["emoji", "external_links"]);
+
+        let response = reindex(None, Arc::new(State::default()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serve_md_core::aliases::AliasMap;
+use tokio::task::spawn_blocking;
+use tracing::{debug, warn};
+
+use crate::config::ReloadableState;
+
+/// The `aliases` of every markdown file under root, re-reading files as they
+/// change, and every file once the options are reloaded.
+pub struct Redirector {
+    map: Arc<RwLock<AliasMap>>,
+    root: PathBuf,
+    state: Arc<ReloadableState>,
+    // Dropping the watcher stops it, so keep it alive alongside the map.
+    _watcher: RecommendedWatcher,
+}
+
+impl Redirector {
+    /// # Errors
+    ///
+    /// Will return `Err` if `root` can't be read or watched.
+    pub fn watch(root: &Path, state: Arc<ReloadableState>) -> anyhow::Result<Redirector> {
+        // Watcher events carry absolute paths, so map against the same.
+        let root = root.canonicalize()?;
+        let map = Arc::new(RwLock::new(AliasMap::build(&root, state.load())?));
+        let mut watcher = notify::recommended_watcher({
+            let map = Arc::clone(&map);
+            move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if !event.kind.is_access() => {
+                    let Ok(mut map) = map.write() else {
+                        return;
+                    };
+                    for path in &event.paths {
+                        debug!(?path, "Re-reading aliases.");
+                        map.update(path);
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => warn!("{error}"),
+            }
+        })?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+
+        Ok(Redirector {
+            map,
+            root,
+            state,
+            _watcher: watcher,
+        })
+    }
+
+    /// Whether the options were reloaded since the map was built, e.g.
+    /// changing `show_drafts` or the ignore rules.
+    fn is_stale(&self) -> bool {
+        let generation = self.state.load().generation;
+        self.map
+            .read()
+            .is_ok_and(|map| map.generation() != generation)
+    }
+
+    /// Reads every file again with the current options, keeping the old map
+    /// if root can't be read.
+    fn rebuild(&self) {
+        match AliasMap::build(&self.root, self.state.load()) {
+            Ok(rebuilt) => {
+                if let Ok(mut map) = self.map.write() {
+                    debug!("Re-read aliases with the reloaded options.");
+                    *map = rebuilt;
+                }
+            }
+            Err(error) => warn!("{error:#}"),
+        }
+    }
+
+    /// The current path of the document with the alias `path`, if any.
+    fn target(&self, path: &str) -> Option<String> {
+        self.map
+            .read()
+            .ok()
+            .and_then(|map| map.target(path).map(String::from))
+    }
+}
+
+/// Middleware answering requests for a document's old path, one of its
+/// `aliases`, with a 301 to its current path, keeping the query string.
+pub async fn redirect<B>(
+    redirector: Arc<Redirector>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if redirector.is_stale() {
+        let stale = Arc::clone(&redirector);
+        if let Err(error) = spawn_blocking(move || stale.rebuild()).await {
+            warn!("{error}");
+        }
+    }
+    let Some(target) = redirector.target(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let location = match request.uri().query() {
+        Some(query) => format!("{target}?{query}"),
+        None => target,
+    };
+    (
+        StatusCode::MOVED_PERMANENTLY,
+        [(header::LOCATION, location)],
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serve_md_core::{formats::Matter, state::State};
+
+    #[test]
+    fn aliases_test_redirector() {
+        let root = std::env::temp_dir().join("serve_md_redirector_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("new.md"), "---\naliases: [/old]\n---\n# New\n").unwrap();
+
+        let mut state = State::default();
+        state.front_matter = Some(Matter::Yaml);
+        let state = Arc::new(ReloadableState::new(state).unwrap());
+        let redirector = Redirector::watch(&root, Arc::clone(&state)).unwrap();
+        assert_eq!(redirector.target("/old"), Some(String::from("/new.html")));
+        assert_eq!(redirector.target("/new.html"), None);
+
+        state.reload().unwrap();
+        assert!(redirector.is_stale());
+        redirector.rebuild();
+        assert!(!redirector.is_stale());
+        assert_eq!(redirector.target("/old"), Some(String::from("/new.html")));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,100 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use futures_util::{stream, StreamExt};
+use hyper::{client::HttpConnector, Body, Client, Method, Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serve_md_core::{
+    links::{self, BrokenLink, LinkReport},
+    state::State,
+};
+use tokio::{task::spawn_blocking, time::timeout};
+
+/// How many external urls are requested at once.
+const CONCURRENCY: usize = 8;
+/// How long an external url has to respond.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Checks the links of every document under `root`, requesting external urls
+/// too when `State::check_external` is set.
+///
+/// # Errors
+///
+/// Will return `Err` if a directory under `root` can't be read or a
+/// document fails to render.
+pub async fn check_links(root: &Path, state: Arc<State>) -> anyhow::Result<LinkReport> {
+    let root = root.to_path_buf();
+    let external = state.check_external;
+    let mut report = spawn_blocking(move || links::check(&root, state)).await??;
+    if external {
+        check_external(&mut report).await;
+    }
+    Ok(report)
+}
+
+/// Requests every external url in `report`, adding those failing to its
+/// broken links, for each document linking to them. Redirects count as
+/// working, and a `HEAD` answered with a 405 or 501 is retried as a `GET`.
+pub async fn check_external(report: &mut LinkReport) {
+    let https = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client: Client<_, Body> = Client::builder().build(https);
+
+    let failures: Vec<(String, String)> = stream::iter(report.external.keys().cloned())
+        .map(|url| {
+            let client = client.clone();
+            async move {
+                let failure = request(&client, &url).await.err();
+                (url, failure)
+            }
+        })
+        .buffer_unordered(CONCURRENCY)
+        .filter_map(|(url, failure)| async move { failure.map(|reason| (url, reason)) })
+        .collect()
+        .await;
+
+    for (url, reason) in failures {
+        for document in &report.external[&url] {
+            report.broken.push(BrokenLink {
+                document: document.clone(),
+                target: url.clone(),
+                reason: reason.clone(),
+            });
+        }
+    }
+    // Stable, so each document's internal links keep their order.
+    report.broken.sort_by(|a, b| a.document.cmp(&b.document));
+}
+
+async fn request(client: &Client<HttpsConnector<HttpConnector>>, url: &str) -> Result<(), String> {
+    // Protocol relative urls are assumed to be https.
+    let url = match url.strip_prefix("//") {
+        Some(rest) => format!("https://{rest}"),
+        None => url.to_string(),
+    };
+    let mut status = StatusCode::OK;
+    for method in [Method::HEAD, Method::GET] {
+        let request = Request::builder()
+            .method(method)
+            .uri(&url)
+            .body(Body::empty())
+            .map_err(|error| error.to_string())?;
+        status = match timeout(TIMEOUT, client.request(request)).await {
+            Ok(Ok(response)) => response.status(),
+            Ok(Err(error)) => return Err(error.to_string()),
+            Err(_) => return Err(format!("Timed out after {}s.", TIMEOUT.as_secs())),
+        };
+        if !matches!(
+            status,
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+        ) {
+            break;
+        }
+    }
+    if status.is_client_error() || status.is_server_error() {
+        return Err(format!("Responded with {status}."));
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serve_md_core::state::State;
+use tracing::{info, warn};
+
+/// The servers options, swapped out whole when the config file is reloaded.
+/// Requests keep the options they started with.
+pub struct ReloadableState {
+    /// The options from the command line, which the config file is layered
+    /// over on every load.
+    cli: State,
+    current: ArcSwap<State>,
+}
+
+impl ReloadableState {
+    /// Loads the config file over `cli`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the config file can't be loaded, including a
+    /// `ConfigError` listing its unknown options & invalid values, see
+    /// `State::try_load_config`.
+    pub fn new(cli: State) -> anyhow::Result<ReloadableState> {
+        let mut state = cli.clone();
+        state.try_load_config()?;
+        state.set_missing();
+        Ok(ReloadableState {
+            cli,
+            current: ArcSwap::from_pointee(state),
+        })
+    }
+
+    /// The current options.
+    pub fn load(&self) -> Arc<State> {
+        self.current.load_full()
+    }
+
+    /// Loads the config file again, replacing the current options. Options
+    /// used on start up, e.g. `port` or the enabled endpoints, need a
+    /// restart to change.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err`, keeping the current options, if the config file
+    /// can't be loaded.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let mut state = self.cli.clone();
+        state.try_load_config()?;
+        state.set_missing();
+        state.generation = self.current.load().generation + 1;
+        self.current.store(Arc::new(state));
+        info!("Reloaded config.");
+        Ok(())
+    }
+}
+
+/// The `POST /__admin/reload` endpoint, answering `StatusCode::NO_CONTENT`,
+/// or `StatusCode::INTERNAL_SERVER_ERROR` with the error as the body when the
+/// config file can't be loaded.
+pub async fn reload(state: Arc<ReloadableState>) -> Response {
+    match state.reload() {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(error) => {
+            warn!("{error}");
+            (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    }
+}
+
+/// Reloads the config file every time the process receives SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_hangup(state: Arc<ReloadableState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            warn!("Unable to listen for SIGHUP. {error}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(error) = state.reload() {
+            warn!("{error}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReloadableState;
+    use clap::Parser;
+    use serve_md_core::state::State;
+    use std::fs;
+
+    #[test]
+    fn config_test_reload() {
+        let path = std::env::temp_dir().join("serve_md_reload_test.toml");
+        fs::write(&path, "toc = true\n").unwrap();
+
+        let cli = State::parse_from(["serve_md", "--config", &path.to_string_lossy()]);
+        let state = ReloadableState::new(cli).unwrap();
+        let before = state.load();
+        assert!(before.toc);
+
+        fs::write(&path, "toc = false\nemoji_shortcodes = true\n").unwrap();
+        assert!(state.reload().is_ok());
+        let after = state.load();
+        assert!(!after.toc);
+        assert!(after.emoji_shortcodes);
+        assert_eq!(after.generation, before.generation + 1);
+        // Requests holding the old options keep them.
+        assert!(before.toc);
+
+        fs::write(&path, "toc = [").unwrap();
+        assert!(state.reload().is_err());
+        assert!(state.load().emoji_shortcodes);
+        assert_eq!(state.load().generation, after.generation);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn config_test_invalid_on_start() {
+        let path = std::env::temp_dir().join("serve_md_invalid_start_test.toml");
+        fs::write(&path, "toc = true\ntable_of_contents = true\n").unwrap();
+
+        let cli = State::parse_from(["serve_md", "--config", &path.to_string_lossy()]);
+        match ReloadableState::new(cli) {
+            Ok(_) => assert!(false, "Should have returned an error."),
+            Err(error) => assert!(error.to_string().contains("table_of_contents")),
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+}
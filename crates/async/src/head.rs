@@ -0,0 +1,55 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+/// Entries kept before the cache starts over, as every combination of
+/// options a document is requested with gets its own.
+const CAPACITY: usize = 4096;
+
+/// The headers of a rendered response, along with the `ETag` of the source
+/// it was rendered from, enough to answer a `HEAD` request without
+/// rendering the document again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    pub etag: String,
+    pub content_type: String,
+    pub content_length: u64,
+}
+
+fn cache() -> &'static Mutex<HashMap<String, Rendered>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Rendered>>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+/// The headers last rendered for `key`, as long as the source still has
+/// `etag`.
+pub fn get(key: &str, etag: &str) -> Option<Rendered> {
+    let cache = cache().lock().ok()?;
+    cache
+        .get(key)
+        .filter(|rendered| rendered.etag == etag)
+        .cloned()
+}
+
+/// Records the headers rendered for `key`, emptying the cache first once
+/// it's full.
+pub fn insert(key: String, rendered: Rendered) {
+    let Ok(mut cache) = cache().lock() else {
+        return;
+    };
+    if cache.len() >= CAPACITY && !cache.contains_key(&key) {
+        cache.clear();
+    }
+    cache.insert(key, rendered);
+}
+
+/// Empties the cache, returning how many entries it held.
+pub fn clear() -> usize {
+    let Ok(mut cache) = cache().lock() else {
+        return 0;
+    };
+    let entries = cache.len();
+    cache.clear();
+    entries
+}
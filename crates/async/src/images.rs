@@ -0,0 +1,176 @@
+use std::{
+    path::Path as SysPath,
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::{anyhow, bail, Context};
+use axum::{
+    extract::Path,
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response, Result},
+};
+use hyper::{
+    body::{Bytes, HttpBody},
+    client::HttpConnector,
+    Body, Client,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serve_md_core::{images, state::State};
+use tokio::fs::{read, read_to_string, rename, write};
+use tracing::{debug, warn};
+
+const MAX_REDIRECTS: usize = 5;
+/// The largest image downloaded without `State::max_file_size`, 25 MiB.
+const MAX_IMAGE_SIZE: u64 = 25 * 1024 * 1024;
+
+/// The `/img-cache/:key` endpoint, serving a remote image recorded by the
+/// `image_cache` plugin, downloading it into the cache directory first when
+/// it's missing.
+///
+/// # Errors
+///
+/// Will return:
+/// - `StatusCode::NOT_FOUND` for keys that were never recorded.
+/// - `StatusCode::BAD_GATEWAY` when the image can't be downloaded, isn't
+///   an image or is larger than `State::max_file_size`.
+pub async fn cached_image(Path(key): Path<String>, state: Arc<State>) -> Result<Response> {
+    let dir = SysPath::new(state.image_cache.as_deref().ok_or(StatusCode::NOT_FOUND)?);
+    let url = images::url(dir, &key).ok_or(StatusCode::NOT_FOUND)?;
+    let body_path = dir.join(&key);
+    let type_path = dir.join(format!("{key}.type"));
+
+    let (content_type, body) = match (read_to_string(&type_path).await, read(&body_path).await) {
+        (Ok(content_type), Ok(body)) => (content_type, Bytes::from(body)),
+        _ => {
+            debug!(url, "Downloading image.");
+            let limit = state.max_file_size.unwrap_or(MAX_IMAGE_SIZE);
+            let (content_type, body) = download(&url, limit).await.map_err(|error| {
+                warn!("{error:#}");
+                StatusCode::BAD_GATEWAY
+            })?;
+            if let Err(error) = store(&body_path, &type_path, &content_type, &body).await {
+                // Still serve the image, it's downloaded again next time.
+                warn!("{error:#}");
+            }
+            (content_type, body)
+        }
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::CACHE_CONTROL,
+                "public, max-age=31536000, immutable".to_string(),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// The client every image is downloaded with, sharing its connections.
+fn client() -> &'static Client<HttpsConnector<HttpConnector>, Body> {
+    static CLIENT: OnceLock<Client<HttpsConnector<HttpConnector>, Body>> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        let https = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Client::builder().build(https)
+    })
+}
+
+/// Fetches `url`, following redirects, returning its content type & body,
+/// giving up on bodies over `limit` bytes.
+async fn download(url: &str, limit: u64) -> anyhow::Result<(String, Bytes)> {
+    let client = client();
+    let mut uri: Uri = url.parse().with_context(|| format!("Invalid url {url}."))?;
+    for _ in 0..=MAX_REDIRECTS {
+        let response = client
+            .get(uri.clone())
+            .await
+            .with_context(|| format!("Requesting {uri} failed."))?;
+        let status = response.status();
+
+        if status.is_redirection() {
+            let location = response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .ok_or_else(|| anyhow!("{uri} redirected without a location."))?;
+            uri = redirect(&uri, location)?;
+            continue;
+        }
+        if !status.is_success() {
+            bail!("{uri} responded with {status}.");
+        }
+
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if !content_type.starts_with("image/") {
+            bail!("{uri} isn't an image, its content type is `{content_type}`.");
+        }
+        let mut body = response.into_body();
+        let mut bytes = vec![];
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            if (bytes.len() + chunk.len()) as u64 > limit {
+                bail!("{uri} is larger than {limit} bytes.");
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        return Ok((content_type, Bytes::from(bytes)));
+    }
+
+    bail!("{url} redirected more than {MAX_REDIRECTS} times.")
+}
+
+/// Resolves a `Location` header, which can be relative to the host of `uri`.
+fn redirect(uri: &Uri, location: &str) -> anyhow::Result<Uri> {
+    if location.starts_with('/') && !location.starts_with("//") {
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(location.parse()?);
+        return Ok(Uri::from_parts(parts)?);
+    }
+    Ok(location.parse()?)
+}
+
+/// Writes the content type before the body, moving the body into place once
+/// it's complete, so a request never reads a partial image.
+async fn store(
+    body_path: &SysPath,
+    type_path: &SysPath,
+    content_type: &str,
+    body: &Bytes,
+) -> anyhow::Result<()> {
+    write(type_path, content_type).await?;
+    let partial = body_path.with_extension("partial");
+    write(&partial, body).await?;
+    rename(&partial, body_path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::redirect;
+    use axum::http::Uri;
+
+    #[test]
+    fn images_test_redirect() {
+        let uri: Uri = "https://example.com/a/cat.png?v=1".parse().unwrap();
+        assert_eq!(
+            redirect(&uri, "/b/cat.png").unwrap(),
+            "https://example.com/b/cat.png"
+        );
+        assert_eq!(
+            redirect(&uri, "https://cdn.example.com/cat.png").unwrap(),
+            "https://cdn.example.com/cat.png"
+        );
+    }
+}
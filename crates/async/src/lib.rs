@@ -1,84 +1,1502 @@
+pub mod access;
+pub mod admin;
+pub mod aliases;
+pub mod auth;
+pub mod check;
+pub mod config;
+pub mod head;
+pub mod images;
+pub mod pages;
+pub mod range;
+pub mod reload;
+pub mod request_id;
+pub mod search;
+
 use std::{
+    collections::HashMap,
     ffi::OsStr,
     io::{Error, ErrorKind},
-    path::Path as SysPath,
+    path::{Component, Path as SysPath, PathBuf},
     str,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use axum::{
-    extract::Path,
-    http::StatusCode,
-    response::{Html, IntoResponse, Response, Result},
+    body::{Bytes, HttpBody},
+    extract::{Path, Query},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{ErrorResponse, IntoResponse, Response, Result},
 };
 
+use httpdate::HttpDate;
+use serde_derive::Deserialize;
+use serve_md_core::bundle;
+use serve_md_core::collection;
+use serve_md_core::error::RenderError;
+use serve_md_core::feed::{self, Channel};
+use serve_md_core::formats::Feed as FeedFormats;
+use serve_md_core::formats::Part;
 use serve_md_core::formats::Payload as PayloadFormats;
 use serve_md_core::generate_payload_from_slice;
+use serve_md_core::generate_payload_from_source;
+use serve_md_core::i18n;
+use serve_md_core::resolve;
+use serve_md_core::resolve_part;
+use serve_md_core::resolve_serializer;
+use serve_md_core::serializer::PayloadSerializer;
 use serve_md_core::state::State;
 use serve_md_core::Payload;
-use tokio::fs::{read, try_exists};
+use tokio::fs::{metadata, read, try_exists};
+use tokio::task::spawn_blocking;
+use tracing::{debug, error};
 
 /// # Errors
 ///
-/// Will return:
-/// - `StatusCode::NOT_FOUND` for unresolved files.
-/// - `StatusCode::BAD_REQUEST` for files not valid UTF8.
-pub async fn determine(Path(path): Path<String>, state: Arc<State>) -> Result<Response> {
-    #[cfg(debug_assertions)]
-    dbg!(&path);
-
-    let path_ext = SysPath::new(&path).extension();
-    let extension = path_ext
+/// Will return, with an `application/problem+json` body:
+/// - `StatusCode::NOT_FOUND` for unresolved or ignored files, see
+///   `collection::is_ignored`.
+/// - `StatusCode::UNSUPPORTED_MEDIA_TYPE` for unknown extensions, or a part
+///   with no form in the format.
+/// - `StatusCode::PAYLOAD_TOO_LARGE` for files over `State::max_file_size`.
+/// - `StatusCode::SERVICE_UNAVAILABLE` for documents taking longer than
+///   `State::render_timeout` to render.
+/// - `StatusCode::BAD_REQUEST` for invalid overrides, see
+///   `State::with_overrides`, an unknown `part` or an unknown profile, see
+///   `State::profiles`.
+/// - `StatusCode::UNPROCESSABLE_ENTITY` for files not valid UTF8 or with
+///   invalid front matter.
+/// - `StatusCode::INTERNAL_SERVER_ERROR` for documents a plugin fails on or
+///   that fail to serialize, with every cause in debug builds.
+pub async fn determine(
+    method: Method,
+    Path(path): Path<String>,
+    Query(mut overrides): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    state: Arc<State>,
+) -> Result<Response> {
+    debug!(path, ?overrides, "Determining response.");
+
+    // Only the front matter or body, from `?part=` or `/path.matter.json`.
+    let (part, path) = match take_part(&mut overrides)? {
+        Some(part) => (Some(part), path),
+        None => match resolve_part(&path, &state) {
+            Some((part, path)) => (Some(part), path),
+            None => (None, path),
+        },
+    };
+    let part = take_table(&mut overrides, part)?;
+    let lang = overrides.remove("lang");
+    let state = with_profile(&mut overrides, &headers, state)?;
+
+    // Rendering options for this request only.
+    let state = if overrides.is_empty() {
+        state
+    } else {
+        let state = state.with_overrides(&overrides).map_err(|error| {
+            debug!("{error}");
+            problem(StatusCode::BAD_REQUEST, &error.to_string())
+        })?;
+        Arc::new(state)
+    };
+
+    let requested = path.clone();
+    let resolved = match resolve(&path, &state) {
+        Some((extension, path)) => Some((Output::Format(extension), path)),
+        None => resolve_serializer(&path, &state)
+            .map(|(serializer, path)| (Output::Custom(serializer), path)),
+    };
+    if let Some((output, path)) = resolved {
+        let (path, language) = if state.i18n {
+            translate(path, lang.as_deref(), &headers, &state)
+        } else {
+            (path, None)
+        };
+        if collection::is_ignored(SysPath::new(&path), &state) {
+            return Err(render_failed(&Error::from(ErrorKind::NotFound).into()));
+        }
+        let input = fetch_md(&path, &state)
+            .await
+            .map_err(|error| render_failed(&error))?;
+        // Documents rendered from other files too have no validators, as
+        // they can change while the source doesn't.
+        let (last_modified, etag) = if state.reads_other_files() {
+            (None, None)
+        } else {
+            let etag = format!("\"{}\"", collection::content_hash(&input, &state));
+            (last_modified(&path).await, Some(etag))
+        };
+        // Skip rendering the markdown file if the client copy is fresh.
+        // `If-None-Match` takes precedence, as its hash is exact.
+        let fresh = match (headers.get(header::IF_NONE_MATCH), &etag) {
+            (Some(value), Some(etag)) => matches_etag(value, etag),
+            (Some(_), None) => false,
+            (None, _) => last_modified.is_some_and(|lm| is_not_modified(&headers, lm)),
+        };
+        if fresh {
+            // Hidden documents are a 404, not a 304 giving them away.
+            state
+                .check_source_requestable(&input)
+                .map_err(|error| render_failed(&error.into()))?;
+            let mut response = StatusCode::NOT_MODIFIED.into_response();
+            set_validators(&mut response, last_modified, etag.as_deref());
+            set_language(&mut response, language.as_deref(), &state);
+            vary_by_profile(&mut response, &state);
+            return Ok(response);
+        }
+
+        // Answer `HEAD` requests from the headers of the last render.
+        let key = render_key(&requested, &path, part, &state);
+        if let (Method::HEAD, Some(etag)) = (&method, &etag) {
+            if let Some(rendered) = head::get(&key, etag) {
+                state
+                    .check_source_requestable(&input)
+                    .map_err(|error| render_failed(&error.into()))?;
+                let mut response = [
+                    (header::CONTENT_TYPE, rendered.content_type),
+                    (header::CONTENT_LENGTH, rendered.content_length.to_string()),
+                ]
+                .into_response();
+                set_validators(&mut response, last_modified, Some(etag));
+                set_language(&mut response, language.as_deref(), &state);
+                vary_by_profile(&mut response, &state);
+                accept_ranges(&mut response);
+                return Ok(response);
+            }
+        }
+
+        let mut response = render(path, input, output, part, Arc::clone(&state)).await?;
+        if let Some(length) = response.body().size_hint().exact() {
+            let content_type = response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            // Sent with `HEAD` responses too, which have their body removed.
+            response
+                .headers_mut()
+                .insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+            if let Some(etag) = &etag {
+                head::insert(
+                    key,
+                    head::Rendered {
+                        etag: etag.clone(),
+                        content_type,
+                        content_length: length,
+                    },
+                );
+            }
+        }
+        set_validators(&mut response, last_modified, etag.as_deref());
+        set_language(&mut response, language.as_deref(), &state);
+        vary_by_profile(&mut response, &state);
+        accept_ranges(&mut response);
+        let last_modified = last_modified.map(|lm| lm.to_string());
+        let validators: Vec<&str> = [etag.as_deref(), last_modified.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect();
+        return Ok(range::partial(response, &headers, &validators).await);
+    }
+    Err(problem(
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        &format!("{path} does not have a supported extension."),
+    ))
+}
+
+/// Serves `path` from the root of the mount at `prefix`, rendered with the
+/// mount's options, see `State::mounts`.
+///
+/// # Errors
+///
+/// As `determine`, along with, with an `application/problem+json` body:
+/// - `StatusCode::NOT_FOUND` for a mount since removed from the config.
+/// - `StatusCode::BAD_REQUEST` for paths outside of the mount's root.
+/// - `StatusCode::INTERNAL_SERVER_ERROR` for a mount with invalid options.
+pub async fn mounted(
+    prefix: String,
+    method: Method,
+    Path(path): Path<String>,
+    query: Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    state: Arc<State>,
+) -> Result<Response> {
+    let Some(mount) = state.mounts.get(&prefix) else {
+        return Err(problem(
+            StatusCode::NOT_FOUND,
+            &format!("Nothing is mounted at {prefix}."),
+        ));
+    };
+    let path = subdirectory(&path).map_err(|_| {
+        problem(
+            StatusCode::BAD_REQUEST,
+            &format!("{path} is outside of {prefix}."),
+        )
+    })?;
+    let path = SysPath::new(&mount.root).join(path);
+    let state = state.for_mount(mount).map_err(|error| {
+        error!("{error}");
+        problem(StatusCode::INTERNAL_SERVER_ERROR, &error.to_string())
+    })?;
+    determine(
+        method,
+        Path(path.to_string_lossy().to_string()),
+        query,
+        headers,
+        Arc::new(state),
+    )
+    .await
+}
+
+/// What a request renders into, a built in format or one registered in
+/// `State::serializers`.
+#[derive(Clone)]
+enum Output {
+    Format(PayloadFormats),
+    Custom(Arc<dyn PayloadSerializer>),
+}
+
+impl Output {
+    /// Built in formats take precedence over registered serializers.
+    fn try_from(extension: &str, state: &State) -> anyhow::Result<Output> {
+        match PayloadFormats::try_from(extension) {
+            Ok(format) => Ok(Output::Format(format)),
+            Err(error) => state
+                .serializers
+                .get(extension)
+                .map(|serializer| Output::Custom(Arc::clone(serializer)))
+                .ok_or(error),
+        }
+    }
+
+    fn content_type(&self) -> &str {
+        match self {
+            Output::Format(format) => format.content_type(),
+            Output::Custom(serializer) => serializer.content_type(),
+        }
+    }
+
+    /// Writes the whole payload, or only `part` of it in a built in format.
+    fn write(
+        &self,
+        payload: Payload,
+        part: Option<Part>,
+        state: &State,
+    ) -> anyhow::Result<Vec<u8>> {
+        match (self, part) {
+            (Output::Format(format), Some(part)) => payload.into_part_for(part, format),
+            (Output::Format(format), None) => payload.into_response_with_state(format, state),
+            // Registered serializers only write whole payloads.
+            (Output::Custom(serializer), Some(part)) => Err(RenderError::UnsupportedPart {
+                part,
+                format: serializer.content_type().to_string(),
+            }
+            .into()),
+            (Output::Custom(serializer), None) => payload.into_response_with(serializer.as_ref()),
+        }
+    }
+}
+
+/// The translation of the document at `path` best suiting the `lang` query
+/// parameter, or otherwise the `Accept-Language` header, along with its
+/// language. Requests for a translation by name, e.g. `page.fr.html`, and
+/// those no translation suits are served the file requested.
+fn translate(
+    path: String,
+    lang: Option<&str>,
+    headers: &HeaderMap,
+    state: &State,
+) -> (String, Option<String>) {
+    let file_name = SysPath::new(&path).file_name().map(OsStr::to_owned);
+    let stem = SysPath::new(&path)
+        .file_stem()
         .and_then(OsStr::to_str)
-        .and_then(|s| PayloadFormats::try_from(s).ok());
+        .unwrap_or_default();
+    if let Some((_, lang)) = i18n::split_language(stem) {
+        let lang = lang.to_string();
+        return (path, Some(lang));
+    }
+    let Ok(variants) = i18n::variants(SysPath::new(&path), state) else {
+        return (path, None);
+    };
+
+    let preferred = match lang {
+        Some(lang) => vec![lang.to_lowercase()],
+        None => headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .map(i18n::accepted_languages)
+            .unwrap_or_default(),
+    };
+    let available: Vec<&str> = variants.iter().map(|(lang, _)| lang.as_str()).collect();
+    let chosen = i18n::negotiate(&available, &preferred)
+        .and_then(|chosen| variants.iter().find(|(lang, _)| lang == chosen));
+    let default = variants
+        .iter()
+        .find(|(_, file)| file.file_name() == file_name.as_deref());
+    match chosen.or(default) {
+        Some((lang, file)) => (file.to_string_lossy().to_string(), Some(lang.clone())),
+        None => (path, None),
+    }
+}
+
+/// Sets the `Content-Language` of a translated document, and the `Vary`
+/// header, as the document served depends on the `Accept-Language` header.
+fn set_language(response: &mut Response, language: Option<&str>, state: &State) {
+    if !state.i18n {
+        return;
+    }
+    let headers = response.headers_mut();
+    headers.append(header::VARY, HeaderValue::from_static("accept-language"));
+    if let Some(value) = language.and_then(|language| HeaderValue::from_str(language).ok()) {
+        headers.insert(header::CONTENT_LANGUAGE, value);
+    }
+}
+
+/// The header picking one of `State::profiles` for a request.
+const PROFILE_HEADER: &str = "x-serve-md-profile";
+
+/// `state` with the options of the profile named by the `profile` query
+/// parameter, which is removed, or otherwise the `X-Serve-Md-Profile` header.
+/// Other query parameters still override the profile's options.
+fn with_profile(
+    query: &mut HashMap<String, String>,
+    headers: &HeaderMap,
+    state: Arc<State>,
+) -> Result<Arc<State>> {
+    let name = query.remove("profile").or_else(|| {
+        headers
+            .get(PROFILE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+    });
+    let Some(name) = name.filter(|name| !name.is_empty()) else {
+        return Ok(state);
+    };
+    let state = state.for_profile(&name).map_err(|error| {
+        debug!("{error}");
+        problem(StatusCode::BAD_REQUEST, &error.to_string())
+    })?;
+    Ok(Arc::new(state))
+}
+
+/// Marks `response` as varying by the profile header, when there are
+/// profiles to pick from.
+fn vary_by_profile(response: &mut Response, state: &State) {
+    if !state.profiles.is_empty() {
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static(PROFILE_HEADER));
+    }
+}
+
+/// Removes the `part` query parameter, so it isn't taken as an override.
+fn take_part(query: &mut HashMap<String, String>) -> Result<Option<Part>> {
+    query
+        .remove("part")
+        .map(|part| Part::try_from(part.as_str()))
+        .transpose()
+        .map_err(|error| problem(StatusCode::BAD_REQUEST, &error.to_string()))
+}
+
+/// Removes the `table` query parameter, narrowing the `tables` part, or a
+/// whole payload, to the table with that 1 based number.
+fn take_table(query: &mut HashMap<String, String>, part: Option<Part>) -> Result<Option<Part>> {
+    let Some(table) = query.remove("table") else {
+        return Ok(part);
+    };
+    let number = table
+        .parse::<usize>()
+        .ok()
+        .filter(|number| *number > 0)
+        .ok_or_else(|| {
+            problem(
+                StatusCode::BAD_REQUEST,
+                &format!("{table} isn't a valid table number."),
+            )
+        })?;
+    match part {
+        None | Some(Part::Tables | Part::Table(_)) => Ok(Some(Part::Table(number))),
+        Some(part) => Err(problem(
+            StatusCode::BAD_REQUEST,
+            &format!("A table can't be taken from the {part}."),
+        )),
+    }
+}
 
-    if let Some(extension) = &extension {
-        let path = path.replace(&(".".to_owned() + &extension.to_string()), ".md");
-        // Handle commonmark requests early
-        if extension == &PayloadFormats::Markdown {
-            let buf = fetch_md(&path).await.or(Err(StatusCode::NOT_FOUND))?;
-            return str::from_utf8(&buf)
-                .or(Err(StatusCode::BAD_REQUEST.into()))
-                .map(ToString::to_string)
-                .map(IntoResponse::into_response);
+async fn render(
+    path: String,
+    input: Vec<u8>,
+    output: Output,
+    part: Option<Part>,
+    state: Arc<State>,
+) -> Result<Response> {
+    // Handle commonmark requests early
+    if let Output::Format(PayloadFormats::Markdown) = output {
+        if let Some(part) = part {
+            let format = PayloadFormats::Markdown.to_string();
+            return Err(render_failed(
+                &RenderError::UnsupportedPart { part, format }.into(),
+            ));
         }
-        let buf = generate_payload(path, state)
-            .await?
-            .into_response_for(extension)
-            .or(Err(StatusCode::BAD_REQUEST))?;
+        if str::from_utf8(&input).is_err() {
+            return Err(render_failed(&RenderError::InvalidUtf8.into()));
+        }
+        state
+            .check_source_requestable(&input)
+            .map_err(|error| render_failed(&error.into()))?;
+        return Ok(respond(input, &output, false));
+    }
+    let watch = state.watch;
+    let format = output.clone();
+    let (state, timeout) = with_deadline(state);
+    let buf = render_blocking(timeout, move || {
+        let payload =
+            generate_payload_from_source(SysPath::new(&path), &input[..], Arc::clone(&state))?;
+        state.check_requestable(&payload.front_matter)?;
+        format.write(payload, part, &state)
+    })
+    .await?;
+    Ok(respond(buf, &output, watch && part.is_none()))
+}
 
-        return str::from_utf8(&buf)
-            .or(Err(StatusCode::BAD_REQUEST.into()))
-            .map(ToString::to_string)
-            .map(|v| {
-                if let PayloadFormats::Html = extension {
-                    Html(v).into_response()
-                } else {
-                    IntoResponse::into_response(v)
-                }
-            });
+/// Sets the content type of a rendered payload, injecting the reload script
+/// into html when `watch` is set. Bodies are sent as bytes, as binary formats
+/// like pickle, cbor & postcard aren't UTF8.
+fn respond(mut buf: Vec<u8>, output: &Output, watch: bool) -> Response {
+    if matches!(output, Output::Format(PayloadFormats::Html)) && watch {
+        buf.extend_from_slice(reload::SCRIPT.as_bytes());
     }
-    Err(StatusCode::BAD_REQUEST.into())
+    let content_type = output.content_type().to_string();
+    ([(header::CONTENT_TYPE, content_type)], buf).into_response()
 }
 
-async fn fetch_md(path: &String) -> std::io::Result<Vec<u8>> {
-    if try_exists(path).await? {
-        return read(path).await;
+/// A json `POST /render` body.
+#[derive(Debug, Deserialize)]
+pub struct RenderRequest {
+    markdown: String,
+    /// Overrides of the servers rendering options, see `State::with_overrides`.
+    #[serde(default)]
+    options: HashMap<String, serde_json::Value>,
+}
+
+/// The `POST /render` endpoint, rendering markdown sent as the body, or as
+/// the `markdown` field of a json body with `options`, into the `format`
+/// query parameter, html by default. `part` picks only the front matter or
+/// body, and other query parameters override rendering options, as they do
+/// for files.
+///
+/// # Errors
+///
+/// Will return, with an `application/problem+json` body:
+/// - `StatusCode::UNSUPPORTED_MEDIA_TYPE` for an unknown format, or a part
+///   with no form in the format.
+/// - `StatusCode::PAYLOAD_TOO_LARGE` for a body over `State::max_file_size`.
+/// - `StatusCode::SERVICE_UNAVAILABLE` for markdown taking longer than
+///   `State::render_timeout` to render.
+/// - `StatusCode::BAD_REQUEST` for a body that isn't valid json, invalid
+///   overrides, an unknown `part` or an unknown profile.
+/// - `StatusCode::UNPROCESSABLE_ENTITY` for markdown that isn't valid UTF8 or
+///   has invalid front matter.
+/// - `StatusCode::INTERNAL_SERVER_ERROR` for markdown a plugin fails on or
+///   that fails to serialize, with every cause in debug builds.
+pub async fn render_markdown(
+    Query(mut query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    state: Arc<State>,
+    body: Bytes,
+) -> Result<Response> {
+    state
+        .check_file_size(body.len() as u64)
+        .map_err(|error| render_failed(&error.into()))?;
+    let format = query.remove("format");
+    let part = take_part(&mut query)?;
+    let part = take_table(&mut query, part)?;
+    let state = with_profile(&mut query, &headers, state)?;
+    let output = Output::try_from(format.as_deref().unwrap_or("html"), &state)
+        .map_err(|error| problem(StatusCode::UNSUPPORTED_MEDIA_TYPE, &error.to_string()))?;
+
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let markdown = if is_json {
+        let request: RenderRequest = serde_json::from_slice(&body)
+            .map_err(|error| problem(StatusCode::BAD_REQUEST, &error.to_string()))?;
+        for (key, value) in request.options {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                value => value.to_string(),
+            };
+            query.insert(key, value);
+        }
+        request.markdown
+    } else {
+        String::from_utf8(body.to_vec())
+            .map_err(|_| render_failed(&RenderError::InvalidUtf8.into()))?
+    };
+
+    if let (Output::Format(PayloadFormats::Markdown), None) = (&output, part) {
+        return Ok(respond(markdown.into_bytes(), &output, false));
     }
+    let state = state.with_overrides(&query).map_err(|error| {
+        debug!("{error}");
+        problem(StatusCode::BAD_REQUEST, &error.to_string())
+    })?;
+    let state = without_image_cache(state);
+    let format = output.clone();
+    let (state, timeout) = with_deadline(Arc::new(state));
+    let buf = render_blocking(timeout, move || {
+        generate_payload_from_slice(markdown.as_bytes(), Arc::clone(&state))
+            .and_then(|payload| format.write(payload, part, &state))
+    })
+    .await?;
+    Ok(respond(buf, &output, false))
+}
 
-    Err(Error::from(ErrorKind::NotFound))
+/// `state` without the `image_cache` plugin, for markdown sent by a client,
+/// which could otherwise have the server download any url.
+fn without_image_cache(mut state: State) -> State {
+    state.image_cache = None;
+    if let Some(plugins) = &mut state.plugins {
+        plugins.retain(|name| name != "image_cache");
+    }
+    state
 }
 
-async fn generate_payload(path: String, state: Arc<State>) -> Result<Payload> {
-    if tokio::fs::try_exists(&path)
+/// The modification time of `path`, truncated to the second resolution of
+/// HTTP dates.
+async fn last_modified(path: &str) -> Option<HttpDate> {
+    metadata(path)
         .await
-        .map_err(|_| StatusCode::NOT_FOUND)?
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(HttpDate::from)
+}
+
+fn is_not_modified(headers: &HeaderMap, last_modified: HttpDate) -> bool {
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<HttpDate>().ok())
+        .is_some_and(|since| last_modified <= since)
+}
+
+/// Identifies what a request for `requested` renders, from the source at
+/// `path`, by a hash of the request along with the options it's rendered
+/// with, see `State::fingerprint`.
+fn render_key(requested: &str, path: &str, part: Option<Part>, state: &State) -> String {
+    let fingerprint = state.fingerprint();
+    serve_md_core::images::hash(format!("{requested}\n{path}\n{part:?}\n{fingerprint}").as_bytes())
+}
+
+/// Marks `response` as answering `Range` requests, see `range::partial`.
+fn accept_ranges(response: &mut Response) {
+    response
+        .headers_mut()
+        .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+}
+
+/// Whether an `If-None-Match` header lists `etag`, or is `*`. Weak & unquoted
+/// tags match too.
+fn matches_etag(value: &HeaderValue, etag: &str) -> bool {
+    let Ok(value) = value.to_str() else {
+        return false;
+    };
+    let hash = etag.trim_matches('"');
+    value.split(',').map(str::trim).any(|tag| {
+        let tag = tag.strip_prefix("W/").unwrap_or(tag);
+        tag == "*" || tag.trim_matches('"') == hash
+    })
+}
+
+/// Sets the `Last-Modified` & `ETag` headers a client revalidates with.
+fn set_validators(response: &mut Response, last_modified: Option<HttpDate>, etag: Option<&str>) {
+    let headers = response.headers_mut();
+    if let Some(value) = last_modified.and_then(|lm| HeaderValue::from_str(&lm.to_string()).ok()) {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+    if let Some(value) = etag.and_then(|etag| HeaderValue::from_str(etag).ok()) {
+        headers.insert(header::ETAG, value);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    /// A subdirectory of root to build the feed, or bundle, from.
+    dir: Option<String>,
+}
+
+/// # Errors
+///
+/// Will return:
+/// - `StatusCode::BAD_REQUEST` for directories outside of root.
+/// - `StatusCode::NOT_FOUND` for unresolved directories.
+/// - `StatusCode::INTERNAL_SERVER_ERROR` for documents that fail to render.
+pub async fn feed(
+    format: FeedFormats,
+    Query(query): Query<FeedQuery>,
+    state: Arc<State>,
+) -> Result<Response> {
+    let mut dir = PathBuf::from(state.root.as_deref().unwrap_or("."));
+    let mut link = state
+        .base_url
+        .as_deref()
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .to_string();
+
+    if let Some(sub) = &query.dir {
+        let sub = subdirectory(sub)?;
+        dir.push(sub);
+        link = link + "/" + &sub.to_string_lossy().replace('\\', "/");
+    }
+
+    if !try_exists(&dir).await.unwrap_or(false) {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    let channel = Channel {
+        title: dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        link,
+    };
+    let content_type = match format {
+        FeedFormats::Rss => "application/rss+xml",
+        FeedFormats::Atom => "application/atom+xml",
+        FeedFormats::Json => "application/feed+json",
+    };
+
+    let buf = spawn_blocking(move || {
+        let mut documents = collection::scan(&dir, &state)?;
+        documents.retain(|document| !document.payload.noindex());
+        feed::sort(&mut documents);
+        feed::generate(&format, &channel, &documents)
+    })
+    .await
+    .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+    .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok(([(header::CONTENT_TYPE, content_type)], buf).into_response())
+}
+
+/// The `GET /bundle.ndjson` endpoint, rendering every markdown file under
+/// root, or the `dir` subdirectory, into one newline delimited json stream of
+/// payloads, each with its `path`.
+///
+/// # Errors
+///
+/// Will return:
+/// - `StatusCode::BAD_REQUEST` for directories outside of root.
+/// - `StatusCode::NOT_FOUND` for unresolved directories.
+/// - `StatusCode::INTERNAL_SERVER_ERROR` for documents that fail to render.
+pub async fn bundle(Query(query): Query<FeedQuery>, state: Arc<State>) -> Result<Response> {
+    let mut dir = PathBuf::from(state.root.as_deref().unwrap_or("."));
+    if let Some(sub) = &query.dir {
+        dir.push(subdirectory(sub)?);
+    }
+
+    if !try_exists(&dir).await.unwrap_or(false) {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    let buf = spawn_blocking(move || {
+        let documents = collection::scan(&dir, &state)?;
+        bundle::generate(&documents)
+    })
+    .await
+    .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+    .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], buf).into_response())
+}
+
+/// The `GET /api/list` endpoint, listing every markdown file under root, or
+/// the `dir` subdirectory, as json, each with its `path` & `hash`, see
+/// `collection::content_hash`. Sending the hash in `If-None-Match` when
+/// requesting a document skips rendering it while it's unchanged.
+///
+/// # Errors
+///
+/// Will return:
+/// - `StatusCode::BAD_REQUEST` for directories outside of root.
+/// - `StatusCode::NOT_FOUND` for unresolved directories.
+/// - `StatusCode::INTERNAL_SERVER_ERROR` for files that can't be read.
+pub async fn list(Query(query): Query<FeedQuery>, state: Arc<State>) -> Result<Response> {
+    let mut dir = PathBuf::from(state.root.as_deref().unwrap_or("."));
+    if let Some(sub) = &query.dir {
+        dir.push(subdirectory(sub)?);
+    }
+
+    if !try_exists(&dir).await.unwrap_or(false) {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    let buf = spawn_blocking(move || {
+        let listings = collection::list(&dir, &state)?;
+        anyhow::Ok(serde_json::to_vec(&listings)?)
+    })
+    .await
+    .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+    .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok(([(header::CONTENT_TYPE, "application/json")], buf).into_response())
+}
+
+/// A `dir` query parameter, which can only name a directory within root.
+fn subdirectory(sub: &str) -> Result<&SysPath> {
+    let sub = SysPath::new(sub);
+    if sub
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
     {
-        // TODO handle errors better.
-        let input = fetch_md(&path).await.map_err(|_| StatusCode::NOT_FOUND)?;
-        return generate_payload_from_slice(&input[..], state)
-            .or(Err(StatusCode::NO_CONTENT.into()));
+        return Err(StatusCode::BAD_REQUEST.into());
     }
+    Ok(sub)
+}
+
+/// The `GET /robots.txt` endpoint, allowing every crawler everything but
+/// `State::robots_disallow`. Documents with `noindex: true` front matter are
+/// kept out of search engines by a robots meta tag in their html instead, as
+/// crawlers never see it on disallowed pages.
+pub async fn robots(state: Arc<State>) -> Response {
+    let mut body = String::from("User-agent: *\n");
+    match state.robots_disallow.as_deref() {
+        Some(paths) if !paths.is_empty() => {
+            for path in paths {
+                body.push_str(&format!("Disallow: {path}\n"));
+            }
+        }
+        _ => body.push_str("Disallow:\n"),
+    }
+    ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response()
+}
+
+/// How long to wait before reading a file again that changed while it was
+/// read, long enough for most editors to finish saving it.
+const REREAD_DELAY: Duration = Duration::from_millis(50);
+
+/// Reads `path`, unless it's over `State::max_file_size`. A file written to
+/// while it's read, e.g. by an editor saving it, is read once more after
+/// `REREAD_DELAY`, so a half written file isn't rendered, or rejected as
+/// invalid UTF8. It counts as changed when its size or modification time
+/// differ before & after the read, or it isn't valid UTF8.
+async fn fetch_md(path: &String, state: &State) -> anyhow::Result<Vec<u8>> {
+    if !try_exists(path).await? {
+        return Err(Error::from(ErrorKind::NotFound).into());
+    }
+
+    let before = metadata(path).await?;
+    state.check_file_size(before.len())?;
+    let buf = read(path).await?;
+    let after = metadata(path).await?;
+    let settled = buf.len() as u64 == after.len()
+        && before.modified().ok() == after.modified().ok()
+        && str::from_utf8(&buf).is_ok();
+    if settled {
+        return Ok(buf);
+    }
+
+    debug!(path, "Changed while read, reading again.");
+    tokio::time::sleep(REREAD_DELAY).await;
+    state.check_file_size(metadata(path).await?.len())?;
+    Ok(read(path).await?)
+}
+
+/// Sets the deadline of this request from `State::render_timeout`, returning
+/// the timeout along with it.
+fn with_deadline(state: Arc<State>) -> (Arc<State>, Option<Duration>) {
+    let Some(timeout) = state.render_timeout.map(Duration::from_millis) else {
+        return (state, None);
+    };
+    let mut state = State::clone(&state);
+    state.deadline = Some(Instant::now() + timeout);
+    (Arc::new(state), Some(timeout))
+}
+
+/// Runs CPU bound rendering on the blocking thread pool, sized by
+/// `State::render_threads`, so large documents don't stall other requests.
+/// Answers once `timeout` passes, with rendering giving up at its next check
+/// of `State::deadline`.
+async fn render_blocking<T, F>(timeout: Option<Duration>, render: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+{
+    let task = spawn_blocking(render);
+    let joined = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, task)
+            .await
+            .map_err(|_| render_failed(&RenderError::TimedOut.into()))?,
+        None => task.await,
+    };
+    joined
+        .map_err(|error| render_failed(&error.into()))?
+        .map_err(|error| render_failed(&error))
+}
+
+/// Maps a document failing to render to its status code, see `RenderError`.
+fn render_failed(error: &anyhow::Error) -> ErrorResponse {
+    let status = if let Some(error) = error.downcast_ref::<RenderError>() {
+        match error {
+            RenderError::InvalidUtf8 | RenderError::InvalidFrontMatter(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            RenderError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            RenderError::TimedOut => StatusCode::SERVICE_UNAVAILABLE,
+            RenderError::UnsupportedFormat(_) | RenderError::UnsupportedPart { .. } => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            RenderError::NoSuchTable { .. } => StatusCode::NOT_FOUND,
+            RenderError::Convert { .. } | RenderError::Serialize { .. } => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    } else if error
+        .downcast_ref::<Error>()
+        .is_some_and(|error| error.kind() == ErrorKind::NotFound)
+    {
+        StatusCode::NOT_FOUND
+    } else {
+        // Plugins failing, see `PluginError`, amongst anything unexpected.
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    if status.is_server_error() {
+        error!("{error:#}");
+        if cfg!(debug_assertions) {
+            // Includes every cause, e.g. an exec plugins exit status.
+            return problem(status, &format!("{error:?}"));
+        }
+    }
+    problem(status, &error.to_string())
+}
+
+/// A json problem details body, see RFC 9457.
+pub(crate) fn problem(status: StatusCode, detail: &str) -> ErrorResponse {
+    let body = serde_json::json!({
+        "type": "about:blank",
+        "title": status.canonical_reason().unwrap_or_default(),
+        "status": status.as_u16(),
+        "detail": detail,
+    });
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/problem+json")],
+        body.to_string(),
+    )
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_markdown;
+    use axum::{
+        body::Bytes,
+        extract::Query,
+        http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+        response::IntoResponse,
+    };
+    use serve_md_core::{formats::Matter, state::State};
+    use std::{collections::HashMap, str, sync::Arc};
+
+    #[tokio::test]
+    async fn render_test_json_body() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        let query = HashMap::from([("format".to_string(), "html".to_string())]);
+        let body = Bytes::from(r#"{"markdown": "Hi :tada:", "options": {"emoji": true}}"#);
+
+        match render_markdown(Query(query), headers, Arc::new(State::default()), body).await {
+            Ok(response) => {
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                assert_eq!(&body[..], "<p>Hi 🎉</p>\n".as_bytes());
+            }
+            Err(_) => assert!(false, "Should NEVER return an error."),
+        }
+    }
+
+    #[tokio::test]
+    async fn render_test_without_image_cache() {
+        let dir = std::env::temp_dir().join("serve_md_render_image_cache_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut state = State::default();
+        state.image_cache = Some(dir.to_string_lossy().to_string());
+        state.plugins = Some(vec!["image_cache".to_string()]);
+
+        let body = Bytes::from("![cat](https://example.com/cat.png)");
+        let response = render_markdown(
+            Query(HashMap::new()),
+            HeaderMap::new(),
+            Arc::new(state),
+            body,
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let html = std::str::from_utf8(&body).unwrap();
+        assert!(
+            html.contains(r#"src="https://example.com/cat.png""#),
+            "{html}"
+        );
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn render_test_binary_formats() {
+        for (format, content_type) in [
+            ("pickle", "application/x-python-pickle"),
+            ("cbor", "application/cbor"),
+            ("postcard", "application/octet-stream"),
+        ] {
+            let query = HashMap::from([("format".to_string(), format.to_string())]);
+            let response = render_markdown(
+                Query(query),
+                HeaderMap::new(),
+                Arc::new(State::default()),
+                Bytes::from("# Hi"),
+            )
+            .await
+            .into_response();
+            assert_eq!(response.status(), StatusCode::OK, "{format}");
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+        }
+    }
+
+    #[tokio::test]
+    async fn render_test_unknown_format() {
+        let query = HashMap::from([("format".to_string(), "docx".to_string())]);
+        let response = render_markdown(
+            Query(query),
+            HeaderMap::new(),
+            Arc::new(State::default()),
+            Bytes::from("# Hi"),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+    }
+
+    #[tokio::test]
+    async fn render_test_registered_serializer() {
+        use serve_md_core::{serializer::PayloadSerializer, Payload};
+        use std::io::Write;
+
+        struct Title;
+
+        impl PayloadSerializer for Title {
+            fn content_type(&self) -> &str {
+                "text/plain; charset=utf-8"
+            }
+
+            fn write(&self, payload: &Payload, writer: &mut dyn Write) -> anyhow::Result<()> {
+                write!(writer, "{}", payload.title().unwrap_or("untitled"))?;
+                Ok(())
+            }
+        }
+
+        let mut state = State::default();
+        state.serializers.register("docx", Title);
+        let query = HashMap::from([("format".to_string(), "docx".to_string())]);
+        match render_markdown(
+            Query(query),
+            HeaderMap::new(),
+            Arc::new(state),
+            Bytes::from("# Hi"),
+        )
+        .await
+        {
+            Ok(response) => {
+                assert_eq!(response.status(), StatusCode::OK);
+                assert_eq!(
+                    response.headers()[header::CONTENT_TYPE],
+                    "text/plain; charset=utf-8"
+                );
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                assert_eq!(&body[..], b"untitled");
+            }
+            Err(_) => assert!(false, "Should NEVER return an error."),
+        }
+    }
+
+    #[tokio::test]
+    async fn render_test_part() {
+        let mut state = State::default();
+        state.front_matter = Some(Matter::Yaml);
+        let state = Arc::new(state);
+        let markdown = "---\ntitle: Hi\n---\n# Hi\n";
+
+        let query = HashMap::from([
+            ("format".to_string(), "json".to_string()),
+            ("part".to_string(), "matter".to_string()),
+        ]);
+        let response = render_markdown(
+            Query(query),
+            HeaderMap::new(),
+            Arc::clone(&state),
+            Bytes::from(markdown),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let matter: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(matter, serde_json::json!({ "title": "Hi" }));
+
+        for (part, status) in [
+            ("matter", StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            ("toc", StatusCode::BAD_REQUEST),
+        ] {
+            let query = HashMap::from([("part".to_string(), part.to_string())]);
+            let response = render_markdown(
+                Query(query),
+                HeaderMap::new(),
+                Arc::clone(&state),
+                Bytes::from(markdown),
+            )
+            .await
+            .into_response();
+            assert_eq!(response.status(), status, "{part}");
+        }
+    }
+
+    #[tokio::test]
+    async fn determine_test_translations() {
+        use super::determine;
+        use axum::extract::Path;
 
-    Err(StatusCode::NOT_FOUND.into())
+        let mut state = State::default();
+        state.front_matter = Some(Matter::Yaml);
+        state.i18n = true;
+        let state = Arc::new(state);
+        let request = |path: &str, query: &[(&str, &str)], accept: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(accept) = accept {
+                headers.insert(
+                    header::ACCEPT_LANGUAGE,
+                    HeaderValue::from_str(accept).unwrap(),
+                );
+            }
+            let query = query
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            determine(
+                Method::GET,
+                Path(format!("../core/resources/i18n/{path}")),
+                Query(query),
+                headers,
+                Arc::clone(&state),
+            )
+        };
+
+        for (path, query, accept, language, heading) in [
+            (
+                "page.html",
+                &[][..],
+                Some("fr-CH, de;q=0.8"),
+                "fr",
+                "Bonjour",
+            ),
+            ("page.html", &[][..], Some("es, de;q=0.5"), "de", "Hallo"),
+            ("page.html", &[][..], Some("es"), "en", "Hello"),
+            (
+                "page.html",
+                &[("lang", "de")][..],
+                Some("fr"),
+                "de",
+                "Hallo",
+            ),
+            ("page.fr.html", &[][..], Some("de"), "fr", "Bonjour"),
+        ] {
+            let response = request(path, query, accept).await.into_response();
+            assert_eq!(response.status(), StatusCode::OK, "{path} {accept:?}");
+            assert_eq!(response.headers()[header::CONTENT_LANGUAGE], language);
+            assert_eq!(response.headers()[header::VARY], "accept-language");
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert!(
+                str::from_utf8(&body).unwrap().contains(heading),
+                "{path} {accept:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn mounted_test_options_and_root() {
+        use super::mounted;
+        use axum::extract::Path;
+        use serve_md_core::state::Mount;
+
+        let mut state = State::default();
+        state.mounts.insert(
+            "/docs".to_string(),
+            Mount {
+                root: "../core/resources".to_string(),
+                options: [("emoji".to_string(), serde_json::json!(true))].into(),
+            },
+        );
+        let state = Arc::new(state);
+        let request = |prefix: &str, path: &str| {
+            mounted(
+                prefix.to_string(),
+                Method::GET,
+                Path(path.to_string()),
+                Query(HashMap::new()),
+                HeaderMap::new(),
+                Arc::clone(&state),
+            )
+        };
+
+        let response = request("/docs", "hio.html").await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(str::from_utf8(&body).unwrap().contains("🚀"));
+
+        for (prefix, path, status) in [
+            ("/docs", "../Cargo.html", StatusCode::BAD_REQUEST),
+            ("/docs", "missing.html", StatusCode::NOT_FOUND),
+            ("/blog", "hio.html", StatusCode::NOT_FOUND),
+        ] {
+            let response = request(prefix, path).await.into_response();
+            assert_eq!(response.status(), status, "{prefix}/{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn determine_test_profiles() {
+        use super::determine;
+        use axum::extract::Path;
+        use serve_md_core::state::Profile;
+
+        let mut state = State::default();
+        state.profiles.insert(
+            "docs".to_string(),
+            Profile {
+                options: [("emoji".to_string(), serde_json::json!(true))].into(),
+                plugin_options: [(
+                    "emoji".to_string(),
+                    serde_json::json!({ "shortcodes": { "rocket": "🛸" } }),
+                )]
+                .into(),
+            },
+        );
+        let state = Arc::new(state);
+        let request = |query: &[(&str, &str)], profile: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(profile) = profile {
+                headers.insert("x-serve-md-profile", profile.parse().unwrap());
+            }
+            let query = query
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            determine(
+                Method::GET,
+                Path("../core/resources/hio.html".to_string()),
+                Query(query),
+                headers,
+                Arc::clone(&state),
+            )
+        };
+
+        for (query, profile, rendered) in [
+            (&[][..], None, ":rocket:"),
+            (&[][..], Some("docs"), "🛸"),
+            (&[("profile", "docs")][..], None, "🛸"),
+            (&[("profile", "docs"), ("emoji", "0")][..], None, ":rocket:"),
+        ] {
+            let response = request(query, profile).await.into_response();
+            assert_eq!(response.status(), StatusCode::OK, "{query:?} {profile:?}");
+            assert_eq!(response.headers()[header::VARY], "x-serve-md-profile");
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert!(
+                str::from_utf8(&body).unwrap().contains(rendered),
+                "{query:?} {profile:?}"
+            );
+        }
+
+        let response = request(&[], Some("api")).await.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn determine_test_content_hash() {
+        use super::{determine, list};
+        use axum::extract::Path;
+
+        let mut state = State::default();
+        state.root = Some("../core/resources/i18n".to_string());
+
+        let response = list(Query(HashMap::new()), Arc::new(state.clone()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let listings: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listings[2]["path"], "page.md");
+        let hash = listings[2]["hash"].as_str().unwrap().to_string();
+
+        let request = |if_none_match: &str, state: State| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_NONE_MATCH, if_none_match.parse().unwrap());
+            determine(
+                Method::GET,
+                Path("../core/resources/i18n/page.json".to_string()),
+                Query(HashMap::new()),
+                headers,
+                Arc::new(state),
+            )
+        };
+        for (if_none_match, status) in [
+            (hash.clone(), StatusCode::NOT_MODIFIED),
+            (format!("\"other\", W/\"{hash}\""), StatusCode::NOT_MODIFIED),
+            ("\"other\"".to_string(), StatusCode::OK),
+        ] {
+            let response = request(&if_none_match, state.clone()).await.into_response();
+            assert_eq!(response.status(), status, "{if_none_match}");
+            assert_eq!(response.headers()[header::ETAG], format!("\"{hash}\""));
+        }
+
+        // Reloading the config, or rendering with other options, makes a
+        // client's copy stale.
+        let mut reloaded = state.clone();
+        reloaded.generation += 1;
+        let mut toc = state.clone();
+        toc.toc = true;
+        for state in [reloaded, toc] {
+            let response = request(&hash, state).await.into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_ne!(response.headers()[header::ETAG], format!("\"{hash}\""));
+        }
+
+        // Neighbours can change without the source changing.
+        let mut navigation = state.clone();
+        navigation.navigation = true;
+        let response = request(&hash, navigation).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(header::ETAG));
+        assert!(!response.headers().contains_key(header::LAST_MODIFIED));
+    }
+
+    #[tokio::test]
+    async fn determine_test_head() {
+        use super::determine;
+        use axum::extract::Path;
+
+        let state = Arc::new(State::default());
+        let request = |method: Method| {
+            determine(
+                method,
+                Path("../core/resources/test.json".to_string()),
+                Query(HashMap::new()),
+                HeaderMap::new(),
+                Arc::clone(&state),
+            )
+        };
+
+        let response = request(Method::GET).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers().clone();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(headers[header::CONTENT_LENGTH], body.len().to_string());
+
+        // Answered from the headers of the render above, without a body.
+        let response = request(Method::HEAD).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        for name in [
+            header::CONTENT_TYPE,
+            header::CONTENT_LENGTH,
+            header::ETAG,
+            header::LAST_MODIFIED,
+        ] {
+            assert_eq!(response.headers()[&name], headers[&name], "{name}");
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn determine_test_range() {
+        use super::determine;
+        use axum::extract::Path;
+
+        let state = Arc::new(State::default());
+        let request = |range: Option<&str>, if_range: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(range) = range {
+                headers.insert(header::RANGE, range.parse().unwrap());
+            }
+            if let Some(if_range) = if_range {
+                headers.insert(header::IF_RANGE, if_range.parse().unwrap());
+            }
+            determine(
+                Method::GET,
+                Path("../core/resources/hio.json".to_string()),
+                Query(HashMap::new()),
+                headers,
+                Arc::clone(&state),
+            )
+        };
+
+        let response = request(None, None).await.into_response();
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let whole = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let len = whole.len();
+
+        let response = request(Some("bytes=10-19"), Some(&etag))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            format!("bytes 10-19/{len}")
+        );
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, whole.slice(10..20));
+
+        let response = request(Some(&format!("bytes={len}-")), None)
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            format!("bytes */{len}")
+        );
+
+        // A stale `If-Range` gets the whole document.
+        let response = request(Some("bytes=10-19"), Some("\"stale\""))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, whole);
+    }
+
+    #[tokio::test]
+    async fn fetch_test_rereads_changed_file() {
+        use super::{fetch_md, REREAD_DELAY};
+
+        let dir = std::env::temp_dir().join("serve_md_fetch_md_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("saving.md");
+        // Cut off part way through a multibyte character, as if mid save.
+        std::fs::write(&path, &"# Héllo".as_bytes()[..4]).unwrap();
+
+        let saving = tokio::spawn({
+            let path = path.clone();
+            async move {
+                tokio::time::sleep(REREAD_DELAY / 5).await;
+                tokio::fs::write(&path, "# Héllo").await.unwrap();
+            }
+        });
+        let path = path.to_string_lossy().to_string();
+        let buf = fetch_md(&path, &State::default()).await.unwrap();
+        saving.await.unwrap();
+        assert_eq!(buf, "# Héllo".as_bytes());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn determine_test_not_modified_draft() {
+        use super::determine;
+        use axum::extract::Path;
+
+        let mut state = State::default();
+        state.front_matter = Some(Matter::Yaml);
+        state.drafts_not_found = true;
+        let state = Arc::new(state);
+        for path in ["unfinished.html", "first.html"] {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::IF_MODIFIED_SINCE,
+                HeaderValue::from_static("Fri, 01 Jan 2100 00:00:00 GMT"),
+            );
+            let response = determine(
+                Method::GET,
+                Path(format!("../core/resources/feed/{path}")),
+                Query(HashMap::new()),
+                headers,
+                Arc::clone(&state),
+            )
+            .await
+            .into_response();
+            let status = if path == "first.html" {
+                StatusCode::NOT_MODIFIED
+            } else {
+                StatusCode::NOT_FOUND
+            };
+            assert_eq!(response.status(), status, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn render_test_unprocessable() {
+        let mut state = State::default();
+        state.front_matter = Some(Matter::Yaml);
+        let state = Arc::new(state);
+        for body in [&b"# \xff"[..], b"---\ntitle: [unclosed\n---\n# Hi\n"] {
+            let response = render_markdown(
+                Query(HashMap::new()),
+                HeaderMap::new(),
+                Arc::clone(&state),
+                Bytes::from(body),
+            )
+            .await
+            .into_response();
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(problem["status"], 422);
+        }
+    }
+
+    #[tokio::test]
+    async fn render_test_payload_too_large() {
+        let mut state = State::default();
+        state.max_file_size = Some(4);
+        let response = render_markdown(
+            Query(HashMap::new()),
+            HeaderMap::new(),
+            Arc::new(state),
+            Bytes::from("# Too long"),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn render_test_timed_out() {
+        let mut state = State::default();
+        state.render_timeout = Some(0);
+        let response = render_markdown(
+            Query(HashMap::new()),
+            HeaderMap::new(),
+            Arc::new(state),
+            Bytes::from("# Slow"),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
 }
@@ -0,0 +1,107 @@
+use std::{path::Path, sync::Arc};
+
+use axum::{
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serve_md_core::{formats::Payload as PayloadFormats, generate_payload_from_path, state::State};
+use tokio::{fs::try_exists, task::spawn_blocking};
+use tracing::warn;
+
+/// Middleware answering html requests failing with a status that has a page
+/// in `State::error_pages`, e.g. `404.md`, with that page rendered like any
+/// other document, keeping the status & headers. Other formats keep their
+/// `application/problem+json` body.
+pub async fn error_pages<B>(state: Arc<State>, request: Request<B>, next: Next<B>) -> Response {
+    let html = wants_html(request.uri().path(), request.headers());
+    let response = next.run(request).await;
+    let status = response.status();
+    if !html || !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+    let Some(page) = render_page(status, state).await else {
+        return response;
+    };
+
+    let (parts, _) = response.into_parts();
+    let mut response = (
+        parts.status,
+        [(header::CONTENT_TYPE, PayloadFormats::Html.content_type())],
+        page,
+    )
+        .into_response();
+    for (name, value) in &parts.headers {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            response.headers_mut().append(name, value.clone());
+        }
+    }
+    response
+}
+
+/// The page for `status` rendered as html, or `None` without one.
+pub async fn render_page(status: StatusCode, state: Arc<State>) -> Option<Vec<u8>> {
+    let dir = state.error_pages.as_deref()?;
+    let path = Path::new(dir).join(format!("{}.md", status.as_u16()));
+    if !try_exists(&path).await.unwrap_or(false) {
+        return None;
+    }
+    let rendered = spawn_blocking(move || {
+        generate_payload_from_path(&path, Arc::clone(&state))
+            .and_then(|payload| payload.into_response_with_state(&PayloadFormats::Html, &state))
+    })
+    .await;
+    match rendered {
+        Ok(Ok(page)) => Some(page),
+        Ok(Err(error)) => {
+            // Better the original error than none at all.
+            warn!("Failed to render the {status} page, {error}");
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// Requests for `.html` paths, or accepting html, e.g. from a browser.
+fn wants_html(path: &str, headers: &HeaderMap) -> bool {
+    path.ends_with(".html")
+        || headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pages_test_render_page() {
+        let dir = std::env::temp_dir().join("serve_md_error_pages_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("404.md"), "# Not found\n").unwrap();
+
+        let mut state = State::default();
+        state.error_pages = Some(dir.to_string_lossy().to_string());
+        let state = Arc::new(state);
+        assert_eq!(
+            render_page(StatusCode::NOT_FOUND, Arc::clone(&state)).await,
+            Some(b"<h1>Not found</h1>\n".to_vec())
+        );
+        assert_eq!(
+            render_page(StatusCode::INTERNAL_SERVER_ERROR, state).await,
+            None
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn pages_test_wants_html() {
+        let mut headers = HeaderMap::new();
+        assert!(wants_html("/readme.html", &headers));
+        assert!(!wants_html("/readme.json", &headers));
+        headers.insert(header::ACCEPT, "text/html,*/*".parse().unwrap());
+        assert!(wants_html("/feed", &headers));
+    }
+}
@@ -0,0 +1,112 @@
+use core::ops::Range;
+
+use axum::{
+    body::{boxed, Full},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// The part of a body a `Range` header asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requested {
+    /// The whole body, for headers that aren't a single `bytes` range, which
+    /// are ignored.
+    Whole,
+    Partial(Range<usize>),
+    /// A range starting past the end of the body.
+    Unsatisfiable,
+}
+
+/// The part of a body `len` bytes long `range` asks for, e.g. `bytes=0-99`,
+/// `bytes=100-` or `bytes=-100` for the last 100 bytes. Several ranges are
+/// answered with the whole body, rather than a multipart one.
+pub fn requested(range: &str, len: usize) -> Requested {
+    let Some((start, end)) = range
+        .trim()
+        .strip_prefix("bytes=")
+        .filter(|ranges| !ranges.contains(','))
+        .and_then(|range| range.split_once('-'))
+    else {
+        return Requested::Whole;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    let number = |value: &str| value.parse::<usize>().ok();
+
+    let (start, end) = match (start.is_empty(), number(start), number(end)) {
+        // The last `end` bytes.
+        (true, _, Some(0)) => return Requested::Unsatisfiable,
+        (true, _, Some(suffix)) => (len.saturating_sub(suffix), len),
+        (false, Some(start), None) if end.is_empty() => (start, len),
+        (false, Some(start), Some(end)) if start <= end => (start, len.min(end.saturating_add(1))),
+        _ => return Requested::Whole,
+    };
+    if start >= len {
+        return Requested::Unsatisfiable;
+    }
+    Requested::Partial(start..end)
+}
+
+/// Narrows a whole `response` to the part the `Range` header in `headers`
+/// asks for, with a `206` & its `Content-Range`, or a `416` for ranges past
+/// the end. An `If-Range` header not matching one of `validators`, the
+/// `ETag` or `Last-Modified` of the response, asks for the whole response
+/// instead, as the client's part is out of date.
+pub async fn partial(response: Response, headers: &HeaderMap, validators: &[&str]) -> Response {
+    let Some(range) = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return response;
+    };
+    let stale = headers
+        .get(header::IF_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|if_range| !validators.contains(&if_range));
+    if response.status() != StatusCode::OK || stale {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(body) = hyper::body::to_bytes(body).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let len = body.len();
+    let (content_range, body) = match requested(range, len) {
+        Requested::Whole => return Response::from_parts(parts, boxed(Full::from(body))),
+        Requested::Partial(range) => {
+            parts.status = StatusCode::PARTIAL_CONTENT;
+            let content_range = format!("bytes {}-{}/{len}", range.start, range.end - 1);
+            (content_range, body.slice(range))
+        }
+        Requested::Unsatisfiable => {
+            parts.status = StatusCode::RANGE_NOT_SATISFIABLE;
+            (format!("bytes */{len}"), body.slice(0..0))
+        }
+    };
+    if let Ok(value) = HeaderValue::from_str(&content_range) {
+        parts.headers.insert(header::CONTENT_RANGE, value);
+    }
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{requested, Requested};
+
+    #[test]
+    fn range_test_requested() {
+        assert_eq!(requested("bytes=0-9", 100), Requested::Partial(0..10));
+        assert_eq!(requested("bytes=90-", 100), Requested::Partial(90..100));
+        assert_eq!(requested("bytes=-10", 100), Requested::Partial(90..100));
+        assert_eq!(requested("bytes=-200", 100), Requested::Partial(0..100));
+        assert_eq!(requested("bytes=50-500", 100), Requested::Partial(50..100));
+        assert_eq!(requested("bytes=100-", 100), Requested::Unsatisfiable);
+        assert_eq!(requested("bytes=-0", 100), Requested::Unsatisfiable);
+        assert_eq!(requested("bytes=9-0", 100), Requested::Whole);
+        assert_eq!(requested("bytes=0-9,20-29", 100), Requested::Whole);
+        assert_eq!(requested("lines=0-9", 100), Requested::Whole);
+    }
+}
@@ -0,0 +1,69 @@
+use std::{convert::Infallible, ffi::OsStr, path::Path, sync::Arc};
+
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::stream::{self, Stream};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::broadcast::{self, error::RecvError, Sender};
+use tracing::{debug, warn};
+
+/// Injected into html responses, reloading the page when the server sends
+/// an event over `/__reload`.
+pub const SCRIPT: &str =
+    "<script>new EventSource('/__reload').onmessage = () => location.reload();</script>";
+
+/// Watches a directory for changes to markdown files, broadcasting to every
+/// connected `/__reload` client.
+pub struct Reloader {
+    sender: Sender<()>,
+    // Dropping the watcher stops it, so keep it alive alongside the sender.
+    _watcher: RecommendedWatcher,
+}
+
+impl Reloader {
+    /// # Errors
+    ///
+    /// Will return `Err` if `root` can't be watched.
+    pub fn watch(root: &Path, extensions: Vec<String>) -> notify::Result<Reloader> {
+        let (sender, _) = broadcast::channel(16);
+        let mut watcher = notify::recommended_watcher({
+            let sender = sender.clone();
+            move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if !event.kind.is_access() => {
+                    if event.paths.iter().any(|path| {
+                        path.extension()
+                            .and_then(OsStr::to_str)
+                            .is_some_and(|extension| extensions.iter().any(|e| e == extension))
+                    }) {
+                        debug!(paths = ?event.paths, "Sending reload.");
+                        // No receivers just means no browser is listening.
+                        let _: Result<usize, _> = sender.send(());
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => warn!("{error}"),
+            }
+        })?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+
+        Ok(Reloader {
+            sender,
+            _watcher: watcher,
+        })
+    }
+}
+
+/// The `/__reload` server sent events endpoint.
+pub async fn events(reloader: Arc<Reloader>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = reloader.sender.subscribe();
+    let stream = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(()) => return Some((Ok(Event::default().data("reload")), receiver)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
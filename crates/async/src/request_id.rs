@@ -0,0 +1,116 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{boxed, Full},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serve_md_core::images;
+
+/// The header carrying the id of a request, sent back with every response
+/// & taken from the request when a proxy in front already set one.
+pub const HEADER: &str = "x-request-id";
+
+/// Middleware tagging every request with an id, so a user reporting a failed
+/// render can be matched to the server logs. The id is set on the request,
+/// for the `request` span to log, on the response & as the `request_id` of
+/// `application/problem+json` bodies.
+pub async fn identify<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let id = request
+        .headers()
+        .get(HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map_or_else(generate, String::from);
+    let Ok(value) = HeaderValue::from_str(&id) else {
+        return next.run(request).await;
+    };
+    request.headers_mut().insert(HEADER, value.clone());
+
+    let mut response = with_request_id(next.run(request).await, &id).await;
+    response.headers_mut().insert(HEADER, value);
+    response
+}
+
+/// Ids passed on by a proxy are kept when short & made of letters, digits,
+/// `-`, `_` or `.`, so they're safe to log.
+fn is_valid(id: &str) -> bool {
+    (1..=64).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+/// A new id, unique within this process & unlikely to repeat across
+/// restarts, as 16 hex digits.
+fn generate() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let count = NEXT.fetch_add(1, Ordering::Relaxed);
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    let mut seed = started.to_le_bytes().to_vec();
+    seed.extend_from_slice(&count.to_le_bytes());
+    images::hash(&seed)
+}
+
+/// Adds `id` to an `application/problem+json` body as its `request_id`,
+/// leaving any other response as it is.
+async fn with_request_id(response: Response, id: &str) -> Response {
+    let is_problem = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value == "application/problem+json");
+    if !is_problem {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(body) = hyper::body::to_bytes(body).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let mut problem: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(problem) => problem,
+        Err(_) => return Response::from_parts(parts, boxed(Full::from(body))),
+    };
+    if let Some(members) = problem.as_object_mut() {
+        members.insert("request_id".to_string(), id.into());
+    }
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, boxed(Full::from(problem.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::problem;
+
+    #[test]
+    fn request_id_test_is_valid() {
+        assert!(is_valid("3f2a-41b0_9c.7"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("id with spaces"));
+        assert!(!is_valid("<script>"));
+        assert!(!is_valid(&"a".repeat(65)));
+        assert_ne!(generate(), generate());
+    }
+
+    #[tokio::test]
+    async fn request_id_test_problem_body() {
+        let response = problem(StatusCode::NOT_FOUND, "Missing.").into_response();
+        let response = with_request_id(response, "abc123").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["request_id"], "abc123");
+        assert_eq!(problem["detail"], "Missing.");
+
+        let response = with_request_id("fine".into_response(), "abc123").await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "fine");
+    }
+}
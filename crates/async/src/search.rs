@@ -0,0 +1,148 @@
+use std::{
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use axum::{extract::Query, Json};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde_derive::Deserialize;
+use serve_md_core::search::{SearchIndex, SearchResult};
+use serve_md_core::state::State;
+use tokio::task::spawn_blocking;
+use tracing::{debug, warn};
+
+use crate::config::ReloadableState;
+
+const DEFAULT_LIMIT: usize = 10;
+
+/// A search index over every markdown file under root, re-indexing files as
+/// they change, and every file once the options are reloaded.
+pub struct Searcher {
+    index: Arc<RwLock<SearchIndex>>,
+    state: Arc<ReloadableState>,
+    // Dropping the watcher stops it, so keep it alive alongside the index.
+    _watcher: RecommendedWatcher,
+}
+
+impl Searcher {
+    /// # Errors
+    ///
+    /// Will return `Err` if `root` can't be indexed or watched.
+    pub fn watch(root: &Path, state: Arc<ReloadableState>) -> anyhow::Result<Searcher> {
+        // Watcher events carry absolute paths, so index against the same.
+        let root = root.canonicalize()?;
+        let index = Arc::new(RwLock::new(SearchIndex::build(&root, state.load())?));
+        let mut watcher = notify::recommended_watcher({
+            let index = Arc::clone(&index);
+            move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if !event.kind.is_access() => {
+                    let Ok(mut index) = index.write() else {
+                        return;
+                    };
+                    for path in &event.paths {
+                        debug!(?path, "Re-indexing.");
+                        index.update(path);
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => warn!("{error}"),
+            }
+        })?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+
+        Ok(Searcher {
+            index,
+            state,
+            _watcher: watcher,
+        })
+    }
+
+    /// Whether the options were reloaded since the index was built, e.g.
+    /// changing `show_drafts` or the ignore rules.
+    fn is_stale(&self) -> bool {
+        let generation = self.state.load().generation;
+        self.index
+            .read()
+            .is_ok_and(|index| index.generation() != generation)
+    }
+
+    /// Indexes every markdown file under root again from scratch, with
+    /// `state`, returning how many documents were indexed. Searches keep
+    /// using the old index until the new one is built.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if root can't be indexed.
+    pub fn rebuild(&self, state: Arc<State>) -> anyhow::Result<usize> {
+        let root = match self.index.read() {
+            Ok(index) => index.root().to_path_buf(),
+            Err(_) => anyhow::bail!("The search index is poisoned."),
+        };
+        let rebuilt = SearchIndex::build(&root, state)?;
+        let documents = rebuilt.len();
+        match self.index.write() {
+            Ok(mut index) => *index = rebuilt,
+            Err(_) => anyhow::bail!("The search index is poisoned."),
+        }
+        Ok(documents)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    limit: Option<usize>,
+}
+
+/// The `/search?q=` endpoint, returning matching paths, snippets and scores.
+pub async fn search(
+    Query(query): Query<SearchQuery>,
+    searcher: Arc<Searcher>,
+) -> Json<Vec<SearchResult>> {
+    if searcher.is_stale() {
+        let stale = Arc::clone(&searcher);
+        let state = stale.state.load();
+        match spawn_blocking(move || stale.rebuild(state)).await {
+            Ok(Ok(documents)) => debug!(documents, "Re-indexed with the reloaded options."),
+            Ok(Err(error)) => warn!("{error:#}"),
+            Err(error) => warn!("{error}"),
+        }
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let results = match searcher.index.read() {
+        Ok(index) => index.search(&query.q, limit),
+        Err(_) => vec![],
+    };
+    Json(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{search, SearchQuery, Searcher};
+    use crate::config::ReloadableState;
+    use axum::extract::Query;
+    use serve_md_core::state::State;
+    use std::{fs, sync::Arc};
+
+    #[tokio::test]
+    async fn search_test_endpoint() {
+        let root = std::env::temp_dir().join("serve_md_async_search_test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.md"), "# Searching\nFind this needle.\n").unwrap();
+
+        let state = Arc::new(ReloadableState::new(State::default()).unwrap());
+        let searcher = Arc::new(Searcher::watch(&root, state).unwrap());
+        let query = SearchQuery {
+            q: "needle".to_string(),
+            limit: None,
+        };
+        let results = search(Query(query), searcher).await.0;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "a.md");
+        assert_eq!(results[0].snippet, "Searching Find this needle.");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
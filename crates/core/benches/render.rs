@@ -0,0 +1,47 @@
+//! Times rendering a document heavy with plugin output, e.g. emoji,
+//! typography, collapsible headers & footnotes. Run with `cargo bench`.
+
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use serve_md_core::{generate_payload_from_slice, state::State};
+
+const SECTION: &str = "## Notes :tada:
+
+Some text :smile: with (c) -> typography... and a footnote[^1] :rocket:.
+
+- :+1: a list item -- with dashes
+- :heart: another (tm) item
+
+[^1]: The footnote :wave:.
+
+";
+
+fn render(c: &mut Criterion) {
+    let markdown = SECTION.repeat(200);
+    let state = State::try_from_args([
+        "serve_md",
+        "--emoji-shortcodes",
+        "--typography",
+        "--footnotes",
+        "--toc",
+        "--collapsible-headers",
+        "h2:Notes",
+    ])
+    .expect("Valid arguments.");
+    let state = Arc::new(state);
+
+    let mut group = c.benchmark_group("render");
+    group.throughput(Throughput::Bytes(markdown.len() as u64));
+    group.bench_function("plugins", |b| {
+        b.iter(|| {
+            let payload = generate_payload_from_slice(markdown.as_bytes(), Arc::clone(&state))
+                .expect("Renders.");
+            black_box(payload)
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
@@ -0,0 +1,19 @@
+#![no_main]
+
+use std::collections::HashMap;
+
+use libfuzzer_sys::fuzz_target;
+use pulldown_cmark::{CowStr, Event};
+use serve_md_core::plugin::{Emoji, StreamPlugin};
+
+fuzz_target!(|text: &str| {
+    // A custom shortcode of each kind, an emoji, an image & html.
+    let custom = HashMap::from([
+        ("shipit".to_string(), "🐿️".to_string()),
+        ("ferris".to_string(), "/images/ferris.png".to_string()),
+        ("logo".to_string(), "<b>logo</b>".to_string()),
+    ]);
+    for mut plugin in [Emoji::default(), Emoji::new(custom)] {
+        let _ = plugin.map(Event::Text(CowStr::Borrowed(text)));
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use serve_md_core::matter::RefDefMatter;
+
+fuzz_target!(|data: &[u8]| {
+    let mut matter = RefDefMatter::new(data);
+    matter.scan();
+    let _ = matter.parse_gray_matter();
+});
@@ -0,0 +1,43 @@
+#![no_main]
+
+use std::sync::{Arc, OnceLock};
+
+use libfuzzer_sys::fuzz_target;
+use serve_md_core::{generate_payload_from_slice, state::State};
+
+/// Every plugin that doesn't read files or run commands, with each front
+/// matter format tried by the first byte of the input.
+fn states() -> &'static [Arc<State>] {
+    static STATES: OnceLock<Vec<Arc<State>>> = OnceLock::new();
+    STATES.get_or_init(|| {
+        ["refdef", "yaml", "toml", "json"]
+            .into_iter()
+            .map(|matter| {
+                let state = State::try_from_args([
+                    "parse_md",
+                    "--gfm",
+                    "--footnotes",
+                    "--emoji-shortcodes",
+                    "--typography",
+                    "--toc",
+                    "--heading-anchors",
+                    "--collapsible-headers",
+                    "h2:Details",
+                    "--front-matter",
+                    matter,
+                ])
+                .expect("Valid arguments.");
+                Arc::new(state)
+            })
+            .collect()
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((first, markdown)) = data.split_first() else {
+        return;
+    };
+    let states = states();
+    let state = &states[usize::from(*first) % states.len()];
+    let _ = generate_payload_from_slice(markdown, Arc::clone(state));
+});
@@ -0,0 +1,368 @@
+//! A stable, owned mirror of pulldown-cmark's events, serialized as json for
+//! plugins running outside of the crate, e.g. `{"type":"text","text":"hi"}`.
+
+use pulldown_cmark as cm;
+use pulldown_cmark::{escape::escape_html, CowStr, HeadingLevel};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Start { tag: Tag },
+    End { tag: Tag },
+    Text { text: String },
+    Code { code: String },
+    Html { html: String },
+    FootnoteReference { label: String },
+    SoftBreak,
+    HardBreak,
+    Rule,
+    TaskListMarker { checked: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Tag {
+    Paragraph,
+    Heading {
+        level: u8,
+        id: Option<String>,
+        classes: Vec<String>,
+    },
+    BlockQuote,
+    /// `info` is `None` for indented code blocks.
+    CodeBlock {
+        info: Option<String>,
+    },
+    List {
+        start: Option<u64>,
+    },
+    Item,
+    FootnoteDefinition {
+        label: String,
+    },
+    Table {
+        alignments: Vec<Alignment>,
+    },
+    TableHead,
+    TableRow,
+    TableCell,
+    Emphasis,
+    Strong,
+    Strikethrough,
+    Link {
+        kind: LinkType,
+        url: String,
+        title: String,
+    },
+    Image {
+        kind: LinkType,
+        url: String,
+        title: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Alignment {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkType {
+    Inline,
+    Reference,
+    ReferenceUnknown,
+    Collapsed,
+    CollapsedUnknown,
+    Shortcut,
+    ShortcutUnknown,
+    Autolink,
+    Email,
+}
+
+/// Serializes the events of a plugin slice as a json array.
+///
+/// # Errors
+///
+/// Will return `Err` if the events fail to serialize.
+pub fn to_json(slice: &[(usize, cm::Event)]) -> serde_json::Result<Vec<u8>> {
+    serde_json::to_vec(&slice.iter().map(|t| Event::from(&t.1)).collect::<Vec<_>>())
+}
+
+/// Deserializes a json array of events.
+///
+/// # Errors
+///
+/// Will return `Err` if `json` isn't an array of valid events.
+pub fn from_json(json: &[u8]) -> serde_json::Result<Vec<cm::Event<'static>>> {
+    let events: Vec<Event> = serde_json::from_slice(json)?;
+    Ok(events.into_iter().map(Event::into_event).collect())
+}
+
+/// Replaces `slice` with the events `transform` returns from its json, for
+/// plugins that run over a whole document outside of the crate.
+///
+/// # Errors
+///
+/// Will return `Err` if `transform` fails or returns invalid json.
+pub fn transform_slice(
+    slice: &[(usize, cm::Event)],
+    transform: impl FnOnce(Vec<u8>) -> anyhow::Result<Vec<u8>>,
+) -> anyhow::Result<Vec<cm::Event<'static>>> {
+    let output = transform(to_json(slice)?)?;
+    Ok(from_json(&output)?)
+}
+
+impl Event {
+    /// Converts back into a pulldown-cmark event. Heading ids and classes can
+    /// only be borrowed from the markdown source, so a heading start with
+    /// either becomes an html event instead.
+    pub fn into_event(self) -> cm::Event<'static> {
+        match self {
+            Event::Start {
+                tag: Tag::Heading { level, id, classes },
+            } if id.is_some() || !classes.is_empty() => {
+                // Writing to a `String` can't fail.
+                let mut html = format!("<{}", heading_level(level));
+                if let Some(id) = id {
+                    html.push_str(r#" id=""#);
+                    let _ = escape_html(&mut html, &id);
+                    html.push('"');
+                }
+                if !classes.is_empty() {
+                    html.push_str(r#" class=""#);
+                    let _ = escape_html(&mut html, &classes.join(" "));
+                    html.push('"');
+                }
+                html.push('>');
+                cm::Event::Html(boxed(html))
+            }
+            Event::Start { tag } => cm::Event::Start(tag.into()),
+            Event::End { tag } => cm::Event::End(tag.into()),
+            Event::Text { text } => cm::Event::Text(boxed(text)),
+            Event::Code { code } => cm::Event::Code(boxed(code)),
+            Event::Html { html } => cm::Event::Html(boxed(html)),
+            Event::FootnoteReference { label } => cm::Event::FootnoteReference(boxed(label)),
+            Event::SoftBreak => cm::Event::SoftBreak,
+            Event::HardBreak => cm::Event::HardBreak,
+            Event::Rule => cm::Event::Rule,
+            Event::TaskListMarker { checked } => cm::Event::TaskListMarker(checked),
+        }
+    }
+}
+
+fn boxed(value: String) -> CowStr<'static> {
+    CowStr::Boxed(value.into())
+}
+
+fn heading_level(level: u8) -> HeadingLevel {
+    HeadingLevel::try_from(usize::from(level.clamp(1, 6))).unwrap_or(HeadingLevel::H6)
+}
+
+impl From<&cm::Event<'_>> for Event {
+    fn from(event: &cm::Event) -> Event {
+        match event {
+            cm::Event::Start(tag) => Event::Start { tag: tag.into() },
+            cm::Event::End(tag) => Event::End { tag: tag.into() },
+            cm::Event::Text(text) => Event::Text {
+                text: text.to_string(),
+            },
+            cm::Event::Code(code) => Event::Code {
+                code: code.to_string(),
+            },
+            cm::Event::Html(html) => Event::Html {
+                html: html.to_string(),
+            },
+            cm::Event::FootnoteReference(label) => Event::FootnoteReference {
+                label: label.to_string(),
+            },
+            cm::Event::SoftBreak => Event::SoftBreak,
+            cm::Event::HardBreak => Event::HardBreak,
+            cm::Event::Rule => Event::Rule,
+            cm::Event::TaskListMarker(checked) => Event::TaskListMarker { checked: *checked },
+        }
+    }
+}
+
+impl From<&cm::Tag<'_>> for Tag {
+    fn from(tag: &cm::Tag) -> Tag {
+        match tag {
+            cm::Tag::Paragraph => Tag::Paragraph,
+            cm::Tag::Heading(level, id, classes) => Tag::Heading {
+                level: *level as u8,
+                id: id.map(ToString::to_string),
+                classes: classes.iter().map(ToString::to_string).collect(),
+            },
+            cm::Tag::BlockQuote => Tag::BlockQuote,
+            cm::Tag::CodeBlock(cm::CodeBlockKind::Indented) => Tag::CodeBlock { info: None },
+            cm::Tag::CodeBlock(cm::CodeBlockKind::Fenced(info)) => Tag::CodeBlock {
+                info: Some(info.to_string()),
+            },
+            cm::Tag::List(start) => Tag::List { start: *start },
+            cm::Tag::Item => Tag::Item,
+            cm::Tag::FootnoteDefinition(label) => Tag::FootnoteDefinition {
+                label: label.to_string(),
+            },
+            cm::Tag::Table(alignments) => Tag::Table {
+                alignments: alignments.iter().map(Alignment::from).collect(),
+            },
+            cm::Tag::TableHead => Tag::TableHead,
+            cm::Tag::TableRow => Tag::TableRow,
+            cm::Tag::TableCell => Tag::TableCell,
+            cm::Tag::Emphasis => Tag::Emphasis,
+            cm::Tag::Strong => Tag::Strong,
+            cm::Tag::Strikethrough => Tag::Strikethrough,
+            cm::Tag::Link(kind, url, title) => Tag::Link {
+                kind: kind.into(),
+                url: url.to_string(),
+                title: title.to_string(),
+            },
+            cm::Tag::Image(kind, url, title) => Tag::Image {
+                kind: kind.into(),
+                url: url.to_string(),
+                title: title.to_string(),
+            },
+        }
+    }
+}
+
+impl From<Tag> for cm::Tag<'static> {
+    /// Heading ids and classes are dropped, see `Event::into_event`.
+    fn from(tag: Tag) -> cm::Tag<'static> {
+        match tag {
+            Tag::Paragraph => cm::Tag::Paragraph,
+            Tag::Heading { level, .. } => cm::Tag::Heading(heading_level(level), None, vec![]),
+            Tag::BlockQuote => cm::Tag::BlockQuote,
+            Tag::CodeBlock { info: None } => cm::Tag::CodeBlock(cm::CodeBlockKind::Indented),
+            Tag::CodeBlock { info: Some(info) } => {
+                cm::Tag::CodeBlock(cm::CodeBlockKind::Fenced(boxed(info)))
+            }
+            Tag::List { start } => cm::Tag::List(start),
+            Tag::Item => cm::Tag::Item,
+            Tag::FootnoteDefinition { label } => cm::Tag::FootnoteDefinition(boxed(label)),
+            Tag::Table { alignments } => {
+                cm::Tag::Table(alignments.into_iter().map(Into::into).collect())
+            }
+            Tag::TableHead => cm::Tag::TableHead,
+            Tag::TableRow => cm::Tag::TableRow,
+            Tag::TableCell => cm::Tag::TableCell,
+            Tag::Emphasis => cm::Tag::Emphasis,
+            Tag::Strong => cm::Tag::Strong,
+            Tag::Strikethrough => cm::Tag::Strikethrough,
+            Tag::Link { kind, url, title } => cm::Tag::Link(kind.into(), boxed(url), boxed(title)),
+            Tag::Image { kind, url, title } => {
+                cm::Tag::Image(kind.into(), boxed(url), boxed(title))
+            }
+        }
+    }
+}
+
+impl From<&cm::Alignment> for Alignment {
+    fn from(alignment: &cm::Alignment) -> Alignment {
+        match alignment {
+            cm::Alignment::None => Alignment::None,
+            cm::Alignment::Left => Alignment::Left,
+            cm::Alignment::Center => Alignment::Center,
+            cm::Alignment::Right => Alignment::Right,
+        }
+    }
+}
+
+impl From<Alignment> for cm::Alignment {
+    fn from(alignment: Alignment) -> cm::Alignment {
+        match alignment {
+            Alignment::None => cm::Alignment::None,
+            Alignment::Left => cm::Alignment::Left,
+            Alignment::Center => cm::Alignment::Center,
+            Alignment::Right => cm::Alignment::Right,
+        }
+    }
+}
+
+impl From<&cm::LinkType> for LinkType {
+    fn from(kind: &cm::LinkType) -> LinkType {
+        match kind {
+            cm::LinkType::Inline => LinkType::Inline,
+            cm::LinkType::Reference => LinkType::Reference,
+            cm::LinkType::ReferenceUnknown => LinkType::ReferenceUnknown,
+            cm::LinkType::Collapsed => LinkType::Collapsed,
+            cm::LinkType::CollapsedUnknown => LinkType::CollapsedUnknown,
+            cm::LinkType::Shortcut => LinkType::Shortcut,
+            cm::LinkType::ShortcutUnknown => LinkType::ShortcutUnknown,
+            cm::LinkType::Autolink => LinkType::Autolink,
+            cm::LinkType::Email => LinkType::Email,
+        }
+    }
+}
+
+impl From<LinkType> for cm::LinkType {
+    fn from(kind: LinkType) -> cm::LinkType {
+        match kind {
+            LinkType::Inline => cm::LinkType::Inline,
+            LinkType::Reference => cm::LinkType::Reference,
+            LinkType::ReferenceUnknown => cm::LinkType::ReferenceUnknown,
+            LinkType::Collapsed => cm::LinkType::Collapsed,
+            LinkType::CollapsedUnknown => cm::LinkType::CollapsedUnknown,
+            LinkType::Shortcut => cm::LinkType::Shortcut,
+            LinkType::ShortcutUnknown => cm::LinkType::ShortcutUnknown,
+            LinkType::Autolink => cm::LinkType::Autolink,
+            LinkType::Email => cm::LinkType::Email,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_json, to_json};
+    use pulldown_cmark::{CowStr, Event, HeadingLevel, LinkType, Tag};
+
+    #[test]
+    fn abi_test_round_trip() {
+        use pretty_assertions::assert_eq;
+
+        let link = || {
+            Tag::Link(
+                LinkType::Inline,
+                CowStr::Borrowed("/a"),
+                CowStr::Borrowed(""),
+            )
+        };
+        let input = [
+            (
+                0,
+                Event::Start(Tag::Heading(HeadingLevel::H2, None, vec![])),
+            ),
+            (1, Event::Text(CowStr::Borrowed("text"))),
+            (2, Event::End(Tag::Heading(HeadingLevel::H2, None, vec![]))),
+            (3, Event::Start(link())),
+            (4, Event::Code(CowStr::Borrowed("code"))),
+            (5, Event::End(link())),
+            (6, Event::SoftBreak),
+        ];
+
+        let json = to_json(&input).unwrap();
+        assert!(std::str::from_utf8(&json)
+            .unwrap()
+            .starts_with(r#"[{"type":"start","tag":{"type":"heading","level":2,"#));
+        let output = from_json(&json).unwrap();
+        assert_eq!(
+            output,
+            input.iter().map(|t| t.1.clone()).collect::<Vec<_>>()
+        );
+
+        let input = [(
+            0,
+            Event::Start(Tag::Heading(HeadingLevel::H1, Some("id"), vec!["a", "b"])),
+        )];
+        let output = from_json(&to_json(&input).unwrap()).unwrap();
+        assert_eq!(
+            output,
+            [Event::Html(CowStr::Borrowed(r#"<h1 id="id" class="a b">"#))]
+        );
+    }
+}
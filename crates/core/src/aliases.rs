@@ -0,0 +1,175 @@
+use crate::{
+    collection::{self, find_markdown},
+    state::State,
+};
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{debug, trace, warn};
+
+/// The old paths of every document under a root, from their `aliases` front
+/// matter, e.g. `aliases: [/old/url]`, mapped to the document's current path,
+/// which can be updated a file at a time.
+#[derive(Debug)]
+pub struct AliasMap {
+    root: PathBuf,
+    state: Arc<State>,
+    /// The aliases of each document, by its path relative to root.
+    documents: BTreeMap<String, Vec<String>>,
+    /// The current path of each alias.
+    targets: HashMap<String, String>,
+}
+
+impl AliasMap {
+    /// Reads the aliases of every document under `root`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a directory under `root` can't be read.
+    pub fn build(root: &Path, state: Arc<State>) -> Result<AliasMap> {
+        let mut map = AliasMap {
+            root: root.to_path_buf(),
+            state,
+            documents: BTreeMap::new(),
+            targets: HashMap::new(),
+        };
+        let mut paths = vec![];
+        find_markdown(root, &map.state.source_extensions(), &map.state, &mut paths)?;
+        for path in paths {
+            map.update(&path);
+        }
+        debug!(aliases = map.targets.len(), "Built alias map.");
+        Ok(map)
+    }
+
+    /// Re-reads the aliases of `path`, removing them when it no longer
+    /// exists, isn't under root or is a draft.
+    pub fn update(&mut self, path: &Path) {
+        let Some(key) = self.key(path) else {
+            return;
+        };
+        self.remove(&key);
+        if collection::is_ignored(path, &self.state)
+            || !collection::within_max_file_size(path, &self.state)
+        {
+            return;
+        }
+        let Ok(source) = fs::read_to_string(path) else {
+            return;
+        };
+        let Some(front_matter) = self.state.source_front_matter(&source) else {
+            return;
+        };
+        if self.state.is_draft(&front_matter) {
+            return;
+        }
+
+        let aliases: Vec<String> = match front_matter.get("aliases") {
+            Some(serde_json::Value::String(alias)) => vec![normalize(alias)],
+            Some(serde_json::Value::Array(aliases)) => aliases
+                .iter()
+                .filter_map(serde_json::Value::as_str)
+                .map(normalize)
+                .collect(),
+            _ => vec![],
+        };
+        if aliases.is_empty() {
+            return;
+        }
+        let target = target(&key);
+        for alias in &aliases {
+            trace!(alias, target, "Aliased.");
+            if let Some(previous) = self.targets.insert(alias.clone(), target.clone()) {
+                if previous != target {
+                    warn!(alias, previous, target, "Alias claimed by two documents.");
+                }
+            }
+        }
+        self.documents.insert(key, aliases);
+    }
+
+    fn remove(&mut self, key: &str) {
+        let Some(aliases) = self.documents.remove(key) else {
+            return;
+        };
+        let target = target(key);
+        for alias in aliases {
+            if self.targets.get(&alias) == Some(&target) {
+                self.targets.remove(&alias);
+            }
+        }
+    }
+
+    /// The path relative to root of a renderable file.
+    fn key(&self, path: &Path) -> Option<String> {
+        let extension = path.extension()?.to_str()?;
+        if !self.state.source_extensions().contains(&extension) {
+            return None;
+        }
+        path.strip_prefix(&self.root)
+            .ok()
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+    }
+
+    /// The `State::generation` of the options the map was built with.
+    pub fn generation(&self) -> u64 {
+        self.state.generation
+    }
+
+    /// The current path of the document with the alias `path`, if any.
+    pub fn target(&self, path: &str) -> Option<&str> {
+        self.targets.get(&normalize(path)).map(String::as_str)
+    }
+}
+
+/// An alias with a leading `/` & without a trailing one, so `old/url/`
+/// matches `/old/url`.
+fn normalize(alias: &str) -> String {
+    format!("/{}", alias.trim().trim_matches('/'))
+}
+
+/// The url of the document at `key`, as html.
+fn target(key: &str) -> String {
+    let html = Path::new(key).with_extension("html");
+    format!("/{}", html.to_string_lossy().replace('\\', "/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::Matter;
+
+    #[test]
+    fn aliases_test_build_update_and_target() {
+        let root = std::env::temp_dir().join("serve_md_aliases_test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("guide")).unwrap();
+        fs::write(
+            root.join("guide/setup.md"),
+            "---\naliases: [/install, old/setup/]\n---\n# Setup\n",
+        )
+        .unwrap();
+        fs::write(root.join("a.md"), "---\naliases: /b\n---\n# A\n").unwrap();
+
+        let mut state = State::default();
+        state.front_matter = Some(Matter::Yaml);
+        let mut map = AliasMap::build(&root, Arc::new(state)).unwrap();
+        assert_eq!(map.target("/install"), Some("/guide/setup.html"));
+        assert_eq!(map.target("/old/setup"), Some("/guide/setup.html"));
+        assert_eq!(map.target("/b/"), Some("/a.html"));
+        assert_eq!(map.target("/guide/setup.html"), None);
+
+        fs::write(root.join("guide/setup.md"), "# Setup\n").unwrap();
+        map.update(&root.join("guide/setup.md"));
+        fs::remove_file(root.join("a.md")).unwrap();
+        map.update(&root.join("a.md"));
+        assert_eq!(map.target("/install"), None);
+        assert_eq!(map.target("/b"), None);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
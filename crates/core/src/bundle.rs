@@ -0,0 +1,41 @@
+use crate::{collection::Document, Payload};
+use anyhow::Result;
+use serde_derive::Serialize;
+use std::io::Write;
+
+/// A line of a bundle, the fields of a document's payload alongside its path.
+#[derive(Serialize)]
+struct Line<'a> {
+    path: &'a str,
+    #[serde(flatten)]
+    payload: &'a Payload,
+}
+
+/// Writes every document as one line of json, newline delimited, for bulk
+/// ingestion by search engines or databases.
+///
+/// # Errors
+///
+/// Will return `Err` if a payload fails to serialize or `writer` fails.
+pub fn write(writer: &mut impl Write, documents: &[Document]) -> Result<()> {
+    for document in documents {
+        let line = Line {
+            path: &document.path,
+            payload: &document.payload,
+        };
+        serde_json::to_writer(&mut *writer, &line)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Like `write`, collecting the bundle into a buffer.
+///
+/// # Errors
+///
+/// Will return `Err` if a payload fails to serialize.
+pub fn generate(documents: &[Document]) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    write(&mut buf, documents)?;
+    Ok(buf)
+}
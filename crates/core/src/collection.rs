@@ -0,0 +1,265 @@
+pub use crate::matter::parse_date;
+use crate::{generate_payload_from_path, images, state::State, Payload};
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use ignore::{gitignore::Gitignore, WalkBuilder};
+use serde_derive::Serialize;
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Component, Path, PathBuf},
+    str,
+    sync::Arc,
+};
+use tracing::warn;
+
+/// A rendered markdown file found while scanning a directory.
+#[derive(Debug)]
+pub struct Document {
+    /// The path relative to the scanned directory, using `/` as the separator.
+    pub path: String,
+    pub payload: Payload,
+}
+
+impl Document {
+    /// The `title` front matter value, falling back to the file stem.
+    pub fn title(&self) -> &str {
+        self.payload
+            .title()
+            .or_else(|| Path::new(&self.path).file_stem().and_then(OsStr::to_str))
+            .unwrap_or(&self.path)
+    }
+
+    /// The `date` front matter value, if it exists and can be parsed.
+    pub fn date(&self) -> Option<DateTime<FixedOffset>> {
+        self.payload
+            .front_matter
+            .get("date")
+            .and_then(serde_json::Value::as_str)
+            .and_then(parse_date)
+    }
+
+    /// The path with its markdown extension replaced by `extension`.
+    pub fn path_with_extension(&self, extension: &str) -> String {
+        Path::new(&self.path)
+            .with_extension(extension)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+}
+
+/// Whether `path` is within `State::max_file_size`, warning when it isn't.
+pub fn within_max_file_size(path: &Path, state: &State) -> bool {
+    let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+    match state.check_file_size(size) {
+        Ok(()) => true,
+        Err(error) => {
+            warn!(path = %path.display(), "Skipping, {error}");
+            false
+        }
+    }
+}
+
+/// Recursively renders every markdown file under `root`, along with those
+/// with a converter, see `State::source_extensions`. Files over
+/// `State::max_file_size` are skipped, as are drafts, see `State::is_hidden`,
+/// and ignored files, see `find_markdown`.
+///
+/// # Errors
+///
+/// Will return `Err` if a directory can't be read or a markdown file fails
+/// to render.
+pub fn scan(root: &Path, state: &Arc<State>) -> Result<Vec<Document>> {
+    let mut paths = vec![];
+    find_markdown(root, &state.source_extensions(), state, &mut paths)?;
+    paths.retain(|path| within_max_file_size(path, state));
+    paths.sort();
+
+    let mut documents = vec![];
+    for path in paths {
+        let payload = generate_payload_from_path(&path, Arc::clone(state))?;
+        if state.is_hidden(&payload.front_matter) {
+            continue;
+        }
+        let path = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        documents.push(Document { path, payload });
+    }
+    Ok(documents)
+}
+
+/// A markdown file found by `list`, without rendering it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Listing {
+    /// The path relative to the listed directory, using `/` as the separator.
+    pub path: String,
+    /// The `content_hash` of its source, which it's served with as its
+    /// `ETag`.
+    pub hash: String,
+}
+
+/// Identifies a version of a document, from its `source`, the options it's
+/// rendered with, see `State::fingerprint`, & the modification time of the
+/// template, the same whatever it's rendered into, so clients can check
+/// whether it changed without rendering it.
+pub fn content_hash(source: &[u8], state: &State) -> String {
+    let template = state
+        .template
+        .as_ref()
+        .and_then(|template| fs::metadata(template).and_then(|m| m.modified()).ok())
+        .map(|modified| format!("{modified:?}"))
+        .unwrap_or_default();
+    let source = images::hash(source);
+    images::hash(format!("{source}\n{}\n{template}", state.fingerprint()).as_bytes())
+}
+
+/// Every markdown file under `root` that `scan` would render, along with
+/// the `content_hash` of each, reading their front matter to skip drafts
+/// but rendering none of them.
+///
+/// # Errors
+///
+/// Will return `Err` if a directory or markdown file can't be read.
+pub fn list(root: &Path, state: &State) -> Result<Vec<Listing>> {
+    let mut paths = vec![];
+    find_markdown(root, &state.source_extensions(), state, &mut paths)?;
+    paths.retain(|path| within_max_file_size(path, state));
+    paths.sort();
+
+    let mut listings = vec![];
+    for path in paths {
+        let source = fs::read(&path)?;
+        let hidden = str::from_utf8(&source)
+            .ok()
+            .and_then(|source| state.source_front_matter(source))
+            .is_some_and(|front_matter| state.is_hidden(&front_matter));
+        if hidden {
+            continue;
+        }
+        listings.push(Listing {
+            path: path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/"),
+            hash: content_hash(&source, state),
+        });
+    }
+    Ok(listings)
+}
+
+/// Files listing patterns of paths to leave out, in the `.gitignore` format.
+pub const IGNORE_FILES: [&str; 2] = [".gitignore", ".mdignore"];
+
+/// Collects every file with one of `extensions` under `dir`, recursively,
+/// skipping dotfiles & paths matched by an `IGNORE_FILES` file under `dir`,
+/// unless `State::show_ignored` is set.
+///
+/// # Errors
+///
+/// Will return `Err` if a directory can't be read.
+pub fn find_markdown(
+    dir: &Path,
+    extensions: &[&str],
+    state: &State,
+    paths: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let mut walk = WalkBuilder::new(dir);
+    // Only the ignore files under `dir`, the same ones `is_ignored` reads.
+    walk.standard_filters(false);
+    if !state.show_ignored {
+        walk.hidden(true)
+            .git_ignore(true)
+            .require_git(false)
+            .add_custom_ignore_filename(IGNORE_FILES[1]);
+    }
+    for entry in walk.build() {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type().is_some_and(|kind| kind.is_file())
+            && path
+                .extension()
+                .and_then(OsStr::to_str)
+                .is_some_and(|extension| extensions.contains(&extension))
+        {
+            paths.push(path.to_path_buf());
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `path` is left out of listings & can't be requested directly,
+/// as `find_markdown` skips it, unless `State::show_ignored` is set.
+pub fn is_ignored(path: &Path, state: &State) -> bool {
+    if state.show_ignored {
+        return false;
+    }
+    let relative = state.relative_path(path);
+    let relative = Path::new(&relative);
+    let dotted = |name: &OsStr| name.to_string_lossy().starts_with('.');
+    // Outside of root only the file itself is checked.
+    let dotted = if relative.has_root() {
+        relative.file_name().is_some_and(dotted)
+    } else {
+        relative
+            .components()
+            .any(|component| matches!(component, Component::Normal(name) if dotted(name)))
+    };
+    if dotted {
+        return true;
+    }
+
+    // Deeper ignore files take precedence, as with git.
+    for dir in directories(path, state).iter().rev() {
+        for name in IGNORE_FILES.iter().rev() {
+            let file = dir.join(name);
+            if !file.is_file() {
+                continue;
+            }
+            let (ignore, _) = Gitignore::new(&file);
+            let matched = ignore.matched_path_or_any_parents(path, false);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+    }
+    false
+}
+
+/// The directories from root down to the one holding `path`, as paths in the
+/// same form as `path`, e.g. `docs` then `docs/guide` for `docs/guide/a.md`
+/// with a root of `docs`.
+pub fn directories(path: &Path, state: &State) -> Vec<PathBuf> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    // Root is as many directories above `path` as it has components
+    // relative to root, and paths outside of root only have their own.
+    let relative = state.relative_path(path);
+    if Path::new(&relative).has_root() {
+        return vec![parent.to_path_buf()];
+    }
+    let depth = Path::new(&relative).components().count();
+    let mut dir = parent
+        .ancestors()
+        .nth(depth.saturating_sub(1))
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+    let mut directories = vec![dir.clone()];
+    for component in Path::new(&relative)
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+    {
+        dir = dir.join(component);
+        directories.push(dir.clone());
+    }
+    directories
+}
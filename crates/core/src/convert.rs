@@ -0,0 +1,124 @@
+use crate::{
+    error::RenderError, exec::run_command, matter::TypedMatter, sanitize, state::State,
+    with_default_front_matter, Payload,
+};
+use anyhow::Result;
+use gray_matter::Pod;
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, str, time::Duration};
+use tracing::debug;
+
+/// A command converting documents of another markup language, e.g.
+/// reStructuredText or AsciiDoc, read from its stdin into html on its stdout.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Converter {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "Converter::default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl Converter {
+    fn default_timeout_ms() -> u64 {
+        5000
+    }
+}
+
+/// Renders a document through `converter` into the same `Payload` markdown
+/// produces. Front matter is removed before converting, while the table of
+/// contents & link reference definitions are left empty, as they're only
+/// found in markdown.
+///
+/// # Errors
+///
+/// Will return `Err` with a `RenderError` when the document or the
+/// converters output isn't valid UTF8, the front matter is invalid or the
+/// converter fails.
+pub fn generate_payload(slice: &[u8], converter: &Converter, state: &State) -> Result<Payload> {
+    let text = str::from_utf8(slice).map_err(|_| RenderError::InvalidUtf8)?;
+    let (pod, content) = match state.front_matter {
+        Some(fm) => fm.try_as_pod(text)?,
+        None => None,
+    }
+    .unwrap_or_else(|| (Pod::String(String::new()), slice.to_vec()));
+    let typed = state.front_matter.and_then(|fm| fm.as_typed(text));
+
+    debug!(command = converter.command, args = ?converter.args, "Converting.");
+    let output = run_command(
+        &converter.command,
+        &converter.args,
+        Duration::from_millis(converter.timeout_ms),
+        content,
+    )
+    .map_err(|source| RenderError::Convert {
+        command: converter.command.clone(),
+        source,
+    })?;
+    let mut html = String::from_utf8(output).map_err(|_| RenderError::InvalidUtf8)?;
+    if state.sanitize {
+        html = sanitize::clean(&html, state.sanitize_allowlist.as_ref());
+    }
+
+    Ok(Payload {
+        html,
+        front_matter: with_default_front_matter(
+            &state.default_front_matter,
+            typed
+                .as_ref()
+                .and_then(TypedMatter::as_json)
+                .unwrap_or_else(|| pod.into()),
+        ),
+        typed_front_matter: typed
+            .and_then(|typed| typed.with_defaults(&state.default_front_matter)),
+        toc: vec![],
+        refdefs: BTreeMap::new(),
+        git: None,
+        navigation: None,
+        lint: vec![],
+        translations: vec![],
+        tables: vec![],
+        path: None,
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{generate_payload, Converter};
+    use crate::{error::RenderError, formats::Matter, state::State};
+
+    #[test]
+    fn convert_test_generate_payload() {
+        let mut state = State::default();
+        state.front_matter = Some(Matter::Yaml);
+        let converter = Converter {
+            command: "sed".to_string(),
+            args: vec!["s/^= \\(.*\\)$/<h1>\\1<\\/h1>/".to_string()],
+            timeout_ms: 5000,
+        };
+        let input = "---\ntitle: Converted\n---\n= Title\n";
+        match generate_payload(input.as_bytes(), &converter, &state) {
+            Ok(payload) => {
+                assert_eq!(payload.html, "<h1>Title</h1>");
+                assert_eq!(payload.title(), Some("Converted"));
+            }
+            Err(error) => {
+                dbg!(&error);
+                assert!(false, "Should NEVER return an error. Error was {error}.")
+            }
+        }
+
+        let failing = Converter {
+            command: "false".to_string(),
+            args: vec![],
+            timeout_ms: 5000,
+        };
+        match generate_payload(input.as_bytes(), &failing, &state) {
+            Ok(_) => assert!(false, "Should have returned an error."),
+            Err(error) => assert!(matches!(
+                error.downcast_ref::<RenderError>(),
+                Some(RenderError::Convert { .. })
+            )),
+        }
+    }
+}
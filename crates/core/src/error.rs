@@ -0,0 +1,107 @@
+use crate::formats::{Matter, Part};
+use core::fmt::Display;
+
+/// Rendering a document failing, wrapped in the `anyhow::Error` returned by
+/// `generate_payload_from_slice` & `Payload::write_response_for`, so servers
+/// can tell failures apart with `downcast_ref`.
+#[derive(Debug)]
+pub enum RenderError {
+    /// The document isn't valid UTF8.
+    InvalidUtf8,
+    /// The document's front matter couldn't be parsed as the configured format.
+    InvalidFrontMatter(Matter),
+    /// The converter of a non markdown document failed, see `State::converters`.
+    Convert {
+        command: String,
+        source: anyhow::Error,
+    },
+    /// The document is larger than `State::max_file_size`, so wasn't read.
+    TooLarge { size: u64, limit: u64 },
+    /// Rendering passed `State::deadline`, checked between plugin passes.
+    TimedOut,
+    /// Not a format a payload can be written as, e.g. `md`.
+    UnsupportedFormat(String),
+    /// A part of the payload with no form in the format, e.g. front matter as
+    /// html.
+    UnsupportedPart { part: Part, format: String },
+    /// A `?table=` number past the tables in the document.
+    NoSuchTable { number: usize, count: usize },
+    /// The payload couldn't be serialized into, or written as, the format.
+    Serialize {
+        format: String,
+        source: anyhow::Error,
+    },
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RenderError::InvalidUtf8 => write!(f, "Content failed to be parsed into utf8."),
+            RenderError::InvalidFrontMatter(matter) => {
+                write!(f, "Front matter failed to be parsed as {matter}.")
+            }
+            RenderError::Convert { command, .. } => {
+                write!(f, "Converting the document with {command} failed.")
+            }
+            RenderError::TooLarge { size, limit } => write!(
+                f,
+                "The document is {size} bytes, over the limit of {limit} bytes."
+            ),
+            RenderError::TimedOut => write!(f, "Rendering the document took too long."),
+            RenderError::UnsupportedFormat(format) => {
+                write!(f, "{format} is not a supported payload format.")
+            }
+            RenderError::UnsupportedPart { part, format } => {
+                write!(f, "The {part} of a payload can't be written as {format}.")
+            }
+            RenderError::NoSuchTable { number, count } => {
+                write!(f, "There's no table {number}, the document has {count}.")
+            }
+            RenderError::Serialize { format, .. } => {
+                write!(f, "Payload failed to be written as {format}.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderError::Convert { source, .. } | RenderError::Serialize { source, .. } => {
+                Some(source.as_ref())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A config file, or environment variable, setting options that don't exist
+/// or values that aren't valid for their option.
+#[derive(Debug)]
+pub struct ConfigError {
+    /// The config file's path, or `the environment`.
+    pub origin: String,
+    pub unknown: Vec<String>,
+    /// Each invalid option, along with why it's invalid.
+    pub invalid: Vec<(String, String)>,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Invalid config in {}.", self.origin)?;
+        if !self.unknown.is_empty() {
+            write!(f, " Unknown options: {}.", self.unknown.join(", "))?;
+        }
+        if !self.invalid.is_empty() {
+            let invalid: Vec<String> = self
+                .invalid
+                .iter()
+                .map(|(key, reason)| format!("{key} ({reason})"))
+                .collect();
+            write!(f, " Invalid values: {}.", invalid.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
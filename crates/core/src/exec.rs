@@ -0,0 +1,144 @@
+use crate::{
+    abi,
+    plugin::{from_config, Plugin, Stage},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use core::ops::Range;
+use pulldown_cmark::Event;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+use tracing::debug;
+
+#[derive(Deserialize)]
+struct ExecConfig {
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default = "ExecConfig::default_timeout_ms")]
+    timeout_ms: u64,
+}
+
+impl ExecConfig {
+    fn default_timeout_ms() -> u64 {
+        5000
+    }
+}
+
+/// Runs an external command over every event of a document, writing a json
+/// array of `abi::Event`s to its stdin and reading the transformed array
+/// from its stdout.
+///
+/// Fails the document when the command can't run, exits unsuccessfully or
+/// runs past its timeout.
+pub struct ExecPlugin {
+    command: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl ExecPlugin {
+    pub fn new(command: String, args: Vec<String>, timeout: Duration) -> ExecPlugin {
+        ExecPlugin {
+            command,
+            args,
+            timeout,
+        }
+    }
+
+    /// Builds the plugin from a config with a `command`, optional `args` and
+    /// `timeout_ms`, for `PluginRegistry`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `command` is missing.
+    pub fn construct(config: &Value) -> Result<Stage> {
+        let config: ExecConfig = from_config(config)?;
+        Ok(Stage::Window(Box::new(ExecPlugin::new(
+            config.command,
+            config.args,
+            Duration::from_millis(config.timeout_ms),
+        ))))
+    }
+
+    fn run(&self, input: Vec<u8>) -> Result<Vec<u8>> {
+        debug!(command = self.command, args = ?self.args, "Running plugin.");
+        run_command(&self.command, &self.args, self.timeout, input)
+    }
+}
+
+/// Runs `command`, writing `input` to its stdin & returning its stdout.
+///
+/// # Errors
+///
+/// Will return `Err` when the command can't run, exits unsuccessfully or
+/// runs past `timeout`.
+pub(crate) fn run_command(
+    command: &str,
+    args: &[String],
+    timeout: Duration,
+    input: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let mut child = Command::new(command)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Unable to run {command}."))?;
+
+    // Write & read on their own threads, so a full pipe can't block the
+    // timeout.
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin."))?;
+    thread::spawn(move || stdin.write_all(&input));
+    let mut stdout = child.stdout.take().ok_or_else(|| anyhow!("No stdout."))?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = vec![];
+        let _ = sender.send(stdout.read_to_end(&mut output).map(|_| output));
+    });
+
+    let Ok(output) = receiver.recv_timeout(timeout) else {
+        let _ = child.kill();
+        let _ = child.wait();
+        bail!("Timed out after {}ms.", timeout.as_millis());
+    };
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("Exited with {status}.");
+    }
+
+    Ok(output?)
+}
+
+impl Plugin for ExecPlugin {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        0
+    }
+
+    fn check_slice(&mut self, _: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        Ok(None)
+    }
+
+    /// Claims the whole document.
+    fn final_check(&mut self, pos: usize) -> Result<Option<Range<usize>>> {
+        Ok(Some(0..(pos + 1)))
+    }
+
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        abi::transform_slice(slice, |input| self.run(input))
+            .with_context(|| format!("Running {} failed.", self.command))
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{collection::Document, formats::Feed as FeedFormats};
+use anyhow::Result;
+use core::fmt::Write;
+use quick_xml::escape::escape;
+use serde_json::json;
+
+/// Feed level metadata shared by every feed format.
+#[derive(Debug)]
+pub struct Channel {
+    pub title: String,
+    /// The absolute url documents are served from, without a trailing `/`.
+    pub link: String,
+}
+
+impl Channel {
+    fn link_to(&self, document: &Document) -> String {
+        format!("{}/{}", self.link, document.path_with_extension("html"))
+    }
+}
+
+/// Sorts `documents` newest first, with undated documents last.
+pub fn sort(documents: &mut [Document]) {
+    documents.sort_by_key(|document| core::cmp::Reverse(document.date()));
+}
+
+/// # Errors
+///
+/// Will return `Err` if the feed fails to be written.
+pub fn generate(
+    format: &FeedFormats,
+    channel: &Channel,
+    documents: &[Document],
+) -> Result<Vec<u8>> {
+    let feed = match format {
+        FeedFormats::Rss => rss(channel, documents)?,
+        FeedFormats::Atom => atom(channel, documents)?,
+        FeedFormats::Json => json_feed(channel, documents)?,
+    };
+
+    Ok(feed.into())
+}
+
+fn rss(channel: &Channel, documents: &[Document]) -> Result<String> {
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(xml, r#"<rss version="2.0">"#)?;
+    writeln!(xml, "<channel>")?;
+    writeln!(xml, "<title>{}</title>", escape(&channel.title))?;
+    writeln!(xml, "<link>{}/</link>", escape(&channel.link))?;
+    writeln!(xml, "<description>{}</description>", escape(&channel.title))?;
+    for document in documents {
+        let link = channel.link_to(document);
+        writeln!(xml, "<item>")?;
+        writeln!(xml, "<title>{}</title>", escape(document.title()))?;
+        writeln!(xml, "<link>{}</link>", escape(&link))?;
+        writeln!(xml, "<guid>{}</guid>", escape(&link))?;
+        if let Some(date) = document.date() {
+            writeln!(xml, "<pubDate>{}</pubDate>", date.to_rfc2822())?;
+        }
+        writeln!(
+            xml,
+            "<description>{}</description>",
+            escape(&document.payload.html)
+        )?;
+        writeln!(xml, "</item>")?;
+    }
+    writeln!(xml, "</channel>")?;
+    write!(xml, "</rss>")?;
+
+    Ok(xml)
+}
+
+fn atom(channel: &Channel, documents: &[Document]) -> Result<String> {
+    // Atom requires an `updated` element, use the newest document date.
+    let updated = documents
+        .iter()
+        .filter_map(Document::date)
+        .max()
+        .map_or_else(
+            || "1970-01-01T00:00:00+00:00".to_string(),
+            |date| date.to_rfc3339(),
+        );
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(xml, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#)?;
+    writeln!(xml, "<title>{}</title>", escape(&channel.title))?;
+    writeln!(xml, r#"<link href="{}/"/>"#, escape(&channel.link))?;
+    writeln!(xml, "<id>{}/</id>", escape(&channel.link))?;
+    writeln!(xml, "<updated>{updated}</updated>")?;
+    for document in documents {
+        let link = channel.link_to(document);
+        writeln!(xml, "<entry>")?;
+        writeln!(xml, "<title>{}</title>", escape(document.title()))?;
+        writeln!(xml, r#"<link href="{}"/>"#, escape(&link))?;
+        writeln!(xml, "<id>{}</id>", escape(&link))?;
+        writeln!(
+            xml,
+            "<updated>{}</updated>",
+            document
+                .date()
+                .map_or_else(|| updated.clone(), |date| date.to_rfc3339())
+        )?;
+        writeln!(
+            xml,
+            r#"<content type="html">{}</content>"#,
+            escape(&document.payload.html)
+        )?;
+        writeln!(xml, "</entry>")?;
+    }
+    write!(xml, "</feed>")?;
+
+    Ok(xml)
+}
+
+/// Builds a [JSON Feed 1.1](https://www.jsonfeed.org/version/1.1/), with each
+/// documents front matter under the `_front_matter` extension key.
+fn json_feed(channel: &Channel, documents: &[Document]) -> Result<String> {
+    let items = documents
+        .iter()
+        .map(|document| {
+            let link = channel.link_to(document);
+            let mut item = json!({
+                "id": link,
+                "url": link,
+                "title": document.title(),
+                "content_html": document.payload.html,
+                "_front_matter": document.payload.front_matter,
+            });
+            if let Some(date) = document.date() {
+                item["date_published"] = json!(date.to_rfc3339());
+            }
+            item
+        })
+        .collect::<Vec<_>>();
+
+    let feed = json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": channel.title,
+        "home_page_url": format!("{}/", channel.link),
+        "items": items,
+    });
+
+    Ok(serde_json::to_string_pretty(&feed)?)
+}
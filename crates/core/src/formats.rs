@@ -1,4 +1,5 @@
-use crate::matter::RefDefMatter;
+use crate::error::RenderError;
+use crate::matter::{RefDefMatter, TypedMatter};
 use anyhow::{anyhow, Error, Result};
 use clap::ValueEnum;
 use core::convert::{TryFrom, TryInto};
@@ -10,6 +11,7 @@ use gray_matter::{
 use serde_derive::{Deserialize, Serialize};
 
 #[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
 pub enum Config {
     Json = Generic::Json as u8,
     Yaml = Generic::Yaml as u8,
@@ -63,6 +65,39 @@ impl Matter {
         }
     }
 
+    /// Like `as_pod`, but front matter that fails to parse is an error rather
+    /// than an empty value.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` with `RenderError::InvalidFrontMatter` when the front
+    /// matter isn't valid json, toml or yaml.
+    pub fn try_as_pod(self, input: &str) -> Result<Option<(Pod, Vec<u8>)>> {
+        if let Some(matter) = self.as_matter(input) {
+            // The engines parse failures into null, so only an explicit null is valid.
+            let explicit = matches!(matter.matter.as_str(), "null" | "~");
+            if matches!(matter.data, Some(Pod::Null)) && !explicit {
+                return Err(RenderError::InvalidFrontMatter(self).into());
+            }
+        }
+        Ok(self.as_pod(input))
+    }
+
+    /// The front matter parsed by its own format, keeping the types lost by
+    /// `as_pod`, e.g. toml datetimes & yaml tags. Only toml & yaml have any.
+    pub fn as_typed(self, input: &str) -> Option<TypedMatter> {
+        let matter = self
+            .as_matter(input)
+            .filter(|matter| matter.data.is_some())?;
+        match self {
+            Matter::Toml => toml::from_str(&matter.matter).ok().map(TypedMatter::Toml),
+            Matter::Yaml => serde_yaml::from_str(&matter.matter)
+                .ok()
+                .map(TypedMatter::Yaml),
+            Matter::Json | Matter::Refdef => None,
+        }
+    }
+
     pub fn as_pod(self, input: &str) -> Option<(Pod, Vec<u8>)> {
         let pod = if let Some(matter) = self.as_matter(input) {
             let buf = matter.content.as_bytes().to_vec();
@@ -79,7 +114,7 @@ impl Matter {
 }
 
 #[repr(u8)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Payload {
     Html = 1,
     Markdown = 2,
@@ -90,6 +125,30 @@ pub enum Payload {
     Pickle = Generic::Pickle as u8,
     Postcard = Generic::Postcard as u8,
     Cbor = Generic::Cbor as u8,
+    Xml = Generic::Xml as u8,
+}
+
+impl Payload {
+    /// Whether the format is binary, rather than UTF8 text.
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Payload::Pickle | Payload::Postcard | Payload::Cbor)
+    }
+
+    /// The media type of a response in this format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Payload::Html => "text/html; charset=utf-8",
+            Payload::Markdown => "text/markdown; charset=utf-8",
+            Payload::Json => "application/json",
+            Payload::Yaml => "application/yaml",
+            Payload::Toml => "application/toml",
+            Payload::Csv => "text/csv; charset=utf-8",
+            Payload::Xml => "application/xml",
+            Payload::Pickle => "application/x-python-pickle",
+            Payload::Postcard => "application/octet-stream",
+            Payload::Cbor => "application/cbor",
+        }
+    }
 }
 
 impl Display for Payload {
@@ -121,6 +180,7 @@ impl TryFrom<&str> for Payload {
             "cbor" => Ok(Payload::Cbor),
             "csv" => Ok(Payload::Csv),
             "postcard" => Ok(Payload::Postcard),
+            "xml" => Ok(Payload::Xml),
             x => Err(anyhow!("{} extension not supported.", x)),
         }
     }
@@ -136,6 +196,7 @@ pub enum Generic {
     Pickle,
     Postcard,
     Cbor,
+    Xml,
 }
 
 impl Display for Generic {
@@ -151,6 +212,7 @@ impl Display for Generic {
                 Generic::Pickle => "pickle",
                 Generic::Postcard => "postcard",
                 Generic::Cbor => "cbor",
+                Generic::Xml => "xml",
             }
         )
     }
@@ -167,6 +229,7 @@ impl TryFrom<&u8> for Generic {
             x if x == &(Generic::Pickle as u8) => Ok(Generic::Pickle),
             x if x == &(Generic::Postcard as u8) => Ok(Generic::Postcard),
             x if x == &(Generic::Cbor as u8) => Ok(Generic::Cbor),
+            x if x == &(Generic::Xml as u8) => Ok(Generic::Xml),
             x => Err(anyhow!("{} is not recognised as a Generic format.", x)),
         }
     }
@@ -184,6 +247,7 @@ impl TryFrom<&Payload> for Generic {
             Payload::Pickle => Ok(Generic::Pickle),
             Payload::Postcard => Ok(Generic::Postcard),
             Payload::Cbor => Ok(Generic::Cbor),
+            Payload::Xml => Ok(Generic::Xml),
         }
     }
 }
@@ -199,3 +263,74 @@ impl TryFrom<&Matter> for Generic {
         }
     }
 }
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Feed {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl Display for Feed {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Feed::Rss => write!(f, "rss"),
+            Feed::Atom => write!(f, "atom"),
+            Feed::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl TryFrom<&str> for Feed {
+    type Error = Error;
+    fn try_from(value: &str) -> core::result::Result<Self, Self::Error> {
+        match value {
+            "rss" => Ok(Feed::Rss),
+            "atom" => Ok(Feed::Atom),
+            "json" => Ok(Feed::Json),
+            x => Err(anyhow!(
+                "{} feed not supported. Use one of rss, atom or json.",
+                x
+            )),
+        }
+    }
+}
+
+/// A single part of a payload, for clients only needing one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    /// The front matter.
+    Matter,
+    /// The html of the document, without the page template.
+    Body,
+    /// Every table in the document.
+    Tables,
+    /// The table with this 1 based number, from `?table=`.
+    Table(usize),
+}
+
+impl Display for Part {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Part::Matter => write!(f, "matter"),
+            Part::Body => write!(f, "body"),
+            Part::Tables => write!(f, "tables"),
+            Part::Table(number) => write!(f, "table {number}"),
+        }
+    }
+}
+
+impl TryFrom<&str> for Part {
+    type Error = Error;
+    fn try_from(value: &str) -> core::result::Result<Self, Self::Error> {
+        match value {
+            "matter" => Ok(Part::Matter),
+            "body" => Ok(Part::Body),
+            "tables" => Ok(Part::Tables),
+            x => Err(anyhow!(
+                "{} part not supported. Use one of matter, body or tables.",
+                x
+            )),
+        }
+    }
+}
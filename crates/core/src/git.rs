@@ -0,0 +1,177 @@
+use serde_derive::{Deserialize, Serialize};
+#[cfg(all(feature = "git", not(target_arch = "wasm32")))]
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+#[cfg(all(feature = "git", not(target_arch = "wasm32")))]
+use anyhow::anyhow;
+
+/// How many commits `GitMetadata::history` keeps without `--git-history`.
+pub const DEFAULT_HISTORY: usize = 10;
+
+/// A document's git history, for docs sites showing when a page was last
+/// updated & by whom.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GitMetadata {
+    /// The RFC 3339 date of the last commit changing the document.
+    pub updated: String,
+    /// The author of the last commit changing the document.
+    pub author: String,
+    /// The commits changing the document, newest first.
+    pub history: Vec<GitCommit>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GitCommit {
+    pub id: String,
+    /// The RFC 3339 date the commit was made, in its author's timezone.
+    pub date: String,
+    pub author: String,
+    /// The first line of the commit message.
+    pub summary: String,
+}
+
+/// The git metadata of every document looked up so far, by path & limit,
+/// along with the `HEAD` it was read at.
+#[cfg(all(feature = "git", not(target_arch = "wasm32")))]
+type Cache = HashMap<(PathBuf, usize), (git2::Oid, Option<GitMetadata>)>;
+
+#[cfg(all(feature = "git", not(target_arch = "wasm32")))]
+fn cache() -> &'static Mutex<Cache> {
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+/// The git history of `path`, up to `limit` commits from `HEAD`, or `None`
+/// when it isn't in a repository or has never been committed. The history
+/// is only walked again once `HEAD` moves.
+///
+/// # Errors
+///
+/// Will return `Err` if the repository can't be read.
+#[cfg(all(feature = "git", not(target_arch = "wasm32")))]
+pub fn metadata(path: &Path, limit: usize) -> anyhow::Result<Option<GitMetadata>> {
+    use git2::{ErrorCode, Repository};
+
+    let path = path.canonicalize()?;
+    let repository = match Repository::discover(path.parent().unwrap_or(&path)) {
+        Ok(repository) => repository,
+        Err(error) if error.code() == ErrorCode::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+    let head = match repository.head() {
+        Ok(head) => head.peel_to_commit()?.id(),
+        // A repository without any commits yet.
+        Err(error) if error.code() == ErrorCode::UnbornBranch => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+
+    let key = (path, limit);
+    if let Some((oid, metadata)) = cache()
+        .lock()
+        .map_err(|error| anyhow!(error.to_string()))?
+        .get(&key)
+    {
+        if *oid == head {
+            return Ok(metadata.clone());
+        }
+    }
+    let metadata = history(&repository, &key.0, head, limit)?;
+    cache()
+        .lock()
+        .map_err(|error| anyhow!(error.to_string()))?
+        .insert(key, (head, metadata.clone()));
+    Ok(metadata)
+}
+
+/// Walks the history from `head`, keeping up to `limit` commits changing
+/// `path`.
+#[cfg(all(feature = "git", not(target_arch = "wasm32")))]
+fn history(
+    repository: &git2::Repository,
+    path: &Path,
+    head: git2::Oid,
+    limit: usize,
+) -> anyhow::Result<Option<GitMetadata>> {
+    let Some(relative) = repository
+        .workdir()
+        .and_then(|workdir| workdir.canonicalize().ok())
+        .and_then(|workdir| path.strip_prefix(workdir).ok().map(Path::to_path_buf))
+    else {
+        return Ok(None);
+    };
+
+    let mut walk = repository.revwalk()?;
+    walk.push(head)?;
+    walk.set_sorting(git2::Sort::TIME)?;
+
+    let mut history = vec![];
+    for id in walk {
+        if history.len() >= limit {
+            break;
+        }
+        let commit = repository.find_commit(id?)?;
+        if changes(&commit, &relative)? {
+            history.push(GitCommit::from(&commit));
+        }
+    }
+
+    Ok(history.first().cloned().map(|last| GitMetadata {
+        updated: last.date,
+        author: last.author,
+        history,
+    }))
+}
+
+/// Whether `commit` changed the file at `path`, differing from every parent,
+/// as `git log` simplifies merges.
+#[cfg(all(feature = "git", not(target_arch = "wasm32")))]
+fn changes(commit: &git2::Commit, path: &Path) -> Result<bool, git2::Error> {
+    let id = |tree: git2::Tree| tree.get_path(path).ok().map(|entry| entry.id());
+    let ours = id(commit.tree()?);
+    if commit.parent_count() == 0 {
+        return Ok(ours.is_some());
+    }
+    for parent in commit.parents() {
+        if id(parent.tree()?) == ours {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(all(feature = "git", not(target_arch = "wasm32")))]
+impl From<&git2::Commit<'_>> for GitCommit {
+    fn from(commit: &git2::Commit) -> GitCommit {
+        use chrono::{DateTime, FixedOffset};
+
+        let author = commit.author();
+        let time = author.when();
+        let date = FixedOffset::east_opt(time.offset_minutes() * 60)
+            .zip(DateTime::from_timestamp(time.seconds(), 0))
+            .map(|(offset, date)| date.with_timezone(&offset).to_rfc3339())
+            .unwrap_or_default();
+        GitCommit {
+            id: commit.id().to_string(),
+            date,
+            author: author.name().unwrap_or_default().to_string(),
+            summary: commit.summary().unwrap_or_default().to_string(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "git", not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_test_outside_repository() {
+        let path = std::env::temp_dir().join("serve_md_git_test.md");
+        std::fs::write(&path, "# Untracked\n").unwrap();
+        assert!(matches!(metadata(&path, DEFAULT_HISTORY), Ok(None)));
+        let _ = std::fs::remove_file(&path);
+    }
+}
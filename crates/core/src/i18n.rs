@@ -0,0 +1,196 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// A language a document is available in, with the path of that version
+/// relative to root & its html extension, e.g. `guide/setup.fr.html`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Translation {
+    pub lang: String,
+    pub path: String,
+}
+
+/// The language of documents without a language suffix, unless
+/// `State::default_language` is set.
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// The language of a file stem with a language suffix, e.g. `fr` for
+/// `page.fr` or `pt-BR` for `page.pt-BR`, along with the stem without it.
+/// Only two letter languages, with an optional two letter region, count, so
+/// stems like `notes.body` aren't mistaken for a translation.
+pub fn split_language(stem: &str) -> Option<(&str, &str)> {
+    let (base, lang) = stem.rsplit_once('.')?;
+    let (language, region) = match lang.split_once('-') {
+        Some((language, region)) => (language, Some(region)),
+        None => (lang, None),
+    };
+    let letters = |part: &str| part.len() == 2 && part.chars().all(|c| c.is_ascii_alphabetic());
+    let valid = !base.is_empty()
+        && letters(language)
+        && language.chars().all(|c| c.is_ascii_lowercase())
+        && region.into_iter().all(letters);
+    valid.then_some((base, lang))
+}
+
+/// The language tags of an `Accept-Language` header, lowercased, most
+/// preferred first. Tags with a `q` of 0 & the `*` wildcard are left out.
+pub fn accepted_languages(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let tag = params.next()?.trim().to_lowercase();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|quality| quality.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // Stable, so tags of equal quality keep their order.
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// How well `lang` suits a client asking for `tag`, `2` for the same tag,
+/// `1` for the same language in another region & `0` otherwise.
+fn score(lang: &str, tag: &str) -> u8 {
+    let primary = |tag: &str| tag.split('-').next().unwrap_or_default().to_lowercase();
+    if lang.eq_ignore_ascii_case(tag) {
+        2
+    } else if primary(lang) == primary(tag) {
+        1
+    } else {
+        0
+    }
+}
+
+/// The language out of `available` best suiting `preferred`, in order of
+/// preference, or `None` when none of them suit.
+pub fn negotiate<'a>(available: &[&'a str], preferred: &[String]) -> Option<&'a str> {
+    preferred.iter().find_map(|tag| {
+        available
+            .iter()
+            .map(|lang| (score(lang, tag), *lang))
+            .filter(|(score, _)| *score > 0)
+            .max_by_key(|(score, _)| *score)
+            .map(|(_, lang)| lang)
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::fs::{translations, variants};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod fs {
+    use super::{split_language, Translation, DEFAULT_LANGUAGE};
+    use crate::{collection::is_ignored, state::State};
+    use anyhow::Result;
+    use std::{
+        ffi::OsStr,
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    /// The directory of `path`, `.` for a bare file name.
+    fn directory(path: &Path) -> &Path {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Every language version of the document at `path`, whether `path` is
+    /// the default file, e.g. `page.md`, or one of its translations, e.g.
+    /// `page.fr.md`, sorted by language. The default file is listed under
+    /// `State::default_language`, when it exists. Ignored & hidden versions
+    /// are left out, see `State::is_hidden`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the document's directory can't be read.
+    pub fn variants(path: &Path, state: &State) -> Result<Vec<(String, PathBuf)>> {
+        let extensions = state.source_extensions();
+        let stem = path.file_stem().and_then(OsStr::to_str).unwrap_or_default();
+        let base = split_language(stem).map_or(stem, |(base, _)| base);
+        let default_language = state
+            .default_language
+            .as_deref()
+            .unwrap_or(DEFAULT_LANGUAGE);
+
+        let mut variants = vec![];
+        for entry in fs::read_dir(directory(path))? {
+            let file = entry?.path();
+            let is_source = file
+                .extension()
+                .and_then(OsStr::to_str)
+                .is_some_and(|extension| extensions.contains(&extension));
+            if !is_source || !file.is_file() || is_ignored(&file, state) {
+                continue;
+            }
+            let Some(file_stem) = file.file_stem().and_then(OsStr::to_str) else {
+                continue;
+            };
+            let lang = match split_language(file_stem) {
+                Some((file_base, lang)) if file_base == base => lang,
+                None if file_stem == base => default_language,
+                _ => continue,
+            };
+            let hidden = fs::read_to_string(&file)
+                .ok()
+                .and_then(|source| state.source_front_matter(&source))
+                .is_some_and(|front_matter| state.is_hidden(&front_matter));
+            if !hidden {
+                variants.push((lang.to_string(), file));
+            }
+        }
+        variants.sort();
+        Ok(variants)
+    }
+
+    /// The `variants` of the document at `path` as links, when it has any
+    /// translations.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the document's directory can't be read.
+    pub fn translations(path: &Path, state: &State) -> Result<Vec<Translation>> {
+        let variants = variants(path, state)?;
+        if variants.len() < 2 {
+            return Ok(vec![]);
+        }
+        Ok(variants
+            .into_iter()
+            .map(|(lang, file)| Translation {
+                lang,
+                path: Path::new(&state.relative_path(&file))
+                    .with_extension("html")
+                    .to_string_lossy()
+                    .replace('\\', "/"),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn i18n_test_split_language() {
+        assert_eq!(split_language("page.fr"), Some(("page", "fr")));
+        assert_eq!(split_language("page.pt-BR"), Some(("page", "pt-BR")));
+        assert_eq!(split_language("notes.body"), None);
+        assert_eq!(split_language("page.FR"), None);
+        assert_eq!(split_language("page"), None);
+        assert_eq!(split_language(".fr"), None);
+    }
+
+    #[test]
+    fn i18n_test_negotiate() {
+        let preferred = accepted_languages("fr-CH, fr;q=0.9, de;q=0.8, *;q=0.5, es;q=0");
+        assert_eq!(preferred, ["fr-ch", "fr", "de"]);
+        assert_eq!(negotiate(&["en", "fr", "fr-CH"], &preferred), Some("fr-CH"));
+        assert_eq!(negotiate(&["en", "fr"], &preferred), Some("fr"));
+        assert_eq!(negotiate(&["en", "de"], &preferred), Some("de"));
+        assert_eq!(negotiate(&["en", "es"], &preferred), None);
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use std::{fs, path::Path};
+
+/// The route remote images are rewritten to, followed by their key.
+pub const ROUTE: &str = "/img-cache/";
+
+/// A stable key for `url`, its `hash`.
+pub fn key(url: &str) -> String {
+    hash(url.as_bytes())
+}
+
+/// The FNV-1a hash of `bytes` as 16 hex digits.
+pub fn hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+/// Whether `value` could have been returned by `key`, so it's safe to use as
+/// a file name.
+pub fn is_key(value: &str) -> bool {
+    value.len() == 16
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Records `url` in `dir` under its key, for the server to download on the
+/// first request, returning the key.
+///
+/// # Errors
+///
+/// Will return `Err` if `dir` can't be created or written to.
+pub fn remember(dir: &Path, url: &str) -> Result<String> {
+    let key = key(url);
+    let path = dir.join(format!("{key}.url"));
+    if !path.exists() {
+        fs::create_dir_all(dir).with_context(|| format!("Unable to create {}.", dir.display()))?;
+        fs::write(&path, url).with_context(|| format!("Unable to write {}.", path.display()))?;
+    }
+    Ok(key)
+}
+
+/// The url recorded by `remember` for `key`.
+pub fn url(dir: &Path, key: &str) -> Option<String> {
+    if !is_key(key) {
+        return None;
+    }
+    fs::read_to_string(dir.join(format!("{key}.url"))).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_key, key};
+
+    #[test]
+    fn images_test_key() {
+        // The FNV-1a test vectors.
+        assert_eq!(key(""), "cbf29ce484222325");
+        assert_eq!(key("a"), "af63dc4c8601ec8c");
+        assert!(is_key(&key("https://example.com/cat.png")));
+        assert!(!is_key("../../etc/passwd"));
+        assert!(!is_key("CBF29CE484222325"));
+    }
+}
@@ -1,54 +1,190 @@
+pub mod abi;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod aliases;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bundle;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod collection;
+pub mod convert;
+pub mod error;
+pub mod exec;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod feed;
 pub mod formats;
+pub mod git;
+pub mod i18n;
+pub mod images;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod links;
+pub mod lint;
 pub mod matter;
+pub mod navigation;
 pub mod plugin;
+pub mod quotes;
+pub mod sanitize;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod search;
+pub mod serializer;
+pub mod slug;
 pub mod state;
+pub mod tables;
+pub mod template;
+pub mod toc;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 use std::{
-    ffi::OsStr,
-    fs::File,
-    io::{ErrorKind, Read},
+    borrow::Cow,
+    collections::BTreeMap,
+    io::{Read, Write},
     path::Path as SysPath,
     str,
     sync::Arc,
     vec,
 };
+#[cfg(not(target_arch = "wasm32"))]
+use std::{ffi::OsStr, fs::File, io::ErrorKind};
 
 use core::ops::Range;
 
-use pulldown_cmark::{html, Event, Options, Parser as CmParser};
+use pulldown_cmark::{html, CowStr, Event, Options, Parser as CmParser};
 
-use anyhow::{anyhow, Context, Result};
-use formats::Payload as PayloadFormats;
+use anyhow::Result;
+#[cfg(not(target_arch = "wasm32"))]
+use anyhow::{anyhow, Context};
+use error::RenderError;
+use formats::{Part, Payload as PayloadFormats};
+use git::GitMetadata;
 use gray_matter::Pod;
-use plugin::{CollapsibleHeaders, Emoji, Plugin};
+use i18n::Translation;
+use lint::LintWarning;
+use matter::TypedMatter;
+use navigation::Navigation;
+use plugin::{HeadingAnchors, Pipeline, Plugin, PluginError, PluginRegistry, Stage};
+use quotes::Quotes;
 use serde_derive::{Deserialize, Serialize};
 use serde_pickle::SerOptions;
+use serializer::PayloadSerializer;
 use state::State;
+use tables::Table;
+use toc::TocEntry;
+use tracing::{debug, trace};
 
+#[cfg(not(target_arch = "wasm32"))]
 pub fn determine(path: &str, state: Arc<State>) -> Result<Vec<u8>> {
-    #[cfg(debug_assertions)]
-    dbg!(&path);
+    debug!(path, "Determining response.");
 
-    let sys_path = SysPath::new(&path);
-    let path_ext = sys_path.extension();
-    let extension = path_ext
-        .and_then(OsStr::to_str)
-        .and_then(|s| PayloadFormats::try_from(s).ok());
+    let (part, path) = match resolve_part(path, &state) {
+        Some((part, path)) => (Some(part), Cow::Owned(path)),
+        None => (None, Cow::Borrowed(path)),
+    };
+    let path = path.as_ref();
 
-    if let Some(extension) = &extension {
-        let path = path.replace(&(".".to_owned() + &extension.to_string()), ".md");
+    if let Some((extension, markdown)) = resolve(path, &state) {
+        if collection::is_ignored(SysPath::new(&markdown), &state) {
+            return Err(std::io::Error::from(ErrorKind::NotFound).into());
+        }
         // Handle commonmark requests early
-        if extension == &PayloadFormats::Markdown {
-            return fetch_md(&path).context(format!(
-                "There was an error trying to read the markdown file {path}"
-            ));
+        if extension == PayloadFormats::Markdown && part.is_none() {
+            let buf = fetch_md(&markdown).context(format!(
+                "There was an error trying to read the markdown file {markdown}"
+            ))?;
+            state.check_source_requestable(&buf)?;
+            return Ok(buf);
+        }
+        let payload = generate_payload_from_path(SysPath::new(&markdown), Arc::clone(&state))?;
+        state.check_requestable(&payload.front_matter)?;
+        if let Some(part) = part {
+            return payload.into_part_for(part, &extension);
+        }
+        return payload.into_response_with_state(&extension, &state);
+    }
+    if let Some((serializer, markdown)) = resolve_serializer(path, &state) {
+        // Registered serializers only write whole payloads.
+        if let Some(part) = part {
+            let format = serializer.content_type().to_string();
+            return Err(RenderError::UnsupportedPart { part, format }.into());
+        }
+        if collection::is_ignored(SysPath::new(&markdown), &state) {
+            return Err(std::io::Error::from(ErrorKind::NotFound).into());
         }
-        return generate_payload_from_path(sys_path, state)?.into_response_for(extension);
+        let payload = generate_payload_from_path(SysPath::new(&markdown), Arc::clone(&state))?;
+        state.check_requestable(&payload.front_matter)?;
+        return payload.into_response_with(serializer.as_ref());
     }
 
     Err(anyhow!("File path {} not found.", path))
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+/// The format requested by `path`'s extension, along with the markdown file
+/// it renders, e.g. `docs/readme.json` renders `docs/readme.md` as json. The
+/// first of `State::source_extensions` existing on disk is picked, falling
+/// back to the first markdown extension.
+pub fn resolve(path: &str, state: &State) -> Option<(PayloadFormats, String)> {
+    let sys_path = SysPath::new(path);
+    let extension = sys_path
+        .extension()
+        .and_then(OsStr::to_str)
+        .and_then(|s| PayloadFormats::try_from(s).ok())?;
+    Some((extension, source_path(sys_path, state)))
+}
+
+/// Like `resolve`, for an extension registered in `State::serializers`
+/// rather than a built in format.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn resolve_serializer(
+    path: &str,
+    state: &State,
+) -> Option<(Arc<dyn PayloadSerializer>, String)> {
+    let sys_path = SysPath::new(path);
+    let serializer = sys_path
+        .extension()
+        .and_then(OsStr::to_str)
+        .and_then(|s| state.serializers.get(s))?;
+    Some((Arc::clone(serializer), source_path(sys_path, state)))
+}
+
+/// Splits the part out of a `path` like `docs/readme.matter.json`, returning
+/// it along with the path of the whole payload, `docs/readme.json`. Paths
+/// whose markdown file exists as named, e.g. `notes.body.md`, aren't split.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn resolve_part(path: &str, state: &State) -> Option<(Part, String)> {
+    let sys_path = SysPath::new(path);
+    let extension = sys_path.extension()?;
+    let whole = sys_path.with_extension("");
+    let part = whole
+        .extension()
+        .and_then(OsStr::to_str)
+        .and_then(|s| Part::try_from(s).ok())?;
+    let exists = state
+        .source_extensions()
+        .iter()
+        .any(|source| sys_path.with_extension(source).exists());
+    if exists {
+        return None;
+    }
+    Some((
+        part,
+        whole
+            .with_extension(extension)
+            .to_string_lossy()
+            .to_string(),
+    ))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn source_path(sys_path: &SysPath, state: &State) -> String {
+    let extensions = state.source_extensions();
+    let markdown = extensions
+        .iter()
+        .map(|extension| sys_path.with_extension(extension))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| sys_path.with_extension(extensions[0]));
+    markdown.to_string_lossy().to_string()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 fn fetch_md(path: &str) -> std::io::Result<Vec<u8>> {
     if SysPath::new(&path).exists() {
         let file = File::open(path);
@@ -60,12 +196,17 @@ fn fetch_md(path: &str) -> std::io::Result<Vec<u8>> {
     Err(std::io::Error::from(ErrorKind::NotFound))
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub fn generate_payload_from_path(
     file_path: &std::path::Path,
     state: Arc<State>,
 ) -> Result<Payload> {
     if file_path.exists() {
-        return generate_payload_from_file(File::open(file_path)?, state);
+        let mut file = File::open(file_path)?;
+        state.check_file_size(file.metadata()?.len())?;
+        let mut buf = vec![];
+        file.read_to_end(&mut buf)?;
+        return generate_payload_from_source(file_path, &buf, state);
     }
 
     Err(anyhow!(
@@ -74,9 +215,50 @@ pub fn generate_payload_from_path(
     ))
 }
 
-pub fn generate_payload_from_file(mut file: File, state: Arc<State>) -> Result<Payload> {
+/// Like `generate_payload_from_slice`, but documents whose `path` has a
+/// converter are rendered through it, see `State::converters`. The payload
+/// keeps `path` relative to root, with `--navigation` gains links to its
+/// neighbours, with `--i18n` its translations, and with `--git` its history.
+pub fn generate_payload_from_source(
+    path: &SysPath,
+    slice: &[u8],
+    state: Arc<State>,
+) -> Result<Payload> {
+    let mut payload = match state.converter_for(path) {
+        Some(converter) => convert::generate_payload(slice, converter, &state)?,
+        None => generate_payload_from_slice(slice, Arc::clone(&state))?,
+    };
+    payload.path = Some(state.relative_path(path));
+    #[cfg(not(target_arch = "wasm32"))]
+    if state.navigation {
+        payload.navigation = Some(navigation::navigate(path, &state)?);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if state.i18n {
+        payload.translations = i18n::translations(path, &state)?;
+    }
+    #[cfg(all(feature = "git", not(target_arch = "wasm32")))]
+    if state.git {
+        let limit = state.git_history.unwrap_or(git::DEFAULT_HISTORY);
+        // A document renders without its history rather than failing.
+        payload.git = git::metadata(path, limit).unwrap_or_else(|error| {
+            tracing::warn!("{error:#}");
+            None
+        });
+    }
+    Ok(payload)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn generate_payload_from_file(file: File, state: Arc<State>) -> Result<Payload> {
+    generate_payload_from_reader(file, state)
+}
+
+/// Reads the whole of `reader` before parsing, as front matter & link
+/// reference definitions can only be found with the entire document.
+pub fn generate_payload_from_reader(mut reader: impl Read, state: Arc<State>) -> Result<Payload> {
     let mut buf = vec![];
-    file.read_to_end(&mut buf)?;
+    reader.read_to_end(&mut buf)?;
     generate_payload_from_slice(&buf, state)
 }
 
@@ -85,9 +267,10 @@ pub fn generate_payload_from_slice(slice: &[u8], state: Arc<State>) -> Result<Pa
 
     // Attempt to extract front matter placed into `pod`, with remaing content as
     // `Vec<u8>`.
-    let tp = state
-        .front_matter
-        .and_then(|fm| str::from_utf8(slice).ok().and_then(|s| fm.as_pod(s)));
+    let (tp, typed) = match (state.front_matter, str::from_utf8(slice)) {
+        (Some(fm), Ok(s)) => (fm.try_as_pod(s)?, fm.as_typed(s)),
+        _ => (None, None),
+    };
 
     let mut input = slice.to_vec();
     if let Some((p, v)) = tp {
@@ -97,39 +280,185 @@ pub fn generate_payload_from_slice(slice: &[u8], state: Arc<State>) -> Result<Pa
 
     return if let Ok(s) = str::from_utf8(&input[..]) {
         let md_parser = make_commonmark_parser(s, &state);
-        let plugins = make_commonmark_plugins(&state);
-        let new_collection = process_commonmark_tokens(md_parser, plugins);
+        // The parser finds every refdef up front, before any events.
+        let refdefs = if state.refdefs {
+            md_parser
+                .reference_definitions()
+                .iter()
+                .map(|(label, def)| {
+                    let def = LinkDefinition {
+                        uri: def.dest.to_string(),
+                        title: def.title.as_ref().map(ToString::to_string),
+                    };
+                    (label.to_string(), def)
+                })
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+        let plugins = make_commonmark_plugins(&state)?;
+        // Lines of `slice`, counting any front matter removed from `input`.
+        let total = slice.iter().filter(|b| **b == b'\n').count();
+        let mut lines = SourceLines::new(s, total);
+        // Linted as written, before any plugin changes the events.
+        let lint = if state.lint {
+            let first_line = SourceLines::new(s, total).line(0);
+            let events = make_commonmark_parser(s, &state).into_offset_iter();
+            lint::lint(events, s, first_line, &state.lint_rules)
+        } else {
+            vec![]
+        };
+        // Escape raw html from the source before any plugin adds its own.
+        let mut events = md_parser
+            .into_offset_iter()
+            .map(|(event, range)| match event {
+                Event::Html(html) if state.no_inline_html => Event::Text(html),
+                Event::TaskListMarker(checked) if state.interactive_tasklists => {
+                    let line = lines.line(range.start);
+                    let checked = if checked { r#" checked="""# } else { "" };
+                    Event::Html(CowStr::Boxed(
+                        format!("<input type=\"checkbox\" data-line=\"{line}\"{checked}/>\n")
+                            .into(),
+                    ))
+                }
+                event => event,
+            })
+            .collect::<Vec<_>>();
+        if state.smart_punctuation {
+            if let Some(quotes) = state.locale.as_deref().and_then(Quotes::for_locale) {
+                quotes::localize(&mut events, quotes);
+            }
+        }
+        // Tables as written, before any plugin changes their markup.
+        let tables = tables::collect(&events);
+        let mut new_collection = process_commonmark_tokens(events.into_iter(), plugins, &state)?;
+
+        let toc = if state.toc {
+            toc::collect(&new_collection, state.slugger())
+        } else {
+            vec![]
+        };
+        // Anchors replace heading tags with html, so run them after the
+        // toc has seen every heading, keeping their slugs in sync.
+        if state.heading_anchors {
+            new_collection = process_commonmark_tokens(
+                new_collection.into_iter(),
+                Pipeline::from_iter([(
+                    "heading_anchors".to_string(),
+                    Stage::Window(Box::new(HeadingAnchors::new(state.slugger()))),
+                )]),
+                &state,
+            )?;
+        }
 
         let mut html_output = String::new();
         html::push_html(&mut html_output, new_collection.into_iter());
-
-        // TODO consider merging other found refdefs into map, if possible at all.
-        /*for i in md_parser.reference_definitions().iter() {
-            println!("{:?}", i);
-        }*/
+        if state.sanitize {
+            html_output = sanitize::clean(&html_output, state.sanitize_allowlist.as_ref());
+        }
 
         Ok(Payload {
             html: html_output,
-            front_matter: pod.into(),
+            front_matter: with_default_front_matter(
+                &state.default_front_matter,
+                typed
+                    .as_ref()
+                    .and_then(TypedMatter::as_json)
+                    .unwrap_or_else(|| pod.into()),
+            ),
+            typed_front_matter: typed
+                .and_then(|typed| typed.with_defaults(&state.default_front_matter)),
+            toc,
+            refdefs,
+            git: None,
+            navigation: None,
+            lint,
+            translations: vec![],
+            tables,
+            path: None,
         })
     } else {
-        // Utf8Error
-        Err(anyhow!("Content failed to be parsed into utf8."))
+        Err(RenderError::InvalidUtf8.into())
     };
 }
 
-fn make_commonmark_parser<'input>(
+/// Layers `front_matter` over `defaults`, merging nested tables. Documents
+/// without front matter get `defaults` as is.
+pub(crate) fn with_default_front_matter(
+    defaults: &serde_json::Map<String, serde_json::Value>,
+    front_matter: serde_json::Value,
+) -> serde_json::Value {
+    use serde_json::Value;
+
+    fn merge(base: &mut Value, over: Value) {
+        match (base, over) {
+            (Value::Object(base), Value::Object(over)) => {
+                for (key, value) in over {
+                    match base.get_mut(&key) {
+                        Some(base) => merge(base, value),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (base, over) => *base = over,
+        }
+    }
+
+    if defaults.is_empty() {
+        return front_matter;
+    }
+    let mut merged = Value::Object(defaults.clone());
+    if front_matter.is_object() {
+        merge(&mut merged, front_matter);
+    }
+    merged
+}
+
+/// Finds the 1 based line of byte offsets into a document, which must be
+/// asked for in order.
+struct SourceLines<'input> {
+    text: &'input str,
+    offset: usize,
+    line: usize,
+}
+
+impl<'input> SourceLines<'input> {
+    /// `total` is the line count of the whole file, which can be more than
+    /// `text` when front matter was removed from its start.
+    fn new(text: &'input str, total: usize) -> SourceLines<'input> {
+        let skipped = total.saturating_sub(text.matches('\n').count());
+        SourceLines {
+            text,
+            offset: 0,
+            line: skipped + 1,
+        }
+    }
+
+    fn line(&mut self, offset: usize) -> usize {
+        if let Some(text) = self.text.get(self.offset..offset) {
+            self.line += text.matches('\n').count();
+            self.offset = offset;
+        }
+        self.line
+    }
+}
+
+/// A parser over `text`, with the markdown extensions enabled by `state`.
+/// Front matter must already be removed from `text`.
+pub fn make_commonmark_parser<'input>(
     text: &'input str,
-    state: &'input Arc<State>,
+    state: &State,
 ) -> CmParser<'input, 'input> {
     let mut md_opt = Options::empty();
-    if state.tables {
+    if state.tables || state.gfm {
         md_opt.insert(Options::ENABLE_TABLES);
     }
     if state.footnotes {
         md_opt.insert(Options::ENABLE_FOOTNOTES);
     }
-    if state.strikethrough {
+    if state.strikethrough || state.gfm {
         md_opt.insert(Options::ENABLE_STRIKETHROUGH);
     }
     if state.smart_punctuation {
@@ -138,155 +467,856 @@ fn make_commonmark_parser<'input>(
     if state.header_attributes {
         md_opt.insert(Options::ENABLE_HEADING_ATTRIBUTES);
     }
-    if state.tasklists {
+    if state.tasklists || state.gfm {
         md_opt.insert(Options::ENABLE_TASKLISTS);
     }
-    #[cfg(debug_assertions)]
-    dbg!(md_opt);
+    debug!(?md_opt);
 
     CmParser::new_ext(text, md_opt)
 }
 
-fn make_commonmark_plugins(state: &Arc<State>) -> Vec<Box<dyn Plugin>> {
-    let mut plugins: Vec<Box<dyn Plugin>> = vec![];
-    if state.emoji_shortcodes {
-        plugins.push(Box::new(Emoji));
+/// Every plugin enabled by `state`, either listed in `plugins` or by their
+/// individual options, in the order they run.
+///
+/// # Errors
+///
+/// Will return `Err` for an unknown plugin, or a plugin with invalid options.
+pub fn make_commonmark_plugins(state: &State) -> Result<Pipeline> {
+    let names = plugin_names(state);
+    debug!(?names, "Plugins.");
+
+    let mut registry = PluginRegistry::default();
+    // Plugins with a `command` or `wasm` path can be listed in `plugins` by
+    // their name.
+    for (name, options) in &state.plugin_options {
+        if options.get("command").is_some() {
+            registry.register(name.clone(), exec::ExecPlugin::construct);
+        }
+        #[cfg(feature = "wasm")]
+        if options.get("wasm").is_some() {
+            registry.register(name.clone(), wasm::WasmPlugin::construct);
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|name| {
+            let stage = registry.construct(name, &plugin_config(name, state))?;
+            Ok((name.to_string(), stage))
+        })
+        .collect()
+}
+
+/// The name & config of every plugin `make_commonmark_plugins` runs for
+/// `state`, in order, without constructing any of them.
+pub fn plugin_configs(state: &State) -> Vec<(String, serde_json::Value)> {
+    plugin_names(state)
+        .into_iter()
+        .map(|name| (name.to_string(), plugin_config(name, state)))
+        .collect()
+}
+
+/// The plugins listed in `plugins`, or otherwise those enabled by their
+/// individual options.
+fn plugin_names(state: &State) -> Vec<&str> {
+    match &state.plugins {
+        Some(names) => names.iter().map(String::as_str).collect(),
+        None => enabled_plugins(state),
+    }
+}
+
+/// The plugins enabled by their individual options, in their default order.
+fn enabled_plugins(state: &State) -> Vec<&'static str> {
+    let mut names = vec![];
+    // Runs first, so every other plugin sees the final heading levels.
+    if state.shift_headings.is_some_and(|by| by > 0) {
+        names.push("shift_headings");
+    }
+    if state.gfm {
+        names.extend(["alerts", "autolinks"]);
+    }
+    if state.emoji_shortcodes || state.emoji_map.is_some() {
+        names.push("emoji");
+    }
+    if state.typography {
+        names.push("typography");
+    }
+    if !state.collapsible_headers.is_empty() {
+        names.push("collapsible_headers");
+    }
+    if state.rewrite_links.is_some() {
+        names.push("rewrite_links");
     }
-    if let Some(options) = &state.collapsible_headers {
-        plugins.push(Box::new(CollapsibleHeaders::new(
-            options.0,
-            options.1.clone(),
-        )));
+    if state.external_links {
+        names.push("external_links");
+    }
+    #[cfg(feature = "server")]
+    if state.image_cache.is_some() {
+        names.push("image_cache");
+    }
+    if state.figures {
+        names.push("figures");
+    }
+    if state.table_class.is_some() || state.sortable_tables {
+        names.push("table_classes");
+    }
+    if state.footnotes
+        && (state.footnote_heading.is_some()
+            || state.footnote_backlink.is_some()
+            || state.footnote_placement.is_some())
+    {
+        names.push("footnotes");
+    }
+    if state.abbreviations {
+        names.push("abbreviations");
+    }
+    // Runs before mermaid & highlighting, which replace whole code blocks.
+    if state.code_titles || state.code_copy {
+        names.push("code_blocks");
+    }
+    // Runs before highlighting, which would otherwise claim the code blocks.
+    if state.mermaid {
+        names.push("mermaid");
+    }
+    if state.highlight {
+        names.push("highlight");
     }
 
-    plugins
+    names
 }
 
-fn process_commonmark_tokens<'input>(
-    parser: CmParser<'input, 'input>,
-    mut plugins: Vec<Box<dyn Plugin>>,
-) -> Vec<Event<'input>> {
-    let mut collection_vec: Vec<_> = (0..).zip(parser).collect();
-    let mut collection_slice = collection_vec.as_slice();
-    let mut new_collection: Vec<Event> = vec![];
-    let len = plugins.len();
+/// The config passed to a plugins constructor, built from its individual
+/// options with its `plugin_options` table layered over the top.
+fn plugin_config(name: &str, state: &State) -> serde_json::Value {
+    use serde_json::{json, Value};
 
-    if plugins.is_empty() {
-        new_collection = collection_slice.iter().map(|c| c.1.clone()).collect();
-    } else {
-        for (index, plugin) in plugins.iter_mut().enumerate() {
-            if index != 0 && index < len {
-                collection_vec = (0..).zip(new_collection).collect();
-                collection_slice = collection_vec.as_slice();
+    let mut config = match name {
+        "collapsible_headers" => Some(json!({
+            "headers": state
+                .collapsible_headers
+                .iter()
+                .map(|(level, text)| json!({ "level": level, "text": text }))
+                .collect::<Vec<_>>(),
+            "open": !state.collapsible_closed,
+        })),
+        "shift_headings" => state.shift_headings.map(|by| json!({ "by": by })),
+        "emoji" => state.emoji_map.as_ref().map(|map| json!({ "map": map })),
+        "rewrite_links" => state.rewrite_links.as_ref().map(
+            |extension| json!({ "extension": extension, "sources": state.markdown_extensions() }),
+        ),
+        "external_links" => {
+            let mut config = json!({});
+            if let Some(rel) = &state.external_link_rel {
+                config["rel"] = json!(rel);
+            }
+            if let Some(target) = &state.external_link_target {
+                config["target"] = json!(target);
+            }
+            Some(config)
+        }
+        #[cfg(feature = "server")]
+        "image_cache" => state.image_cache.as_ref().map(|dir| json!({ "dir": dir })),
+        "figures" => Some(json!({ "lazy": state.figure_lazy, "sizes": state.figure_sizes })),
+        "table_classes" => Some(json!({
+            "class": state.table_class,
+            "sortable": state.sortable_tables,
+        })),
+        "footnotes" => {
+            let mut config = json!({ "heading": state.footnote_heading });
+            if let Some(backlink) = &state.footnote_backlink {
+                config["backlink"] = json!(backlink);
             }
+            if let Some(placement) = &state.footnote_placement {
+                config["placement"] = json!(placement);
+            }
+            Some(config)
+        }
+        "code_blocks" => Some(json!({ "copy": state.code_copy })),
+        "highlight" => Some(json!({ "theme": state.highlight_theme })),
+        _ => None,
+    }
+    .unwrap_or(Value::Null);
 
-            new_collection = if let Some(ranges) = check_collection_with(plugin, collection_slice) {
-                rewrite_collection_with(plugin, collection_slice, &ranges)
-            } else {
-                collection_slice.iter().map(|c| c.1.clone()).collect()
+    match (&mut config, state.plugin_options.get(name)) {
+        (Value::Object(config), Some(Value::Object(options))) => {
+            config.extend(options.clone());
+        }
+        (_, Some(options)) => config = options.clone(),
+        (_, None) => {}
+    }
+
+    // Wasm plugins are stopped at the render deadline, see `State::deadline`.
+    #[cfg(feature = "wasm")]
+    if let (Some(deadline), Value::Object(config)) = (state.deadline, &mut config) {
+        if config.contains_key("wasm") {
+            let timeout = config
+                .get("timeout_ms")
+                .and_then(Value::as_u64)
+                .unwrap_or(wasm::DEFAULT_TIMEOUT_MS);
+            let left = deadline
+                .saturating_duration_since(std::time::Instant::now())
+                .as_millis();
+            let left = u64::try_from(left).unwrap_or(u64::MAX);
+            config.insert("timeout_ms".to_string(), json!(timeout.min(left)));
+        }
+    }
+
+    config
+}
+
+/// Runs `events` through every stage of `pipeline` in order. Stream stages
+/// are chained lazily, only window stages collect the events, which are then
+/// moved rather than cloned into the next stage.
+///
+/// Only plugins run, `generate_payload_from_slice` also escapes inline html,
+/// numbers task list checkboxes & adds heading anchors as `state` sets.
+///
+/// # Errors
+///
+/// Will return a `PluginError` for the first plugin to fail, or
+/// `RenderError::TimedOut` once past `State::deadline`.
+pub fn process_commonmark_tokens<'input>(
+    events: impl Iterator<Item = Event<'input>> + 'input,
+    pipeline: Pipeline,
+    state: &State,
+) -> Result<Vec<Event<'input>>> {
+    let mut events: Box<dyn Iterator<Item = Result<Event<'input>>> + 'input> =
+        Box::new(events.map(Ok));
+    // Consecutive window plugins share one indexed collection, only collected
+    // again after a stream plugin.
+    let mut collection: Option<Vec<(usize, Event<'input>)>> = None;
+
+    for (name, stage) in pipeline.into_stages() {
+        match stage {
+            Stage::Stream(mut plugin) => {
+                if let Some(collection) = collection.take() {
+                    events = Box::new(collection.into_iter().map(|(_, event)| Ok(event)));
+                }
+                events = Box::new(events.map(move |event| {
+                    event.and_then(|event| {
+                        plugin
+                            .map(event)
+                            .map_err(|source| plugin_error(&name, source))
+                    })
+                }));
+            }
+            Stage::Window(mut plugin) => {
+                let mut indexed = match collection.take() {
+                    Some(indexed) => indexed,
+                    None => events
+                        .by_ref()
+                        .enumerate()
+                        .map(|(index, event)| event.map(|event| (index, event)))
+                        .collect::<Result<Vec<_>>>()?,
+                };
+                // Gives up between passes, as a plugin can't be interrupted.
+                state.check_deadline()?;
+                check_collection_with(plugin.as_mut(), &indexed)
+                    .and_then(|ranges| match ranges {
+                        Some(ranges) => {
+                            splice_collection_with(plugin.as_ref(), &mut indexed, &ranges)
+                        }
+                        None => Ok(()),
+                    })
+                    .map_err(|source| plugin_error(&name, source))?;
+                collection = Some(indexed);
             }
         }
     }
 
-    debug_assert!(!new_collection.is_empty());
-    new_collection
+    let new_collection = match collection {
+        Some(collection) => collection.into_iter().map(|(_, event)| event).collect(),
+        None => events.collect::<Result<Vec<_>>>()?,
+    };
+    state.check_deadline()?;
+    Ok(new_collection)
+}
+
+fn plugin_error(name: &str, source: anyhow::Error) -> anyhow::Error {
+    PluginError {
+        plugin: name.to_string(),
+        source,
+    }
+    .into()
 }
 
 fn check_collection_with(
-    plugin: &mut Box<dyn Plugin>,
+    plugin: &mut dyn Plugin,
     collection: &[(usize, Event)],
-) -> Option<Vec<Range<usize>>> {
+) -> Result<Option<Vec<Range<usize>>>> {
     let mut ranges = Vec::new();
     for slice in collection.windows(plugin.window_size()) {
-        if let Some(range) = plugin.check_slice(slice) {
+        if let Some(range) = plugin.check_slice(slice)? {
             ranges.push(range);
         }
     }
 
     // TODO maybe reuse `check_slice` but with a single item.
     // `final_check` has more meaning than a single item being passed in.
-    if let Some(range) = collection
-        .last()
-        .and_then(|item| plugin.final_check(item.0))
-    {
-        #[cfg(debug_assertions)]
-        dbg!(&range);
-        ranges.push(range);
+    if let Some(item) = collection.last() {
+        if let Some(range) = plugin.final_check(item.0)? {
+            trace!(?range, "Final check matched.");
+            ranges.push(range);
+        }
     }
 
     if ranges.is_empty() {
-        None
+        Ok(None)
     } else {
-        Some(ranges)
+        Ok(Some(ranges))
     }
 }
 
-#[allow(clippy::indexing_slicing)]
-fn rewrite_collection_with<'input>(
-    plugin: &Box<dyn Plugin>,
-    collection: &[(usize, Event<'input>)],
+/// Replaces the events within `ranges` in place, leaving every other event
+/// where it is. Each range is passed to `replace_slice` in order, clamped to
+/// the events not already replaced, then spliced in last first so the earlier
+/// ranges still line up. Events are renumbered for the next plugin.
+fn splice_collection_with<'input>(
+    plugin: &dyn Plugin,
+    collection: &mut Vec<(usize, Event<'input>)>,
     ranges: &[Range<usize>],
-) -> Vec<Event<'input>> {
-    let mut idx: usize = 0;
-    let mut range_idx: usize = 0;
-
+) -> Result<()> {
     debug_assert!(!ranges.is_empty());
-    debug_assert!(ranges.iter().fold(0, |acc, r| acc + r.len()) < collection.len());
-
-    let mut plugin_collection: Vec<Event> =
-        Vec::with_capacity(collection.len() + (ranges.len() * plugin.window_size()));
-
-    while idx < collection.len() {
-        let pair = &collection[idx];
-        if let Some(range) = ranges.get(range_idx) {
-            if !range.contains(&pair.0) {
-                plugin_collection.push(pair.1.clone());
-                idx += 1;
-                continue;
-            }
-
-            plugin_collection.extend_from_slice(&plugin.replace_slice(&collection[range.clone()]));
+    debug_assert!(ranges.iter().fold(0, |acc, r| acc + r.len()) <= collection.len());
 
-            idx += range.len();
-            range_idx += 1;
-        } else {
-            #[cfg(debug_assertions)]
-            dbg!(&pair);
-            plugin_collection.push(pair.1.clone());
-            idx += 1;
+    let mut replacements = Vec::with_capacity(ranges.len());
+    let mut replaced = 0;
+    for range in ranges {
+        // A range reaching past the last event, or overlapping the last range.
+        let range = range.start.max(replaced)..range.end.min(collection.len());
+        if range.is_empty() {
+            continue;
         }
+        replaced = range.end;
+        let events = plugin.replace_slice(&collection[range.clone()])?;
+        replacements.push((range, events));
     }
 
-    plugin_collection
+    for (range, events) in replacements.into_iter().rev() {
+        trace!(?range, "Splicing plugin events.");
+        collection.splice(range, events.into_iter().map(|event| (0, event)));
+    }
+    for (index, pair) in collection.iter_mut().enumerate() {
+        pair.0 = index;
+    }
+    Ok(())
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Payload {
     pub front_matter: serde_json::Value,
+    /// The front matter as parsed by its own format, which toml & yaml output
+    /// use in place of `front_matter` when it's in the same format.
+    #[serde(skip)]
+    pub typed_front_matter: Option<TypedMatter>,
     pub html: String,
+    /// Every heading in the document, when `--toc` is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub toc: Vec<TocEntry>,
+    /// Every link reference definition in the document by its label, when
+    /// `--refdefs` is enabled.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub refdefs: BTreeMap<String, LinkDefinition>,
+    /// The document's git history, when `--git` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git: Option<GitMetadata>,
+    /// Links to the document's neighbours, when `--navigation` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub navigation: Option<Navigation>,
+    /// Problems found in the document, when `--lint` is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lint: Vec<LintWarning>,
+    /// Every language the document is available in, including its own, when
+    /// `--i18n` is enabled & it has a translation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub translations: Vec<Translation>,
+    /// Every table in the document, only written as the `tables` part.
+    #[serde(skip)]
+    pub tables: Vec<Table>,
+    /// The path of the document relative to root, when rendered from a file.
+    #[serde(skip)]
+    pub path: Option<String>,
 }
 
+/// The destination & optional title of a link reference definition.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LinkDefinition {
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// A `Payload` with its typed front matter, for output in the front matter's
+/// own format.
+#[derive(Serialize)]
+struct TypedPayload<'a, M> {
+    front_matter: &'a M,
+    html: &'a str,
+    #[serde(skip_serializing_if = "<[TocEntry]>::is_empty")]
+    toc: &'a [TocEntry],
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    refdefs: &'a BTreeMap<String, LinkDefinition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git: Option<&'a GitMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    navigation: Option<&'a Navigation>,
+    #[serde(skip_serializing_if = "<[LintWarning]>::is_empty")]
+    lint: &'a [LintWarning],
+    #[serde(skip_serializing_if = "<[Translation]>::is_empty")]
+    translations: &'a [Translation],
+}
+
+/// How a `Payload` is written as postcard. postcard isn't self describing, so
+/// the front matter is written as a json string, and no field is ever skipped.
+#[derive(Serialize, Deserialize)]
+struct PostcardPayload<'a> {
+    front_matter: Cow<'a, str>,
+    html: Cow<'a, str>,
+    toc: Vec<(u8, Cow<'a, str>, Cow<'a, str>)>,
+    refdefs: Vec<PostcardDefinition<'a>>,
+    git: Option<Cow<'a, GitMetadata>>,
+    navigation: Option<Cow<'a, Navigation>>,
+    lint: Cow<'a, [LintWarning]>,
+    translations: Cow<'a, [Translation]>,
+}
+
+/// A link reference definition's label, uri & title.
+type PostcardDefinition<'a> = (Cow<'a, str>, Cow<'a, str>, Option<Cow<'a, str>>);
+
 impl Payload {
+    /// Reads back a payload written by the `postcard` format.
+    pub fn from_postcard(bytes: &[u8]) -> Result<Payload> {
+        let wire: PostcardPayload = postcard::from_bytes(bytes)?;
+        Ok(Payload {
+            front_matter: serde_json::from_str(&wire.front_matter)?,
+            typed_front_matter: None,
+            html: wire.html.into_owned(),
+            toc: wire
+                .toc
+                .into_iter()
+                .map(|(level, text, slug)| TocEntry {
+                    level,
+                    text: text.into_owned(),
+                    slug: slug.into_owned(),
+                })
+                .collect(),
+            refdefs: wire
+                .refdefs
+                .into_iter()
+                .map(|(label, uri, title)| {
+                    let definition = LinkDefinition {
+                        uri: uri.into_owned(),
+                        title: title.map(Cow::into_owned),
+                    };
+                    (label.into_owned(), definition)
+                })
+                .collect(),
+            git: wire.git.map(Cow::into_owned),
+            navigation: wire.navigation.map(Cow::into_owned),
+            lint: wire.lint.into_owned(),
+            translations: wire.translations.into_owned(),
+            tables: vec![],
+            path: None,
+        })
+    }
+
+    fn to_postcard(&self) -> Result<PostcardPayload<'_>> {
+        Ok(PostcardPayload {
+            front_matter: Cow::Owned(serde_json::to_string(&self.front_matter)?),
+            html: Cow::Borrowed(&self.html),
+            toc: self
+                .toc
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.level,
+                        Cow::Borrowed(&*entry.text),
+                        Cow::Borrowed(&*entry.slug),
+                    )
+                })
+                .collect(),
+            refdefs: self
+                .refdefs
+                .iter()
+                .map(|(label, definition)| {
+                    (
+                        Cow::Borrowed(&**label),
+                        Cow::Borrowed(&*definition.uri),
+                        definition.title.as_deref().map(Cow::Borrowed),
+                    )
+                })
+                .collect(),
+            git: self.git.as_ref().map(Cow::Borrowed),
+            navigation: self.navigation.as_ref().map(Cow::Borrowed),
+            lint: Cow::Borrowed(&self.lint),
+            translations: Cow::Borrowed(&self.translations),
+        })
+    }
+
+    /// The `title` front matter value, if it exists.
+    pub fn title(&self) -> Option<&str> {
+        self.front_matter
+            .get("title")
+            .and_then(serde_json::Value::as_str)
+    }
+
+    /// Whether the `noindex` front matter value is `true`, keeping the
+    /// document out of search engines & feeds.
+    pub fn noindex(&self) -> bool {
+        self.front_matter
+            .get("noindex")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+    }
+
+    /// Like `into_response_for`, but html is wrapped in the page template
+    /// configured by `state`.
+    pub fn into_response_with_state(
+        self,
+        extension: &PayloadFormats,
+        state: &State,
+    ) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        self.write_response_with_state(&mut buf, extension, state)?;
+        Ok(buf)
+    }
+
+    /// Serializes the payload with `serializer`, for formats that aren't
+    /// built in, see `State::serializers`.
+    pub fn into_response_with(self, serializer: &dyn PayloadSerializer) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        serializer.write(&self, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Like `into_response_for`, but only `part` of the payload, see
+    /// `write_part_for`.
+    pub fn into_part_for(self, part: Part, extension: &PayloadFormats) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        self.write_part_for(&mut buf, part, extension)?;
+        Ok(buf)
+    }
+
     pub fn into_response_for(self, extension: &PayloadFormats) -> Result<Vec<u8>> {
-        match extension {
-            PayloadFormats::Html => Ok(self.html.into()),
-            PayloadFormats::Json => {
-                let s = serde_json::to_string_pretty(&self)?;
-                Ok(s.into())
+        let mut buf = vec![];
+        self.write_response_for(&mut buf, extension)?;
+        Ok(buf)
+    }
+
+    /// Like `write_response_for`, but html is wrapped in the page template
+    /// configured by `state`.
+    pub fn write_response_with_state(
+        &self,
+        writer: &mut impl Write,
+        extension: &PayloadFormats,
+        state: &State,
+    ) -> Result<()> {
+        if extension == &PayloadFormats::Html {
+            writer.write_all(template::render(self, state)?.as_bytes())?;
+            return Ok(());
+        }
+        self.write_response_for(writer, extension)
+    }
+
+    /// Serializes the payload straight into `writer`, without building the
+    /// whole response in memory first where the format allows it.
+    pub fn write_response_for(
+        &self,
+        writer: &mut impl Write,
+        extension: &PayloadFormats,
+    ) -> Result<()> {
+        self.write_format(writer, extension)
+            .map_err(|source| serialize_error(extension, source))
+    }
+
+    /// Serializes only `part` of the payload into `writer`. The front matter
+    /// can be written in any format but html, and the body, the document's
+    /// html without the page template, as html, json, yaml, pickle, cbor,
+    /// postcard or xml.
+    ///
+    /// # Errors
+    ///
+    /// Will return `RenderError::UnsupportedPart` for a format the part has no
+    /// form in.
+    pub fn write_part_for(
+        &self,
+        writer: &mut impl Write,
+        part: Part,
+        extension: &PayloadFormats,
+    ) -> Result<()> {
+        self.write_part(writer, part, extension)
+            .map_err(|source| serialize_error(extension, source))
+    }
+
+    fn typed<'a, M>(&'a self, front_matter: &'a M) -> TypedPayload<'a, M> {
+        TypedPayload {
+            front_matter,
+            html: &self.html,
+            toc: &self.toc,
+            refdefs: &self.refdefs,
+            git: self.git.as_ref(),
+            navigation: self.navigation.as_ref(),
+            lint: &self.lint,
+            translations: &self.translations,
+        }
+    }
+
+    fn write_part(
+        &self,
+        writer: &mut impl Write,
+        part: Part,
+        extension: &PayloadFormats,
+    ) -> Result<()> {
+        match (part, extension) {
+            (_, PayloadFormats::Markdown)
+            | (Part::Matter | Part::Tables | Part::Table(_), PayloadFormats::Html)
+            | (Part::Body, PayloadFormats::Toml | PayloadFormats::Csv)
+            | (Part::Tables, PayloadFormats::Toml) => {
+                return Err(RenderError::UnsupportedPart {
+                    part,
+                    format: extension.to_string(),
+                }
+                .into());
+            }
+            (Part::Body, PayloadFormats::Html) => writer.write_all(self.html.as_bytes())?,
+            (Part::Body, _) => write_value(writer, "html", &self.html, extension)?,
+            (Part::Tables, PayloadFormats::Csv) => write_csv_tables(writer, &self.tables)?,
+            (Part::Tables, _) => write_value(writer, "tables", &self.tables, extension)?,
+            (Part::Table(number), _) => {
+                let table = number
+                    .checked_sub(1)
+                    .and_then(|index| self.tables.get(index))
+                    .ok_or(RenderError::NoSuchTable {
+                        number,
+                        count: self.tables.len(),
+                    })?;
+                match extension {
+                    PayloadFormats::Csv => write_csv_tables(writer, core::slice::from_ref(table))?,
+                    _ => write_value(writer, "table", table, extension)?,
+                }
+            }
+            (Part::Matter, PayloadFormats::Csv) => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer.write_record(["key", "value"])?;
+                for row in flatten_front_matter(&self.front_matter) {
+                    writer.write_record([row.0, row.1])?;
+                }
+                writer.flush()?;
+            }
+            (Part::Matter, PayloadFormats::Yaml | PayloadFormats::Toml) => {
+                match (&self.typed_front_matter, extension) {
+                    (Some(TypedMatter::Yaml(matter)), PayloadFormats::Yaml) => {
+                        serde_yaml::to_writer(writer, matter)?;
+                    }
+                    (Some(TypedMatter::Toml(matter)), PayloadFormats::Toml) => {
+                        writer.write_all(toml::to_string_pretty(matter)?.as_bytes())?;
+                    }
+                    // Documents without front matter are an empty toml table.
+                    (_, PayloadFormats::Toml) if !self.front_matter.is_object() => {}
+                    _ => write_value(writer, "front_matter", &self.front_matter, extension)?,
+                }
+            }
+            // As with whole payloads, postcard front matter is a json string.
+            (Part::Matter, PayloadFormats::Postcard) => {
+                postcard::to_io(&serde_json::to_string(&self.front_matter)?, writer)?;
+            }
+            (Part::Matter, PayloadFormats::Xml) => {
+                let front_matter = xml_names(&self.front_matter);
+                write_value(writer, "front_matter", &front_matter, extension)?;
             }
-            PayloadFormats::Yaml => {
-                let yaml = serde_yaml::to_string(&self)?;
-                Ok(yaml.into())
+            (Part::Matter, _) => {
+                write_value(writer, "front_matter", &self.front_matter, extension)?
             }
+        }
+        Ok(())
+    }
+
+    fn write_format(&self, writer: &mut impl Write, extension: &PayloadFormats) -> Result<()> {
+        match extension {
+            PayloadFormats::Html => writer.write_all(self.html.as_bytes())?,
+            PayloadFormats::Json => serde_json::to_writer_pretty(writer, self)?,
+            PayloadFormats::Yaml => match &self.typed_front_matter {
+                Some(TypedMatter::Yaml(matter)) => {
+                    serde_yaml::to_writer(writer, &self.typed(matter))?
+                }
+                _ => serde_yaml::to_writer(writer, self)?,
+            },
             PayloadFormats::Toml => {
-                let toml = toml::to_string_pretty(&self)?;
-                Ok(toml.into())
+                let toml = match &self.typed_front_matter {
+                    Some(TypedMatter::Toml(matter)) => toml::to_string_pretty(&self.typed(matter))?,
+                    _ => toml::to_string_pretty(self)?,
+                };
+                writer.write_all(toml.as_bytes())?;
+            }
+            PayloadFormats::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer.write_record(["key", "value"])?;
+                for row in flatten_front_matter(&self.front_matter) {
+                    writer.write_record([row.0, row.1])?;
+                }
+                writer.flush()?;
+            }
+            PayloadFormats::Pickle => serde_pickle::to_writer(writer, self, SerOptions::default())?,
+            PayloadFormats::Cbor => ciborium::into_writer(self, writer)?,
+            PayloadFormats::Postcard => {
+                postcard::to_io(&self.to_postcard()?, writer)?;
             }
-            PayloadFormats::Pickle => {
-                let pickle = serde_pickle::to_vec(&self, SerOptions::default())?;
-                Ok(pickle)
+            PayloadFormats::Xml => {
+                // Front matter keys & refdef labels are written as element
+                // names, so can't be used as they are.
+                let front_matter = xml_names(&self.front_matter);
+                let refdefs: BTreeMap<_, _> = self
+                    .refdefs
+                    .iter()
+                    .map(|(label, def)| (xml_name(label), def.clone()))
+                    .collect();
+                let payload = TypedPayload {
+                    refdefs: &refdefs,
+                    ..self.typed(&front_matter)
+                };
+                let xml = quick_xml::se::to_string_with_root("Payload", &payload)?;
+                writer.write_all(xml.as_bytes())?;
+            }
+            PayloadFormats::Markdown => {
+                return Err(RenderError::UnsupportedFormat(extension.to_string()).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wraps a failure writing `extension` in `RenderError::Serialize`, unless
+/// it's already a `RenderError`.
+fn serialize_error(extension: &PayloadFormats, source: anyhow::Error) -> anyhow::Error {
+    if source.is::<RenderError>() {
+        return source;
+    }
+    RenderError::Serialize {
+        format: extension.to_string(),
+        source,
+    }
+    .into()
+}
+
+/// Writes a single part of a payload, with xml wrapping it in a `root`
+/// element. Html, csv & markdown are left to `Payload::write_part`.
+fn write_value<T>(
+    writer: &mut impl Write,
+    root: &str,
+    value: &T,
+    extension: &PayloadFormats,
+) -> Result<()>
+where
+    T: serde::Serialize + ?Sized,
+{
+    match extension {
+        PayloadFormats::Json => serde_json::to_writer_pretty(writer, value)?,
+        PayloadFormats::Yaml => serde_yaml::to_writer(writer, value)?,
+        PayloadFormats::Toml => writer.write_all(toml::to_string_pretty(value)?.as_bytes())?,
+        PayloadFormats::Pickle => serde_pickle::to_writer(writer, value, SerOptions::default())?,
+        PayloadFormats::Cbor => ciborium::into_writer(value, writer)?,
+        PayloadFormats::Postcard => {
+            postcard::to_io(value, writer)?;
+        }
+        PayloadFormats::Xml => {
+            let xml = quick_xml::se::to_string_with_root(root, value)?;
+            writer.write_all(xml.as_bytes())?;
+        }
+        PayloadFormats::Html | PayloadFormats::Csv | PayloadFormats::Markdown => {
+            return Err(RenderError::UnsupportedFormat(extension.to_string()).into());
+        }
+    }
+    Ok(())
+}
+
+/// `value` with every object key made an xml element name, see `xml_name`.
+fn xml_names(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(members) => serde_json::Value::Object(
+            members
+                .iter()
+                .map(|(key, value)| (xml_name(key), xml_names(value)))
+                .collect(),
+        ),
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.iter().map(xml_names).collect())
+        }
+        _ => value.clone(),
+    }
+}
+
+/// `key` as an xml element name, with each character a name can't hold
+/// replaced by `_`, & a `_` in front of names starting with anything but a
+/// letter, e.g. `my key` as `my_key` & `1st` as `_1st`.
+fn xml_name(key: &str) -> String {
+    let mut name: String = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' | '_' => c,
+            // Latin-1 letters below `À` can't be in a name.
+            _ if c >= '\u{C0}' && c.is_alphanumeric() => c,
+            _ => '_',
+        })
+        .collect();
+    if !name.starts_with(|c: char| c == '_' || c.is_alphabetic()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// Writes each table as its header row then its rows, separating tables with
+/// an empty line.
+fn write_csv_tables(writer: &mut impl Write, tables: &[Table]) -> Result<()> {
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+    for (index, table) in tables.iter().enumerate() {
+        if index > 0 {
+            writer.flush()?;
+            writer.get_mut().write_all(b"\n")?;
+        }
+        writer.write_record(&table.header)?;
+        for row in &table.rows {
+            writer.write_record(row)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Flattens nested front matter into `(key, value)` rows, joining nested
+/// object keys and array indexes with a `.`, e.g. `name.0.uri`.
+fn flatten_front_matter(value: &serde_json::Value) -> Vec<(String, String)> {
+    let mut rows = vec![];
+    flatten_value(String::new(), value, &mut rows);
+    rows
+}
+
+fn flatten_value(key: String, value: &serde_json::Value, rows: &mut Vec<(String, String)>) {
+    use serde_json::Value;
+
+    let join = |k: &str| {
+        if key.is_empty() {
+            k.to_string()
+        } else {
+            format!("{key}.{k}")
+        }
+    };
+
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                flatten_value(join(k), v, rows);
+            }
+        }
+        Value::Array(vec) => {
+            for (i, v) in vec.iter().enumerate() {
+                flatten_value(join(&i.to_string()), v, rows);
             }
-            _ => Err(anyhow!("Not valid.")),
         }
+        // Documents without front matter have an empty top level value.
+        Value::Null if key.is_empty() => {}
+        Value::String(s) if key.is_empty() && s.is_empty() => {}
+        Value::Null => rows.push((key, String::new())),
+        Value::String(s) => rows.push((key, s.clone())),
+        x => rows.push((key, x.to_string())),
     }
 }
@@ -0,0 +1,224 @@
+use crate::{
+    collection::{find_markdown, within_max_file_size},
+    generate_payload_from_path,
+    state::State,
+};
+use anyhow::{Context, Result};
+use serde_derive::Serialize;
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+use tracing::trace;
+
+/// Paths the server answers without a file behind them.
+const GENERATED: [&str; 6] = [
+    "/feed.rss",
+    "/feed.atom",
+    "/feed.json",
+    "/bundle.ndjson",
+    "/search",
+    "/robots.txt",
+];
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// The path relative to root of the document linking to `target`.
+    pub document: String,
+    pub target: String,
+    /// Why `target` is broken, e.g. `Not found.` or an external url's status.
+    pub reason: String,
+}
+
+/// The links of every document under a root, for catching broken links in
+/// CI before readers do.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct LinkReport {
+    /// How many documents were checked, leaving out hidden ones.
+    pub documents: usize,
+    /// How many links they have, internal & external.
+    pub links: usize,
+    pub broken: Vec<BrokenLink>,
+    /// Every external url with the documents linking to it, which are left
+    /// to the caller to request.
+    #[serde(skip)]
+    pub external: BTreeMap<String, Vec<String>>,
+}
+
+impl LinkReport {
+    /// Whether every checked link works.
+    pub fn is_ok(&self) -> bool {
+        self.broken.is_empty()
+    }
+}
+
+/// Renders every document under `root`, checking that the target of each
+/// `href` & `src` within it exists. Absolute paths are relative to `root`,
+/// and a rendered path, e.g. `guide.html`, exists when its source does.
+///
+/// # Errors
+///
+/// Will return `Err` if a directory under `root` can't be read or a
+/// document fails to render.
+pub fn check(root: &Path, state: Arc<State>) -> Result<LinkReport> {
+    let extensions = state.source_extensions();
+    let mut files = vec![];
+    find_markdown(root, &extensions, &state, &mut files)?;
+    files.retain(|file| within_max_file_size(file, &state));
+    files.sort();
+
+    let mut report = LinkReport::default();
+    for file in files {
+        let payload = generate_payload_from_path(&file, Arc::clone(&state))
+            .with_context(|| format!("Failed to render {}.", file.display()))?;
+        if state.is_hidden(&payload.front_matter) {
+            continue;
+        }
+        let document = file
+            .strip_prefix(root)
+            .unwrap_or(&file)
+            .to_string_lossy()
+            .replace('\\', "/");
+        report.documents += 1;
+
+        for link in links(&payload.html) {
+            report.links += 1;
+            match classify(&link) {
+                Kind::External => report
+                    .external
+                    .entry(link.clone())
+                    .or_default()
+                    .push(document.clone()),
+                Kind::Internal(path) => {
+                    let path = percent_decode(path);
+                    if GENERATED.contains(&path.as_str()) {
+                        continue;
+                    }
+                    let target = match path.strip_prefix('/') {
+                        Some(absolute) => root.join(absolute),
+                        None => file.parent().unwrap_or(root).join(&path),
+                    };
+                    let exists = target.exists()
+                        || extensions
+                            .iter()
+                            .any(|extension| target.with_extension(extension).is_file());
+                    trace!(document, link, exists, "Checked link.");
+                    if !exists {
+                        report.broken.push(BrokenLink {
+                            document: document.clone(),
+                            target: link.clone(),
+                            reason: String::from("Not found."),
+                        });
+                    }
+                }
+                Kind::Skipped => {}
+            }
+        }
+    }
+    Ok(report)
+}
+
+enum Kind<'a> {
+    /// An `http`, `https` or protocol relative url.
+    External,
+    /// The path of a link within the site, without its query or fragment.
+    Internal(&'a str),
+    /// Same page fragments & other schemes, e.g. `mailto:`, which can't be
+    /// checked.
+    Skipped,
+}
+
+fn classify(link: &str) -> Kind {
+    let lowercase = link.to_ascii_lowercase();
+    if link.starts_with("//")
+        || lowercase.starts_with("http://")
+        || lowercase.starts_with("https://")
+    {
+        return Kind::External;
+    }
+    let path = link.split(['?', '#']).next().unwrap_or_default();
+    let scheme = path
+        .find(':')
+        .is_some_and(|colon| !path[..colon].contains('/'));
+    if path.is_empty() || scheme {
+        return Kind::Skipped;
+    }
+    Kind::Internal(path)
+}
+
+/// The quoted `href` & `src` attribute values in `html`, unescaping `&amp;`.
+fn links(html: &str) -> Vec<String> {
+    let mut links = vec![];
+    for attribute in [" href=", " src="] {
+        let mut rest = html;
+        while let Some(start) = rest.find(attribute) {
+            rest = &rest[start + attribute.len()..];
+            let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+                continue;
+            };
+            rest = &rest[1..];
+            let Some(end) = rest.find(quote) else {
+                break;
+            };
+            links.push(rest[..end].replace("&amp;", "&"));
+            rest = &rest[end + 1..];
+        }
+    }
+    links
+}
+
+/// Decodes `%20` style escapes, leaving invalid ones as they are.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = match bytes[index] {
+            b'%' => path
+                .get(index + 1..index + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_test_links() {
+        let html = "<p><a href=\"a.html?x=1&amp;y=2\">a</a> <img src='b.png' alt=\"\" /></p>";
+        assert_eq!(links(html), vec!["a.html?x=1&y=2", "b.png"]);
+    }
+
+    #[test]
+    fn links_test_classify() {
+        assert!(matches!(classify("https://example.com"), Kind::External));
+        assert!(matches!(classify("//example.com/a"), Kind::External));
+        assert!(matches!(classify("mailto:me@example.com"), Kind::Skipped));
+        assert!(matches!(classify("#usage"), Kind::Skipped));
+        assert!(matches!(
+            classify("guide.html#usage"),
+            Kind::Internal("guide.html")
+        ));
+        assert!(matches!(
+            classify("/a:b/c.html"),
+            Kind::Internal("/a:b/c.html")
+        ));
+    }
+
+    #[test]
+    fn links_test_percent_decode() {
+        assert_eq!(percent_decode("my%20notes.md"), "my notes.md");
+        assert_eq!(percent_decode("100%.md"), "100%.md");
+    }
+}
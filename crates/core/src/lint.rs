@@ -0,0 +1,258 @@
+use core::{fmt::Debug, ops::Range};
+use pulldown_cmark::{CowStr, Event, Tag};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// A problem found in a document with `--lint`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LintWarning {
+    /// The name of the rule finding it, e.g. `heading-increment`.
+    pub rule: String,
+    /// The 1 based line it was found on, counting any front matter.
+    pub line: usize,
+    pub message: String,
+}
+
+/// A check over the events of a document as parsed, before any plugin runs.
+/// A rule is constructed for each document, so can keep state between
+/// events.
+pub trait LintRule {
+    /// Checks the next event, found at `range` of `source`, returning the
+    /// byte offset & message of each problem.
+    fn check(&mut self, event: &Event, range: Range<usize>, source: &str) -> Vec<(usize, String)>;
+}
+
+type RuleConstructor = Arc<dyn Fn() -> Box<dyn LintRule> + Send + Sync>;
+
+/// Lint rules run along with the built in ones, by name. A rule registered
+/// with the name of a built in rule replaces it.
+#[derive(Clone, Default)]
+pub struct LintRegistry {
+    rules: BTreeMap<String, RuleConstructor>,
+}
+
+impl LintRegistry {
+    /// Adds `name`, replacing any rule already registered for it.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        constructor: impl Fn() -> Box<dyn LintRule> + Send + Sync + 'static,
+    ) {
+        self.rules.insert(name.into(), Arc::new(constructor));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.rules.contains_key(name)
+    }
+
+    /// The built in & registered rules, constructed for a new document.
+    fn construct(&self) -> BTreeMap<String, Box<dyn LintRule>> {
+        let mut rules: BTreeMap<String, Box<dyn LintRule>> = BTreeMap::new();
+        rules.insert("bare-url".into(), Box::<BareUrls>::default());
+        rules.insert("empty-link".into(), Box::<EmptyLinks>::default());
+        rules.insert(
+            "heading-increment".into(),
+            Box::<HeadingIncrement>::default(),
+        );
+        rules.insert("heading-whitespace".into(), Box::new(HeadingWhitespace));
+        for (name, constructor) in &self.rules {
+            rules.insert(name.clone(), constructor());
+        }
+        rules
+    }
+}
+
+impl Debug for LintRegistry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_set().entries(self.rules.keys()).finish()
+    }
+}
+
+/// Runs the built in rules & those in `registry` over the `events` of
+/// `source`, which starts on `first_line` after any front matter. Warnings
+/// are in source order.
+pub fn lint<'a>(
+    events: impl Iterator<Item = (Event<'a>, Range<usize>)>,
+    source: &str,
+    first_line: usize,
+    registry: &LintRegistry,
+) -> Vec<LintWarning> {
+    let mut rules = registry.construct();
+    let mut found = vec![];
+    for (event, range) in events {
+        for (name, rule) in &mut rules {
+            for (offset, message) in rule.check(&event, range.clone(), source) {
+                found.push((offset, name.clone(), message));
+            }
+        }
+    }
+    found.sort_by_key(|(offset, ..)| *offset);
+    found
+        .into_iter()
+        .map(|(offset, rule, message)| LintWarning {
+            rule,
+            line: first_line
+                + source
+                    .get(..offset)
+                    .map_or(0, |text| text.matches('\n').count()),
+            message,
+        })
+        .collect()
+}
+
+/// Headings skipping a level, e.g. an h3 straight after an h1.
+#[derive(Default)]
+struct HeadingIncrement {
+    previous: Option<usize>,
+}
+
+impl LintRule for HeadingIncrement {
+    fn check(&mut self, event: &Event, range: Range<usize>, _: &str) -> Vec<(usize, String)> {
+        let Event::Start(Tag::Heading(level, ..)) = event else {
+            return vec![];
+        };
+        let level = *level as usize;
+        let previous = self.previous.replace(level);
+        match previous {
+            Some(previous) if level > previous + 1 => vec![(
+                range.start,
+                format!("Heading level jumps from h{previous} to h{level}."),
+            )],
+            _ => vec![],
+        }
+    }
+}
+
+/// Links without a destination or without any text.
+#[derive(Default)]
+struct EmptyLinks {
+    /// The offset of each open link, & whether it has any text yet.
+    open: Vec<(usize, bool)>,
+}
+
+impl LintRule for EmptyLinks {
+    fn check(&mut self, event: &Event, range: Range<usize>, _: &str) -> Vec<(usize, String)> {
+        let mut warnings = vec![];
+        match event {
+            Event::Start(Tag::Link(_, destination, _)) => {
+                if destination.trim().is_empty() {
+                    warnings.push((range.start, String::from("Link without a destination.")));
+                }
+                self.open.push((range.start, false));
+            }
+            Event::End(Tag::Link(..)) => {
+                if let Some((offset, false)) = self.open.pop() {
+                    warnings.push((offset, String::from("Link without text.")));
+                }
+            }
+            Event::Text(text) if text.trim().is_empty() => {}
+            _ => {
+                if let Some(open) = self.open.last_mut() {
+                    open.1 = true;
+                }
+            }
+        }
+        warnings
+    }
+}
+
+/// `http` & `https` urls in text, which only some renderers link.
+#[derive(Default)]
+struct BareUrls {
+    /// How deep in code blocks, links & images the next event is.
+    depth: usize,
+}
+
+impl LintRule for BareUrls {
+    fn check(&mut self, event: &Event, range: Range<usize>, _: &str) -> Vec<(usize, String)> {
+        match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..)) => self.depth += 1,
+            Event::End(Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..)) => {
+                self.depth = self.depth.saturating_sub(1);
+            }
+            Event::Text(text) if self.depth == 0 && contains_url(text) => {
+                return vec![(
+                    range.start,
+                    String::from("Bare url, wrap it in `<>` or a link."),
+                )];
+            }
+            _ => {}
+        }
+        vec![]
+    }
+}
+
+fn contains_url(text: &CowStr) -> bool {
+    text.contains("http://") || text.contains("https://")
+}
+
+/// Headings whose line ends in spaces or tabs.
+struct HeadingWhitespace;
+
+impl LintRule for HeadingWhitespace {
+    fn check(&mut self, event: &Event, range: Range<usize>, source: &str) -> Vec<(usize, String)> {
+        let Event::Start(Tag::Heading(..)) = event else {
+            return vec![];
+        };
+        let line = source
+            .get(range.clone())
+            .and_then(|heading| heading.lines().next())
+            .unwrap_or_default();
+        if line.ends_with([' ', '\t']) {
+            return vec![(range.start, String::from("Trailing whitespace in heading."))];
+        }
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::Parser;
+
+    fn warnings(source: &str, registry: &LintRegistry) -> Vec<(String, usize)> {
+        lint(Parser::new(source).into_offset_iter(), source, 1, registry)
+            .into_iter()
+            .map(|warning| (warning.rule, warning.line))
+            .collect()
+    }
+
+    #[test]
+    fn lint_test_built_in_rules() {
+        let source = "# Title\n\n### Skipped \n\nSee https://example.com, [](/a) & [b]().\n\n```\nhttps://example.com\n```\n";
+        assert_eq!(
+            warnings(source, &LintRegistry::default()),
+            vec![
+                (String::from("heading-increment"), 3),
+                (String::from("heading-whitespace"), 3),
+                (String::from("bare-url"), 5),
+                (String::from("empty-link"), 5),
+                (String::from("empty-link"), 5),
+            ]
+        );
+    }
+
+    struct NoTodos;
+
+    impl LintRule for NoTodos {
+        fn check(&mut self, event: &Event, range: Range<usize>, _: &str) -> Vec<(usize, String)> {
+            match event {
+                Event::Text(text) if text.contains("TODO") => {
+                    vec![(range.start, String::from("Unfinished."))]
+                }
+                _ => vec![],
+            }
+        }
+    }
+
+    #[test]
+    fn lint_test_registered_rule() {
+        let mut registry = LintRegistry::default();
+        registry.register("no-todos", || Box::new(NoTodos));
+        assert!(registry.contains("no-todos"));
+        assert_eq!(
+            warnings("# Notes\n\nTODO\n", &registry),
+            vec![(String::from("no-todos"), 3)]
+        );
+    }
+}
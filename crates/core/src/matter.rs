@@ -1,131 +1,388 @@
-use core::ops::Range;
+use chrono::{DateTime, FixedOffset, NaiveDate};
 use gray_matter::Pod;
-use regex::Match;
 use std::{collections::HashMap, str};
+use tracing::trace;
+
+/// Parses either a RFC 3339 datetime, `2023-09-01T10:00:00Z`, or a plain
+/// date, `2023-09-01`, which is treated as midnight UTC.
+pub fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value).ok().or_else(|| {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|datetime| datetime.and_utc().fixed_offset())
+    })
+}
+
+/// Front matter as parsed by its own format, keeping the types json can't
+/// represent, e.g. toml datetimes & yaml tags.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedMatter {
+    Toml(toml::Table),
+    Yaml(serde_yaml::Value),
+}
+
+impl TypedMatter {
+    /// Layers the front matter over `defaults`, as `Payload::front_matter`
+    /// is. `None` if the defaults can't be represented in the format, e.g. a
+    /// null in toml.
+    pub(crate) fn with_defaults(
+        self,
+        defaults: &serde_json::Map<String, serde_json::Value>,
+    ) -> Option<TypedMatter> {
+        if defaults.is_empty() {
+            return Some(self);
+        }
+        match self {
+            TypedMatter::Toml(table) => {
+                let toml::Value::Table(mut base) = toml::Value::try_from(defaults).ok()? else {
+                    return None;
+                };
+                for (key, value) in table {
+                    match base.get_mut(&key) {
+                        Some(base) => merge_toml(base, value),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+                Some(TypedMatter::Toml(base))
+            }
+            TypedMatter::Yaml(value) => {
+                let mut base = serde_yaml::to_value(defaults).ok()?;
+                if value.is_mapping() {
+                    merge_yaml(&mut base, value);
+                }
+                Some(TypedMatter::Yaml(base))
+            }
+        }
+    }
+
+    /// Toml front matter as json, with datetimes as strings, in place of its
+    /// `Pod`, which mangles them. `None` for yaml, whose `Pod` is accurate.
+    pub(crate) fn as_json(&self) -> Option<serde_json::Value> {
+        match self {
+            TypedMatter::Toml(table) => Some(serde_json::Value::Object(
+                table
+                    .iter()
+                    .map(|(key, value)| (key.clone(), toml_to_json(value)))
+                    .collect(),
+            )),
+            TypedMatter::Yaml(_) => None,
+        }
+    }
+}
+
+fn toml_to_json(value: &toml::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        toml::Value::String(value) => Value::from(value.as_str()),
+        toml::Value::Integer(value) => Value::from(*value),
+        toml::Value::Float(value) => Value::from(*value),
+        toml::Value::Boolean(value) => Value::from(*value),
+        toml::Value::Datetime(value) => Value::from(value.to_string()),
+        toml::Value::Array(values) => values.iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.clone(), toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn merge_toml(base: &mut toml::Value, over: toml::Value) {
+    match (base, over) {
+        (toml::Value::Table(base), toml::Value::Table(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(base) => merge_toml(base, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+fn merge_yaml(base: &mut serde_yaml::Value, over: serde_yaml::Value) {
+    match (base, over) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(base) => merge_yaml(base, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// A single link reference definition, `[id]: uri "title"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefDef<'input> {
+    pub id: &'input str,
+    pub uri: &'input str,
+    pub title: Option<&'input str>,
+}
 
 #[derive(Debug, Clone)]
 pub struct RefDefMatter<'input> {
     slice: &'input [u8],
-    range: Option<Range<usize>>,
+    refdefs: Vec<RefDef<'input>>,
 }
 
 impl<'input> RefDefMatter<'input> {
     pub fn new(slice: &'input [u8]) -> RefDefMatter<'input> {
-        RefDefMatter { range: None, slice }
+        RefDefMatter {
+            slice,
+            refdefs: vec![],
+        }
     }
 
+    /// Collects the refdefs preceding the main content, one per line,
+    /// skipping blank lines. A title can sit on the line after its uri.
+    /// Scanning stops at the first line that isn't part of a refdef.
     pub fn scan(&mut self) {
-        // Currently each refdef needs to be contained on a single line.
-        // Unlike valid commonmark, no newlines can exist between any part of a refdef.
-        let mut til_end: bool = false;
-
-        for i in 0..self.slice.len() {
-            match self.slice[i] {
-                b'[' if !til_end => {
-                    til_end = true;
-                    if self.range.is_none() {
-                        self.range = Some(i..0);
-                    }
-                }
-                b'\n' => {
-                    if let Some(ref mut range) = self.range {
-                        range.end = i;
-                    }
-                    til_end = false;
-                }
-                _ if til_end => {
-                    continue;
-                }
-                x => {
-                    #[cfg(debug_assertions)]
-                    dbg!(i, str::from_utf8(&[x]).unwrap());
-                    break;
+        let Ok(text) = str::from_utf8(self.slice) else {
+            return;
+        };
+        let mut lines = text
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .peekable();
+
+        while let Some(line) = lines.next() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Some(mut refdef) = RefDefMatter::parse_line(line) else {
+                trace!(line, "Refdef scan stopped.");
+                break;
+            };
+            if refdef.title.is_none() {
+                if let Some(title) = lines.peek().and_then(|line| parse_title(line.trim())) {
+                    refdef.title = Some(title);
+                    lines.next();
                 }
             }
+            self.refdefs.push(refdef);
         }
 
-        #[cfg(debug_assertions)]
-        {
-            dbg!(&self.range);
-            if let Some(ref mut r) = self.range {
-                dbg!(str::from_utf8(&self.slice[r.start..r.end]).unwrap());
-            }
+        trace!(refdefs = ?self.refdefs);
+    }
+
+    /// Parses `[id]: uri`, `[id]: <uri>` or either followed by a title in
+    /// double quotes, single quotes or parentheses. Like commonmark, the line
+    /// can be indented by up to three spaces.
+    pub fn parse_line(line: &'input str) -> Option<RefDef<'input>> {
+        let trimmed = line.trim_start_matches(' ');
+        if line.len() - trimmed.len() > 3 {
+            return None;
         }
+
+        let rest = trimmed.trim_end().strip_prefix('[')?;
+        let (id, rest) = rest.split_once(']')?;
+        if id.trim().is_empty() || id.contains('[') {
+            return None;
+        }
+        let rest = rest.strip_prefix(':')?.trim_start();
+
+        let (uri, rest) = if let Some(rest) = rest.strip_prefix('<') {
+            let (uri, rest) = rest.split_once('>')?;
+            if uri.contains('<') {
+                return None;
+            }
+            (uri, rest)
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let (uri, rest) = rest.split_at(end);
+            if uri.is_empty() {
+                return None;
+            }
+            (uri, rest)
+        };
+
+        let title = if rest.is_empty() {
+            None
+        } else if rest.starts_with(char::is_whitespace) {
+            Some(parse_title(rest.trim_start())?)
+        } else {
+            return None;
+        };
+
+        Some(RefDef { id, uri, title })
     }
 
     pub fn parse_gray_matter(&'input mut self) -> Option<Pod> {
-        use regex::Regex;
-
-        if let Some(r) = &self.range {
-            // Currently confused why removing `gray_matter` & `lines`, merging
-            // into a single iterator causes the inferred type to change & fail
-            // type checking. What changes when assigning vs a long iter chain?
-            let gray_matter = &self.slice[r.start..r.end];
-
-            let lines = gray_matter
-                .split(|c| c.eq(&b'\n'))
-                .map(str::from_utf8)
-                .filter_map(Result::ok);
-
-            // It would be nice to have regex syntax highlighting & compile time
-            // checks to make sure its valid. Clippy? cargo extension?? IDE extension???
-            if let Ok(re) =
-                Regex::new(r#"\[(?<id>[^\[\]]+)\]:\s(?<uri>[^\n\r"]+)("(?<title>[^"]+)")?"#)
-            {
-                let mut map: HashMap<String, Pod> = HashMap::new();
-
-                lines
-                    .filter_map(|line| re.is_match(line).then_some(re.captures_iter(line)))
-                    .flatten()
-                    .map(|value| {
-                        let id = value.name("id");
-                        let uri = value.name("uri");
-                        let title = value.name("title");
-                        (id, uri, title)
-                    })
-                    .for_each(|values| {
-                        let id = values.0;
-                        let uri = values.1;
-
-                        if id.is_none() || uri.is_none() {
-                            return;
-                        }
-                        let id = id.unwrap();
-                        let uri = uri.unwrap();
-                        let title = values.2;
-
-                        #[cfg(debug_assertions)]
-                        dbg!(id, uri, title);
-                        let key = id.as_str();
-                        if let Some(Pod::Array(vec)) = map.get_mut(key) {
-                            vec.push(Pod::Hash(RefDefMatter::regex_to_hash_entries(uri, title)));
-                        } else {
-                            map.insert(
-                                key.to_string(),
-                                Pod::Array(vec![Pod::Hash(RefDefMatter::regex_to_hash_entries(
-                                    uri, title,
-                                ))]),
-                            );
-                        }
-                    });
+        if self.refdefs.is_empty() {
+            return None;
+        }
 
-                return Some(Pod::Hash(map));
+        let mut map: HashMap<String, Pod> = HashMap::new();
+        for refdef in &self.refdefs {
+            trace!(?refdef);
+            let entry = Pod::Hash(RefDefMatter::to_hash_entries(refdef));
+            if let Some(Pod::Array(vec)) = map.get_mut(refdef.id) {
+                vec.push(entry);
+            } else {
+                map.insert(refdef.id.to_string(), Pod::Array(vec![entry]));
             }
         }
 
-        None
+        Some(Pod::Hash(map))
     }
 
-    fn regex_to_hash_entries(uri: Match, title: Option<Match>) -> HashMap<String, Pod> {
+    fn to_hash_entries(refdef: &RefDef) -> HashMap<String, Pod> {
         [
-            Some(("uri".to_string(), Pod::String(uri.as_str().to_string()))),
-            title.is_some().then_some((
-                "title".to_string(),
-                title.map_or(Pod::Null, |t| Pod::String(t.as_str().to_string())),
-            )),
+            Some(("uri".to_string(), Pod::String(refdef.uri.to_string()))),
+            refdef
+                .title
+                .map(|title| ("title".to_string(), Pod::String(title.to_string()))),
         ]
         .into_iter()
         .flatten()
         .collect::<HashMap<_, _>>()
     }
 }
+
+/// The inside of a title wrapped in `"`, `'` or `(` & `)`, making up all of
+/// `text`.
+fn parse_title(text: &str) -> Option<&str> {
+    let close = match text.chars().next()? {
+        '"' => '"',
+        '\'' => '\'',
+        '(' => ')',
+        _ => return None,
+    };
+    let inner = text.get(1..)?.strip_suffix(close)?;
+    (!inner.contains(close)).then_some(inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RefDef, RefDefMatter};
+
+    fn scan(input: &str) -> Vec<RefDef<'_>> {
+        let mut matter = RefDefMatter::new(input.as_bytes());
+        matter.scan();
+        matter.refdefs
+    }
+
+    #[test]
+    fn refdef_test_title_less() {
+        assert_eq!(
+            scan("[a]: /a\n[b]: </b c>\n"),
+            vec![
+                RefDef {
+                    id: "a",
+                    uri: "/a",
+                    title: None
+                },
+                RefDef {
+                    id: "b",
+                    uri: "/b c",
+                    title: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn refdef_test_titles() {
+        let titles: Vec<_> = scan("[a]: /a \"double\"\n[b]: /b 'single'\n[c]: /c (parens)\n")
+            .into_iter()
+            .map(|refdef| refdef.title)
+            .collect();
+        assert_eq!(titles, vec![Some("double"), Some("single"), Some("parens")]);
+    }
+
+    #[test]
+    fn refdef_test_multi_line_title() {
+        assert_eq!(
+            scan("[a]: /a\n  'title'\n\n# Header\n"),
+            vec![RefDef {
+                id: "a",
+                uri: "/a",
+                title: Some("title")
+            }]
+        );
+    }
+
+    #[test]
+    fn refdef_test_crlf() {
+        assert_eq!(
+            scan("[a]: /a \"title\"\r\n[b]: /b\r\n\r\n# Header\r\n"),
+            vec![
+                RefDef {
+                    id: "a",
+                    uri: "/a",
+                    title: Some("title")
+                },
+                RefDef {
+                    id: "b",
+                    uri: "/b",
+                    title: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn refdef_test_indented() {
+        assert_eq!(scan("   [a]: /a\n").len(), 1);
+        // Four spaces is an indented code block.
+        assert!(scan("    [a]: /a\n").is_empty());
+    }
+
+    #[test]
+    fn refdef_test_trailing_whitespace() {
+        assert_eq!(
+            scan("[a]: /a   \n[b]: /b \"title\" \t\n"),
+            vec![
+                RefDef {
+                    id: "a",
+                    uri: "/a",
+                    title: None
+                },
+                RefDef {
+                    id: "b",
+                    uri: "/b",
+                    title: Some("title")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn refdef_test_stops_at_content() {
+        assert_eq!(scan("[a]: /a\nsome text\n[b]: /b\n").len(), 1);
+        assert!(scan("[a] /a\n").is_empty());
+        assert!(scan("[a]: /a \"unclosed\n").is_empty());
+    }
+
+    #[test]
+    fn refdef_test_multibyte() {
+        assert_eq!(
+            scan("[日本]: /ü \"é\"\n[ß]:\u{a0}/a\n\"\n"),
+            vec![
+                RefDef {
+                    id: "日本",
+                    uri: "/ü",
+                    title: Some("é")
+                },
+                RefDef {
+                    id: "ß",
+                    uri: "/a",
+                    title: None
+                },
+            ]
+        );
+    }
+}
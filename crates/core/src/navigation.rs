@@ -0,0 +1,185 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// A link to another document, with its path relative to root & its html
+/// extension, e.g. `guide/setup.html`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NavLink {
+    pub title: String,
+    pub path: String,
+}
+
+/// Links from a document to its neighbours, for docs style navigation.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Navigation {
+    /// The sibling before the document in its directory, other than its
+    /// `index`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev: Option<NavLink>,
+    /// The sibling after the document in its directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<NavLink>,
+    /// The `index` document of each directory from root down to the
+    /// document's own, skipping directories without one & the document
+    /// itself.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub breadcrumbs: Vec<NavLink>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::fs::navigate;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod fs {
+    use super::{NavLink, Navigation};
+    use crate::{
+        collection::{directories, is_ignored, within_max_file_size},
+        state::State,
+    };
+    use anyhow::Result;
+    use serde_json::Value;
+    use std::{
+        cmp::Ordering,
+        ffi::OsStr,
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    /// The stem of the document standing in for its directory.
+    const INDEX: &str = "index";
+
+    struct Sibling {
+        path: PathBuf,
+        front_matter: Value,
+    }
+
+    impl Sibling {
+        fn read(path: PathBuf, state: &State) -> Sibling {
+            let front_matter = fs::read_to_string(&path)
+                .ok()
+                .and_then(|source| state.source_front_matter(&source))
+                .unwrap_or(Value::Null);
+            Sibling { path, front_matter }
+        }
+
+        /// The `weight`, or `order`, front matter value.
+        fn weight(&self) -> Option<f64> {
+            self.front_matter
+                .get("weight")
+                .or_else(|| self.front_matter.get("order"))
+                .and_then(Value::as_f64)
+        }
+
+        fn link(&self, state: &State) -> NavLink {
+            let stem = self.path.file_stem().and_then(OsStr::to_str);
+            // An untitled index is named after its directory.
+            let fallback = match stem {
+                Some(INDEX) => self
+                    .path
+                    .parent()
+                    .and_then(Path::file_name)
+                    .and_then(OsStr::to_str)
+                    .or(stem),
+                _ => stem,
+            };
+            let title = self
+                .front_matter
+                .get("title")
+                .and_then(Value::as_str)
+                .or(fallback)
+                .unwrap_or_default();
+            NavLink {
+                title: title.to_string(),
+                path: Path::new(&state.relative_path(&self.path))
+                    .with_extension("html")
+                    .to_string_lossy()
+                    .replace('\\', "/"),
+            }
+        }
+    }
+
+    /// Sorted by `weight`, or `order`, front matter, with unweighted
+    /// documents last, then by file name.
+    fn compare(a: &Sibling, b: &Sibling) -> Ordering {
+        match (a.weight(), b.weight()) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+        .then_with(|| a.path.file_name().cmp(&b.path.file_name()))
+    }
+
+    /// The directory of `path`, `.` for a bare file name.
+    fn directory(path: &Path) -> &Path {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    }
+
+    /// The prev, next & breadcrumb links of the document at `path`, from the
+    /// other documents in its directory & those above it up to root. Hidden
+    /// documents are left out, see `State::is_hidden`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the document's directory can't be read.
+    pub fn navigate(path: &Path, state: &State) -> Result<Navigation> {
+        let extensions = state.source_extensions();
+        let is_source = |path: &Path| {
+            path.extension()
+                .and_then(OsStr::to_str)
+                .is_some_and(|extension| extensions.contains(&extension))
+        };
+
+        let mut siblings = vec![];
+        for entry in fs::read_dir(directory(path))? {
+            let sibling = entry?.path();
+            // The index stands for its directory, so is left to the breadcrumbs.
+            let index = sibling.file_stem() == Some(OsStr::new(INDEX));
+            if !index
+                && sibling.is_file()
+                && is_source(&sibling)
+                && !is_ignored(&sibling, state)
+                && within_max_file_size(&sibling, state)
+            {
+                siblings.push(Sibling::read(sibling, state));
+            }
+        }
+        siblings.retain(|sibling| !state.is_hidden(&sibling.front_matter));
+        siblings.sort_by(compare);
+
+        let mut navigation = Navigation::default();
+        if let Some(position) = siblings
+            .iter()
+            .position(|sibling| sibling.path.file_name() == path.file_name())
+        {
+            navigation.prev = position
+                .checked_sub(1)
+                .and_then(|position| siblings.get(position))
+                .map(|sibling| sibling.link(state));
+            navigation.next = siblings
+                .get(position + 1)
+                .map(|sibling| sibling.link(state));
+        }
+
+        for level in directories(path, state) {
+            let index = extensions
+                .iter()
+                .map(|extension| level.join(INDEX).with_extension(extension))
+                .find(|index| index.is_file() && !is_ignored(index, state));
+            let Some(index) = index else {
+                continue;
+            };
+            if directory(path) == level && path.file_stem() == Some(OsStr::new(INDEX)) {
+                continue;
+            }
+            let index = Sibling::read(index, state);
+            if !state.is_hidden(&index.front_matter) {
+                navigation.breadcrumbs.push(index.link(state));
+            }
+        }
+
+        Ok(navigation)
+    }
+}
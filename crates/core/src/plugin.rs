@@ -1,5 +1,35 @@
-use core::ops::Range;
-use pulldown_cmark::{CowStr, Event, HeadingLevel, Tag};
+use crate::{formats::Config, images, slug::Slugger};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use core::{
+    fmt::Display,
+    ops::{Range, RangeInclusive},
+};
+use pulldown_cmark::{
+    escape::{escape_href, escape_html},
+    Alignment, CodeBlockKind, CowStr, Event, HeadingLevel, InlineStr, LinkType, Tag,
+};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    fs,
+    path::{Path as SysPath, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::ThemeSet,
+    html::{
+        start_highlighted_html_snippet, styled_line_to_highlighted_html, ClassStyle,
+        ClassedHTMLGenerator, IncludeBackground,
+    },
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+use tracing::{trace, warn};
 
 pub trait Plugin {
     /*
@@ -15,258 +45,2704 @@ pub trait Plugin {
     Returns `Some(min_index..max_index)` for items that will be replaced in
     future `replace_slice` call.
     */
-    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Option<Range<usize>>;
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>>;
 
-    fn final_check(&mut self, pos: usize) -> Option<Range<usize>>;
+    /*
+    Called once every slice was checked, with `pos`, the index of the last
+    item. Returns `Some(min_index..max_index)` for items still to be replaced,
+    e.g. a section left open, where `max_index` is exclusive, so `pos + 1`
+    includes the last item.
+    */
+    fn final_check(&mut self, pos: usize) -> Result<Option<Range<usize>>>;
 
     /*
     Recieves a slice the size of a range `max - min` returned by an earlier
     call to `check_slice`, which will be replaced by the returned array
     of `Event`'s.
     */
-    fn replace_slice<'input>(&self, slice: &[(usize, Event<'input>)]) -> Vec<Event<'input>>;
+    fn replace_slice<'input>(&self, slice: &[(usize, Event<'input>)])
+        -> Result<Vec<Event<'input>>>;
+}
+
+/// Rewrites one event at a time. Consecutive stream plugins are chained as
+/// iterator adapters, so they run in a single pass without collecting or
+/// cloning the document.
+pub trait StreamPlugin {
+    fn map<'input>(&mut self, event: Event<'input>) -> Result<Event<'input>>;
 }
 
+/// A single pass over the document's events.
+pub enum Stage {
+    /// Needs to look at several events at once, collects the document.
+    Window(Box<dyn Plugin>),
+    /// Runs lazily, event by event.
+    Stream(Box<dyn StreamPlugin>),
+}
+
+/// The stages run over a document's events, in order, each named by its
+/// plugin for `PluginError`. See `make_commonmark_plugins` for the stages
+/// a `State` enables.
 #[derive(Default)]
-pub struct CollapsibleHeaders {
-    range: Option<Range<usize>>,
+pub struct Pipeline {
+    stages: Vec<(String, Stage)>,
+}
+
+impl Pipeline {
+    /// Adds `stage`, to run after every stage already added.
+    pub fn push(&mut self, name: impl Into<String>, stage: Stage) {
+        self.stages.push((name.into(), stage));
+    }
+
+    /// The name of every stage, in the order they run.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.stages.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub(crate) fn into_stages(self) -> Vec<(String, Stage)> {
+        self.stages
+    }
+}
+
+impl FromIterator<(String, Stage)> for Pipeline {
+    fn from_iter<I: IntoIterator<Item = (String, Stage)>>(iter: I) -> Pipeline {
+        Pipeline {
+            stages: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// A plugin failing while rendering a document, wrapped in the `anyhow::Error`
+/// returned by `generate_payload_from_slice`.
+#[derive(Debug)]
+pub struct PluginError {
+    /// The plugins name, as used by the `plugins` option.
+    pub plugin: String,
+    pub source: anyhow::Error,
+}
+
+impl Display for PluginError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "The {} plugin failed.", self.plugin)
+    }
+}
+
+impl std::error::Error for PluginError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// Builds a plugin from its config, e.g. `{ "theme": "InspiredGitHub" }`.
+pub type PluginConstructor = fn(&Value) -> Result<Stage>;
+
+/// Maps plugin names, as used by the `plugins` option, to their constructors.
+pub struct PluginRegistry {
+    constructors: HashMap<String, PluginConstructor>,
+}
+
+impl PluginRegistry {
+    /// A registry without any plugins, see `default` for the built in plugins.
+    pub fn empty() -> PluginRegistry {
+        PluginRegistry {
+            constructors: HashMap::new(),
+        }
+    }
+
+    /// Adds `name`, replacing any plugin already registered under it.
+    pub fn register(&mut self, name: impl Into<String>, constructor: PluginConstructor) {
+        self.constructors.insert(name.into(), constructor);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.constructors.contains_key(name)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if `name` isn't registered or `config` isn't valid
+    /// for the plugin.
+    pub fn construct(&self, name: &str, config: &Value) -> Result<Stage> {
+        let constructor = self
+            .constructors
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown plugin {name}."))?;
+        constructor(config).with_context(|| format!("Invalid config for the {name} plugin."))
+    }
+}
+
+impl Default for PluginRegistry {
+    fn default() -> PluginRegistry {
+        let mut registry = PluginRegistry::empty();
+        registry.register("shift_headings", |config| {
+            let config: ShiftHeadingsConfig = from_config(config)?;
+            Ok(Stage::Stream(Box::new(ShiftHeadings::new(config.by))))
+        });
+        registry.register("alerts", |_| Ok(Stage::Window(Box::<Alerts>::default())));
+        registry.register("autolinks", |_| {
+            Ok(Stage::Window(Box::<Autolinks>::default()))
+        });
+        registry.register("emoji", |config| {
+            let config: EmojiConfig = from_config(config)?;
+            let emoji = match &config.map {
+                Some(path) => Emoji::with_map(path, config.shortcodes)?,
+                None => Emoji::new(config.shortcodes),
+            };
+            Ok(Stage::Stream(Box::new(emoji)))
+        });
+        registry.register("typography", |config| {
+            let config: TypographyConfig = from_config(config)?;
+            let mut replacements: HashMap<String, String> = HashMap::new();
+            if config.defaults {
+                replacements.extend(
+                    Typography::DEFAULTS
+                        .iter()
+                        .map(|(from, to)| (from.to_string(), to.to_string())),
+                );
+            }
+            replacements.extend(config.replacements);
+            Ok(Stage::Window(Box::new(Typography::new(replacements))))
+        });
+        registry.register("collapsible_headers", |config| {
+            let config: CollapsibleHeadersConfig = from_config(config)?;
+            let headers = config
+                .headers
+                .into_iter()
+                .map(|header| (header.level, header.text))
+                .collect();
+            Ok(Stage::Window(Box::new(CollapsibleHeaders::new(
+                headers,
+                config.open,
+            ))))
+        });
+        registry.register("rewrite_links", |config| {
+            let config: LinkRewritingConfig = from_config(config)?;
+            Ok(Stage::Stream(Box::new(LinkRewriting::new(
+                config.extension,
+                config.sources,
+            ))))
+        });
+        registry.register("external_links", |config| {
+            let config: ExternalLinksConfig = from_config(config)?;
+            Ok(Stage::Stream(Box::new(ExternalLinks::new(
+                config.rel,
+                config.target,
+            ))))
+        });
+        registry.register("image_cache", |config| {
+            let config: ImageCacheConfig = from_config(config)?;
+            Ok(Stage::Stream(Box::new(ImageCache::new(config.dir))))
+        });
+        registry.register("figures", |config| {
+            let config: FiguresConfig = from_config(config)?;
+            Ok(Stage::Window(Box::new(Figures::new(
+                config.lazy,
+                config.sizes,
+            ))))
+        });
+        registry.register("table_classes", |config| {
+            let config: TableClassesConfig = from_config(config)?;
+            Ok(Stage::Stream(Box::new(TableClasses::new(
+                config.class,
+                config.head_class,
+                config.body_class,
+                config.sortable,
+                config.attributes,
+            ))))
+        });
+        registry.register("footnotes", |config| {
+            let config: FootnotesConfig = from_config(config)?;
+            Ok(Stage::Window(Box::new(Footnotes::new(
+                config.heading,
+                config.backlink,
+                config.placement,
+            ))))
+        });
+        registry.register("abbreviations", |config| {
+            let config: AbbreviationsConfig = from_config(config)?;
+            Ok(Stage::Window(Box::new(Abbreviations::new(
+                config.definitions,
+            ))))
+        });
+        registry.register("code_blocks", |config| {
+            let config: CodeBlocksConfig = from_config(config)?;
+            Ok(Stage::Window(Box::new(CodeBlocks::new(config.copy))))
+        });
+        registry.register("mermaid", |_| Ok(Stage::Window(Box::<Mermaid>::default())));
+        registry.register("highlight", |config| {
+            let config: SyntaxHighlightingConfig = from_config(config)?;
+            Ok(Stage::Window(Box::new(SyntaxHighlighting::new(
+                config.theme,
+            ))))
+        });
+        registry
+    }
+}
+
+/// Deserializes a plugins config, treating a missing config as empty.
+pub(crate) fn from_config<T: DeserializeOwned>(config: &Value) -> Result<T> {
+    let config = match config {
+        Value::Null => Value::Object(Map::new()),
+        config => config.clone(),
+    };
+    Ok(serde_json::from_value(config)?)
+}
+
+#[derive(Deserialize)]
+struct EmojiConfig {
+    /// A toml or json file of custom shortcodes.
+    #[serde(default)]
+    map: Option<String>,
+    /// Custom shortcodes used over those in `map`.
+    #[serde(default)]
+    shortcodes: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct TypographyConfig {
+    /// Whether to start from `Typography::DEFAULTS`.
+    #[serde(default = "TypographyConfig::default_defaults")]
+    defaults: bool,
+    /// Custom replacements, used over the defaults.
+    #[serde(default)]
+    replacements: HashMap<String, String>,
+}
+
+impl TypographyConfig {
+    fn default_defaults() -> bool {
+        true
+    }
+}
+
+#[derive(Deserialize)]
+struct CollapsibleHeadersConfig {
+    headers: Vec<CollapsibleHeaderConfig>,
+    #[serde(default = "CollapsibleHeadersConfig::default_open")]
+    open: bool,
+}
+
+impl CollapsibleHeadersConfig {
+    fn default_open() -> bool {
+        true
+    }
+}
+
+#[derive(Deserialize)]
+struct CollapsibleHeaderConfig {
     level: u8,
     text: String,
 }
 
+#[derive(Deserialize)]
+struct LinkRewritingConfig {
+    #[serde(default = "LinkRewritingConfig::default_extension")]
+    extension: String,
+    #[serde(default = "LinkRewritingConfig::default_sources")]
+    sources: Vec<String>,
+}
+
+impl LinkRewritingConfig {
+    fn default_extension() -> String {
+        "html".to_string()
+    }
+
+    fn default_sources() -> Vec<String> {
+        vec!["md".to_string()]
+    }
+}
+
+#[derive(Deserialize)]
+struct ExternalLinksConfig {
+    #[serde(default = "ExternalLinksConfig::default_rel")]
+    rel: String,
+    #[serde(default = "ExternalLinksConfig::default_target")]
+    target: String,
+}
+
+impl ExternalLinksConfig {
+    fn default_rel() -> String {
+        "noopener noreferrer".to_string()
+    }
+
+    fn default_target() -> String {
+        "_blank".to_string()
+    }
+}
+
+#[derive(Deserialize)]
+struct ShiftHeadingsConfig {
+    by: u8,
+}
+
+#[derive(Deserialize)]
+struct ImageCacheConfig {
+    dir: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct FiguresConfig {
+    #[serde(default)]
+    lazy: bool,
+    #[serde(default)]
+    sizes: bool,
+}
+
+#[derive(Deserialize)]
+struct TableClassesConfig {
+    #[serde(default)]
+    class: Option<String>,
+    #[serde(default)]
+    head_class: Option<String>,
+    #[serde(default)]
+    body_class: Option<String>,
+    #[serde(default)]
+    sortable: bool,
+    /// Extra attributes of every table, e.g. `data-controller`.
+    #[serde(default)]
+    attributes: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct FootnotesConfig {
+    #[serde(default)]
+    heading: Option<String>,
+    #[serde(default = "FootnotesConfig::default_backlink")]
+    backlink: String,
+    #[serde(default)]
+    placement: FootnotePlacement,
+}
+
+impl FootnotesConfig {
+    fn default_backlink() -> String {
+        "↩".to_string()
+    }
+}
+
+#[derive(Deserialize)]
+struct AbbreviationsConfig {
+    /// Abbreviations defined for every document.
+    #[serde(default)]
+    definitions: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct CodeBlocksConfig {
+    #[serde(default)]
+    copy: bool,
+}
+
+#[derive(Deserialize)]
+struct SyntaxHighlightingConfig {
+    #[serde(default)]
+    theme: Option<String>,
+}
+
+#[derive(Default)]
+pub struct CollapsibleHeaders {
+    range: Option<Range<usize>>,
+    /// Each `(level, text)` pair, matching headings of `level` or deeper.
+    headers: Vec<(u8, String)>,
+    open: bool,
+    /// The index, level and plain text of the heading being read.
+    heading: Option<(usize, HeadingLevel, String)>,
+}
+
 impl CollapsibleHeaders {
-    pub fn new(level: u8, text: String) -> CollapsibleHeaders {
+    pub fn new(headers: Vec<(u8, String)>, open: bool) -> CollapsibleHeaders {
         CollapsibleHeaders {
-            level,
-            text,
+            headers,
+            open,
             ..Default::default()
         }
     }
+
+    fn matches(&self, level: HeadingLevel, value: &str) -> bool {
+        self.headers
+            .iter()
+            .any(|(min, text)| (level as u8) >= *min && value == text)
+    }
+
+    /// Ends any open section just before `idx`.
+    fn close(&mut self, idx: usize) -> Option<Range<usize>> {
+        self.range.take().map(|range| range.start..idx)
+    }
 }
 
 impl Plugin for CollapsibleHeaders {
     fn window_size(&self) -> usize {
-        4
+        1
     }
 
     fn new_items(&self) -> usize {
         5
     }
 
-    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Option<Range<usize>> {
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
         debug_assert!(slice.len() == self.window_size());
-        #[cfg(debug_assertions)]
-        println!("{slice:?}");
-        match slice {
-            [(a, Event::Start(Tag::Heading(lvl, _, _))), (_, Event::Start(Tag::Emphasis)), (_, Event::Text(CowStr::Borrowed(v))), (b, Event::End(Tag::Emphasis))] => {
-                if (*lvl as u8) >= self.level && v == &self.text.as_str() {
-                    if let Some(ref mut range) = self.range {
-                        range.end = *b;
-                        let r = range.clone();
-                        self.range = None;
-                        return Some(r);
-                    }
-
-                    if self.range.is_none() {
-                        self.range = Some(*a..*b);
-                    }
-                }
+        trace!(?slice);
+        // Headings are matched on their plain text, ignoring any inline
+        // formatting, so the whole heading is read before deciding.
+        match (&mut self.heading, &slice[0]) {
+            (None, (idx, Event::Start(Tag::Heading(lvl, _, _)))) => {
+                self.heading = Some((*idx, *lvl, String::new()));
             }
-            [(idx, Event::Start(Tag::Heading(lvl, _, _))), ..] => {
-                if lvl < &HeadingLevel::H5 {
-                    if let Some(ref mut range) = self.range {
-                        range.end = *idx;
-                        let r = range.clone();
-                        self.range = None;
-                        return Some(r);
-                    }
-                }
+            (Some((_, _, text)), (_, Event::Text(value) | Event::Code(value))) => {
+                text.push_str(value);
             }
-            [(idx, Event::Rule), ..] => {
-                if let Some(ref mut range) = self.range {
-                    range.end = *idx;
-                    let r = range.clone();
-                    self.range = None;
-                    return Some(r);
+            (Some(_), (_, Event::End(Tag::Heading(..)))) => {
+                if let Some((start, lvl, text)) = self.heading.take() {
+                    if self.matches(lvl, &text) {
+                        // A matching heading closes any open section, starting its own.
+                        let previous = self.close(start);
+                        self.range = Some(start..start);
+                        return Ok(previous);
+                    }
+                    if lvl < HeadingLevel::H5 {
+                        return Ok(self.close(start));
+                    }
                 }
             }
+            (None, (idx, Event::Rule)) => return Ok(self.close(*idx)),
             _ => {}
         }
 
-        None
+        Ok(None)
     }
 
-    fn final_check(&mut self, pos: usize) -> Option<Range<usize>> {
-        #[cfg(debug_assertions)]
-        dbg!(pos);
+    fn final_check(&mut self, pos: usize) -> Result<Option<Range<usize>>> {
+        trace!(pos);
+        // Open sections run to the end of the document.
         if let Some(ref mut range) = self.range {
-            range.end = pos;
+            range.end = pos + 1;
         }
-        self.range.clone()
+        Ok(self.range.clone())
     }
 
-    fn replace_slice<'input>(&self, slice: &[(usize, Event<'input>)]) -> Vec<Event<'input>> {
-        #[cfg(debug_assertions)]
-        println!("{slice:?}");
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        trace!(?slice);
+        let summary_end = slice
+            .iter()
+            .position(|(_, event)| matches!(event, Event::End(Tag::Heading(..))))
+            .unwrap_or(slice.len());
         let mut r = vec![
-            Event::Html(CowStr::Borrowed("<details open>")),
+            Event::Html(CowStr::Borrowed(if self.open {
+                "<details open>"
+            } else {
+                "<details>"
+            })),
             Event::SoftBreak,
             Event::Html(CowStr::Borrowed("<summary>")),
         ];
-        if let (Some((_, a)), Some((_, b)), Some((_, c))) =
-            (slice.get(1), slice.get(2), slice.get(3))
-        {
-            r.extend([a.clone(), b.clone(), c.clone()]);
-        }
+        r.extend(slice.iter().take(summary_end).skip(1).map(|t| t.1.clone()));
         r.push(Event::Html(CowStr::Borrowed("</summary>")));
-        r.extend(slice.iter().skip(5).map(|t| t.1.clone()));
+        r.extend(slice.iter().skip(summary_end + 1).map(|t| t.1.clone()));
         r.push(Event::Html(CowStr::Borrowed("</details>")));
-        r
+        Ok(r)
+    }
+}
+
+/// The built custom shortcodes of every `--emoji-map` read so far, by path.
+fn emoji_maps() -> &'static Mutex<HashMap<String, Arc<Shortcodes>>> {
+    static EMOJI_MAPS: OnceLock<Mutex<HashMap<String, Arc<Shortcodes>>>> = OnceLock::new();
+    EMOJI_MAPS.get_or_init(Mutex::default)
+}
+
+/// Custom shortcodes, with their replacement & whether it's html.
+type Shortcodes = HashMap<String, (String, bool)>;
+
+/// Replaces `:shortcode:`s in text with their emoji, using GitHub flavoured
+/// shortcodes. Custom shortcodes are used over the built in ones, and can
+/// be replaced by an emoji, an image url or html.
+#[derive(Default)]
+pub struct Emoji {
+    /// Custom shortcodes, with images & html built once up front.
+    custom: Shortcodes,
+    /// The built shortcodes of an `--emoji-map`, shared by every render.
+    map: Arc<Shortcodes>,
+}
+
+/// What a shortcode is replaced with.
+enum Replacement<'a> {
+    Text(&'a str),
+    Html(&'a str),
+    Emoji(&'static str),
+}
+
+impl Emoji {
+    pub fn new(custom: HashMap<String, String>) -> Emoji {
+        Emoji {
+            custom: Emoji::build(custom),
+            map: Arc::default(),
+        }
+    }
+
+    /// Uses the custom shortcodes at `path`, see `Emoji::cached_map`, with
+    /// `custom` used over them.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or isn't a table of
+    /// strings.
+    pub fn with_map(path: &str, custom: HashMap<String, String>) -> Result<Emoji> {
+        Ok(Emoji {
+            custom: Emoji::build(custom),
+            map: Emoji::cached_map(path)?,
+        })
+    }
+
+    /// Builds the image & html of each custom shortcode.
+    fn build(custom: HashMap<String, String>) -> Shortcodes {
+        custom
+            .into_iter()
+            .map(|(shortcode, value)| {
+                let replacement = if value.starts_with('<') {
+                    (value, true)
+                } else if is_external(&value) || value.starts_with('/') {
+                    // Writing to a `String` can't fail.
+                    let mut html = String::from(r#"<img class="emoji" src=""#);
+                    let _ = escape_href(&mut html, &value);
+                    html.push_str(r#"" alt=":"#);
+                    let _ = escape_html(&mut html, &shortcode);
+                    html.push_str(r#":">"#);
+                    (html, true)
+                } else {
+                    (value, false)
+                };
+                (shortcode, replacement)
+            })
+            .collect()
+    }
+
+    /// Reads custom shortcodes from a toml or json file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or isn't a table of
+    /// strings.
+    pub fn load_map(path: &str) -> Result<HashMap<String, String>> {
+        let ext = SysPath::new(path)
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default();
+        let text = fs::read_to_string(path).with_context(|| format!("Unable to read {path}."))?;
+        Ok(match Config::try_from(ext)? {
+            Config::Json => serde_json::from_str(&text)?,
+            Config::Toml => toml::from_str(&text)?,
+            Config::Yaml => serde_yaml::from_str(&text)?,
+        })
+    }
+
+    /// The custom shortcodes at `path`, read & built on first use then
+    /// shared by every later render, until `Emoji::reload_map` reads them
+    /// again.
+    fn cached_map(path: &str) -> Result<Arc<Shortcodes>> {
+        let mut maps = emoji_maps()
+            .lock()
+            .map_err(|error| anyhow!(error.to_string()))?;
+        if let Some(map) = maps.get(path) {
+            return Ok(Arc::clone(map));
+        }
+        let map = Arc::new(Emoji::build(Emoji::load_map(path)?));
+        maps.insert(path.to_string(), Arc::clone(&map));
+        Ok(map)
+    }
+
+    /// Reads the custom shortcodes at `path` again, replacing any cached by
+    /// `Emoji::with_map`, as the options are (re)loaded.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or isn't a table of
+    /// strings, keeping the shortcodes cached before.
+    pub fn reload_map(path: &str) -> Result<()> {
+        let map = Arc::new(Emoji::build(Emoji::load_map(path)?));
+        emoji_maps()
+            .lock()
+            .map_err(|error| anyhow!(error.to_string()))?
+            .insert(path.to_string(), map);
+        Ok(())
+    }
+
+    fn lookup(&self, shortcode: &str) -> Option<Replacement<'_>> {
+        let custom = self
+            .custom
+            .get(shortcode)
+            .or_else(|| self.map.get(shortcode));
+        if let Some((value, is_html)) = custom {
+            return Some(if *is_html {
+                Replacement::Html(value)
+            } else {
+                Replacement::Text(value)
+            });
+        }
+        trace!(shortcode);
+        emojis::get_by_shortcode(shortcode).map(|emoji| Replacement::Emoji(emoji.as_str()))
+    }
+
+    /// Replaces every valid shortcode `:{value}:` in `value`, returning
+    /// `None` when there aren't any. The result is html, with the text
+    /// escaped, when any replacement is html. Text that is only a built in
+    /// emoji borrows it rather than allocating.
+    fn replace_shortcodes(&self, value: &str) -> Option<(CowStr<'static>, bool)> {
+        let mut parts: Vec<Replacement> = vec![];
+        let mut rest = value;
+        let mut found = false;
+        while let Some(start) = rest.find(':') {
+            let after = &rest[start + 1..];
+            let Some(end) = after.find(':') else {
+                break;
+            };
+            let shortcode = &after[..end];
+            let replacement = (!shortcode.is_empty() && !shortcode.contains(char::is_whitespace))
+                .then(|| self.lookup(shortcode))
+                .flatten();
+
+            if let Some(replacement) = replacement {
+                parts.push(Replacement::Text(&rest[..start]));
+                parts.push(replacement);
+                rest = &after[end + 1..];
+                found = true;
+            } else {
+                // The closing colon can start the next shortcode.
+                parts.push(Replacement::Text(&rest[..=start]));
+                rest = after;
+            }
+        }
+        if !found {
+            return None;
+        }
+        parts.push(Replacement::Text(rest));
+        if let [Replacement::Text(""), Replacement::Emoji(emoji), Replacement::Text("")] = parts[..]
+        {
+            return Some((CowStr::Borrowed(emoji), false));
+        }
+
+        let is_html = parts.iter().any(|p| matches!(p, Replacement::Html(_)));
+        let mut result = String::with_capacity(value.len());
+        for part in parts {
+            match part {
+                Replacement::Text(text) if is_html => {
+                    let _ = escape_html(&mut result, text);
+                }
+                Replacement::Text(text) | Replacement::Emoji(text) => result.push_str(text),
+                Replacement::Html(html) => result.push_str(html),
+            }
+        }
+        trace!(result);
+        Some((result.into(), is_html))
+    }
+}
+
+impl StreamPlugin for Emoji {
+    fn map<'input>(&mut self, event: Event<'input>) -> Result<Event<'input>> {
+        Ok(match event {
+            Event::Text(value) => match self.replace_shortcodes(&value) {
+                Some((html, true)) => Event::Html(html),
+                Some((text, false)) => Event::Text(text),
+                None => Event::Text(value),
+            },
+            event => event,
+        })
+    }
+}
+
+/// Copies a short slice, like a word of autolinked text, inline into its
+/// event instead of allocating, see `CowStr::Inlined`. Owned strings are
+/// already allocated, so are moved with `CowStr::from` instead.
+pub(crate) fn cow_str<'a>(value: &str) -> CowStr<'a> {
+    match InlineStr::try_from(value) {
+        Ok(inline) => CowStr::Inlined(inline),
+        Err(_) => CowStr::Boxed(value.into()),
+    }
+}
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme_set() -> &'static ThemeSet {
+    static THEME_SET: OnceLock<ThemeSet> = OnceLock::new();
+    THEME_SET.get_or_init(ThemeSet::load_defaults)
+}
+
+/// Highlights fenced code blocks with syntect. Without a theme the output
+/// uses css classes, otherwise the theme colours are inlined.
+#[derive(Default)]
+pub struct SyntaxHighlighting {
+    theme: Option<String>,
+    start: Option<usize>,
+}
+
+impl SyntaxHighlighting {
+    pub fn new(theme: Option<String>) -> SyntaxHighlighting {
+        SyntaxHighlighting {
+            theme,
+            ..Default::default()
+        }
+    }
+
+    fn highlight(
+        &self,
+        lang: &str,
+        code: &str,
+        lines: &LineAnnotations,
+    ) -> Result<String, syntect::Error> {
+        let syntax_set = syntax_set();
+        let syntax = syntax_set
+            .find_syntax_by_token(lang)
+            .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+
+        if let Some(name) = &self.theme {
+            if let Some(theme) = theme_set().themes.get(name) {
+                let (mut html, background) = start_highlighted_html_snippet(theme);
+                let mut highlighter = HighlightLines::new(syntax, theme);
+                let mut body = String::new();
+                for line in LinesWithEndings::from(code) {
+                    let regions = highlighter.highlight_line(line, syntax_set)?;
+                    body.push_str(&styled_line_to_highlighted_html(
+                        &regions,
+                        IncludeBackground::IfDifferent(background),
+                    )?);
+                }
+                html.push_str(&lines.wrap(body));
+                html.push_str("</pre>\n");
+                return Ok(html);
+            }
+            warn!("Highlight theme {name} not found, using css classes instead.");
+        }
+
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, syntax_set, ClassStyle::Spaced);
+        for line in LinesWithEndings::from(code) {
+            generator.parse_html_for_line_which_includes_newline(line)?;
+        }
+        // The info string is untrusted, so it can't close the attribute.
+        let mut class = String::new();
+        // Writing to a `String` can't fail.
+        let _ = escape_html(&mut class, lang);
+        Ok(format!(
+            r#"<pre class="code"><code class="language-{class}">{}</code></pre>"#,
+            lines.wrap(generator.finalize())
+        ))
+    }
+}
+
+/// The lines of a fenced code block to highlight & whether to number them,
+/// from its info string, e.g. `rust {3-5,8} linenos`.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct LineAnnotations {
+    highlighted: Vec<RangeInclusive<usize>>,
+    numbers: bool,
+}
+
+impl LineAnnotations {
+    fn parse(info: &str) -> LineAnnotations {
+        let highlighted = info
+            .split_once('{')
+            .and_then(|(_, rest)| rest.split_once('}'))
+            .map(|(ranges, _)| {
+                ranges
+                    .split(',')
+                    .filter_map(|range| match range.split_once('-') {
+                        Some((start, end)) => {
+                            Some(start.trim().parse().ok()?..=end.trim().parse().ok()?)
+                        }
+                        None => range.trim().parse().ok().map(|line| line..=line),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let numbers = info
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .any(|word| word == "linenos");
+        LineAnnotations {
+            highlighted,
+            numbers,
+        }
+    }
+
+    /// Wraps each line of highlighted `html` in a `<span class="line">`,
+    /// adding `highlighted` to the class of highlighted lines & the 1 based
+    /// `data-line` to every line when numbered. Without annotations `html`
+    /// is returned as is.
+    fn wrap(&self, html: String) -> String {
+        if self.highlighted.is_empty() && !self.numbers {
+            return html;
+        }
+        let mut wrapped = String::with_capacity(html.len());
+        for (index, line) in split_lines(&html).into_iter().enumerate() {
+            let number = index + 1;
+            wrapped.push_str(r#"<span class="line"#);
+            if self.highlighted.iter().any(|range| range.contains(&number)) {
+                wrapped.push_str(" highlighted");
+            }
+            wrapped.push('"');
+            if self.numbers {
+                wrapped.push_str(&format!(r#" data-line="{number}""#));
+            }
+            wrapped.push('>');
+            wrapped.push_str(&line);
+            wrapped.push_str("\n</span>");
+        }
+        wrapped
+    }
+}
+
+/// Splits highlighted html into its lines, without their newlines. Spans
+/// still open at the end of a line are closed, then opened again at the
+/// start of the next, so each line stands alone.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    let mut open: Vec<&str> = vec![];
+    let mut rest = html;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("</span>") {
+            match open.pop() {
+                // Leaves out spans left empty by opening them again.
+                Some(tag) if line.ends_with(tag) => line.truncate(line.len() - tag.len()),
+                _ => line.push_str("</span>"),
+            }
+            rest = after;
+        } else if rest.starts_with("<span") {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            open.push(&rest[..end]);
+            line.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if let Some(after) = rest.strip_prefix('\n') {
+            line.push_str(&"</span>".repeat(open.len()));
+            lines.push(core::mem::replace(&mut line, open.concat()));
+            rest = after;
+        } else {
+            let first = rest.chars().next().map_or(0, char::len_utf8);
+            let end = rest[first..]
+                .find(['<', '\n'])
+                .map_or(rest.len(), |end| end + first);
+            line.push_str(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+    if line != open.concat() {
+        line.push_str(&"</span>".repeat(open.len()));
+        lines.push(line);
+    }
+    lines
+}
+
+impl Plugin for SyntaxHighlighting {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        1
+    }
+
+    /// Returns the range of a code block once its end is reached.
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        match slice {
+            [(i, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(_))))] => {
+                self.start = Some(*i);
+                Ok(None)
+            }
+            [(i, Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(_))))] => {
+                Ok(self.start.take().map(|start| start..(i + 1)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn final_check(&mut self, _: usize) -> Result<Option<Range<usize>>> {
+        Ok(None)
+    }
+
+    /// Replaces an entire code block with a single html event.
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        if let Some((_, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))))) = slice.first()
+        {
+            // The info string can contain more than the language, e.g. `rust,ignore`.
+            let lang = info
+                .split(|c: char| c.is_whitespace() || c == ',' || c == '{')
+                .next()
+                .unwrap_or_default();
+            let code = slice
+                .iter()
+                .filter_map(|(_, event)| match event {
+                    Event::Text(text) => Some(text.as_ref()),
+                    _ => None,
+                })
+                .collect::<String>();
+
+            let html = self.highlight(lang, &code, &LineAnnotations::parse(info))?;
+            return Ok(vec![Event::Html(CowStr::from(html))]);
+        }
+
+        Ok(slice.iter().map(|t| t.1.clone()).collect())
+    }
+}
+
+/// Gives every heading a unique `id`, keeping any set by header attributes,
+/// and appends a `#` anchor linking to it.
+#[derive(Default)]
+pub struct HeadingAnchors {
+    slugger: Slugger,
+    heading: Option<(usize, String)>,
+    slugs: HashMap<usize, String>,
+}
+
+impl HeadingAnchors {
+    /// Slugs headings without an id with `slugger`.
+    pub fn new(slugger: Slugger) -> HeadingAnchors {
+        HeadingAnchors {
+            slugger,
+            ..HeadingAnchors::default()
+        }
+    }
+}
+
+impl Plugin for HeadingAnchors {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        2
+    }
+
+    /// Collects the text of a heading, returning its range once its end is
+    /// reached.
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        match slice {
+            [(i, Event::Start(Tag::Heading(..)))] => {
+                self.heading = Some((*i, String::new()));
+                Ok(None)
+            }
+            [(_, Event::Text(text) | Event::Code(text))] => {
+                if let Some((_, heading)) = &mut self.heading {
+                    heading.push_str(text);
+                }
+                Ok(None)
+            }
+            [(i, Event::End(Tag::Heading(_, id, _)))] => {
+                Ok(self.heading.take().map(|(start, text)| {
+                    let slug = match id {
+                        Some(id) => self.slugger.unique((*id).to_string()),
+                        None => self.slugger.slug(&text),
+                    };
+                    trace!(start, slug);
+                    self.slugs.insert(start, slug);
+                    start..(i + 1)
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn final_check(&mut self, _: usize) -> Result<Option<Range<usize>>> {
+        Ok(None)
+    }
+
+    /// Replaces the heading tags with html, keeping the inner events.
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        if let Some((start, Event::Start(Tag::Heading(level, _, classes)))) = slice.first() {
+            if let Some(slug) = self.slugs.get(start) {
+                // Ids set by header attributes are user input. Writing to a
+                // `String` can't fail.
+                let mut id = String::new();
+                let _ = escape_html(&mut id, slug);
+                let mut open = format!(r#"<{level} id="{id}""#);
+                if !classes.is_empty() {
+                    open.push_str(r#" class=""#);
+                    let _ = escape_html(&mut open, &classes.join(" "));
+                    open.push('"');
+                }
+                open.push('>');
+
+                let mut r = vec![Event::Html(CowStr::from(open))];
+                let inner = slice.get(1..slice.len() - 1).unwrap_or_default();
+                r.extend(inner.iter().map(|t| t.1.clone()));
+                r.push(Event::Html(CowStr::Boxed(
+                    format!(
+                        r##"<a class="anchor" href="#{id}" aria-hidden="true">#</a></{level}>"##
+                    )
+                    .into(),
+                )));
+                r.push(Event::SoftBreak);
+                return Ok(r);
+            }
+        }
+
+        Ok(slice.iter().map(|t| t.1.clone()).collect())
+    }
+}
+
+/// Loads mermaid.js, which renders every `<pre class="mermaid">` on the page.
+pub const MERMAID_SCRIPT: &str = r#"<script type="module">import mermaid from 'https://cdn.jsdelivr.net/npm/mermaid@10/dist/mermaid.esm.min.mjs'; mermaid.initialize({ startOnLoad: true });</script>"#;
+
+/// Wraps ```mermaid fenced code blocks in `<pre class="mermaid">`, leaving
+/// the diagram source for mermaid.js to render in the browser.
+#[derive(Default)]
+pub struct Mermaid {
+    start: Option<usize>,
+}
+
+fn is_mermaid(info: &str) -> bool {
+    info.split(|c: char| c.is_whitespace() || c == ',').next() == Some("mermaid")
+}
+
+impl Plugin for Mermaid {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        1
+    }
+
+    /// Returns the range of a mermaid code block once its end is reached.
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        match slice {
+            [(i, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))))]
+                if is_mermaid(info) =>
+            {
+                self.start = Some(*i);
+                Ok(None)
+            }
+            [(i, Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(_))))] => {
+                Ok(self.start.take().map(|start| start..(i + 1)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn final_check(&mut self, _: usize) -> Result<Option<Range<usize>>> {
+        Ok(None)
+    }
+
+    /// Replaces an entire mermaid code block with a single html event.
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        let mut html = String::from(r#"<pre class="mermaid">"#);
+        for (_, event) in slice {
+            if let Event::Text(text) = event {
+                // Writing to a `String` can't fail.
+                let _ = escape_html(&mut html, text);
+            }
+        }
+        html.push_str("</pre>\n");
+        Ok(vec![Event::Html(CowStr::from(html))])
+    }
+}
+
+/// Copies the code of a block to the clipboard when its `.code-copy` button
+/// is clicked.
+pub const CODE_COPY_SCRIPT: &str = r#"<script>document.addEventListener('click', event => { const button = event.target.closest('.code-copy'); if (button) navigator.clipboard.writeText(button.closest('.code-block').querySelector('pre').innerText); });</script>"#;
+
+/// Wraps fenced code blocks in a `<div class="code-block">` with a header
+/// bar, labelled by the filename set by `title="main.rs"` in their info
+/// string, e.g. ```` ```rust title="main.rs" ````. With `copy` every block
+/// gets the bar, with a button copying its code. Blocks with neither are
+/// left alone, as are mermaid diagrams.
+#[derive(Default)]
+pub struct CodeBlocks {
+    copy: bool,
+    start: Option<usize>,
+}
+
+impl CodeBlocks {
+    pub fn new(copy: bool) -> CodeBlocks {
+        CodeBlocks {
+            copy,
+            ..CodeBlocks::default()
+        }
+    }
+}
+
+/// The value of `title` in a fenced code block's info string, quoted or not.
+fn code_title(info: &str) -> Option<&str> {
+    let (start, _) = info.match_indices("title=").find(|(start, _)| {
+        *start == 0 || info[..*start].ends_with(|c: char| c.is_whitespace() || c == ',')
+    })?;
+    let value = &info[start + "title=".len()..];
+    let title = match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => value[1..].split(quote).next(),
+        _ => value.split(|c: char| c.is_whitespace() || c == ',').next(),
+    };
+    title.filter(|title| !title.is_empty())
+}
+
+impl Plugin for CodeBlocks {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        2
+    }
+
+    /// Returns the range of a code block with a title, or any code block
+    /// with `copy`, once its end is reached.
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        match slice {
+            [(i, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))))]
+                if !is_mermaid(info) && (self.copy || code_title(info).is_some()) =>
+            {
+                self.start = Some(*i);
+                Ok(None)
+            }
+            [(i, Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(_))))] => {
+                Ok(self.start.take().map(|start| start..(i + 1)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn final_check(&mut self, _: usize) -> Result<Option<Range<usize>>> {
+        Ok(None)
+    }
+
+    /// Surrounds the code block with the wrapper & header bar, keeping its
+    /// events for highlighting.
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        let title = match slice.first() {
+            Some((_, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))))) => {
+                code_title(info)
+            }
+            _ => None,
+        };
+
+        // Writing to a `String` can't fail.
+        let mut header = String::from(r#"<div class="code-block"><div class="code-header">"#);
+        if let Some(title) = title {
+            header.push_str(r#"<span class="code-title">"#);
+            let _ = escape_html(&mut header, title);
+            header.push_str("</span>");
+        }
+        if self.copy {
+            header.push_str(
+                r#"<button class="code-copy" type="button" aria-label="Copy code">Copy</button>"#,
+            );
+        }
+        header.push_str("</div>\n");
+
+        let mut events = vec![Event::Html(CowStr::from(header))];
+        events.extend(slice.iter().map(|t| t.1.clone()));
+        events.push(Event::Html(CowStr::Borrowed("</div>\n")));
+        Ok(events)
+    }
+}
+
+/// Rewrites relative links to markdown files with one of the `sources`
+/// extensions, e.g. `./foo.md#bar`, to use `extension` instead,
+/// `./foo.html#bar`.
+pub struct LinkRewriting {
+    extension: String,
+    sources: Vec<String>,
+}
+
+impl LinkRewriting {
+    pub fn new(extension: String, sources: Vec<String>) -> LinkRewriting {
+        LinkRewriting { extension, sources }
+    }
+
+    fn rewrite(&self, href: &str) -> Option<String> {
+        // Skip absolute urls, e.g. `https://` or `mailto:`, and fragments.
+        let path_end = href.find(['#', '?']).unwrap_or(href.len());
+        let (path, rest) = href.split_at(path_end);
+        if path.starts_with("//") || path.split('/').next()?.contains(':') {
+            return None;
+        }
+
+        self.sources
+            .iter()
+            .find_map(|source| path.strip_suffix(source)?.strip_suffix('.'))
+            .filter(|stem| !stem.is_empty())
+            .map(|stem| format!("{stem}.{}{rest}", self.extension))
+    }
+}
+
+impl StreamPlugin for LinkRewriting {
+    fn map<'input>(&mut self, event: Event<'input>) -> Result<Event<'input>> {
+        match event {
+            Event::Start(Tag::Link(kind, href, title)) => {
+                let href = self.rewrite(&href).map_or(href, cow_str);
+                Ok(Event::Start(Tag::Link(kind, href, title)))
+            }
+            event => Ok(event),
+        }
+    }
+}
+
+/// Adds `rel` and `target` attributes to links pointing at absolute
+/// `http(s)` urls. Empty values leave the attribute out.
+pub struct ExternalLinks {
+    rel: String,
+    target: String,
+}
+
+impl ExternalLinks {
+    pub fn new(rel: String, target: String) -> ExternalLinks {
+        ExternalLinks { rel, target }
+    }
+}
+
+fn is_external(href: &str) -> bool {
+    href.starts_with("https://") || href.starts_with("http://") || href.starts_with("//")
+}
+
+impl StreamPlugin for ExternalLinks {
+    /// Replaces the opening link tag with html, the closing tag is left as is.
+    fn map<'input>(&mut self, event: Event<'input>) -> Result<Event<'input>> {
+        match event {
+            Event::Start(Tag::Link(_, href, title)) if is_external(&href) => {
+                // Mirrors pulldown-cmark's own link output. Writing to a
+                // `String` can't fail.
+                let mut html = String::from(r#"<a href=""#);
+                let _ = escape_href(&mut html, &href);
+                if !title.is_empty() {
+                    html.push_str(r#"" title=""#);
+                    let _ = escape_html(&mut html, &title);
+                }
+                for (name, value) in [("rel", &self.rel), ("target", &self.target)] {
+                    if !value.is_empty() {
+                        html.push_str(&format!(r#"" {name}=""#));
+                        let _ = escape_html(&mut html, value);
+                    }
+                }
+                html.push_str(r#"">"#);
+                Ok(Event::Html(CowStr::from(html)))
+            }
+            event => Ok(event),
+        }
+    }
+}
+
+/// Adds classes & attributes to tables, always writing a `<thead>` &
+/// `<tbody>`. Sortable tables get `data-sortable`, and each header cell its
+/// column as `data-column` along with `aria-sort="none"`, for a client side
+/// script to sort by.
+#[derive(Default)]
+pub struct TableClasses {
+    class: Option<String>,
+    head_class: Option<String>,
+    body_class: Option<String>,
+    sortable: bool,
+    attributes: BTreeMap<String, String>,
+    /// The alignment of each column of the current table.
+    alignments: Vec<Alignment>,
+    head: bool,
+    column: usize,
+}
+
+impl TableClasses {
+    pub fn new(
+        class: Option<String>,
+        head_class: Option<String>,
+        body_class: Option<String>,
+        sortable: bool,
+        attributes: BTreeMap<String, String>,
+    ) -> TableClasses {
+        TableClasses {
+            class,
+            head_class,
+            body_class,
+            sortable,
+            attributes,
+            ..TableClasses::default()
+        }
+    }
+}
+
+/// Writes ` name="value"`, escaping the value. Writing to a `String` can't
+/// fail.
+fn push_attribute(html: &mut String, name: &str, value: &str) {
+    html.push_str(&format!(r#" {name}=""#));
+    let _ = escape_html(html, value);
+    html.push('"');
+}
+
+impl StreamPlugin for TableClasses {
+    /// Replaces every table tag with html, mirroring pulldown-cmark's own
+    /// table output, so the cell alignments are kept.
+    fn map<'input>(&mut self, event: Event<'input>) -> Result<Event<'input>> {
+        let mut html = String::new();
+        match event {
+            Event::Start(Tag::Table(alignments)) => {
+                self.alignments = alignments;
+                html.push_str("<table");
+                if let Some(class) = &self.class {
+                    push_attribute(&mut html, "class", class);
+                }
+                if self.sortable {
+                    html.push_str(" data-sortable");
+                }
+                for (name, value) in &self.attributes {
+                    push_attribute(&mut html, name, value);
+                }
+                html.push('>');
+            }
+            Event::End(Tag::Table(_)) => html.push_str("</tbody></table>\n"),
+            Event::Start(Tag::TableHead) => {
+                self.head = true;
+                self.column = 0;
+                html.push_str("<thead");
+                if let Some(class) = &self.head_class {
+                    push_attribute(&mut html, "class", class);
+                }
+                html.push_str("><tr>");
+            }
+            Event::End(Tag::TableHead) => {
+                self.head = false;
+                html.push_str("</tr></thead><tbody");
+                if let Some(class) = &self.body_class {
+                    push_attribute(&mut html, "class", class);
+                }
+                html.push_str(">\n");
+            }
+            Event::Start(Tag::TableRow) => {
+                self.column = 0;
+                html.push_str("<tr>");
+            }
+            Event::End(Tag::TableRow) => html.push_str("</tr>\n"),
+            Event::Start(Tag::TableCell) => {
+                html.push_str(if self.head { "<th" } else { "<td" });
+                match self.alignments.get(self.column) {
+                    Some(Alignment::Left) => html.push_str(r#" style="text-align: left""#),
+                    Some(Alignment::Center) => html.push_str(r#" style="text-align: center""#),
+                    Some(Alignment::Right) => html.push_str(r#" style="text-align: right""#),
+                    _ => {}
+                }
+                if self.head && self.sortable {
+                    html.push_str(&format!(
+                        r#" data-column="{}" aria-sort="none""#,
+                        self.column
+                    ));
+                }
+                html.push('>');
+            }
+            Event::End(Tag::TableCell) => {
+                html.push_str(if self.head { "</th>" } else { "</td>" });
+                self.column += 1;
+            }
+            event => return Ok(event),
+        }
+        Ok(Event::Html(CowStr::from(html)))
+    }
+}
+
+/// Rewrites remote image urls to `/img-cache/{key}`, recording each url in
+/// `dir` for the server to download & cache on the first request.
+pub struct ImageCache {
+    dir: PathBuf,
+}
+
+impl ImageCache {
+    pub fn new(dir: PathBuf) -> ImageCache {
+        ImageCache { dir }
+    }
+
+    fn rewrite<'input>(&self, url: CowStr<'input>) -> Result<CowStr<'input>> {
+        if !is_external(&url) {
+            return Ok(url);
+        }
+        let url = match url.strip_prefix("//") {
+            Some(rest) => format!("https://{rest}"),
+            None => url.to_string(),
+        };
+        let key = images::remember(&self.dir, &url)?;
+        Ok(CowStr::from(format!("{}{key}", images::ROUTE)))
+    }
+}
+
+impl StreamPlugin for ImageCache {
+    fn map<'input>(&mut self, event: Event<'input>) -> Result<Event<'input>> {
+        Ok(match event {
+            Event::Start(Tag::Image(kind, url, title)) => {
+                Event::Start(Tag::Image(kind, self.rewrite(url)?, title))
+            }
+            Event::End(Tag::Image(kind, url, title)) => {
+                Event::End(Tag::Image(kind, self.rewrite(url)?, title))
+            }
+            event => event,
+        })
+    }
+}
+
+/// Offsets every heading level by `by`, capped at `h6`, for documents embedded
+/// under a page's own headings.
+pub struct ShiftHeadings {
+    by: u8,
+}
+
+impl ShiftHeadings {
+    pub fn new(by: u8) -> ShiftHeadings {
+        ShiftHeadings { by }
+    }
+
+    fn shift(&self, level: HeadingLevel) -> HeadingLevel {
+        HeadingLevel::try_from((level as usize + usize::from(self.by)).min(6))
+            .unwrap_or(HeadingLevel::H6)
+    }
+}
+
+impl StreamPlugin for ShiftHeadings {
+    fn map<'input>(&mut self, event: Event<'input>) -> Result<Event<'input>> {
+        Ok(match event {
+            Event::Start(Tag::Heading(level, id, classes)) => {
+                Event::Start(Tag::Heading(self.shift(level), id, classes))
+            }
+            Event::End(Tag::Heading(level, id, classes)) => {
+                Event::End(Tag::Heading(self.shift(level), id, classes))
+            }
+            event => event,
+        })
+    }
+}
+
+/// Wraps images standing alone in a paragraph in a `<figure>`, captioned by
+/// their title, or their alt text without one. Optionally lazy loads them
+/// and reads their `width` & `height` from `?w=300&h=200` in the url.
+#[derive(Default)]
+pub struct Figures {
+    lazy: bool,
+    sizes: bool,
+    /// The paragraph start and whether its image has ended.
+    candidate: Option<(usize, FigureState)>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FigureState {
+    Paragraph,
+    Image,
+    AfterImage,
+}
+
+impl Figures {
+    pub fn new(lazy: bool, sizes: bool) -> Figures {
+        Figures {
+            lazy,
+            sizes,
+            ..Default::default()
+        }
+    }
+
+    /// Splits the `w` & `h` parameters out of `url`'s query, dropping the
+    /// query when nothing else is left.
+    pub fn split_sizes(url: &str) -> (String, Option<&str>, Option<&str>) {
+        let Some((path, query)) = url.split_once('?') else {
+            return (url.to_string(), None, None);
+        };
+        let is_size = |value: &str| !value.is_empty() && value.chars().all(|c| c.is_ascii_digit());
+        let (mut width, mut height) = (None, None);
+        let mut rest = vec![];
+        for pair in query.split('&') {
+            match pair.split_once('=') {
+                Some(("w", value)) if is_size(value) => width = Some(value),
+                Some(("h", value)) if is_size(value) => height = Some(value),
+                _ => rest.push(pair),
+            }
+        }
+        let url = if rest.is_empty() {
+            path.to_string()
+        } else {
+            format!("{path}?{}", rest.join("&"))
+        };
+        (url, width, height)
+    }
+
+    fn open_html(&self, url: &str, title: &str, alt: &str) -> String {
+        let (src, width, height) = if self.sizes {
+            Figures::split_sizes(url)
+        } else {
+            (url.to_string(), None, None)
+        };
+        // Writing to a `String` can't fail.
+        let mut html = String::from("<figure>\n<img src=\"");
+        let _ = escape_href(&mut html, &src);
+        html.push_str(r#"" alt=""#);
+        let _ = escape_html(&mut html, alt);
+        html.push('"');
+        if !title.is_empty() {
+            html.push_str(r#" title=""#);
+            let _ = escape_html(&mut html, title);
+            html.push('"');
+        }
+        for (name, value) in [("width", width), ("height", height)] {
+            if let Some(value) = value {
+                html.push_str(&format!(r#" {name}="{value}""#));
+            }
+        }
+        if self.lazy {
+            html.push_str(r#" loading="lazy""#);
+        }
+        html.push_str(" />\n");
+        html
+    }
+}
+
+impl Plugin for Figures {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        0
+    }
+
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        let [(idx, event)] = slice else {
+            return Ok(None);
+        };
+        self.candidate = match (self.candidate, event) {
+            (None, Event::Start(Tag::Paragraph)) => Some((*idx, FigureState::Paragraph)),
+            (Some((start, FigureState::Paragraph)), Event::Start(Tag::Image(..))) => {
+                Some((start, FigureState::Image))
+            }
+            (Some((start, FigureState::Image)), Event::End(Tag::Image(..))) => {
+                Some((start, FigureState::AfterImage))
+            }
+            (Some((_, FigureState::Image)), _) => self.candidate,
+            (Some((start, FigureState::AfterImage)), Event::End(Tag::Paragraph)) => {
+                self.candidate = None;
+                return Ok(Some(start..(*idx + 1)));
+            }
+            _ => None,
+        };
+        Ok(None)
+    }
+
+    fn final_check(&mut self, _pos: usize) -> Result<Option<Range<usize>>> {
+        Ok(None)
+    }
+
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        let (Some((_, Event::Start(Tag::Image(_, url, title)))), Some(inner)) =
+            (slice.get(1), slice.get(2..slice.len().saturating_sub(2)))
+        else {
+            return Ok(slice.iter().map(|t| t.1.clone()).collect());
+        };
+        let alt: String = inner
+            .iter()
+            .filter_map(|(_, event)| match event {
+                Event::Text(value) | Event::Code(value) => Some(value.as_ref()),
+                _ => None,
+            })
+            .collect();
+
+        let mut r = vec![Event::Html(CowStr::Boxed(
+            self.open_html(url, title, &alt).into(),
+        ))];
+        if !title.is_empty() || !alt.is_empty() {
+            r.push(Event::Html(CowStr::Borrowed("<figcaption>")));
+            if title.is_empty() {
+                r.extend(inner.iter().map(|t| t.1.clone()));
+            } else {
+                r.push(Event::Text(title.clone()));
+            }
+            r.push(Event::Html(CowStr::Borrowed("</figcaption>\n")));
+        }
+        r.push(Event::Html(CowStr::Borrowed("</figure>\n")));
+        Ok(r)
+    }
+}
+
+/// Where `Footnotes` places footnote definitions.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FootnotePlacement {
+    /// Where each definition is written.
+    Inline,
+    /// In a list after the rest of the document.
+    #[default]
+    End,
+}
+
+/// Renders footnotes with a backlink to every reference, either in place or
+/// gathered into a `<section class="footnotes">` after the document.
+#[derive(Default)]
+pub struct Footnotes {
+    heading: Option<String>,
+    backlink: String,
+    placement: FootnotePlacement,
+    found: bool,
+}
+
+impl Footnotes {
+    pub fn new(
+        heading: Option<String>,
+        backlink: String,
+        placement: FootnotePlacement,
+    ) -> Footnotes {
+        Footnotes {
+            heading,
+            backlink,
+            placement,
+            ..Default::default()
+        }
+    }
+
+    /// The id of the `n`th reference to `label`, which backlinks point to.
+    fn reference_id(label: &str, n: usize) -> String {
+        if n == 1 {
+            format!("fnref-{label}")
+        } else {
+            format!("fnref-{label}-{n}")
+        }
+    }
+
+    /// Appends backlinks to a definitions last paragraph, or after it when
+    /// it doesn't end with one.
+    fn push_backlinks<'input>(
+        &self,
+        events: &mut Vec<Event<'input>>,
+        label: &str,
+        references: usize,
+    ) {
+        let mut html = String::new();
+        for n in 1..=references {
+            html.push_str(&format!(
+                r##" <a href="#{}" class="footnote-backref">"##,
+                Footnotes::reference_id(label, n)
+            ));
+            let _ = escape_html(&mut html, &self.backlink);
+            html.push_str("</a>");
+        }
+        let backlinks = Event::Html(CowStr::from(html));
+
+        if let Some(Event::End(Tag::Paragraph)) = events.last() {
+            events.insert(events.len() - 1, backlinks);
+        } else {
+            events.push(backlinks);
+        }
+    }
+
+    fn render<'input>(&self, events: Vec<Event<'input>>) -> Vec<Event<'input>> {
+        // Numbered by the first mention of each label, like pulldown-cmark.
+        let mut numbers: HashMap<String, usize> = HashMap::new();
+        let mut references: HashMap<String, usize> = HashMap::new();
+        for event in &events {
+            let (Event::FootnoteReference(label) | Event::Start(Tag::FootnoteDefinition(label))) =
+                event
+            else {
+                continue;
+            };
+            let next = numbers.len() + 1;
+            numbers.entry(label.to_string()).or_insert(next);
+            if let Event::FootnoteReference(_) = event {
+                *references.entry(label.to_string()).or_default() += 1;
+            }
+        }
+
+        let mut r = Vec::with_capacity(events.len());
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        let mut definition: Option<(String, Vec<Event>)> = None;
+        let mut definitions = vec![];
+        for event in events {
+            match event {
+                Event::FootnoteReference(label) => {
+                    let n = seen.entry(label.to_string()).or_default();
+                    *n += 1;
+                    // Labels are user input. Writing to a `String` can't fail.
+                    let mut id = String::new();
+                    let _ = escape_html(&mut id, &label);
+                    r.push(Event::Html(CowStr::Boxed(
+                        format!(
+                            r##"<sup class="footnote-reference" id="{}"><a href="#{id}">{}</a></sup>"##,
+                            Footnotes::reference_id(&id, *n),
+                            numbers.get(label.as_ref()).copied().unwrap_or_default(),
+                        )
+                        .into(),
+                    )));
+                }
+                Event::Start(Tag::FootnoteDefinition(label)) => {
+                    definition = Some((label.to_string(), vec![]));
+                }
+                Event::End(Tag::FootnoteDefinition(_)) => {
+                    let Some((label, mut inner)) = definition.take() else {
+                        continue;
+                    };
+                    let mut id = String::new();
+                    let _ = escape_html(&mut id, &label);
+                    let number = numbers.get(&label).copied().unwrap_or_default();
+                    let count = references.get(&label).copied().unwrap_or_default();
+                    self.push_backlinks(&mut inner, &id, count);
+
+                    match self.placement {
+                        FootnotePlacement::Inline => {
+                            r.push(Event::Html(CowStr::Boxed(
+                                format!(
+                                    r#"<div class="footnote-definition" id="{id}"><sup class="footnote-definition-label">{number}</sup>"#
+                                )
+                                .into(),
+                            )));
+                            r.extend(inner);
+                            r.push(Event::Html(CowStr::Borrowed("</div>\n")));
+                        }
+                        FootnotePlacement::End => definitions.push((number, id, inner)),
+                    }
+                }
+                event => match &mut definition {
+                    Some((_, inner)) => inner.push(event),
+                    None => r.push(event),
+                },
+            }
+        }
+
+        if !definitions.is_empty() {
+            definitions.sort_by_key(|d| d.0);
+            let mut open = String::from("<section class=\"footnotes\">\n");
+            if let Some(heading) = &self.heading {
+                open.push_str("<h2>");
+                let _ = escape_html(&mut open, heading);
+                open.push_str("</h2>\n");
+            }
+            open.push_str("<ol>\n");
+            r.push(Event::Html(CowStr::from(open)));
+            for (number, id, inner) in definitions {
+                r.push(Event::Html(CowStr::from(format!(
+                    r#"<li id="{id}" value="{number}">"#
+                ))));
+                r.extend(inner);
+                r.push(Event::Html(CowStr::Borrowed("</li>\n")));
+            }
+            r.push(Event::Html(CowStr::Borrowed("</ol>\n</section>\n")));
+        }
+
+        r
+    }
+}
+
+impl Plugin for Footnotes {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        0
+    }
+
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        if let [(_, Event::FootnoteReference(_) | Event::Start(Tag::FootnoteDefinition(_)))] = slice
+        {
+            self.found = true;
+        }
+        Ok(None)
+    }
+
+    /// Claims the whole document once it has any footnotes, as definitions
+    /// can move & references need every definition numbered.
+    fn final_check(&mut self, pos: usize) -> Result<Option<Range<usize>>> {
+        Ok(self.found.then_some(0..(pos + 1)))
+    }
+
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        Ok(self.render(slice.iter().map(|t| t.1.clone()).collect()))
+    }
+}
+
+/// Replaces plain text like `(c)` with `©` or `->` with `→`, outside of code.
+/// Goes beyond smart punctuation, which only handles quotes, dashes & ellipses.
+#[derive(Default)]
+pub struct Typography {
+    /// Longest first, so `<->` is matched before `->`.
+    replacements: Vec<(String, String)>,
+    in_code: bool,
+    /// The index and merged text of consecutive text events.
+    run: Option<(usize, String)>,
+}
+
+impl Typography {
+    pub const DEFAULTS: [(&'static str, &'static str); 10] = [
+        ("(c)", "©"),
+        ("(C)", "©"),
+        ("(r)", "®"),
+        ("(R)", "®"),
+        ("(tm)", "™"),
+        ("(TM)", "™"),
+        ("+-", "±"),
+        ("<->", "↔"),
+        ("->", "→"),
+        ("<-", "←"),
+    ];
+
+    pub fn new(replacements: HashMap<String, String>) -> Typography {
+        let mut replacements: Vec<_> = replacements
+            .into_iter()
+            .filter(|(from, _)| !from.is_empty())
+            .collect();
+        replacements.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+        Typography {
+            replacements,
+            ..Default::default()
+        }
+    }
+
+    /// Replaces every match in `value`, returning `None` when there aren't any.
+    fn replace(&self, value: &str) -> Option<String> {
+        let mut result = String::with_capacity(value.len());
+        let mut found = false;
+        let mut rest = value;
+        while let Some(c) = rest.chars().next() {
+            match self
+                .replacements
+                .iter()
+                .find(|(from, _)| rest.starts_with(from.as_str()))
+            {
+                Some((from, to)) => {
+                    result.push_str(to);
+                    rest = &rest[from.len()..];
+                    found = true;
+                }
+                None => {
+                    result.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        found.then_some(result)
+    }
+
+    /// Ends the current run of text before `idx`, claiming it when any of it
+    /// needs replacing.
+    fn end_run(&mut self, idx: usize) -> Option<Range<usize>> {
+        let (start, text) = self.run.take()?;
+        self.replace(&text).map(|_| start..idx)
+    }
+}
+
+impl Plugin for Typography {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        0
+    }
+
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        // pulldown-cmark splits text on characters like `<`, so runs of text
+        // events are merged before matching.
+        Ok(match slice {
+            [(idx, Event::Text(value))] if !self.in_code => {
+                self.run
+                    .get_or_insert_with(|| (*idx, String::new()))
+                    .1
+                    .push_str(value);
+                None
+            }
+            [(idx, event)] => {
+                match event {
+                    Event::Start(Tag::CodeBlock(_)) => self.in_code = true,
+                    Event::End(Tag::CodeBlock(_)) => self.in_code = false,
+                    _ => {}
+                }
+                self.end_run(*idx)
+            }
+            _ => None,
+        })
+    }
+
+    fn final_check(&mut self, pos: usize) -> Result<Option<Range<usize>>> {
+        Ok(self.end_run(pos + 1))
+    }
+
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        let text: String = slice
+            .iter()
+            .filter_map(|(_, event)| match event {
+                Event::Text(value) => Some(value.as_ref()),
+                _ => None,
+            })
+            .collect();
+        let text = self.replace(&text).unwrap_or(text);
+        Ok(vec![Event::Text(CowStr::from(text))])
+    }
+}
+
+/// Links bare `http://`, `https://` & `www.` urls in text, like GitHub's
+/// extended autolinks.
+#[derive(Default)]
+pub struct Autolinks {
+    /// Inside code blocks, links or images.
+    skip: usize,
+    /// The index and merged text of consecutive text events.
+    run: Option<(usize, String)>,
+}
+
+impl Autolinks {
+    /// The byte ranges of every url in `text`, without trailing punctuation.
+    pub fn find(text: &str) -> Vec<Range<usize>> {
+        let mut found = vec![];
+        let mut previous: Option<char> = None;
+        let mut end = 0;
+        for (start, c) in text.char_indices() {
+            let before = previous.replace(c);
+            if start < end || !before.map_or(true, |p| p.is_whitespace() || "*_~(".contains(p)) {
+                continue;
+            }
+            let rest = &text[start..];
+            let Some(prefix) = ["https://", "http://", "www."]
+                .into_iter()
+                .find(|prefix| rest.starts_with(prefix))
+            else {
+                continue;
+            };
+            let mut url = rest
+                .find(|c: char| c.is_whitespace() || c == '<')
+                .map_or(rest, |end| &rest[..end]);
+            // Trailing punctuation & unbalanced parentheses end the sentence,
+            // not the url.
+            while let Some(last) = url.chars().next_back() {
+                let unbalanced = last == ')' && url.matches(')').count() > url.matches('(').count();
+                if "?!.,:*_~'\"".contains(last) || unbalanced {
+                    url = &url[..url.len() - last.len_utf8()];
+                } else {
+                    break;
+                }
+            }
+            let domain = url[prefix.len()..].split('/').next().unwrap_or_default();
+            if domain.is_empty() || (prefix == "www." && !domain.contains('.')) {
+                continue;
+            }
+            end = start + url.len();
+            found.push(start..end);
+        }
+        found
+    }
+
+    fn end_run(&mut self, idx: usize) -> Option<Range<usize>> {
+        let (start, text) = self.run.take()?;
+        (!Autolinks::find(&text).is_empty()).then_some(start..idx)
+    }
+}
+
+impl Plugin for Autolinks {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        0
+    }
+
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        // pulldown-cmark splits text on characters like `_`, so runs of text
+        // events are merged before matching.
+        Ok(match slice {
+            [(idx, Event::Text(value))] if self.skip == 0 => {
+                self.run
+                    .get_or_insert_with(|| (*idx, String::new()))
+                    .1
+                    .push_str(value);
+                None
+            }
+            [(idx, event)] => {
+                match event {
+                    Event::Start(Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..)) => {
+                        self.skip += 1;
+                    }
+                    Event::End(Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..)) => {
+                        self.skip = self.skip.saturating_sub(1);
+                    }
+                    _ => {}
+                }
+                self.end_run(*idx)
+            }
+            _ => None,
+        })
+    }
+
+    fn final_check(&mut self, pos: usize) -> Result<Option<Range<usize>>> {
+        Ok(self.end_run(pos + 1))
+    }
+
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        let text: String = slice
+            .iter()
+            .filter_map(|(_, event)| match event {
+                Event::Text(value) => Some(value.as_ref()),
+                _ => None,
+            })
+            .collect();
+        let mut r = vec![];
+        let mut last = 0;
+        for range in Autolinks::find(&text) {
+            if last < range.start {
+                r.push(Event::Text(cow_str(&text[last..range.start])));
+            }
+            let url = &text[range.clone()];
+            let href = if url.starts_with("www.") {
+                CowStr::from(format!("http://{url}"))
+            } else {
+                cow_str(url)
+            };
+            let tag = Tag::Link(LinkType::Autolink, href, CowStr::Borrowed(""));
+            r.push(Event::Start(tag.clone()));
+            r.push(Event::Text(cow_str(url)));
+            r.push(Event::End(tag));
+            last = range.end;
+        }
+        if last < text.len() {
+            r.push(Event::Text(cow_str(&text[last..])));
+        }
+        Ok(r)
+    }
+}
+
+/// Renders GitHub style alerts, blockquotes starting with a line of just
+/// `[!NOTE]`, `[!TIP]`, `[!IMPORTANT]`, `[!WARNING]` or `[!CAUTION]`.
+#[derive(Default)]
+pub struct Alerts {
+    quote: Option<(usize, usize, AlertState)>,
+}
+
+enum AlertState {
+    AwaitParagraph,
+    FirstLine(String),
+    Alert,
+    Skip,
+}
+
+impl Alerts {
+    /// The class & title of an alert marker, e.g. `[!NOTE]`.
+    pub fn parse_marker(line: &str) -> Option<(&'static str, &'static str)> {
+        let kind = line.trim().strip_prefix("[!")?.strip_suffix(']')?;
+        [
+            ("note", "Note"),
+            ("tip", "Tip"),
+            ("important", "Important"),
+            ("warning", "Warning"),
+            ("caution", "Caution"),
+        ]
+        .into_iter()
+        .find(|(class, _)| kind.eq_ignore_ascii_case(class))
+    }
+}
+
+impl Plugin for Alerts {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        0
+    }
+
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        let [(idx, event)] = slice else {
+            return Ok(None);
+        };
+        let Some((start, depth, state)) = &mut self.quote else {
+            if let Event::Start(Tag::BlockQuote) = event {
+                self.quote = Some((*idx, 1, AlertState::AwaitParagraph));
+            }
+            return Ok(None);
+        };
+
+        let next = match (&mut *state, event) {
+            (AlertState::AwaitParagraph, Event::Start(Tag::Paragraph)) => {
+                Some(AlertState::FirstLine(String::new()))
+            }
+            (AlertState::FirstLine(line), Event::Text(value)) => {
+                line.push_str(value);
+                None
+            }
+            (AlertState::FirstLine(line), Event::SoftBreak | Event::End(Tag::Paragraph)) => {
+                Some(if Alerts::parse_marker(line).is_some() {
+                    AlertState::Alert
+                } else {
+                    AlertState::Skip
+                })
+            }
+            (AlertState::AwaitParagraph | AlertState::FirstLine(_), _) => Some(AlertState::Skip),
+            _ => None,
+        };
+        if let Some(next) = next {
+            *state = next;
+        }
+
+        match event {
+            Event::Start(Tag::BlockQuote) => *depth += 1,
+            Event::End(Tag::BlockQuote) => {
+                *depth -= 1;
+                if *depth == 0 {
+                    let range = matches!(state, AlertState::Alert).then_some(*start..(*idx + 1));
+                    self.quote = None;
+                    return Ok(range);
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn final_check(&mut self, _pos: usize) -> Result<Option<Range<usize>>> {
+        Ok(None)
+    }
+
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        // The blockquote & paragraph starts, the marker, then the event
+        // ending the first line.
+        let line_end = slice
+            .iter()
+            .position(|(_, event)| matches!(event, Event::SoftBreak | Event::End(Tag::Paragraph)))
+            .unwrap_or(slice.len() - 1);
+        let marker: String = slice[..line_end]
+            .iter()
+            .filter_map(|(_, event)| match event {
+                Event::Text(value) => Some(value.as_ref()),
+                _ => None,
+            })
+            .collect();
+        let Some((class, title)) = Alerts::parse_marker(&marker) else {
+            return Ok(slice.iter().map(|t| t.1.clone()).collect());
+        };
+
+        let mut r = vec![Event::Html(CowStr::Boxed(
+            format!(
+                "<div class=\"markdown-alert markdown-alert-{class}\">\n<p class=\"markdown-alert-title\">{title}</p>\n"
+            )
+            .into(),
+        ))];
+        if let Some((_, Event::SoftBreak)) = slice.get(line_end) {
+            r.push(Event::Start(Tag::Paragraph));
+        }
+        r.extend(
+            slice[(line_end + 1)..(slice.len() - 1)]
+                .iter()
+                .map(|t| t.1.clone()),
+        );
+        r.push(Event::Html(CowStr::Borrowed("</div>\n")));
+        Ok(r)
+    }
+}
+
+/// Wraps abbreviations in `<abbr title="...">`, using PHP Markdown Extra style
+/// `*[HTML]: HyperText Markup Language` definitions, which are removed from
+/// the document. Headings, images & code are left alone.
+#[derive(Default)]
+pub struct Abbreviations {
+    definitions: HashMap<String, String>,
+    found: bool,
+}
+
+impl Abbreviations {
+    /// `definitions` apply to every document, under each documents own.
+    pub fn new(definitions: HashMap<String, String>) -> Abbreviations {
+        Abbreviations {
+            definitions,
+            ..Default::default()
+        }
+    }
+
+    /// Parses `*[abbr]: title`, where the title can be empty.
+    pub fn parse_definition(line: &str) -> Option<(&str, &str)> {
+        let rest = line.trim().strip_prefix("*[")?;
+        let (abbr, title) = rest.split_once("]:")?;
+        let abbr = abbr.trim();
+        (!abbr.is_empty()).then(|| (abbr, title.trim()))
+    }
+
+    /// The definitions of a paragraph made up only of definitions.
+    fn paragraph_definitions(events: &[Event]) -> Option<Vec<(String, String)>> {
+        let mut text = String::new();
+        for event in events {
+            match event {
+                Event::Text(value) => text.push_str(value),
+                Event::SoftBreak => text.push('\n'),
+                _ => return None,
+            }
+        }
+        text.lines()
+            .map(|line| {
+                Abbreviations::parse_definition(line)
+                    .map(|(abbr, title)| (abbr.to_string(), title.to_string()))
+            })
+            .collect()
+    }
+
+    /// Wraps every whole word occurrence of an abbreviation in `value`,
+    /// returning `None` when there aren't any.
+    fn wrap(definitions: &[(String, String)], value: &str) -> Option<String> {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let mut html = String::new();
+        let mut last = 0;
+        let mut pos = 0;
+        while pos < value.len() {
+            let boundary = value[..pos]
+                .chars()
+                .next_back()
+                .map_or(true, |c| !is_word(c));
+            // Longest first, see `render`.
+            let found = boundary
+                .then(|| {
+                    definitions.iter().find(|(abbr, _)| {
+                        value[pos..].starts_with(abbr.as_str())
+                            && value[pos + abbr.len()..]
+                                .chars()
+                                .next()
+                                .map_or(true, |c| !is_word(c))
+                    })
+                })
+                .flatten();
+
+            if let Some((abbr, title)) = found {
+                // Writing to a `String` can't fail.
+                let _ = escape_html(&mut html, &value[last..pos]);
+                if title.is_empty() {
+                    html.push_str("<abbr>");
+                } else {
+                    html.push_str(r#"<abbr title=""#);
+                    let _ = escape_html(&mut html, title);
+                    html.push_str(r#"">"#);
+                }
+                let _ = escape_html(&mut html, abbr);
+                html.push_str("</abbr>");
+                pos += abbr.len();
+                last = pos;
+            } else {
+                pos += value[pos..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        if last == 0 {
+            return None;
+        }
+        let _ = escape_html(&mut html, &value[last..]);
+        Some(html)
+    }
+
+    fn render<'input>(&self, events: Vec<Event<'input>>) -> Vec<Event<'input>> {
+        let mut definitions = self.definitions.clone();
+        let mut kept = Vec::with_capacity(events.len());
+        let mut paragraph: Option<Vec<Event>> = None;
+        for event in events {
+            match (&mut paragraph, event) {
+                (None, Event::Start(Tag::Paragraph)) => paragraph = Some(vec![]),
+                (Some(inner), Event::End(Tag::Paragraph)) => {
+                    let inner = core::mem::take(inner);
+                    paragraph = None;
+                    if let Some(found) = Abbreviations::paragraph_definitions(&inner) {
+                        definitions.extend(found);
+                        continue;
+                    }
+                    kept.push(Event::Start(Tag::Paragraph));
+                    kept.extend(inner);
+                    kept.push(Event::End(Tag::Paragraph));
+                }
+                (Some(inner), event) => inner.push(event),
+                (None, event) => kept.push(event),
+            }
+        }
+        if definitions.is_empty() {
+            return kept;
+        }
+
+        // Longest first, so `HTML5` is matched before `HTML`.
+        let mut definitions: Vec<_> = definitions.into_iter().collect();
+        definitions.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+
+        let mut skip = 0usize;
+        kept.into_iter()
+            .map(|event| match event {
+                Event::Start(Tag::Heading(..) | Tag::Image(..) | Tag::CodeBlock(_)) => {
+                    skip += 1;
+                    event
+                }
+                Event::End(Tag::Heading(..) | Tag::Image(..) | Tag::CodeBlock(_)) => {
+                    skip = skip.saturating_sub(1);
+                    event
+                }
+                Event::Text(value) if skip == 0 => {
+                    match Abbreviations::wrap(&definitions, &value) {
+                        Some(html) => Event::Html(CowStr::from(html)),
+                        None => Event::Text(value),
+                    }
+                }
+                event => event,
+            })
+            .collect()
     }
 }
 
-#[derive(Default)]
-pub struct Emoji;
-
-impl Plugin for Emoji {
+impl Plugin for Abbreviations {
     fn window_size(&self) -> usize {
         1
     }
 
     fn new_items(&self) -> usize {
-        1
+        0
     }
 
-    /// Checks for the existence of a single emoji shortcode `:{value}:`.
-    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Option<Range<usize>> {
-        match slice {
-            [(i, Event::Text(value))] => value
-                .find(':')
-                .and_then(|start| {
-                    value[start + 1..]
-                        .find(':')
-                        .map(|end| ((start + 1)..=(start + end)))
-                })
-                .and_then(|range| {
-                    #[cfg(debug_assertions)]
-                    dbg!(&value[range.clone()]);
-                    emojis::get_by_shortcode(&value[range])
-                })
-                .map(|_| i.to_owned()..(i + 1).to_owned()),
-            _ => None,
+    fn check_slice(&mut self, slice: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        // pulldown-cmark leaves the `*` of a definition as text.
+        if let [(_, Event::Text(text))] = slice {
+            self.found |= text.starts_with('*');
         }
+        Ok(None)
     }
 
-    fn final_check(&mut self, _: usize) -> Option<Range<usize>> {
-        None
+    /// Claims the whole document once it could have any definitions, as they
+    /// apply to the text before them too.
+    fn final_check(&mut self, pos: usize) -> Result<Option<Range<usize>>> {
+        Ok((self.found || !self.definitions.is_empty()).then_some(0..(pos + 1)))
     }
 
-    /// Replaces every occurance of a valid shortcode `:{value}:` with its emoji.
-    fn replace_slice<'input>(&self, slice: &[(usize, Event<'input>)]) -> Vec<Event<'input>> {
-        match slice {
-            [(_, /*event @ */ Event::Text(value))] => {
-                let mut ranges = vec![];
-                let mut range = None;
-                for value in value.char_indices() {
-                    match range {
-                        None => {
-                            if value.1 == ':' {
-                                range = Some(value.0..0);
-                            }
-                        }
-                        Some(incomplete) if value.1 == ':' => {
-                            if value.0 + 1 - incomplete.start > 2 {
-                                ranges.push(incomplete.start..value.0 + 1);
-                            }
-                            range = None;
-                        }
-                        _ => {}
-                    }
-                }
-                if let Some(incomplete) = range {
-                    if incomplete.end == 0 {
-                        let tmp = incomplete.start..value.len();
-                        if tmp.len() > 2 {
-                            ranges.push(tmp);
-                        }
-                        //range = None;
-                    }
-                }
-                ranges.reverse();
-                let mut result = value.clone().into_string();
-                #[cfg(debug_assertions)]
-                dbg!(&ranges);
-                for range in ranges {
-                    let opt = value
-                        .get(range.clone())
-                        .map(|s| (s, emojis::get_by_shortcode(&s[1..s.len() - 1])))
-                        .and_then(|(s, emoji)| {
-                            #[cfg(debug_assertions)]
-                            dbg!(&s, &emoji);
-                            emoji.map(emojis::Emoji::as_str).map(|e| (s, e))
-                        });
-                    #[cfg(debug_assertions)]
-                    dbg!(&opt);
-                    if let Some((s, val)) = opt {
-                        result = result.replace(s, val);
-                    }
-                }
-                #[cfg(debug_assertions)]
-                dbg!(&result);
-                vec![Event::Text(CowStr::Boxed(result.into()))]
-            }
-            _ => slice.iter().map(|t| t.1.clone()).collect(),
-        }
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        Ok(self.render(slice.iter().map(|t| t.1.clone()).collect()))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::vec;
+    use std::{collections::HashMap, vec};
 
     use pulldown_cmark::CowStr;
     use pulldown_cmark::Event;
     use pulldown_cmark::HeadingLevel;
+    use pulldown_cmark::LinkType;
     use pulldown_cmark::Tag;
 
+    use pulldown_cmark::CodeBlockKind;
+
+    use super::Abbreviations;
+    use super::Alerts;
+    use super::Autolinks;
+    use super::CodeBlocks;
     use super::CollapsibleHeaders;
     use super::Emoji;
+    use super::ExternalLinks;
+    use super::Figures;
+    use super::FootnotePlacement;
+    use super::Footnotes;
+    use super::HeadingAnchors;
+    use super::ImageCache;
+    use super::LinkRewriting;
+    use super::Mermaid;
     use super::Plugin;
+    use super::PluginRegistry;
+    use super::ShiftHeadings;
+    use super::Stage;
+    use super::StreamPlugin;
+    use super::SyntaxHighlighting;
+    use super::Typography;
+
+    #[test]
+    fn typo_test_merged_text() {
+        use pretty_assertions::assert_eq;
+
+        let input: Vec<_> = [
+            Event::Start(Tag::Paragraph),
+            Event::Text(CowStr::Borrowed("a ")),
+            Event::Text(CowStr::Borrowed("<")),
+            Event::Text(CowStr::Borrowed("-> b (c) --")),
+            Event::Code(CowStr::Borrowed("->")),
+            Event::Text(CowStr::Borrowed(" plain")),
+            Event::End(Tag::Paragraph),
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(CowStr::Borrowed("")))),
+            Event::Text(CowStr::Borrowed("a -> b")),
+            Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(CowStr::Borrowed("")))),
+        ]
+        .into_iter()
+        .enumerate()
+        .collect();
+
+        let mut replacements: HashMap<String, String> = Typography::DEFAULTS
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect();
+        replacements.insert("--".to_string(), "–".to_string());
+        let mut plugin = Typography::new(replacements);
+        let mut ranges = vec![];
+        for slice in input.windows(plugin.window_size()) {
+            if let Some(range) = plugin.check_slice(slice).unwrap() {
+                ranges.push(range);
+            }
+        }
+        assert_eq!(plugin.final_check(input.len() - 1).unwrap(), None);
+        assert_eq!(ranges, vec![1..4]);
+        assert_eq!(
+            plugin.replace_slice(&input[1..4]).unwrap(),
+            vec![Event::Text(CowStr::Borrowed("a ↔ b © –"))]
+        );
+    }
+
+    #[test]
+    fn ic_test_map() {
+        let dir = std::env::temp_dir().join("serve_md_ic_test_map");
+        let mut plugin = ImageCache::new(dir.clone());
+        let image = |url| {
+            Tag::Image(
+                LinkType::Inline,
+                CowStr::Borrowed(url),
+                CowStr::Borrowed(""),
+            )
+        };
+        let key = crate::images::key("https://example.com/cat.png");
+        let route = format!("/img-cache/{key}");
+
+        assert_eq!(
+            plugin
+                .map(Event::Start(image("//example.com/cat.png")))
+                .unwrap(),
+            Event::Start(image(&route))
+        );
+        assert_eq!(
+            crate::images::url(&dir, &key).as_deref(),
+            Some("https://example.com/cat.png")
+        );
+        assert_eq!(
+            plugin.map(Event::Start(image("/local.png"))).unwrap(),
+            Event::Start(image("/local.png"))
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn fig_test_split_sizes() {
+        assert_eq!(
+            Figures::split_sizes("/a.png?w=300&h=200"),
+            ("/a.png".to_string(), Some("300"), Some("200"))
+        );
+        assert_eq!(
+            Figures::split_sizes("/a.png?v=2&w=300&h=auto"),
+            ("/a.png?v=2&h=auto".to_string(), Some("300"), None)
+        );
+        assert_eq!(
+            Figures::split_sizes("/a.png"),
+            ("/a.png".to_string(), None, None)
+        );
+    }
+
+    #[test]
+    fn fig_test_standalone_images() {
+        use pretty_assertions::assert_eq;
+
+        let image = |title| Tag::Image(LinkType::Inline, CowStr::Borrowed("/a.png?w=3"), title);
+        let input: Vec<_> = [
+            Event::Start(Tag::Paragraph),
+            Event::Start(image(CowStr::Borrowed(""))),
+            Event::Text(CowStr::Borrowed("alt ")),
+            Event::Start(Tag::Emphasis),
+            Event::Text(CowStr::Borrowed("text")),
+            Event::End(Tag::Emphasis),
+            Event::End(image(CowStr::Borrowed(""))),
+            Event::End(Tag::Paragraph),
+            Event::Start(Tag::Paragraph),
+            Event::Text(CowStr::Borrowed("inline ")),
+            Event::Start(image(CowStr::Borrowed("Title"))),
+            Event::End(image(CowStr::Borrowed("Title"))),
+            Event::End(Tag::Paragraph),
+        ]
+        .into_iter()
+        .enumerate()
+        .collect();
+
+        let mut plugin = Figures::new(true, true);
+        let mut ranges = vec![];
+        for slice in input.windows(plugin.window_size()) {
+            if let Some(range) = plugin.check_slice(slice).unwrap() {
+                ranges.push(range);
+            }
+        }
+        assert_eq!(ranges, vec![0..8]);
+        assert_eq!(
+            plugin.replace_slice(&input[0..8]).unwrap(),
+            vec![
+                Event::Html(CowStr::Borrowed(
+                    "<figure>\n<img src=\"/a.png\" alt=\"alt text\" width=\"3\" loading=\"lazy\" />\n"
+                )),
+                Event::Html(CowStr::Borrowed("<figcaption>")),
+                Event::Text(CowStr::Borrowed("alt ")),
+                Event::Start(Tag::Emphasis),
+                Event::Text(CowStr::Borrowed("text")),
+                Event::End(Tag::Emphasis),
+                Event::Html(CowStr::Borrowed("</figcaption>\n")),
+                Event::Html(CowStr::Borrowed("</figure>\n")),
+            ]
+        );
+    }
+
+    #[test]
+    fn autolinks_test_find() {
+        let find = |text| {
+            Autolinks::find(text)
+                .into_iter()
+                .map(|range| &text[range])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            find("see www.example.com/a_b, and https://x.y/z_(w)."),
+            vec!["www.example.com/a_b", "https://x.y/z_(w)"]
+        );
+        assert_eq!(find("(https://x.y/z)"), vec!["https://x.y/z"]);
+        assert!(find("awww.example.com www.localhost http://").is_empty());
+    }
+
+    #[test]
+    fn alerts_test_replace_slice() {
+        use pretty_assertions::assert_eq;
+
+        assert_eq!(
+            Alerts::parse_marker("[!warning]"),
+            Some(("warning", "Warning"))
+        );
+        assert_eq!(Alerts::parse_marker("[!TIP] x"), None);
+
+        let input: Vec<_> = [
+            Event::Start(Tag::BlockQuote),
+            Event::Start(Tag::Paragraph),
+            Event::Text(CowStr::Borrowed("[")),
+            Event::Text(CowStr::Borrowed("!NOTE")),
+            Event::Text(CowStr::Borrowed("]")),
+            Event::SoftBreak,
+            Event::Text(CowStr::Borrowed("Useful.")),
+            Event::End(Tag::Paragraph),
+            Event::End(Tag::BlockQuote),
+            Event::Start(Tag::BlockQuote),
+            Event::Start(Tag::Paragraph),
+            Event::Text(CowStr::Borrowed("Quote.")),
+            Event::End(Tag::Paragraph),
+            Event::End(Tag::BlockQuote),
+        ]
+        .into_iter()
+        .enumerate()
+        .collect();
+
+        let mut plugin = Alerts::default();
+        let mut ranges = vec![];
+        for slice in input.windows(plugin.window_size()) {
+            if let Some(range) = plugin.check_slice(slice).unwrap() {
+                ranges.push(range);
+            }
+        }
+        assert_eq!(ranges, vec![0..9]);
+        assert_eq!(
+            plugin.replace_slice(&input[0..9]).unwrap(),
+            vec![
+                Event::Html(CowStr::Borrowed(
+                    "<div class=\"markdown-alert markdown-alert-note\">\n<p class=\"markdown-alert-title\">Note</p>\n"
+                )),
+                Event::Start(Tag::Paragraph),
+                Event::Text(CowStr::Borrowed("Useful.")),
+                Event::End(Tag::Paragraph),
+                Event::Html(CowStr::Borrowed("</div>\n")),
+            ]
+        );
+    }
+
+    #[test]
+    fn abbr_test_parse_definition() {
+        assert_eq!(
+            Abbreviations::parse_definition("*[HTML]: HyperText Markup Language"),
+            Some(("HTML", "HyperText Markup Language"))
+        );
+        assert_eq!(
+            Abbreviations::parse_definition("*[W3C]:"),
+            Some(("W3C", ""))
+        );
+        assert_eq!(Abbreviations::parse_definition("*[]: Empty"), None);
+        assert_eq!(Abbreviations::parse_definition("[HTML]: /html"), None);
+    }
 
     #[test]
-    fn emoji_test_check_and_replace_slice() {
-        let mut plugin = Emoji {};
+    fn abbr_test_render() {
+        use pretty_assertions::assert_eq;
+
         let input = [
-            (
-                0,
-                Event::Text(CowStr::Borrowed(
-                    "Random text w/ shortcode :+1: emoji :smile: mixed in. :tada:",
+            Event::Start(Tag::Paragraph),
+            Event::Text(CowStr::Borrowed("HTML & HTML5, not HTMLish.")),
+            Event::End(Tag::Paragraph),
+            Event::Start(Tag::Heading(HeadingLevel::H2, None, vec![])),
+            Event::Text(CowStr::Borrowed("HTML")),
+            Event::End(Tag::Heading(HeadingLevel::H2, None, vec![])),
+            Event::Start(Tag::Paragraph),
+            Event::Text(CowStr::Borrowed("*")),
+            Event::Text(CowStr::Borrowed("[HTML]: HyperText <Markup> Language")),
+            Event::SoftBreak,
+            Event::Text(CowStr::Borrowed("*[HTML5]:")),
+            Event::End(Tag::Paragraph),
+        ];
+        let mut plugin = Abbreviations::default();
+        let input: Vec<_> = input.into_iter().enumerate().collect();
+        for slice in input.windows(plugin.window_size()) {
+            assert!(plugin.check_slice(slice).unwrap().is_none());
+        }
+        let range = plugin.final_check(input.len() - 1).unwrap();
+        assert_eq!(range, Some(0..input.len()));
+
+        assert_eq!(
+            plugin.replace_slice(&input).unwrap(),
+            vec![
+                Event::Start(Tag::Paragraph),
+                Event::Html(CowStr::Borrowed(
+                    r#"<abbr title="HyperText &lt;Markup&gt; Language">HTML</abbr> &amp; <abbr>HTML5</abbr>, not HTMLish."#
                 )),
-            ),
-            (1, Event::Text(CowStr::Borrowed(":rocket::rocket::rocket:"))),
+                Event::End(Tag::Paragraph),
+                Event::Start(Tag::Heading(HeadingLevel::H2, None, vec![])),
+                Event::Text(CowStr::Borrowed("HTML")),
+                Event::End(Tag::Heading(HeadingLevel::H2, None, vec![])),
+            ]
+        );
+    }
+
+    #[test]
+    fn emoji_test_map() {
+        let mut plugin = Emoji::default();
+        let input = [
+            Event::Text(CowStr::Borrowed(
+                "Random text w/ shortcode :+1: emoji :smile: mixed in. :tada:",
+            )),
+            Event::Text(CowStr::Borrowed(":rocket::rocket::rocket:")),
+            Event::Text(CowStr::Borrowed("No shortcodes: here.")),
         ];
-        let mut ranges = vec![];
         let expected = [
             Event::Text(CowStr::Borrowed(
                 "Random text w/ shortcode 👍 emoji 😄 mixed in. 🎉",
             )),
             Event::Text(CowStr::Borrowed("🚀🚀🚀")),
+            Event::Text(CowStr::Borrowed("No shortcodes: here.")),
         ];
-        for slice in input.windows(plugin.window_size()) {
-            ranges.push(plugin.check_slice(slice));
-        }
-        assert!(!ranges.is_empty());
-        assert_eq!(ranges.len(), 2);
-        assert_eq!(ranges.iter().filter(|o| o.is_some()).count(), 2);
-        let mut results = vec![];
-        for op in ranges {
-            if let Some(range) = op {
-                results.extend_from_slice(&plugin.replace_slice(&input[range]))
-            } else {
-                assert!(false);
-            }
-        }
-        assert!(!results.is_empty());
+        let results: Vec<_> = input.into_iter().map(|e| plugin.map(e).unwrap()).collect();
         assert_eq!(expected.len(), results.len());
         for i in 0..expected.len() {
             assert_eq!(expected[i], results[i]);
@@ -275,30 +2751,130 @@ mod tests {
 
     #[test]
     fn emoji_test_incomplete_shortcode() {
-        let mut plugin = Emoji {};
-        let input = [(0, Event::Text(CowStr::Borrowed(":+1::+1:+1:")))];
+        let mut plugin = Emoji::default();
+        let input = Event::Text(CowStr::Borrowed(":+1::+1:+1:"));
+        let expected = Event::Text(CowStr::Borrowed("👍👍+1:"));
+        assert_eq!(expected, plugin.map(input).unwrap());
+    }
+
+    #[test]
+    fn emoji_test_multibyte_text() {
+        let mut plugin = Emoji::default();
+        let input = Event::Text(CowStr::Borrowed("é:smile:ü :日本: ::ß:tada:"));
+        let expected = Event::Text(CowStr::Borrowed("é😄ü :日本: ::ß🎉"));
+        assert_eq!(expected, plugin.map(input).unwrap());
+    }
+
+    #[test]
+    fn emoji_test_lone_shortcode_borrows() {
+        let mut plugin = Emoji::default();
+        let input = Event::Text(CowStr::Borrowed(":tada:"));
+        assert!(matches!(
+            plugin.map(input).unwrap(),
+            Event::Text(CowStr::Borrowed("🎉"))
+        ));
+    }
+
+    #[test]
+    fn ch_test_multiple_headers_closed() {
+        use pretty_assertions::assert_eq;
+
+        let heading = |level, text| {
+            [
+                Event::Start(Tag::Heading(level, None, vec![])),
+                Event::Start(Tag::Emphasis),
+                Event::Text(CowStr::Borrowed(text)),
+                Event::End(Tag::Emphasis),
+                Event::End(Tag::Heading(level, None, vec![])),
+                Event::Start(Tag::Paragraph),
+                Event::Text(CowStr::Borrowed("content")),
+                Event::End(Tag::Paragraph),
+            ]
+        };
+        let input: Vec<_> = heading(HeadingLevel::H2, "Notes")
+            .into_iter()
+            .chain(heading(HeadingLevel::H3, "Details"))
+            .enumerate()
+            .collect();
+
+        let mut plugin = CollapsibleHeaders::new(
+            vec![(2, "Notes".to_string()), (3, "Details".to_string())],
+            false,
+        );
         let mut ranges = vec![];
-        let expected = [Event::Text(CowStr::Borrowed("👍👍+1:"))];
         for slice in input.windows(plugin.window_size()) {
-            ranges.push(plugin.check_slice(slice));
-        }
-        dbg!(&ranges);
-        assert!(!ranges.is_empty());
-        assert_eq!(ranges.len(), 1);
-        assert_eq!(ranges.iter().filter(|o| o.is_some()).count(), 1);
-        let mut results = vec![];
-        for op in ranges {
-            if let Some(range) = op {
-                results.extend_from_slice(&plugin.replace_slice(&input[range]))
-            } else {
-                assert!(false);
+            if let Some(range) = plugin.check_slice(slice).unwrap() {
+                ranges.push(range);
             }
         }
-        assert!(!results.is_empty());
-        assert_eq!(expected.len(), results.len());
-        for i in 0..expected.len() {
-            assert_eq!(expected[i], results[i]);
+        if let Some(range) = plugin.final_check(input.len() - 1).unwrap() {
+            ranges.push(range);
         }
+        assert_eq!(ranges, vec![0..8, 8..16]);
+
+        let output = plugin.replace_slice(&input[ranges[1].clone()]).unwrap();
+        assert_eq!(output[0], Event::Html(CowStr::Borrowed("<details>")));
+        assert_eq!(output[4], Event::Text(CowStr::Borrowed("Details")));
+    }
+
+    #[test]
+    fn ch_test_formatted_and_owned_text() {
+        let input = [
+            (
+                0,
+                Event::Start(Tag::Heading(HeadingLevel::H2, None, vec![])),
+            ),
+            (1, Event::Start(Tag::Strong)),
+            (2, Event::Text(CowStr::Boxed("Notes".into()))),
+            (3, Event::End(Tag::Strong)),
+            (4, Event::Text(CowStr::Borrowed(" on "))),
+            (5, Event::Code(CowStr::Borrowed("main"))),
+            (6, Event::End(Tag::Heading(HeadingLevel::H2, None, vec![]))),
+            (
+                7,
+                Event::Start(Tag::Heading(HeadingLevel::H2, None, vec![])),
+            ),
+            (8, Event::Text(CowStr::Borrowed("Other"))),
+            (9, Event::End(Tag::Heading(HeadingLevel::H2, None, vec![]))),
+        ];
+        let mut plugin = CollapsibleHeaders::new(vec![(2, "Notes on main".to_string())], true);
+        let ranges: Vec<_> = input
+            .windows(plugin.window_size())
+            .filter_map(|slice| plugin.check_slice(slice).unwrap())
+            .collect();
+        assert_eq!(ranges, vec![0..7]);
+        assert_eq!(plugin.final_check(input.len() - 1).unwrap(), None);
+    }
+
+    #[test]
+    fn emoji_test_custom_shortcodes() {
+        use pretty_assertions::assert_eq;
+
+        let custom = [
+            ("smile", "🙂"),
+            ("ferris", "/ferris.png"),
+            ("wave", r#"<span class="wave">👋</span>"#),
+        ];
+        let mut plugin = Emoji::new(
+            custom
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        );
+        assert_eq!(
+            plugin
+                .map(Event::Text(CowStr::Borrowed(":smile: :tada:")))
+                .unwrap(),
+            Event::Text(CowStr::Borrowed("🙂 🎉"))
+        );
+        assert_eq!(
+            plugin
+                .map(Event::Text(CowStr::Borrowed("<:ferris: & :wave:>")))
+                .unwrap(),
+            Event::Html(CowStr::Borrowed(
+                r#"&lt;<img class="emoji" src="/ferris.png" alt=":ferris:"> &amp; <span class="wave">👋</span>&gt;"#
+            ))
+        );
     }
 
     #[test]
@@ -341,14 +2917,14 @@ mod tests {
 
         let level = 5;
         let mut ranges = vec![];
-        let mut plugin = CollapsibleHeaders::new(level, "text".to_string());
+        let mut plugin = CollapsibleHeaders::new(vec![(level, "text".to_string())], true);
         for slice in input.windows(plugin.window_size()) {
-            if let Some(range) = plugin.check_slice(slice) {
+            if let Some(range) = plugin.check_slice(slice).unwrap() {
                 ranges.push(range);
             }
         }
         // Checks for any partially open ranges.
-        if let Some(range) = plugin.final_check(input.len()) {
+        if let Some(range) = plugin.final_check(input.len() - 1).unwrap() {
             ranges.push(range);
         }
 
@@ -361,7 +2937,7 @@ mod tests {
             assert_eq!(ranges[0], 0..8);
             assert_eq!(ranges[1], 12..17);
 
-            let output = &plugin.replace_slice(&input[ranges[0].clone()]);
+            let output = &plugin.replace_slice(&input[ranges[0].clone()]).unwrap();
             assert!(!output.is_empty());
             assert_eq!(
                 [
@@ -380,7 +2956,7 @@ mod tests {
                 output[..]
             );
 
-            let output = &plugin.replace_slice(&input[ranges[1].clone()]);
+            let output = &plugin.replace_slice(&input[ranges[1].clone()]).unwrap();
             dbg!(&input[ranges[1].clone()]);
             dbg!(&output);
             assert!(!output.is_empty());
@@ -399,4 +2975,483 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn sh_test_check_and_replace_slice() {
+        let rust = || Tag::CodeBlock(CodeBlockKind::Fenced(CowStr::Borrowed("rust")));
+        let input = [
+            (0, Event::Start(Tag::Paragraph)),
+            (1, Event::Text(CowStr::Borrowed("some text."))),
+            (2, Event::End(Tag::Paragraph)),
+            (3, Event::Start(rust())),
+            (4, Event::Text(CowStr::Borrowed("fn main() {\n"))),
+            (5, Event::Text(CowStr::Borrowed("}\n"))),
+            (6, Event::End(rust())),
+        ];
+
+        for (theme, prefix) in [
+            (None, r#"<pre class="code"><code class="language-rust">"#),
+            (Some("InspiredGitHub".to_string()), "<pre style="),
+        ] {
+            let mut plugin = SyntaxHighlighting::new(theme);
+            let mut ranges = vec![];
+            for slice in input.windows(plugin.window_size()) {
+                if let Some(range) = plugin.check_slice(slice).unwrap() {
+                    ranges.push(range);
+                }
+            }
+            assert!(plugin.final_check(input.len() - 1).unwrap().is_none());
+            assert_eq!(ranges, [3..7]);
+
+            let output = plugin.replace_slice(&input[ranges[0].clone()]).unwrap();
+            dbg!(&output);
+            assert_eq!(output.len(), 1);
+            match &output[0] {
+                Event::Html(html) => {
+                    assert!(html.starts_with(prefix));
+                    assert!(html.contains("main"));
+                }
+                event => assert!(false, "Expected an html event, got {event:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn sh_test_line_annotations() {
+        use pretty_assertions::assert_eq;
+
+        let lines = super::LineAnnotations::parse("rust {1,3-4} linenos");
+        assert_eq!(lines.highlighted, vec![1..=1, 3..=4]);
+        assert!(lines.numbers);
+        assert_eq!(
+            super::LineAnnotations::parse("rust,ignore"),
+            super::LineAnnotations::default()
+        );
+
+        assert_eq!(
+            super::split_lines("<span a>x\n<span b>y\nz</span>\n</span>"),
+            vec![
+                String::from("<span a>x</span>"),
+                String::from("<span a><span b>y</span></span>"),
+                String::from("<span a><span b>z</span></span>"),
+            ]
+        );
+
+        assert_eq!(
+            lines.wrap(String::from("a\nb\nc\n")),
+            concat!(
+                r#"<span class="line highlighted" data-line="1">a"#,
+                "\n</span>",
+                r#"<span class="line" data-line="2">b"#,
+                "\n</span>",
+                r#"<span class="line highlighted" data-line="3">c"#,
+                "\n</span>",
+            )
+        );
+
+        let plugin = SyntaxHighlighting::new(None);
+        let info = "rust {2}";
+        let input = [
+            (
+                0,
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info.into()))),
+            ),
+            (1, Event::Text(CowStr::Borrowed("/* a\nb */\n"))),
+            (
+                2,
+                Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(info.into()))),
+            ),
+        ];
+        match &plugin.replace_slice(&input).unwrap()[..] {
+            [Event::Html(html)] => {
+                assert!(html.starts_with(
+                    r#"<pre class="code"><code class="language-rust"><span class="line">"#
+                ));
+                assert_eq!(html.matches(r#"<span class="line"#).count(), 2);
+                assert_eq!(
+                    html.matches(r#"<span class="line highlighted">"#).count(),
+                    1
+                );
+            }
+            events => assert!(false, "Expected an html event, got {events:?}"),
+        }
+    }
+
+    #[test]
+    fn sh_test_escapes_language() {
+        let plugin = SyntaxHighlighting::new(None);
+        let info = r#""><img/src/onerror=alert(1)>"#;
+        let input = [
+            (
+                0,
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info.into()))),
+            ),
+            (1, Event::Text(CowStr::Borrowed("x\n"))),
+            (
+                2,
+                Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(info.into()))),
+            ),
+        ];
+        match &plugin.replace_slice(&input).unwrap()[..] {
+            [Event::Html(html)] => {
+                assert!(html.starts_with(
+                    r#"<pre class="code"><code class="language-&quot;&gt;&lt;img/src/onerror=alert(1)&gt;">"#
+                ));
+                assert!(!html.contains("<img"));
+            }
+            events => assert!(false, "Expected an html event, got {events:?}"),
+        }
+    }
+
+    #[test]
+    fn ha_test_check_and_replace_slice() {
+        let h2 = |id| Tag::Heading(HeadingLevel::H2, id, vec![]);
+        let input = [
+            (0, Event::Start(h2(None))),
+            (1, Event::Text(CowStr::Borrowed("Hello, "))),
+            (2, Event::Code(CowStr::Borrowed("World"))),
+            (3, Event::End(h2(None))),
+            (4, Event::Start(h2(None))),
+            (5, Event::Text(CowStr::Borrowed("hello world"))),
+            (6, Event::End(h2(None))),
+            (7, Event::Start(h2(Some("custom")))),
+            (8, Event::Text(CowStr::Borrowed("Custom"))),
+            (9, Event::End(h2(Some("custom")))),
+        ];
+
+        let mut plugin = HeadingAnchors::default();
+        let mut ranges = vec![];
+        for slice in input.windows(plugin.window_size()) {
+            if let Some(range) = plugin.check_slice(slice).unwrap() {
+                ranges.push(range);
+            }
+        }
+        assert!(plugin.final_check(input.len() - 1).unwrap().is_none());
+        assert_eq!(ranges, [0..4, 4..7, 7..10]);
+
+        let output = plugin.replace_slice(&input[ranges[0].clone()]).unwrap();
+        assert_eq!(
+            output,
+            [
+                Event::Html(CowStr::Borrowed(r#"<h2 id="hello-world">"#)),
+                Event::Text(CowStr::Borrowed("Hello, ")),
+                Event::Code(CowStr::Borrowed("World")),
+                Event::Html(CowStr::Borrowed(
+                    r##"<a class="anchor" href="#hello-world" aria-hidden="true">#</a></h2>"##
+                )),
+                Event::SoftBreak,
+            ]
+        );
+
+        let output = plugin.replace_slice(&input[ranges[1].clone()]).unwrap();
+        assert_eq!(
+            output[0],
+            Event::Html(CowStr::Borrowed(r#"<h2 id="hello-world-1">"#))
+        );
+
+        let output = plugin.replace_slice(&input[ranges[2].clone()]).unwrap();
+        assert_eq!(
+            output[0],
+            Event::Html(CowStr::Borrowed(r#"<h2 id="custom">"#))
+        );
+    }
+
+    #[test]
+    fn mermaid_test_check_and_replace_slice() {
+        let fenced = |info| Tag::CodeBlock(CodeBlockKind::Fenced(CowStr::Borrowed(info)));
+        let input = [
+            (0, Event::Start(fenced("rust"))),
+            (1, Event::Text(CowStr::Borrowed("fn main() {}\n"))),
+            (2, Event::End(fenced("rust"))),
+            (3, Event::Start(fenced("mermaid"))),
+            (4, Event::Text(CowStr::Borrowed("graph TD\n"))),
+            (5, Event::Text(CowStr::Borrowed("A --> B\n"))),
+            (6, Event::End(fenced("mermaid"))),
+        ];
+
+        let mut plugin = Mermaid::default();
+        let mut ranges = vec![];
+        for slice in input.windows(plugin.window_size()) {
+            if let Some(range) = plugin.check_slice(slice).unwrap() {
+                ranges.push(range);
+            }
+        }
+        assert!(plugin.final_check(input.len() - 1).unwrap().is_none());
+        assert_eq!(ranges, vec![3..7]);
+
+        let output = plugin.replace_slice(&input[ranges[0].clone()]).unwrap();
+        assert_eq!(
+            output,
+            [Event::Html(CowStr::Borrowed(
+                "<pre class=\"mermaid\">graph TD\nA --&gt; B\n</pre>\n"
+            ))]
+        );
+    }
+
+    #[test]
+    fn code_test_title_and_copy() {
+        assert_eq!(
+            super::code_title(r#"rust title="main.rs""#),
+            Some("main.rs")
+        );
+        assert_eq!(
+            super::code_title("toml title='Cargo.toml'"),
+            Some("Cargo.toml")
+        );
+        assert_eq!(super::code_title("sh,title=run.sh,ignore"), Some("run.sh"));
+        assert_eq!(super::code_title("rust subtitle=main.rs"), None);
+        assert_eq!(super::code_title(r#"rust title="""#), None);
+
+        let fenced = |info| Tag::CodeBlock(CodeBlockKind::Fenced(CowStr::Borrowed(info)));
+        let input = [
+            (0, Event::Start(fenced(r#"rust title="<main>.rs""#))),
+            (1, Event::Text(CowStr::Borrowed("fn main() {}\n"))),
+            (2, Event::End(fenced(r#"rust title="<main>.rs""#))),
+            (3, Event::Start(fenced("sh"))),
+            (4, Event::Text(CowStr::Borrowed("cargo run\n"))),
+            (5, Event::End(fenced("sh"))),
+        ];
+
+        let mut plugin = CodeBlocks::new(false);
+        let mut ranges = vec![];
+        for slice in input.windows(plugin.window_size()) {
+            if let Some(range) = plugin.check_slice(slice).unwrap() {
+                ranges.push(range);
+            }
+        }
+        assert_eq!(ranges, vec![0..3]);
+
+        let output = plugin.replace_slice(&input[ranges[0].clone()]).unwrap();
+        assert_eq!(output.len(), 5);
+        assert_eq!(
+            output[0],
+            Event::Html(CowStr::Borrowed(concat!(
+                r#"<div class="code-block"><div class="code-header">"#,
+                r#"<span class="code-title">&lt;main&gt;.rs</span></div>"#,
+                "\n"
+            )))
+        );
+        assert_eq!(output[4], Event::Html(CowStr::Borrowed("</div>\n")));
+
+        let mut plugin = CodeBlocks::new(true);
+        let mut ranges = vec![];
+        for slice in input.windows(plugin.window_size()) {
+            if let Some(range) = plugin.check_slice(slice).unwrap() {
+                ranges.push(range);
+            }
+        }
+        assert_eq!(ranges, vec![0..3, 3..6]);
+
+        let output = plugin.replace_slice(&input[ranges[1].clone()]).unwrap();
+        assert_eq!(
+            output[0],
+            Event::Html(CowStr::Borrowed(concat!(
+                r#"<div class="code-block"><div class="code-header">"#,
+                r#"<button class="code-copy" type="button" aria-label="Copy code">Copy</button></div>"#,
+                "\n"
+            )))
+        );
+    }
+
+    #[test]
+    fn lr_test_rewrite() {
+        use pretty_assertions::assert_eq;
+
+        let plugin = LinkRewriting::new(
+            "html".to_string(),
+            vec!["md".to_string(), "markdown".to_string()],
+        );
+        assert_eq!(plugin.rewrite("foo.md").as_deref(), Some("foo.html"));
+        assert_eq!(plugin.rewrite("foo.markdown").as_deref(), Some("foo.html"));
+        assert_eq!(
+            plugin.rewrite("../docs/foo.md#usage").as_deref(),
+            Some("../docs/foo.html#usage")
+        );
+        assert_eq!(
+            plugin.rewrite("/foo.md?q=1").as_deref(),
+            Some("/foo.html?q=1")
+        );
+        assert_eq!(plugin.rewrite("https://example.com/foo.md"), None);
+        assert_eq!(plugin.rewrite("//example.com/foo.md"), None);
+        assert_eq!(plugin.rewrite("#foo.md"), None);
+        assert_eq!(plugin.rewrite("foo.mdx"), None);
+        assert_eq!(plugin.rewrite("foo.html"), None);
+        assert_eq!(plugin.rewrite("foomd"), None);
+    }
+
+    #[test]
+    fn lr_test_map() {
+        use pretty_assertions::assert_eq;
+
+        let link = |href| {
+            Tag::Link(
+                LinkType::Inline,
+                CowStr::Borrowed(href),
+                CowStr::Borrowed(""),
+            )
+        };
+        let mut plugin = LinkRewriting::new("html".to_string(), vec!["md".to_string()]);
+        assert_eq!(
+            plugin.map(Event::Start(link("./foo.md#bar"))).unwrap(),
+            Event::Start(link("./foo.html#bar"))
+        );
+        assert_eq!(
+            plugin
+                .map(Event::Start(link("https://example.com/foo.md")))
+                .unwrap(),
+            Event::Start(link("https://example.com/foo.md"))
+        );
+        assert_eq!(
+            plugin.map(Event::Text(CowStr::Borrowed("foo.md"))).unwrap(),
+            Event::Text(CowStr::Borrowed("foo.md"))
+        );
+    }
+
+    #[test]
+    fn el_test_map() {
+        use pretty_assertions::assert_eq;
+
+        let link = |href, title| {
+            Tag::Link(
+                LinkType::Inline,
+                CowStr::Borrowed(href),
+                CowStr::Borrowed(title),
+            )
+        };
+        let input = [
+            Event::Start(link("./local.html", "")),
+            Event::Text(CowStr::Borrowed("local")),
+            Event::End(link("./local.html", "")),
+            Event::Start(link("https://example.com/?a=1&b=2", "A \"title\"")),
+            Event::Text(CowStr::Borrowed("external")),
+            Event::End(link("https://example.com/?a=1&b=2", "A \"title\"")),
+        ];
+
+        let mut plugin =
+            ExternalLinks::new("noopener noreferrer".to_string(), "_blank".to_string());
+        let output: Vec<_> = input
+            .clone()
+            .into_iter()
+            .map(|e| plugin.map(e).unwrap())
+            .collect();
+        assert_eq!(output[..3], input[..3]);
+        assert_eq!(
+            output[3],
+            Event::Html(CowStr::Borrowed(
+                r#"<a href="https://example.com/?a=1&amp;b=2" title="A &quot;title&quot;" rel="noopener noreferrer" target="_blank">"#
+            ))
+        );
+        assert_eq!(output[4..], input[4..]);
+
+        let mut plugin = ExternalLinks::new(String::new(), "_blank".to_string());
+        assert_eq!(
+            plugin.map(input[3].clone()).unwrap(),
+            Event::Html(CowStr::Borrowed(
+                r#"<a href="https://example.com/?a=1&amp;b=2" title="A &quot;title&quot;" target="_blank">"#
+            ))
+        );
+    }
+
+    #[test]
+    fn registry_test_construct() {
+        use serde_json::{json, Value};
+
+        let mut registry = PluginRegistry::default();
+        assert!(matches!(
+            registry.construct("emoji", &Value::Null),
+            Ok(Stage::Stream(_))
+        ));
+        assert!(matches!(
+            registry.construct("highlight", &json!({ "theme": "InspiredGitHub" })),
+            Ok(Stage::Window(_))
+        ));
+
+        match registry.construct("collapsible_headers", &Value::Null) {
+            Ok(_) => assert!(false, "Should have returned an error."),
+            Err(error) => assert_eq!(
+                error.to_string(),
+                "Invalid config for the collapsible_headers plugin."
+            ),
+        }
+        match registry.construct("custom", &Value::Null) {
+            Ok(_) => assert!(false, "Should have returned an error."),
+            Err(error) => assert_eq!(error.to_string(), "Unknown plugin custom."),
+        }
+
+        registry.register("custom", |_| Ok(Stage::Stream(Box::<Emoji>::default())));
+        assert!(registry.contains("custom"));
+        assert!(registry.construct("custom", &Value::Null).is_ok());
+    }
+
+    #[test]
+    fn fn_test_render() {
+        use indoc::indoc;
+        use pretty_assertions::assert_eq;
+        use pulldown_cmark::{html, Options, Parser};
+
+        let input = "Text[^a] and[^b] again[^a].\n\n[^b]: Note *b*.\n\n[^a]: Note a.\n";
+        let render = |plugin: Footnotes| {
+            let events = Parser::new_ext(input, Options::ENABLE_FOOTNOTES).collect();
+            let mut output = String::new();
+            html::push_html(&mut output, plugin.render(events).into_iter());
+            output
+        };
+
+        let plugin = Footnotes::new(
+            Some("Notes".to_string()),
+            "↩".to_string(),
+            FootnotePlacement::End,
+        );
+        assert_eq!(
+            render(plugin),
+            indoc! {r##"
+            <p>Text<sup class="footnote-reference" id="fnref-a"><a href="#a">1</a></sup> and<sup class="footnote-reference" id="fnref-b"><a href="#b">2</a></sup> again<sup class="footnote-reference" id="fnref-a-2"><a href="#a">1</a></sup>.</p>
+            <section class="footnotes">
+            <h2>Notes</h2>
+            <ol>
+            <li id="a" value="1">
+            <p>Note a. <a href="#fnref-a" class="footnote-backref">↩</a> <a href="#fnref-a-2" class="footnote-backref">↩</a></p>
+            </li>
+            <li id="b" value="2">
+            <p>Note <em>b</em>. <a href="#fnref-b" class="footnote-backref">↩</a></p>
+            </li>
+            </ol>
+            </section>
+            "##}
+        );
+
+        let plugin = Footnotes::new(None, "^".to_string(), FootnotePlacement::Inline);
+        assert_eq!(
+            render(plugin),
+            indoc! {r##"
+            <p>Text<sup class="footnote-reference" id="fnref-a"><a href="#a">1</a></sup> and<sup class="footnote-reference" id="fnref-b"><a href="#b">2</a></sup> again<sup class="footnote-reference" id="fnref-a-2"><a href="#a">1</a></sup>.</p>
+            <div class="footnote-definition" id="b"><sup class="footnote-definition-label">2</sup>
+            <p>Note <em>b</em>. <a href="#fnref-b" class="footnote-backref">^</a></p>
+            </div>
+            <div class="footnote-definition" id="a"><sup class="footnote-definition-label">1</sup>
+            <p>Note a. <a href="#fnref-a" class="footnote-backref">^</a> <a href="#fnref-a-2" class="footnote-backref">^</a></p>
+            </div>
+            "##}
+        );
+    }
+
+    #[test]
+    fn shift_test_map() {
+        use pretty_assertions::assert_eq;
+
+        let heading = |level| Tag::Heading(level, Some("id"), vec!["class"]);
+        let mut plugin = ShiftHeadings::new(2);
+        assert_eq!(
+            plugin.map(Event::Start(heading(HeadingLevel::H1))).unwrap(),
+            Event::Start(heading(HeadingLevel::H3))
+        );
+        assert_eq!(
+            plugin.map(Event::End(heading(HeadingLevel::H5))).unwrap(),
+            Event::End(heading(HeadingLevel::H6))
+        );
+        assert_eq!(
+            plugin.map(Event::Text(CowStr::Borrowed("h1"))).unwrap(),
+            Event::Text(CowStr::Borrowed("h1"))
+        );
+    }
 }
@@ -0,0 +1,118 @@
+use pulldown_cmark::{CowStr, Event};
+
+/// The opening & closing quotes of a locale, for quotes & quotes within
+/// quotes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quotes {
+    pub double: (&'static str, &'static str),
+    pub single: (&'static str, &'static str),
+}
+
+impl Quotes {
+    /// The quotes of `locale`, a language tag such as `fr` or `de-CH`,
+    /// matching on the language alone unless the region changes the quotes.
+    pub fn for_locale(locale: &str) -> Option<Quotes> {
+        let locale = locale.to_lowercase().replace('_', "-");
+        let language = locale.split('-').next().unwrap_or_default();
+        let (double, single) = match (locale.as_str(), language) {
+            ("de-ch" | "fr-ch", _) => (("«", "»"), ("‹", "›")),
+            (_, "en" | "nl" | "zh") => (("“", "”"), ("‘", "’")),
+            (_, "de" | "cs" | "sk") => (("„", "“"), ("‚", "‘")),
+            (_, "fr") => (("«\u{a0}", "\u{a0}»"), ("‹\u{a0}", "\u{a0}›")),
+            (_, "es" | "it" | "pt" | "ca") => (("«", "»"), ("“", "”")),
+            (_, "ru" | "uk") => (("«", "»"), ("„", "“")),
+            (_, "pl" | "ro" | "hu") => (("„", "”"), ("«", "»")),
+            (_, "sv" | "fi") => (("”", "”"), ("’", "’")),
+            (_, "da") => (("»", "«"), ("›", "‹")),
+            (_, "ja") => (("「", "」"), ("『", "』")),
+            _ => return None,
+        };
+        Some(Quotes { double, single })
+    }
+}
+
+/// Swaps the english curly quotes smart punctuation writes in `events` for
+/// `quotes`. A `’` between two letters or digits is kept as an apostrophe,
+/// as is any with no `‘` open before it.
+pub fn localize(events: &mut [Event], quotes: Quotes) {
+    // The first character of the text straight after each event.
+    let mut following = vec![None; events.len()];
+    let mut next = None;
+    for (index, event) in events.iter().enumerate().rev() {
+        following[index] = next;
+        next = match event {
+            Event::Text(text) => text.chars().next(),
+            _ => None,
+        };
+    }
+
+    let mut previous: Option<char> = None;
+    let mut open = 0_usize;
+    for (index, event) in events.iter_mut().enumerate() {
+        let Event::Text(text) = event else {
+            previous = None;
+            continue;
+        };
+        if !text.contains(['“', '”', '‘', '’']) {
+            previous = text.chars().next_back().or(previous);
+            continue;
+        }
+
+        let mut localized = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            let next = chars.peek().copied().or(following[index]);
+            let apostrophe = previous.is_some_and(char::is_alphanumeric)
+                && next.is_some_and(char::is_alphanumeric);
+            match c {
+                '“' => localized.push_str(quotes.double.0),
+                '”' => localized.push_str(quotes.double.1),
+                '‘' => {
+                    open += 1;
+                    localized.push_str(quotes.single.0);
+                }
+                '’' if open > 0 && !apostrophe => {
+                    open -= 1;
+                    localized.push_str(quotes.single.1);
+                }
+                c => localized.push(c),
+            }
+            previous = Some(c);
+        }
+        *event = Event::Text(CowStr::Boxed(localized.into()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Options, Parser};
+
+    fn render(source: &str, locale: &str) -> String {
+        let mut events: Vec<_> =
+            Parser::new_ext(source, Options::ENABLE_SMART_PUNCTUATION).collect();
+        localize(&mut events, Quotes::for_locale(locale).unwrap());
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        html
+    }
+
+    #[test]
+    fn quotes_test_localize() {
+        let source = "\"It's 'quoted' *here*,\" she said.";
+        assert_eq!(
+            render(source, "en-GB"),
+            "<p>“It’s ‘quoted’ <em>here</em>,” she said.</p>\n"
+        );
+        assert_eq!(
+            render(source, "de"),
+            "<p>„It’s ‚quoted‘ <em>here</em>,“ she said.</p>\n"
+        );
+        assert_eq!(
+            render(source, "fr_FR"),
+            "<p>«\u{a0}It’s ‹\u{a0}quoted\u{a0}› <em>here</em>,\u{a0}» she said.</p>\n"
+        );
+        assert_eq!(render("'don't'", "de-CH"), "<p>‹don’t›</p>\n");
+        assert_eq!(Quotes::for_locale("tlh"), None);
+    }
+}
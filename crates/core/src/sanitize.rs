@@ -0,0 +1,33 @@
+use ammonia::Builder;
+use serde_derive::{Deserialize, Serialize};
+
+/// Tags and attributes allowed through `--sanitize`, on top of ammonia's
+/// defaults. Only settable from a config file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Allowlist {
+    /// Extra tags to keep, e.g. `video`.
+    pub tags: Vec<String>,
+    /// Extra attributes to keep on every tag, e.g. `data-id`.
+    pub attributes: Vec<String>,
+}
+
+/// Removes anything that could run script from `html`, like `<script>`
+/// elements, event handler attributes and `javascript:` urls.
+///
+/// The ids, classes and link targets added by serve_md's own plugins are
+/// always kept.
+pub fn clean(html: &str, allowlist: Option<&Allowlist>) -> String {
+    let mut builder = Builder::default();
+    builder
+        .add_generic_attributes(["id", "class", "aria-hidden"])
+        .add_tag_attributes("a", ["target"]);
+
+    if let Some(allowlist) = allowlist {
+        builder
+            .add_tags(allowlist.tags.iter().map(String::as_str))
+            .add_generic_attributes(allowlist.attributes.iter().map(String::as_str));
+    }
+
+    builder.clean(html).to_string()
+}
@@ -0,0 +1,350 @@
+use crate::{
+    collection::{self, find_markdown},
+    make_commonmark_parser,
+    state::State,
+};
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use pulldown_cmark::{Event, Tag};
+use serde_derive::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    str,
+    sync::Arc,
+};
+use tracing::{debug, trace};
+
+/// BM25's term frequency saturation & length normalisation.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+/// The words kept either side of the first match in a snippet.
+const SNIPPET_BEFORE: usize = 8;
+const SNIPPET_AFTER: usize = 16;
+
+/// A match for a search query, best first.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchResult {
+    /// The path relative to the indexed root, using `/` as the separator.
+    pub path: String,
+    pub snippet: String,
+    pub score: f64,
+}
+
+#[derive(Debug)]
+struct IndexedDocument {
+    text: String,
+    terms: usize,
+    /// Each distinct term, so removing the document only touches its own
+    /// postings.
+    vocabulary: Vec<String>,
+    /// When a scheduled document becomes searchable.
+    published: Option<DateTime<FixedOffset>>,
+}
+
+/// An inverted index over the plain text of every markdown file under a root,
+/// which can be updated a file at a time.
+#[derive(Debug)]
+pub struct SearchIndex {
+    root: PathBuf,
+    state: Arc<State>,
+    documents: BTreeMap<String, IndexedDocument>,
+    /// The number of times each term appears in each document.
+    postings: HashMap<String, HashMap<String, usize>>,
+}
+
+impl SearchIndex {
+    /// Indexes every markdown file under `root`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a directory under `root` can't be read.
+    pub fn build(root: &Path, state: Arc<State>) -> Result<SearchIndex> {
+        let mut index = SearchIndex {
+            root: root.to_path_buf(),
+            state,
+            documents: BTreeMap::new(),
+            postings: HashMap::new(),
+        };
+        let mut paths = vec![];
+        find_markdown(
+            root,
+            &index.state.markdown_extensions(),
+            &index.state,
+            &mut paths,
+        )?;
+        for path in paths {
+            index.update(&path);
+        }
+        debug!(documents = index.documents.len(), "Built search index.");
+        Ok(index)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The number of documents indexed.
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// The `State::generation` of the options the index was built with.
+    pub fn generation(&self) -> u64 {
+        self.state.generation
+    }
+
+    /// Re-indexes `path`, removing it when it no longer exists or isn't
+    /// under root.
+    pub fn update(&mut self, path: &Path) {
+        let Some(key) = self.key(path) else {
+            return;
+        };
+        self.remove(&key);
+        if collection::is_ignored(path, &self.state) {
+            return;
+        }
+        if !collection::within_max_file_size(path, &self.state) {
+            return;
+        }
+        let Ok(buf) = fs::read(path) else {
+            return;
+        };
+        let Ok(markdown) = str::from_utf8(&buf) else {
+            return;
+        };
+        let front_matter = self.state.source_front_matter(markdown);
+        if front_matter
+            .as_ref()
+            .is_some_and(|front_matter| self.state.is_draft(front_matter))
+        {
+            trace!(key, "Skipping draft.");
+            return;
+        }
+        // Scheduled documents are indexed now, but left out of results until published.
+        let published = front_matter
+            .as_ref()
+            .and_then(|front_matter| self.state.publish_date(front_matter));
+
+        let text = plain_text(markdown, &self.state);
+        let tokens = tokenize(&text);
+        let mut frequencies: HashMap<String, usize> = HashMap::new();
+        for token in &tokens {
+            *frequencies.entry(token.clone()).or_default() += 1;
+        }
+        let vocabulary = frequencies.keys().cloned().collect();
+        for (token, frequency) in frequencies {
+            self.postings
+                .entry(token)
+                .or_default()
+                .insert(key.clone(), frequency);
+        }
+        trace!(key, terms = tokens.len(), "Indexed.");
+        self.documents.insert(
+            key,
+            IndexedDocument {
+                text,
+                terms: tokens.len(),
+                vocabulary,
+                published,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &str) {
+        let Some(document) = self.documents.remove(key) else {
+            return;
+        };
+        for term in &document.vocabulary {
+            let Some(documents) = self.postings.get_mut(term) else {
+                continue;
+            };
+            documents.remove(key);
+            if documents.is_empty() {
+                self.postings.remove(term);
+            }
+        }
+    }
+
+    /// The path relative to root of a markdown file.
+    fn key(&self, path: &Path) -> Option<String> {
+        if !self.state.is_markdown(path) {
+            return None;
+        }
+        path.strip_prefix(&self.root)
+            .ok()
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+    }
+
+    /// Ranks documents containing any term of `query` with BM25.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchResult> {
+        let terms = tokenize(query);
+        if terms.is_empty() || self.documents.is_empty() {
+            return vec![];
+        }
+
+        let count = self.documents.len() as f64;
+        let average = self.documents.values().map(|d| d.terms).sum::<usize>() as f64 / count;
+        let mut scores: HashMap<&str, f64> = HashMap::new();
+        for term in &terms {
+            let Some(documents) = self.postings.get(term) else {
+                continue;
+            };
+            let found = documents.len() as f64;
+            let idf = ((count - found + 0.5) / (found + 0.5) + 1.0).ln();
+            for (key, frequency) in documents {
+                let terms = self.documents.get(key).map_or(0, |d| d.terms) as f64;
+                let frequency = *frequency as f64;
+                let score = idf * (frequency * (K1 + 1.0))
+                    / (frequency + K1 * (1.0 - B + B * terms / average.max(1.0)));
+                *scores.entry(key).or_default() += score;
+            }
+        }
+
+        let now = Utc::now();
+        let mut results: Vec<SearchResult> = scores
+            .into_iter()
+            .filter(|(key, _)| {
+                self.documents
+                    .get(key)
+                    .and_then(|d| d.published)
+                    .map_or(true, |published| published <= now)
+            })
+            .map(|(key, score)| SearchResult {
+                path: key.to_string(),
+                snippet: self
+                    .documents
+                    .get(key)
+                    .map(|d| snippet(&d.text, &terms))
+                    .unwrap_or_default(),
+                score,
+            })
+            .collect();
+        results.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        results.truncate(limit);
+        results
+    }
+}
+
+/// The text of `markdown`, without front matter or markup.
+pub fn plain_text(markdown: &str, state: &Arc<State>) -> String {
+    let content = state
+        .front_matter
+        .and_then(|fm| fm.as_pod(markdown))
+        .and_then(|(_, content)| String::from_utf8(content).ok());
+    let content = content.as_deref().unwrap_or(markdown);
+
+    let mut text = String::with_capacity(content.len());
+    for event in make_commonmark_parser(content, state) {
+        match event {
+            Event::Text(value) | Event::Code(value) => text.push_str(&value),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(
+                Tag::Paragraph
+                | Tag::Heading(..)
+                | Tag::Item
+                | Tag::CodeBlock(_)
+                | Tag::TableCell
+                | Tag::BlockQuote,
+            ) => text.push('\n'),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Lowercased words, split on anything that isn't alphanumeric.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The words around the first one matching any of `terms`, or the start of
+/// `text` without a match.
+fn snippet(text: &str, terms: &[String]) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let position = words
+        .iter()
+        .position(|word| tokenize(word).iter().any(|token| terms.contains(token)))
+        .unwrap_or(0);
+    let start = position.saturating_sub(SNIPPET_BEFORE);
+    let end = (position + SNIPPET_AFTER).min(words.len());
+
+    let mut snippet = words[start..end].join(" ");
+    if start > 0 {
+        snippet.insert_str(0, "… ");
+    }
+    if end < words.len() {
+        snippet.push_str(" …");
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plain_text, snippet, tokenize, SearchIndex};
+    use crate::{formats::Matter, state::State};
+    use std::{fs, sync::Arc};
+
+    #[test]
+    fn search_test_plain_text() {
+        let mut state = State::default();
+        state.front_matter = Some(Matter::Yaml);
+        let state = Arc::new(state);
+        let markdown = "---\ntitle: Hidden\n---\n# Heading\nSome *emphasised* `code`.\n";
+        assert_eq!(
+            plain_text(markdown, &state),
+            "Heading\nSome emphasised code.\n"
+        );
+    }
+
+    #[test]
+    fn search_test_snippet() {
+        let text = (1..=40)
+            .map(|n| format!("w{n}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(
+            snippet(&text, &tokenize("W20")),
+            "… w12 w13 w14 w15 w16 w17 w18 w19 w20 w21 w22 w23 w24 w25 w26 w27 w28 w29 w30 w31 w32 w33 w34 w35 …"
+        );
+    }
+
+    #[test]
+    fn search_test_build_update_and_search() {
+        let root = std::env::temp_dir().join("serve_md_search_test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.md"), "# Rust\nRust is fast. Rust is safe.\n").unwrap();
+        fs::write(root.join("sub/b.md"), "# Notes\nSome notes on rust.\n").unwrap();
+        fs::write(root.join("c.txt"), "rust").unwrap();
+
+        let mut index = SearchIndex::build(&root, Arc::new(State::default())).unwrap();
+        let results = index.search("rust", 10);
+        let paths: Vec<_> = results.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.md", "sub/b.md"]);
+        assert!(results[0].score > results[1].score);
+        assert_eq!(results[1].snippet, "Notes Some notes on rust.");
+
+        fs::write(root.join("a.md"), "# Go\n").unwrap();
+        index.update(&root.join("a.md"));
+        fs::remove_file(root.join("sub/b.md")).unwrap();
+        index.update(&root.join("sub/b.md"));
+        assert!(index.search("rust", 10).is_empty());
+        assert!(!index.postings.contains_key("rust"));
+        assert_eq!(index.search("go", 10)[0].path, "a.md");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::Payload;
+use anyhow::Result;
+use std::{collections::HashMap, fmt::Debug, io::Write, sync::Arc};
+
+/// Writes a `Payload` in a format that isn't built in, e.g. protobuf or
+/// Avro, for requests with its registered extension.
+pub trait PayloadSerializer: Send + Sync {
+    /// The media type of a response in this format.
+    fn content_type(&self) -> &str;
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the payload fails to serialize or `writer` fails.
+    fn write(&self, payload: &Payload, writer: &mut dyn Write) -> Result<()>;
+}
+
+/// Maps extensions, without the leading `.`, to serializers for formats that
+/// aren't built in. Built in formats always take precedence.
+#[derive(Clone, Default)]
+pub struct SerializerRegistry {
+    serializers: HashMap<String, Arc<dyn PayloadSerializer>>,
+}
+
+impl SerializerRegistry {
+    /// Adds `extension`, replacing any serializer already registered for it.
+    pub fn register(
+        &mut self,
+        extension: impl Into<String>,
+        serializer: impl PayloadSerializer + 'static,
+    ) {
+        self.serializers
+            .insert(extension.into(), Arc::new(serializer));
+    }
+
+    pub fn get(&self, extension: &str) -> Option<&Arc<dyn PayloadSerializer>> {
+        self.serializers.get(extension)
+    }
+
+    pub fn contains(&self, extension: &str) -> bool {
+        self.serializers.contains_key(extension)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.serializers.is_empty()
+    }
+}
+
+impl Debug for SerializerRegistry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_set().entries(self.serializers.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_payload_from_slice;
+
+    struct Lines;
+
+    impl PayloadSerializer for Lines {
+        fn content_type(&self) -> &str {
+            "text/plain; charset=utf-8"
+        }
+
+        fn write(&self, payload: &Payload, writer: &mut dyn Write) -> Result<()> {
+            for line in payload.html.lines() {
+                writeln!(writer, "> {line}")?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn serializer_test_into_response_with() {
+        let mut registry = SerializerRegistry::default();
+        registry.register("txt", Lines);
+        assert!(registry.contains("txt"));
+        assert_eq!(format!("{registry:?}"), r#"{"txt"}"#);
+
+        let serializer = registry.get("txt").expect("Registered serializer.");
+        let payload = generate_payload_from_slice(b"# Header\nsome text.", Arc::default())
+            .and_then(|payload| payload.into_response_with(serializer.as_ref()));
+        match payload {
+            Ok(buf) => assert_eq!(
+                String::from_utf8_lossy(&buf),
+                "> <h1>Header</h1>\n> <p>some text.</p>\n"
+            ),
+            Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+        }
+    }
+}
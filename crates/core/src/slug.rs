@@ -0,0 +1,179 @@
+use clap::ValueEnum;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How `Slugger` turns heading text into a slug.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlugStyle {
+    /// Lowercased, dropping punctuation & replacing each space with `-`, as
+    /// GitHub does.
+    #[default]
+    Github,
+    /// Lowercased, replacing each run of anything other than letters &
+    /// digits with a single `-`.
+    Kebab,
+    /// Kebab case, transliterating accented latin letters to ascii & dropping
+    /// any other non ascii character, for ascii only anchors.
+    Ascii,
+}
+
+/// Generates heading slugs in a `SlugStyle`, deduplicating repeats by
+/// appending `-1`, `-2` etc, or the count after another separator.
+#[derive(Debug)]
+pub struct Slugger {
+    style: SlugStyle,
+    separator: String,
+    seen: HashMap<String, usize>,
+}
+
+impl Default for Slugger {
+    fn default() -> Slugger {
+        Slugger::new(SlugStyle::default(), "-")
+    }
+}
+
+impl Slugger {
+    /// `separator` goes between a repeated slug & its count, e.g. `_` for
+    /// `intro_1` as mkdocs does.
+    pub fn new(style: SlugStyle, separator: impl Into<String>) -> Slugger {
+        Slugger {
+            style,
+            separator: separator.into(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Slugs `text` in this sluggers style, as `section` when nothing is
+    /// left of it.
+    pub fn slug(&mut self, text: &str) -> String {
+        let text = text.trim().to_lowercase();
+        let slug = match self.style {
+            SlugStyle::Github => text
+                .chars()
+                .filter_map(|c| match c {
+                    ' ' => Some('-'),
+                    '-' | '_' => Some(c),
+                    c if c.is_alphanumeric() => Some(c),
+                    _ => None,
+                })
+                .collect::<String>(),
+            SlugStyle::Kebab => kebab(text.chars()),
+            SlugStyle::Ascii => {
+                let mut ascii = String::with_capacity(text.len());
+                for c in text.chars() {
+                    match c {
+                        c if c.is_ascii() => ascii.push(c),
+                        c => ascii.push_str(transliterate(c)),
+                    }
+                }
+                kebab(ascii.chars())
+            }
+        };
+
+        self.unique(if slug.is_empty() {
+            "section".to_string()
+        } else {
+            slug
+        })
+    }
+
+    /// Returns `slug` unchanged the first time it's seen, otherwise suffixed
+    /// with the next unused count.
+    pub fn unique(&mut self, slug: String) -> String {
+        let Some(mut count) = self.seen.get(&slug).copied() else {
+            self.seen.insert(slug.clone(), 0);
+            return slug;
+        };
+
+        let candidate = loop {
+            count += 1;
+            let candidate = format!("{slug}{}{count}", self.separator);
+            if !self.seen.contains_key(&candidate) {
+                break candidate;
+            }
+        };
+        self.seen.insert(slug, count);
+        self.seen.insert(candidate.clone(), 0);
+        candidate
+    }
+}
+
+/// Joins the runs of letters & digits in `chars` with `-`.
+fn kebab(chars: impl Iterator<Item = char>) -> String {
+    let mut slug = String::new();
+    let mut separated = false;
+    for c in chars {
+        if c.is_alphanumeric() {
+            if separated && !slug.is_empty() {
+                slug.push('-');
+            }
+            separated = false;
+            slug.push(c);
+        } else {
+            separated = true;
+        }
+    }
+    slug
+}
+
+/// The ascii spelling of a lowercase latin letter, or nothing for any other
+/// character.
+fn transliterate(c: char) -> &'static str {
+    match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'ď' | 'đ' | 'ð' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'ĥ' | 'ħ' => "h",
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'ĵ' => "j",
+        'ķ' => "k",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'œ' => "oe",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'ś' | 'ŝ' | 'ş' | 'š' => "s",
+        'ß' => "ss",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'ŵ' => "w",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SlugStyle, Slugger};
+
+    #[test]
+    fn slugger_test_deduplicates() {
+        let mut slugger = Slugger::default();
+        assert_eq!(slugger.slug("Hello, World!"), "hello-world");
+        assert_eq!(slugger.slug("Hello World"), "hello-world-1");
+        assert_eq!(slugger.slug("hello world"), "hello-world-2");
+        assert_eq!(slugger.slug("hello-world-1"), "hello-world-1-1");
+        assert_eq!(slugger.slug("Ünïcode & `code`"), "ünïcode--code");
+        assert_eq!(slugger.slug("!!!"), "section");
+    }
+
+    #[test]
+    fn slugger_test_styles() {
+        let mut slugger = Slugger::new(SlugStyle::Kebab, "_");
+        assert_eq!(slugger.slug("Hello,  World!"), "hello-world");
+        assert_eq!(slugger.slug("hello world"), "hello-world_1");
+        assert_eq!(slugger.slug("Ünïcode & `code`"), "ünïcode-code");
+
+        let mut slugger = Slugger::new(SlugStyle::Ascii, "_");
+        assert_eq!(slugger.slug("Ünïcode & `code`"), "unicode-code");
+        assert_eq!(slugger.slug("Straße – Ærø"), "strasse-aero");
+        assert_eq!(slugger.slug("日本語"), "section");
+        assert_eq!(slugger.slug("日本"), "section_1");
+    }
+}
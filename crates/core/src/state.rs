@@ -1,16 +1,41 @@
+use crate::convert::Converter;
+use crate::error::{ConfigError, RenderError};
 use crate::formats::{Config, Matter};
+use crate::images;
+use crate::lint::LintRegistry;
+use crate::matter::parse_date;
+use crate::plugin::{Emoji, FootnotePlacement};
+use crate::sanitize::Allowlist;
+use crate::serializer::SerializerRegistry;
+use crate::slug::{SlugStyle, Slugger};
+use crate::template::{MatterInHtml, Theme};
 use anyhow::anyhow;
-use clap::Parser as CliParser;
+use chrono::{DateTime, FixedOffset, Utc};
+use clap::{
+    parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser as CliParser, ValueEnum,
+};
 use core::convert::TryFrom;
 use serde_derive::{Deserialize, Serialize};
+use tracing::{debug, trace, warn};
 
-use std::{ffi::OsStr, fs::File, io::Read, path::Path as SysPath, str};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
+    fs::File,
+    io::Read,
+    path::Path as SysPath,
+    str,
+    sync::OnceLock,
+    time::Instant,
+};
 
-#[cfg(feature = "server")]
 use std::env;
 
+#[cfg(feature = "server")]
+use std::net::{IpAddr, Ipv4Addr};
+
 // TODO idk if its appropiate rust to use an state object as a cli/bin - dual purpose and all?
-#[derive(Debug, Default, CliParser, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, CliParser, Deserialize, Serialize)]
 #[serde(default = "State::default")]
 pub struct State {
     // --- Http server options.
@@ -19,11 +44,138 @@ pub struct State {
     #[cfg_attr(feature = "server", arg(long))]
     pub root: Option<String>,
 
+    /// Directories served under url prefixes, e.g. `/docs` from `./docs`, each with its own rendering options, only settable from a config file
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(skip))]
+    pub mounts: BTreeMap<String, Mount>,
+
+    /// Named sets of rendering & plugin options, e.g. `api` or `minimal`, picked per request by the `X-Serve-Md-Profile` header or `?profile=`, only settable from a config file
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(skip))]
+    pub profiles: BTreeMap<String, Profile>,
+
     /// The port to bind the serve_md server too
     #[cfg(feature = "server")]
     #[cfg_attr(feature = "server", arg(long, default_value_t = 8083))]
     pub port: u16,
 
+    /// The address to bind the serve_md server to, e.g. 0.0.0.0 or :: [default: 127.0.0.1]
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, visible_alias = "bind"))]
+    pub host: Option<IpAddr>,
+
+    /// The public url of the serve_md server, used to build absolute links
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    pub base_url: Option<String>,
+
+    /// The path to a PEM encoded certificate, serving over HTTPS when set
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, requires = "tls_key"))]
+    pub tls_cert: Option<String>,
+
+    /// The path to the PEM encoded private key of the certificate
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, requires = "tls_cert"))]
+    pub tls_key: Option<String>,
+
+    /// Requires every request to send `Authorization: Bearer TOKEN`
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, value_name = "TOKEN"))]
+    pub auth_token: Option<String>,
+
+    /// Requires every request to send these HTTP basic auth credentials
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, value_name = "USER:PASS"))]
+    pub basic_auth: Option<String>,
+
+    /// Only answers clients from these addresses or CIDR ranges, e.g. `127.0.0.1,10.0.0.0/8,::1`
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, value_delimiter = ','))]
+    pub allow: Option<Vec<String>>,
+
+    /// The requests per second each client address can make, answering those over it with a 429
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, value_name = "PER_SECOND"))]
+    pub rate_limit: Option<u32>,
+
+    /// The log level or filter directives, e.g. `debug`, falling back to `RUST_LOG`
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    #[serde(skip)]
+    pub log_level: Option<String>,
+
+    /// Reloads open html pages when a .md file under root changes
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    pub watch: bool,
+
+    /// Enables POST /render, rendering markdown sent in the request body
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    pub render_endpoint: bool,
+
+    /// Enables POST /__admin/reload, reloading the config file without a restart, as SIGHUP does on unix
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    pub reload_endpoint: bool,
+
+    /// Enables the /__admin endpoints, flushing the HEAD cache, rebuilding the search index & showing the plugins & options, for requests sending `Authorization: Bearer TOKEN`
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, value_name = "TOKEN"))]
+    pub admin_token: Option<String>,
+
+    /// Enables GET /search?q=, a full text search of every .md file under root, kept up to date as files change
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    pub search: bool,
+
+    /// Redirects the `aliases` front matter paths of every .md file under root to it with a 301, kept up to date as files change
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    pub aliases: bool,
+
+    /// Enables GET /robots.txt, allowing every crawler everything but --robots-disallow
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    pub robots: bool,
+
+    /// The paths /robots.txt disallows crawlers from, e.g. `/drafts/,/private/`
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, value_delimiter = ','))]
+    pub robots_disallow: Option<Vec<String>>,
+
+    /// A directory of markdown pages named by status code, e.g. `404.md`, answering html requests failing with that status
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, value_name = "DIR"))]
+    pub error_pages: Option<String>,
+
+    /// Checks the links of every .md file under root, printing a json report of those broken, then exits
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    #[serde(skip)]
+    pub check: bool,
+
+    /// Also requests every external url when checking links with --check
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    pub check_external: bool,
+
+    /// The most threads rendering documents at once, off the async runtime [default: 512]
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long))]
+    pub render_threads: Option<usize>,
+
+    /// The most milliseconds a document can take to render, answering those taking longer with a 503
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, value_name = "MILLISECONDS"))]
+    pub render_timeout: Option<u64>,
+
+    /// Rewrites remote image urls to /img-cache/{hash}, downloading & caching them into DIR on the first request
+    #[cfg(feature = "server")]
+    #[cfg_attr(feature = "server", arg(long, value_name = "DIR"))]
+    pub image_cache: Option<String>,
+
     // The path to the .md file to load
     #[cfg(not(feature = "server"))]
     #[cfg_attr(not(feature = "server"), arg(short = 'i', long))]
@@ -34,7 +186,82 @@ pub struct State {
     #[cfg_attr(not(feature = "server"), arg(short, long))]
     pub output: Option<String>,
 
+    /// The number of files rendered at once when --file is a directory or glob pattern [default: one per core]
+    #[cfg(not(feature = "server"))]
+    #[cfg_attr(not(feature = "server"), arg(short, long))]
+    pub jobs: Option<usize>,
+
+    /// Re-renders the output whenever the input file, directory or glob pattern matches change
+    #[cfg(not(feature = "server"))]
+    #[cfg_attr(not(feature = "server"), arg(short, long))]
+    pub watch: bool,
+
+    /// Refuses to render files larger than BYTES, instead of reading them into memory
+    #[arg(long, value_name = "BYTES")]
+    pub max_file_size: Option<u64>,
+
+    /// Includes documents with `draft: true` front matter in feeds, search & directory renders
+    #[arg(long)]
+    pub show_drafts: bool,
+
+    /// Answers direct requests for drafts with a 404, unless --show-drafts is set
+    #[arg(long)]
+    pub drafts_not_found: bool,
+
+    /// Includes documents with a `publish_at`, or `date`, still to come, which are otherwise hidden & answered with a 404
+    #[arg(long)]
+    pub show_scheduled: bool,
+
+    /// Includes dotfiles & files matched by a `.gitignore` or `.mdignore` under root, which are otherwise left out of listings & answered with a 404
+    #[arg(long)]
+    pub show_ignored: bool,
+
+    /// Adds prev, next & breadcrumb links between the documents of a directory to their payloads, ordered by their `weight`, or `order`, front matter then file name
+    #[arg(long)]
+    pub navigation: bool,
+
+    /// Serves `page.fr.md` style translations of `page.md` by the Accept-Language header or `?lang=`, adding every language of a document to its payload
+    #[arg(long)]
+    pub i18n: bool,
+    /// The language of documents without a language suffix [default: en]
+    #[arg(long, value_name = "LANG")]
+    pub default_language: Option<String>,
+
+    /// Adds each document's last commit date, author & history from git to its payload
+    #[cfg(feature = "git")]
+    #[cfg_attr(feature = "git", arg(long))]
+    pub git: bool,
+    /// The most commits kept in a document's git history, defaulting to 10
+    #[cfg(feature = "git")]
+    #[cfg_attr(feature = "git", arg(long, value_name = "COMMITS"))]
+    pub git_history: Option<usize>,
+
+    /// The path to a page template html output is wrapped in
+    #[arg(long)]
+    pub template: Option<String>,
+    /// The built in stylesheet html output is styled with
+    #[arg(long, value_enum)]
+    pub theme: Option<Theme>,
+    /// The url of a stylesheet html output links to
+    #[arg(long)]
+    pub stylesheet: Option<String>,
+    /// Adds the front matter to html output as meta tags, a json-ld script or a table
+    #[arg(long, value_enum)]
+    pub matter_in_html: Option<MatterInHtml>,
+    /// Adds Open Graph & Twitter card meta tags to html output, from the title, description, image & author front matter
+    #[arg(long)]
+    pub social_meta: bool,
+    /// Open Graph & Twitter card meta tags replacing those from front matter, e.g. `og:site_name`, empty to leave one out, only settable from a config file
+    #[arg(skip)]
+    pub social_meta_overrides: BTreeMap<String, String>,
+    /// The url html output links to as "Edit this page", followed by the document's path relative to root, e.g. `https://github.com/user/repo/edit/main/`
+    #[arg(long, value_name = "URL")]
+    pub edit_base_url: Option<String>,
+
     // --- Markdown options.
+    /// Enables every GitHub flavoured extension, tables, strikethrough, task lists, autolinks & alerts
+    #[arg(long)]
+    pub gfm: bool,
     /// Enables parsing tables
     #[arg(short, long)]
     pub tables: bool,
@@ -47,47 +274,241 @@ pub struct State {
     /// Enables parsing tasklists
     #[arg(short = 'l', long)]
     pub tasklists: bool,
+    /// Renders task list checkboxes as enabled inputs, with the line of their task as `data-line`
+    #[arg(long)]
+    pub interactive_tasklists: bool,
+    /// Escapes raw html in the markdown, showing it as text instead
+    #[arg(long)]
+    pub no_inline_html: bool,
     /// Enables smart punctuation
     #[arg(short = 'p', long)]
     pub smart_punctuation: bool,
+    /// The locale of documents, e.g. `fr` or `de-CH`, choosing the quotes smart punctuation uses [default: en]
+    #[arg(long)]
+    pub locale: Option<String>,
     /// Enables header attributes
     #[arg(short = 'a', long)]
     pub header_attributes: bool,
     /// The type of front matter
     #[arg(short = 'm', long, value_enum)]
     pub front_matter: Option<Matter>,
+    /// Front matter every document starts with, overridden by its own, only settable from a config file
+    #[arg(skip)]
+    pub default_front_matter: serde_json::Map<String, serde_json::Value>,
+    /// The extensions of markdown files, tried in order when resolving a request, e.g. `md,markdown,mdown,mkd` [default: md]
+    #[arg(long, value_delimiter = ',')]
+    pub markdown_extensions: Option<Vec<String>>,
+    /// Commands converting other markup languages into html by file extension, e.g. `adoc` or `rst`, only settable from a config file
+    #[arg(skip)]
+    pub converters: BTreeMap<String, Converter>,
 
     // --- Plugin options.
+    /// The plugins to run in order, e.g. `emoji,highlight`, instead of those enabled by their own options
+    #[arg(long, value_delimiter = ',')]
+    pub plugins: Option<Vec<String>>,
+    /// Settings for each plugin by name, only settable from a config file
+    #[arg(skip)]
+    pub plugin_options: HashMap<String, serde_json::Value>,
+    /// Offsets every heading level by N, e.g. 1 turns h1 into h2, capped at h6
+    #[arg(long, value_name = "N")]
+    pub shift_headings: Option<u8>,
     /// Enables parsing emoji shortcodes, using GitHub flavoured shortcodes
     #[arg(short, long)]
     pub emoji_shortcodes: bool,
-    /// Enables converting headers into collapsible sections using the <details> element
+    /// The path to a toml, json or yaml file of custom shortcodes, each replaced by an emoji, image url or html
+    #[arg(long)]
+    pub emoji_map: Option<String>,
+    /// Enables typographic replacements outside of code, e.g. (c) to ©, (tm) to ™ and -> to →
+    #[arg(long)]
+    pub typography: bool,
+    /// Enables converting headers into collapsible sections using the <details> element, repeat to match more headers
     #[arg(short = 'k', long, value_parser = parse_collapsible_headers)]
-    pub collapsible_headers: Option<(u8, String)>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub collapsible_headers: Vec<(u8, String)>,
+    /// Renders collapsible sections closed, instead of open
+    #[arg(long)]
+    pub collapsible_closed: bool,
+    /// Enables rewriting relative links to .md files to use another extension [default: html]
+    #[arg(long, num_args = 0..=1, default_missing_value = "html")]
+    pub rewrite_links: Option<String>,
+    /// Enables adding rel and target attributes to links to absolute http(s) urls
+    #[arg(long)]
+    pub external_links: bool,
+    /// The rel attribute of external links, empty to leave it out [default: "noopener noreferrer"]
+    #[arg(long)]
+    pub external_link_rel: Option<String>,
+    /// The target attribute of external links, empty to leave it out [default: _blank]
+    #[arg(long)]
+    pub external_link_target: Option<String>,
+    /// Enables wrapping images that stand alone in a paragraph in a <figure>, captioned by their title or alt text
+    #[arg(long)]
+    pub figures: bool,
+    /// Adds loading="lazy" to figure images
+    #[arg(long)]
+    pub figure_lazy: bool,
+    /// Reads the width & height of figure images from `?w=300&h=200` in their url
+    #[arg(long)]
+    pub figure_sizes: bool,
+    /// The class of every table, e.g. `table striped`
+    #[arg(long)]
+    pub table_class: Option<String>,
+    /// Adds data-sortable to tables & data-column to their header cells, for client side sorting
+    #[arg(long)]
+    pub sortable_tables: bool,
+    /// The heading above footnotes placed at the end of the document
+    #[arg(long)]
+    pub footnote_heading: Option<String>,
+    /// The symbol of the backlink after a footnote to each of its references [default: ↩]
+    #[arg(long)]
+    pub footnote_backlink: Option<String>,
+    /// Where footnotes are placed, at the end of the document or where they are written [default: end]
+    #[arg(long, value_enum)]
+    pub footnote_placement: Option<FootnotePlacement>,
+    /// Enables `*[HTML]: HyperText Markup Language` abbreviation definitions, wrapping each use in <abbr>
+    #[arg(long)]
+    pub abbreviations: bool,
+    /// Enables giving every heading a unique id and a # anchor linking to it
+    #[arg(long)]
+    pub heading_anchors: bool,
+    /// How heading ids & toc slugs are made from heading text, github, kebab or ascii for kebab case transliterated to ascii [default: github]
+    #[arg(long, value_enum)]
+    pub slug_style: Option<SlugStyle>,
+    /// The separator before the count added to repeated slugs, e.g. `_` for `intro_1` as mkdocs does [default: -]
+    #[arg(long)]
+    pub slug_duplicates: Option<String>,
+    /// Enables adding a table of contents of every heading to the payload
+    #[arg(long)]
+    pub toc: bool,
+    /// Enables adding every link reference definition, wherever it is in the document, to the payload
+    #[arg(long)]
+    pub refdefs: bool,
+    /// Enables adding warnings about heading level jumps, empty links, bare urls & trailing whitespace in headings to the payload
+    #[arg(long)]
+    pub lint: bool,
+    /// Enables a header bar above fenced code blocks labelled with the filename from `title="main.rs"` in their info string
+    #[arg(long)]
+    pub code_titles: bool,
+    /// Adds a copy to clipboard button to the header bar of every fenced code block, loading its script in html output
+    #[arg(long)]
+    pub code_copy: bool,
+    /// Enables rendering mermaid fenced code blocks as diagrams, loading mermaid.js in html output
+    #[arg(long)]
+    pub mermaid: bool,
+    /// Enables syntax highlighting fenced code blocks, using css classes unless a theme is set
+    #[arg(long)]
+    pub highlight: bool,
+    /// The syntect theme highlighting colours are inlined from, e.g. InspiredGitHub
+    #[arg(long)]
+    pub highlight_theme: Option<String>,
+    /// Enables removing scripts, event handlers & other unsafe html from the output
+    #[arg(long)]
+    pub sanitize: bool,
+    /// Extra tags & attributes `--sanitize` keeps, only settable from a config file
+    #[arg(skip)]
+    pub sanitize_allowlist: Option<Allowlist>,
 
     // ---
-    /// Use a configuration file instead
+    /// When rendering is given up on, set per request from `render_timeout`
+    #[arg(skip)]
+    #[serde(skip)]
+    pub deadline: Option<Instant>,
+    /// Formats registered by an embedder, for extensions that aren't built in
+    #[arg(skip)]
+    #[serde(skip)]
+    pub serializers: SerializerRegistry,
+    /// Lint rules registered by an embedder, run along with the built in ones under `--lint`
+    #[arg(skip)]
+    #[serde(skip)]
+    pub lint_rules: LintRegistry,
+    /// How many times the server reloaded the config file, changing the `ETag` of every document, as files read on load may have changed
+    #[arg(skip)]
+    #[serde(skip)]
+    pub generation: u64,
+    /// A hash of every option, see `State::fingerprint`
+    #[arg(skip)]
+    #[serde(skip)]
+    fingerprint: Fingerprint,
+
+    /// Read options from a json, toml or yaml configuration file
     #[arg(short, long)]
     #[serde(skip)]
     config: Option<String>,
+
+    /// Prints the merged options as a json, toml or yaml config file, then exits
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    #[serde(skip)]
+    pub print_config: Option<Config>,
+
+    /// The options set on the command line, which take precedence over the
+    /// environment & config file.
+    #[arg(skip)]
+    #[serde(skip)]
+    explicit: serde_json::Map<String, serde_json::Value>,
+}
+
+/// `State::fingerprint`, left out of copies, as they're made to change
+/// options.
+#[derive(Debug, Default)]
+struct Fingerprint(OnceLock<String>);
+
+impl Clone for Fingerprint {
+    fn clone(&self) -> Fingerprint {
+        Fingerprint::default()
+    }
+}
+
+/// A directory served under a url prefix, see `State::mounts`.
+#[cfg(feature = "server")]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Mount {
+    pub root: String,
+    /// Rendering options for the mount only, as query parameters override
+    /// them, see `State::with_overrides`.
+    #[serde(default)]
+    pub options: BTreeMap<String, serde_json::Value>,
+}
+
+/// A named set of options picked per request, see `State::profiles`.
+#[cfg(feature = "server")]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Profile {
+    /// Rendering options, as query parameters override them, see
+    /// `State::with_overrides`.
+    #[serde(default)]
+    pub options: BTreeMap<String, serde_json::Value>,
+    /// Settings for each plugin by name, layered over `State::plugin_options`.
+    #[serde(default)]
+    pub plugin_options: HashMap<String, serde_json::Value>,
+}
+
+/// `options` as query parameters, strings as they are & anything else as json.
+#[cfg(feature = "server")]
+fn as_query(options: &BTreeMap<String, serde_json::Value>) -> HashMap<String, String> {
+    options
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            (key.clone(), value)
+        })
+        .collect()
 }
 
 // @see https://github.com/clap-rs/clap/blob/7f8df272d90afde89e40de086492e1c9f5749897/examples/typed-derive.rs#L24
 fn parse_collapsible_headers(
     s: &str,
 ) -> Result<(u8, String), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    #[cfg(debug_assertions)]
-    dbg!(s);
+    trace!(s);
     let mut level = 1;
     let mut iter = s.chars();
-    #[cfg(debug_assertions)]
-    dbg!(&iter);
+    trace!(?iter);
     let a = iter.next();
     let b = iter.next();
     if let (Some('h'), Some(b)) = (a, b) {
         if let Some(digit) = b.to_digit(10) {
-            #[cfg(debug_assertions)]
-            dbg!(digit);
+            trace!(digit);
             match u8::try_from(digit) {
                 Ok(value) if (1..=6).contains(&value) => {
                     level = value;
@@ -124,51 +545,298 @@ fn parse_collapsible_headers(
     Ok((level, remainder))
 }
 
+/// Accepts a single `[level, "text"]` pair, as well as an array of them.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<(u8, String)>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One((u8, String)),
+        Many(Vec<(u8, String)>),
+    }
+
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        OneOrMany::One(pair) => vec![pair],
+        OneOrMany::Many(pairs) => pairs,
+    })
+}
+
 impl State {
-    // TODO either:
-    //  - return Result and handle errors
-    //  - continue and use sensible defaults
-    //      + implement sensible defaults
+    /// Like `try_load_config`, continuing with the current options when the
+    /// config file can't be loaded.
     pub fn load_config(&mut self) {
-        if let Some(config) = &self.config {
-            let path = SysPath::new(&config);
-            let mut buf = String::new();
-            let possible_state = path
-                .extension()
-                .and_then(OsStr::to_str)
-                .ok_or_else(|| {
-                    anyhow!(
-                        "Unable to convert the path {} which is of type `OsStr`, to `&str`.",
-                        path.display()
-                    )
+        if let Err(error) = self.try_load_config() {
+            warn!("{error}");
+        }
+    }
+
+    /// Parses the command line, remembering which options were set on it so
+    /// they take precedence over the environment & config file, see
+    /// `try_load_config`. Exits with the usage on invalid arguments.
+    pub fn from_command_line() -> State {
+        let matches = State::command().get_matches();
+        State::from_matches(&matches).unwrap_or_else(|error| error.exit())
+    }
+
+    /// Like `from_command_line`, parsing `args` instead.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `args` aren't valid.
+    pub fn try_from_args<I, T>(args: I) -> Result<State, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = State::command().try_get_matches_from(args)?;
+        State::from_matches(&matches)
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Result<State, clap::Error> {
+        let mut state = State::from_arg_matches(matches)?;
+        if let Ok(serde_json::Value::Object(values)) = serde_json::to_value(&state) {
+            state.explicit = matches
+                .ids()
+                .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+                .filter_map(|id| {
+                    let value = values.get(id.as_str())?;
+                    Some((id.to_string(), value.clone()))
                 })
-                .and_then(Config::try_from)
-                .and_then(|ext| {
-                    if path.exists() {
-                        File::open(path)
-                            .and_then(|mut file| file.read_to_string(&mut buf))
-                            .map_err(|error| anyhow!(error.to_string()))
-                            .and_then(|_| State::try_from((buf.as_str(), ext)))
-                    } else {
-                        Err(anyhow!(
-                            "{} does not exist. Continuing with defaults.",
-                            path.display()
-                        ))
-                    }
-                });
-            // TODO consider returning the `Result<T, E>` object instead of handling it.
-            match possible_state {
-                Ok(state) => {
-                    *self = state;
-                    #[cfg(debug_assertions)]
-                    dbg!(toml::to_string_pretty(&self).ok());
-                }
-                Err(error) => {
-                    #[cfg(debug_assertions)]
-                    dbg!(error);
+                .collect();
+        }
+        Ok(state)
+    }
+
+    /// Merges every option, in order of precedence, from the command line,
+    /// `SERVE_MD_*` environment variables, e.g. `SERVE_MD_TOC=true`, the config
+    /// file, if one is set, and the defaults. The path to the config file is
+    /// kept so it can be loaded again.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the config file doesn't exist, can't be read or
+    /// isn't valid json, toml or yaml, with a `ConfigError` listing every
+    /// unknown option & invalid value in the config file or environment, or
+    /// if `emoji_map` can't be read.
+    pub fn try_load_config(&mut self) -> anyhow::Result<()> {
+        let serde_json::Value::Object(mut merged) = serde_json::to_value(State::default())? else {
+            return Err(anyhow!("The default options failed to serialize."));
+        };
+
+        if let Some(config) = &self.config {
+            let path = SysPath::new(config);
+            let values = read_config(path)?;
+            let (unknown, invalid) = validate(&merged, &values);
+            if !unknown.is_empty() || !invalid.is_empty() {
+                return Err(ConfigError {
+                    origin: path.display().to_string(),
+                    unknown,
+                    invalid,
                 }
+                .into());
+            }
+            merged.extend(values);
+        }
+
+        let (environment, invalid) = from_environment(&merged);
+        if !invalid.is_empty() {
+            return Err(ConfigError {
+                origin: "the environment".to_string(),
+                unknown: vec![],
+                invalid,
+            }
+            .into());
+        }
+        merged.extend(environment);
+        merged.extend(self.explicit.clone());
+
+        let mut state: State = serde_json::from_value(serde_json::Value::Object(merged))?;
+        state.config = self.config.take();
+        state.print_config = self.print_config.take();
+        state.serializers = core::mem::take(&mut self.serializers);
+        state.lint_rules = core::mem::take(&mut self.lint_rules);
+        state.explicit = core::mem::take(&mut self.explicit);
+        #[cfg(feature = "server")]
+        {
+            state.log_level = self.log_level.take();
+            state.check = self.check;
+        }
+        // Read once here rather than on every render, failing a bad map early.
+        if let Some(map) = &state.emoji_map {
+            Emoji::reload_map(map)
+                .map_err(|error| anyhow!("Invalid emoji_map {map}: {error:#}"))?;
+        }
+        *self = state;
+        debug!(state = ?self, "Loaded config.");
+        Ok(())
+    }
+
+    /// Reads `options`, a json object of the same options as a config file,
+    /// over the defaults. An empty string reads as no options.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `options` isn't a json object, or with a
+    /// `ConfigError` listing every unknown option & invalid value.
+    pub fn try_from_options(options: &str) -> anyhow::Result<State> {
+        let serde_json::Value::Object(mut merged) = serde_json::to_value(State::default())? else {
+            return Err(anyhow!("The default options failed to serialize."));
+        };
+        let values = match serde_json::from_str(options) {
+            _ if options.trim().is_empty() => Options::new(),
+            Ok(serde_json::Value::Object(values)) => values,
+            Ok(_) => return Err(anyhow!("The options aren't a json object.")),
+            Err(error) => return Err(error.into()),
+        };
+        let (unknown, invalid) = validate(&merged, &values);
+        if !unknown.is_empty() || !invalid.is_empty() {
+            return Err(ConfigError {
+                origin: "the options".to_string(),
+                unknown,
+                invalid,
             }
+            .into());
         }
+        merged.extend(values);
+        Ok(serde_json::from_value(serde_json::Value::Object(merged))?)
+    }
+
+    /// Checks a document of `size` bytes is within `max_file_size`, if set.
+    ///
+    /// # Errors
+    ///
+    /// Will return `RenderError::TooLarge` for documents over the limit.
+    pub fn check_file_size(&self, size: u64) -> Result<(), RenderError> {
+        match self.max_file_size {
+            Some(limit) if size > limit => Err(RenderError::TooLarge { size, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// A hash of every option & the `generation`, telling output rendered
+    /// with other options apart. Worked out once, on first use, so options
+    /// need setting before, e.g. on a copy.
+    pub fn fingerprint(&self) -> &str {
+        self.fingerprint.0.get_or_init(|| {
+            let options = serde_json::to_string(self).unwrap_or_default();
+            images::hash(format!("{options}\n{}", self.generation).as_bytes())
+        })
+    }
+
+    /// Whether documents are rendered with more than their own source &
+    /// options, i.e. their neighbours, translations or git history, which
+    /// `collection::content_hash` can't tell changed.
+    pub fn reads_other_files(&self) -> bool {
+        #[cfg(feature = "git")]
+        if self.git {
+            return true;
+        }
+        self.navigation || self.i18n
+    }
+
+    /// Checks rendering hasn't passed `deadline`, if set.
+    ///
+    /// # Errors
+    ///
+    /// Will return `RenderError::TimedOut` once past the deadline.
+    pub fn check_deadline(&self) -> Result<(), RenderError> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(RenderError::TimedOut),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether a document with `front_matter` is hidden, either a draft or
+    /// scheduled, see `is_draft` & `is_scheduled`.
+    pub fn is_hidden(&self, front_matter: &serde_json::Value) -> bool {
+        self.is_draft(front_matter) || self.is_scheduled(front_matter)
+    }
+
+    /// Whether a document with `front_matter` is a draft, with `draft: true`,
+    /// unless `show_drafts` is set.
+    pub fn is_draft(&self, front_matter: &serde_json::Value) -> bool {
+        !self.show_drafts
+            && front_matter
+                .get("draft")
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or(false)
+    }
+
+    /// When a document with `front_matter` is published, its `publish_at`,
+    /// or `date`, unless `show_scheduled` is set.
+    pub fn publish_date(&self, front_matter: &serde_json::Value) -> Option<DateTime<FixedOffset>> {
+        if self.show_scheduled {
+            return None;
+        }
+        ["publish_at", "date"]
+            .into_iter()
+            .find_map(|key| front_matter.get(key))
+            .and_then(serde_json::Value::as_str)
+            .and_then(parse_date)
+    }
+
+    /// Whether a document with `front_matter` has a publish date still to
+    /// come, checked at the time of asking. Never on wasm32, where reading
+    /// the clock panics.
+    pub fn is_scheduled(&self, front_matter: &serde_json::Value) -> bool {
+        !cfg!(target_arch = "wasm32")
+            && self
+                .publish_date(front_matter)
+                .is_some_and(|date| date > Utc::now())
+    }
+
+    /// Checks a document with `front_matter` can be requested directly, which
+    /// scheduled documents can't, nor drafts with `drafts_not_found`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `ErrorKind::NotFound` for hidden documents.
+    pub fn check_requestable(&self, front_matter: &serde_json::Value) -> std::io::Result<()> {
+        if (self.drafts_not_found && self.is_draft(front_matter)) || self.is_scheduled(front_matter)
+        {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+        }
+        Ok(())
+    }
+
+    /// Like `check_requestable`, for the unrendered `source` of a document,
+    /// e.g. a markdown request.
+    ///
+    /// # Errors
+    ///
+    /// Will return `ErrorKind::NotFound` for hidden documents.
+    pub fn check_source_requestable(&self, source: &[u8]) -> std::io::Result<()> {
+        let front_matter = str::from_utf8(source)
+            .ok()
+            .and_then(|source| self.source_front_matter(source));
+        match front_matter {
+            Some(front_matter) => self.check_requestable(&front_matter),
+            None => Ok(()),
+        }
+    }
+
+    /// The front matter of `source` as `front_matter` parses it, without
+    /// rendering the document.
+    pub fn source_front_matter(&self, source: &str) -> Option<serde_json::Value> {
+        self.front_matter
+            .and_then(|matter| matter.as_pod(source))
+            .map(|(pod, _)| pod.into())
+    }
+
+    /// Serializes the options as a config file, which loads back into the
+    /// same options.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the options can't be serialized as `format`.
+    pub fn to_config(&self, format: Config) -> anyhow::Result<String> {
+        Ok(match format {
+            Config::Json => serde_json::to_string_pretty(self)?,
+            Config::Toml => toml::to_string_pretty(self)?,
+            Config::Yaml => serde_yaml::to_string(self)?,
+        })
     }
 
     #[cfg(feature = "server")]
@@ -177,6 +845,9 @@ impl State {
         if self.port == 0 {
             self.port = 8083;
         }
+        if self.host.is_none() {
+            self.host = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        }
         if self.root.is_none() {
             if let Ok(path) = env::current_dir() {
                 if let Some(path) = path.to_str() {
@@ -188,6 +859,174 @@ impl State {
 
     #[cfg(not(feature = "server"))]
     pub fn set_missing(&mut self) {}
+
+    /// A `Slugger` for a new document, with the slug style & duplicate
+    /// separator set.
+    pub fn slugger(&self) -> Slugger {
+        Slugger::new(
+            self.slug_style.unwrap_or_default(),
+            self.slug_duplicates.as_deref().unwrap_or("-"),
+        )
+    }
+
+    /// The extensions of markdown files, `md` unless set.
+    pub fn markdown_extensions(&self) -> Vec<&str> {
+        match &self.markdown_extensions {
+            Some(extensions) if !extensions.is_empty() => {
+                extensions.iter().map(String::as_str).collect()
+            }
+            _ => vec!["md"],
+        }
+    }
+
+    /// The extensions of every renderable file, the markdown extensions
+    /// followed by those with a converter.
+    pub fn source_extensions(&self) -> Vec<&str> {
+        let mut extensions = self.markdown_extensions();
+        extensions.extend(self.converters.keys().map(String::as_str));
+        extensions
+    }
+
+    /// `path` relative to root, using `/` as the separator, e.g. for links
+    /// back to a document's source. Absolute paths are relative to root
+    /// within the current directory, and paths outside of root are kept as
+    /// they are.
+    pub fn relative_path(&self, path: &SysPath) -> String {
+        #[cfg(feature = "server")]
+        let root = SysPath::new(self.root.as_deref().unwrap_or("."));
+        #[cfg(not(feature = "server"))]
+        let root = SysPath::new(".");
+        let relative = path
+            .strip_prefix(root)
+            .ok()
+            .or_else(|| {
+                let absolute = env::current_dir().ok()?.join(root);
+                path.strip_prefix(absolute).ok()
+            })
+            .unwrap_or(path);
+        relative.to_string_lossy().replace('\\', "/")
+    }
+
+    /// The converter for `path`'s extension, unless it's markdown.
+    pub fn converter_for(&self, path: &SysPath) -> Option<&Converter> {
+        if self.is_markdown(path) {
+            return None;
+        }
+        path.extension()
+            .and_then(OsStr::to_str)
+            .and_then(|extension| self.converters.get(extension))
+    }
+
+    /// Whether `path` has one of the markdown extensions.
+    pub fn is_markdown(&self, path: &SysPath) -> bool {
+        path.extension()
+            .and_then(OsStr::to_str)
+            .is_some_and(|extension| self.markdown_extensions().contains(&extension))
+    }
+
+    /// A copy serving `mount`, with its root & rendering options.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if an option isn't valid, see `with_overrides`.
+    #[cfg(feature = "server")]
+    pub fn for_mount(&self, mount: &Mount) -> anyhow::Result<State> {
+        let mut state = self.with_overrides(&as_query(&mount.options))?;
+        state.root = Some(mount.root.clone());
+        Ok(state)
+    }
+
+    /// A copy with the rendering options of the profile `name`, its
+    /// `plugin_options` tables extending, or replacing, those already set.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there is no profile `name`, or an option isn't
+    /// valid, see `with_overrides`.
+    #[cfg(feature = "server")]
+    pub fn for_profile(&self, name: &str) -> anyhow::Result<State> {
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| anyhow!("There is no {name} profile."))?;
+        let mut state = self.with_overrides(&as_query(&profile.options))?;
+        for (plugin, options) in &profile.plugin_options {
+            match (state.plugin_options.get_mut(plugin), options) {
+                (Some(serde_json::Value::Object(existing)), serde_json::Value::Object(options)) => {
+                    existing.extend(options.clone());
+                }
+                _ => {
+                    state.plugin_options.insert(plugin.clone(), options.clone());
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    /// A copy with the rendering options in `query` applied, e.g.
+    /// `toc=1&emoji=0&theme=dark`. Flags accept `1`, `true`, `on`, `0`,
+    /// `false` or `off`. Unknown keys are ignored. Options reading files,
+    /// running commands or sanitizing output can't be overridden.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a value isn't valid for its option.
+    pub fn with_overrides(&self, query: &HashMap<String, String>) -> anyhow::Result<State> {
+        let mut state = self.clone();
+        for (key, value) in query {
+            let flag = || -> anyhow::Result<bool> {
+                match value.as_str() {
+                    "1" | "true" | "on" | "" => Ok(true),
+                    "0" | "false" | "off" => Ok(false),
+                    _ => Err(anyhow!("{value} isn't a valid value for the {key} flag.")),
+                }
+            };
+            let invalid = |name: &str| anyhow!("{value} isn't a valid {name}.");
+            match key.as_str() {
+                "gfm" => state.gfm = flag()?,
+                "tables" => state.tables = flag()?,
+                "footnotes" => state.footnotes = flag()?,
+                "strikethrough" => state.strikethrough = flag()?,
+                "tasklists" => state.tasklists = flag()?,
+                "smart_punctuation" => state.smart_punctuation = flag()?,
+                "locale" => state.locale = Some(value.clone()),
+                "header_attributes" => state.header_attributes = flag()?,
+                "emoji" | "emoji_shortcodes" => state.emoji_shortcodes = flag()?,
+                "typography" => state.typography = flag()?,
+                "external_links" => state.external_links = flag()?,
+                "figures" => state.figures = flag()?,
+                "abbreviations" => state.abbreviations = flag()?,
+                "heading_anchors" => state.heading_anchors = flag()?,
+                "toc" => state.toc = flag()?,
+                "refdefs" => state.refdefs = flag()?,
+                "lint" => state.lint = flag()?,
+                "code_titles" => state.code_titles = flag()?,
+                "code_copy" => state.code_copy = flag()?,
+                "mermaid" => state.mermaid = flag()?,
+                "highlight" => state.highlight = flag()?,
+                "social_meta" => state.social_meta = flag()?,
+                "shift_headings" => {
+                    state.shift_headings =
+                        Some(value.parse().map_err(|_| invalid("heading shift"))?);
+                }
+                "slug_style" => {
+                    state.slug_style =
+                        Some(SlugStyle::from_str(value, true).map_err(|_| invalid("slug style"))?);
+                }
+                "theme" => {
+                    state.theme = Some(Theme::from_str(value, true).map_err(|_| invalid("theme"))?);
+                }
+                "matter_in_html" => {
+                    state.matter_in_html = Some(
+                        MatterInHtml::from_str(value, true)
+                            .map_err(|_| invalid("front matter html mode"))?,
+                    );
+                }
+                _ => trace!(key, "Ignoring unknown override."),
+            }
+        }
+        Ok(state)
+    }
 }
 
 impl TryFrom<(&str, Config)> for State {
@@ -201,9 +1040,93 @@ impl TryFrom<(&str, Config)> for State {
     }
 }
 
+type Options = serde_json::Map<String, serde_json::Value>;
+
+/// Reads the config file at `path` as json, toml or yaml, based on its extension.
+fn read_config(path: &SysPath) -> anyhow::Result<Options> {
+    let ext = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .ok_or_else(|| {
+            anyhow!(
+                "Unable to convert the path {} which is of type `OsStr`, to `&str`.",
+                path.display()
+            )
+        })
+        .and_then(Config::try_from)?;
+    if !path.exists() {
+        return Err(anyhow!("{} does not exist.", path.display()));
+    }
+    let mut buf = String::new();
+    File::open(path)?.read_to_string(&mut buf)?;
+    let value: serde_json::Value = match ext {
+        Config::Json => serde_json::from_str(&buf)?,
+        Config::Toml => toml::from_str(&buf)?,
+        Config::Yaml => serde_yaml::from_str(&buf)?,
+    };
+    match value {
+        serde_json::Value::Object(options) => Ok(options),
+        serde_json::Value::Null => Ok(Options::new()),
+        _ => Err(anyhow!(
+            "{} does not contain a table of options.",
+            path.display()
+        )),
+    }
+}
+
+/// Checks a single option by deserializing it on its own, returning why it's invalid.
+fn check(key: &str, value: &serde_json::Value) -> Result<(), String> {
+    let single = Options::from_iter([(key.to_string(), value.clone())]);
+    serde_json::from_value::<State>(serde_json::Value::Object(single))
+        .map(|_| ())
+        .map_err(|error| error.to_string())
+}
+
+/// Returns the `options` which aren't `known`, and those with invalid values.
+fn validate(known: &Options, options: &Options) -> (Vec<String>, Vec<(String, String)>) {
+    let mut unknown = vec![];
+    let mut invalid = vec![];
+    for (key, value) in options {
+        if !known.contains_key(key) {
+            unknown.push(key.clone());
+        } else if let Err(reason) = check(key, value) {
+            invalid.push((key.clone(), reason));
+        }
+    }
+    (unknown, invalid)
+}
+
+/// Reads a `SERVE_MD_*` environment variable for each `known` option. Values
+/// are parsed as json, falling back to a plain string.
+fn from_environment(known: &Options) -> (Options, Vec<(String, String)>) {
+    let mut options = Options::new();
+    let mut invalid = vec![];
+    for key in known.keys() {
+        let name = format!("SERVE_MD_{}", key.to_uppercase());
+        let Ok(raw) = env::var(&name) else {
+            continue;
+        };
+        let string = serde_json::Value::String(raw.clone());
+        let value = match serde_json::from_str(&raw) {
+            Ok(value) if check(key, &value).is_ok() => value,
+            _ => string,
+        };
+        match check(key, &value) {
+            Ok(_) => {
+                options.insert(key.clone(), value);
+            }
+            Err(reason) => invalid.push((name, reason)),
+        }
+    }
+    (options, invalid)
+}
+
 #[cfg(test)]
 mod tests {
     use super::parse_collapsible_headers;
+    use super::State;
+    use crate::formats::Config;
+    use crate::sanitize::Allowlist;
 
     #[test]
     fn pch_test_ascii_digits() {
@@ -308,4 +1231,276 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn state_test_collapsible_headers_from_config() {
+        let toml = r#"
+        collapsible_headers = [5, "in case you missed it"]
+        "#;
+        match State::try_from((toml, Config::Toml)) {
+            Ok(state) => assert_eq!(
+                state.collapsible_headers,
+                vec![(5, "in case you missed it".to_string())]
+            ),
+            Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+        }
+
+        let toml = r#"
+        collapsible_headers = [[2, "Notes"], [3, "Details"]]
+        collapsible_closed = true
+        "#;
+        match State::try_from((toml, Config::Toml)) {
+            Ok(state) => {
+                assert!(state.collapsible_closed);
+                assert_eq!(
+                    state.collapsible_headers,
+                    vec![(2, "Notes".to_string()), (3, "Details".to_string())]
+                );
+            }
+            Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+        }
+    }
+
+    #[test]
+    fn state_test_with_overrides() {
+        use crate::template::Theme;
+        use std::collections::HashMap;
+
+        let state = State {
+            toc: true,
+            emoji_shortcodes: true,
+            ..State::default()
+        };
+        let query: HashMap<String, String> = [
+            ("toc", "0"),
+            ("emoji", "false"),
+            ("theme", "dark"),
+            ("v", "2"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        match state.with_overrides(&query) {
+            Ok(derived) => {
+                assert!(!derived.toc);
+                assert!(!derived.emoji_shortcodes);
+                assert_eq!(derived.theme, Some(Theme::Dark));
+                // The original is left as is.
+                assert!(state.toc);
+            }
+            Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+        }
+
+        let query = HashMap::from([("toc".to_string(), "maybe".to_string())]);
+        assert!(state.with_overrides(&query).is_err());
+    }
+
+    #[test]
+    fn state_test_converters_from_config() {
+        use std::path::Path;
+
+        let toml = r#"
+        [converters.adoc]
+        command = "asciidoctor"
+        args = ["-s", "-o", "-", "-"]
+        "#;
+        match State::try_from((toml, Config::Toml)) {
+            Ok(state) => {
+                assert_eq!(state.source_extensions(), vec!["md", "adoc"]);
+                match state.converter_for(Path::new("docs/usage.adoc")) {
+                    Some(converter) => {
+                        assert_eq!(converter.command, "asciidoctor");
+                        assert_eq!(converter.timeout_ms, 5000);
+                    }
+                    None => assert!(false, "Should have found the adoc converter."),
+                }
+                assert!(state.converter_for(Path::new("readme.md")).is_none());
+            }
+            Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+        }
+    }
+
+    #[test]
+    fn state_test_sanitize_allowlist_from_config() {
+        let toml = r#"
+        sanitize = true
+
+        [sanitize_allowlist]
+        tags = ["video"]
+        "#;
+        match State::try_from((toml, Config::Toml)) {
+            Ok(state) => {
+                assert!(state.sanitize);
+                assert_eq!(
+                    state.sanitize_allowlist,
+                    Some(Allowlist {
+                        tags: vec!["video".to_string()],
+                        attributes: vec![],
+                    })
+                );
+            }
+            Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+        }
+    }
+
+    #[test]
+    fn state_test_scheduled() {
+        let future = serde_json::json!({ "date": "2020-01-01", "publish_at": "2999-01-01" });
+        let past = serde_json::json!({ "date": "2020-01-01T10:00:00Z" });
+
+        let mut state = State::default();
+        assert!(state.is_scheduled(&future));
+        assert!(state.is_hidden(&future));
+        assert!(state.check_requestable(&future).is_err());
+        assert!(!state.is_scheduled(&past));
+        assert!(state.check_requestable(&past).is_ok());
+
+        state.show_scheduled = true;
+        assert!(!state.is_scheduled(&future));
+        assert!(state.check_requestable(&future).is_ok());
+    }
+
+    #[test]
+    fn state_test_default_front_matter_from_config() {
+        let toml = r#"
+        front_matter = "Yaml"
+
+        [default_front_matter]
+        author = "Skial"
+        "#;
+        match State::try_from((toml, Config::Toml)) {
+            Ok(state) => {
+                assert_eq!(
+                    state.default_front_matter.get("author"),
+                    Some(&serde_json::Value::from("Skial"))
+                );
+            }
+            Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+        }
+    }
+
+    #[test]
+    fn state_test_config_merges_with_command_line_and_environment() {
+        let path = std::env::temp_dir().join("serve_md_merge_test.toml");
+        let toml = r#"
+        tables = true
+        template = "file.html"
+        stylesheet = "file.css"
+        shift_headings = 1
+        "#;
+        std::fs::write(&path, toml).unwrap();
+        std::env::set_var("SERVE_MD_STYLESHEET", "env.css");
+        std::env::set_var("SERVE_MD_SHIFT_HEADINGS", "2");
+
+        let args = [
+            "serve_md",
+            "--config",
+            &path.to_string_lossy(),
+            "--template",
+            "cli.html",
+            "--footnotes",
+        ];
+        let result = State::try_from_args(args)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut state| state.try_load_config().map(|_| state));
+        std::env::remove_var("SERVE_MD_STYLESHEET");
+        std::env::remove_var("SERVE_MD_SHIFT_HEADINGS");
+
+        match result {
+            Ok(state) => {
+                assert!(state.tables);
+                assert!(state.footnotes);
+                assert_eq!(state.template.as_deref(), Some("cli.html"));
+                assert_eq!(state.stylesheet.as_deref(), Some("env.css"));
+                assert_eq!(state.shift_headings, Some(2));
+                assert!(!state.strikethrough);
+            }
+            Err(error) => {
+                dbg!(&error);
+                assert!(false, "Should NEVER return an error. Error was {error}.")
+            }
+        }
+    }
+
+    #[test]
+    fn state_test_config_with_unknown_and_invalid_options() {
+        use crate::error::ConfigError;
+
+        let path = std::env::temp_dir().join("serve_md_validation_test.toml");
+        let toml = r#"
+        tables = "yes"
+        stylesheets = "file.css"
+        shift_headings = 1
+        "#;
+        std::fs::write(&path, toml).unwrap();
+
+        let mut state = State::try_from_args(["serve_md", "--config", &path.to_string_lossy()])
+            .expect("Valid arguments.");
+        match state.try_load_config() {
+            Ok(_) => assert!(false, "Should have rejected the config file."),
+            Err(error) => match error.downcast_ref::<ConfigError>() {
+                Some(config) => {
+                    assert_eq!(config.unknown, vec!["stylesheets".to_string()]);
+                    assert_eq!(config.invalid.len(), 1);
+                    assert_eq!(config.invalid[0].0, "tables");
+                    assert!(error.to_string().contains("Unknown options: stylesheets."));
+                }
+                None => assert!(false, "Should be a `ConfigError`. Error was {error}."),
+            },
+        }
+    }
+
+    #[test]
+    fn state_test_from_options() {
+        use crate::error::ConfigError;
+
+        match State::try_from_options(r#"{ "tables": true, "shift_headings": 2 }"#) {
+            Ok(state) => {
+                assert!(state.tables);
+                assert_eq!(state.shift_headings, Some(2));
+                assert!(!state.toc);
+            }
+            Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+        }
+        assert!(State::try_from_options("").is_ok());
+        assert!(State::try_from_options("[]").is_err());
+        match State::try_from_options(r#"{ "tables": "yes", "stylesheets": "a.css" }"#) {
+            Ok(_) => assert!(false, "Should have rejected the options."),
+            Err(error) => match error.downcast_ref::<ConfigError>() {
+                Some(config) => {
+                    assert_eq!(config.unknown, vec!["stylesheets".to_string()]);
+                    assert_eq!(config.invalid[0].0, "tables");
+                }
+                None => assert!(false, "Should be a `ConfigError`. Error was {error}."),
+            },
+        }
+    }
+
+    #[test]
+    fn state_test_to_config_loads_back() {
+        let toml = r#"
+        tables = true
+        stylesheet = "/site.css"
+        shift_headings = 1
+        collapsible_headers = [2, "details"]
+        "#;
+        let state = State::try_from((toml, Config::Toml)).expect("Valid toml.");
+        for format in [Config::Json, Config::Toml, Config::Yaml] {
+            let loaded = state
+                .to_config(format)
+                .and_then(|config| State::try_from((config.as_str(), format)));
+            match loaded {
+                Ok(loaded) => {
+                    assert!(loaded.tables);
+                    assert_eq!(loaded.stylesheet.as_deref(), Some("/site.css"));
+                    assert_eq!(loaded.shift_headings, Some(1));
+                    assert_eq!(loaded.collapsible_headers, state.collapsible_headers);
+                }
+                Err(error) => {
+                    dbg!(&error);
+                    assert!(false, "Should NEVER return an error. Error was {error}.")
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,51 @@
+use pulldown_cmark::{Event, Tag};
+use serde_derive::{Deserialize, Serialize};
+
+/// A table within a document, as the plain text of each cell.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Collects every table in `events`, in document order. Cells keep the text
+/// & inline code within them, dropping any other markup.
+pub fn collect(events: &[Event]) -> Vec<Table> {
+    let mut tables = vec![];
+    let mut table: Option<Table> = None;
+    let mut row: Vec<String> = vec![];
+    let mut cell: Option<String> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Table(_)) => table = Some(Table::default()),
+            Event::End(Tag::Table(_)) => tables.extend(table.take()),
+            Event::Start(Tag::TableHead | Tag::TableRow) => row.clear(),
+            Event::End(Tag::TableHead) => {
+                if let Some(table) = &mut table {
+                    table.header = core::mem::take(&mut row);
+                }
+            }
+            Event::End(Tag::TableRow) => {
+                if let Some(table) = &mut table {
+                    table.rows.push(core::mem::take(&mut row));
+                }
+            }
+            Event::Start(Tag::TableCell) => cell = Some(String::new()),
+            Event::End(Tag::TableCell) => row.extend(cell.take()),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(cell) = &mut cell {
+                    cell.push_str(text);
+                }
+            }
+            Event::SoftBreak | Event::HardBreak => {
+                if let Some(cell) = &mut cell {
+                    cell.push(' ');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    tables
+}
@@ -0,0 +1,291 @@
+use crate::{
+    plugin::{CODE_COPY_SCRIPT, MERMAID_SCRIPT},
+    state::State,
+    Payload,
+};
+use anyhow::Result;
+use clap::ValueEnum;
+use minijinja::{context, Environment, Value};
+use pulldown_cmark::escape::escape_html;
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs};
+
+/// Used when a theme or stylesheet is set without a `--template`.
+pub const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{ title }}</title>
+{% if head %}{{ head }}{% endif %}{% if stylesheet %}<link rel="stylesheet" href="{{ stylesheet }}">
+{% endif %}{% if style %}<style>
+{{ style }}</style>
+{% endif %}</head>
+<body>
+{{ content }}</body>
+</html>
+"#;
+
+/// The url of a document's source from `--edit-base-url` & its `path`
+/// relative to root.
+pub fn edit_url(base: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+/// The "Edit this page" link added below the content of html output.
+pub fn edit_link_html(url: &str) -> String {
+    let mut html = String::from(r#"<p class="edit-page"><a href=""#);
+    let _ = escape_html(&mut html, url);
+    html.push_str("\">Edit this page</a></p>\n");
+    html
+}
+
+/// Added to the `head` of documents with `noindex: true` front matter.
+pub const ROBOTS_NOINDEX: &str = "<meta name=\"robots\" content=\"noindex\">\n";
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum, Deserialize, Serialize)]
+pub enum Theme {
+    Light,
+    Dark,
+    Github,
+}
+
+impl Theme {
+    pub fn css(self) -> &'static str {
+        match self {
+            Theme::Light => include_str!("../themes/light.css"),
+            Theme::Dark => include_str!("../themes/dark.css"),
+            Theme::Github => include_str!("../themes/github.css"),
+        }
+    }
+}
+
+/// How `--matter-in-html` adds the front matter to html output.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatterInHtml {
+    /// A `<meta>` tag for each top level value, skipping nested tables.
+    Meta,
+    /// A `<script type="application/ld+json">` of the whole front matter.
+    JsonLd,
+    /// A `<table class="front-matter">` above the content.
+    Table,
+}
+
+/// The front matter as html for `mode`, empty without any front matter.
+pub fn front_matter_html(front_matter: &serde_json::Value, mode: MatterInHtml) -> String {
+    let Some(map) = front_matter.as_object().filter(|map| !map.is_empty()) else {
+        return String::new();
+    };
+
+    // Writing to a `String` can't fail.
+    let mut html = String::new();
+    match mode {
+        MatterInHtml::Meta => {
+            for (key, value) in map {
+                let Some(content) = meta_content(value) else {
+                    continue;
+                };
+                html.push_str(r#"<meta name=""#);
+                let _ = escape_html(&mut html, key);
+                html.push_str(r#"" content=""#);
+                let _ = escape_html(&mut html, &content);
+                html.push_str("\">\n");
+            }
+        }
+        MatterInHtml::JsonLd => {
+            // Stops values closing the script early.
+            let json = front_matter.to_string().replace("</", r"<\/");
+            html.push_str(&format!(
+                "<script type=\"application/ld+json\">{json}</script>\n"
+            ));
+        }
+        MatterInHtml::Table => {
+            html.push_str("<table class=\"front-matter\">\n<tbody>\n");
+            for (key, value) in map {
+                html.push_str("<tr><th>");
+                let _ = escape_html(&mut html, key);
+                html.push_str("</th><td>");
+                let value = match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                let _ = escape_html(&mut html, &value);
+                html.push_str("</td></tr>\n");
+            }
+            html.push_str("</tbody>\n</table>\n");
+        }
+    }
+
+    html
+}
+
+/// Open Graph & Twitter card `<meta>` tags from the `title`, `description`,
+/// `image` & `author` front matter, with `overrides` replacing or adding tags
+/// by name. An empty override leaves its tag out.
+pub fn social_meta_html(
+    front_matter: &serde_json::Value,
+    overrides: &BTreeMap<String, String>,
+) -> String {
+    let value = |key: &str| {
+        front_matter
+            .get(key)
+            .and_then(meta_content)
+            .filter(|value| !value.is_empty())
+    };
+
+    let mapping: [(&str, &[&str]); 4] = [
+        ("title", &["og:title", "twitter:title"]),
+        ("description", &["og:description", "twitter:description"]),
+        ("image", &["og:image", "twitter:image"]),
+        ("author", &["article:author"]),
+    ];
+    let mut tags = vec![("og:type".to_string(), "article".to_string())];
+    for (key, names) in mapping {
+        if let Some(value) = value(key) {
+            tags.extend(names.iter().map(|name| (name.to_string(), value.clone())));
+        }
+    }
+    let card = if value("image").is_some() {
+        "summary_large_image"
+    } else {
+        "summary"
+    };
+    tags.push(("twitter:card".to_string(), card.to_string()));
+
+    for (name, content) in overrides {
+        match tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some(tag) => tag.1 = content.clone(),
+            None => tags.push((name.clone(), content.clone())),
+        }
+    }
+
+    let mut html = String::new();
+    for (name, content) in tags.iter().filter(|(_, content)| !content.is_empty()) {
+        // Open Graph uses `property`, Twitter cards `name`.
+        let attribute = if name.starts_with("twitter:") {
+            "name"
+        } else {
+            "property"
+        };
+        html.push_str(&format!("<meta {attribute}=\""));
+        let _ = escape_html(&mut html, name);
+        html.push_str(r#"" content=""#);
+        let _ = escape_html(&mut html, content);
+        html.push_str("\">\n");
+    }
+    html
+}
+
+/// Strings, numbers & booleans as is, with lists of them comma separated.
+fn meta_content(value: &serde_json::Value) -> Option<String> {
+    use serde_json::Value;
+
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        Value::Array(values) => {
+            let values = values
+                .iter()
+                .filter(|value| !value.is_array())
+                .map(meta_content)
+                .collect::<Option<Vec<_>>>()?;
+            Some(values.join(", "))
+        }
+        Value::Null | Value::Object(_) => None,
+    }
+}
+
+/// Wraps the html of `payload` in the page template set by `--template`,
+/// or the default template when only a theme or stylesheet is set,
+/// otherwise the html fragment is returned as is. Pages containing a
+/// mermaid diagram have the mermaid.js script appended to their content, as
+/// do pages with a code copy button its script.
+/// With `--matter-in-html` the front matter is added to the page `head`, or
+/// above the content for a table or when there's no template, as is a
+/// robots `noindex` meta tag for documents with `noindex: true`, and the
+/// Open Graph & Twitter card tags with `--social-meta`. With
+/// `--edit-base-url` an "Edit this page" link follows the content, unless a
+/// `--template` places `edit_url` itself.
+///
+/// Templates have access to `title`, `front_matter`, `content`, the
+/// rendered markdown which is never escaped, the `edit_url` of the
+/// document, `style`, the css of the selected theme, the `stylesheet` url,
+/// the `toc` entries, `git`, the document's history with `--git`, `prev`,
+/// `next` & `breadcrumbs` links with `--navigation`, `translations`, the
+/// `lang` & `path` of each language with `--i18n`, and `head`, the front
+/// matter as `<meta>` tags or json-ld along with any other tags.
+///
+/// # Errors
+///
+/// Will return `Err` if the template can't be read or fails to render.
+pub fn render(payload: &Payload, state: &State) -> Result<String> {
+    let mut html = payload.html.clone();
+    if state.mermaid && html.contains(r#"<pre class="mermaid">"#) {
+        html.push_str(MERMAID_SCRIPT);
+    }
+    if state.code_copy && html.contains(r#"<button class="code-copy""#) {
+        html.push_str(CODE_COPY_SCRIPT);
+    }
+
+    let mut head = String::new();
+    match state.matter_in_html {
+        Some(MatterInHtml::Table) => {
+            html.insert_str(
+                0,
+                &front_matter_html(&payload.front_matter, MatterInHtml::Table),
+            );
+        }
+        Some(mode) => head = front_matter_html(&payload.front_matter, mode),
+        None => {}
+    }
+    if payload.noindex() {
+        head.insert_str(0, ROBOTS_NOINDEX);
+    }
+    if state.social_meta {
+        head.push_str(&social_meta_html(
+            &payload.front_matter,
+            &state.social_meta_overrides,
+        ));
+    }
+
+    let edit_url = state
+        .edit_base_url
+        .as_deref()
+        .zip(payload.path.as_deref())
+        .map(|(base, path)| edit_url(base, path));
+    if let Some(url) = edit_url.as_deref().filter(|_| state.template.is_none()) {
+        html.push_str(&edit_link_html(url));
+    }
+
+    let source = match &state.template {
+        Some(path) => fs::read_to_string(path)?,
+        None if state.theme.is_some() || state.stylesheet.is_some() => DEFAULT_TEMPLATE.to_string(),
+        None => return Ok(head + &html),
+    };
+
+    let mut env = Environment::new();
+    // The `.html` extension enables auto escaping.
+    env.add_template("page.html", &source)?;
+    let page = env.get_template("page.html")?.render(context! {
+        title => payload.title().unwrap_or_default(),
+        front_matter => payload.front_matter,
+        content => Value::from_safe_string(html),
+        edit_url,
+        style => state.theme.map(|theme| Value::from_safe_string(theme.css().to_string())),
+        stylesheet => state.stylesheet,
+        toc => payload.toc,
+        git => payload.git,
+        prev => payload.navigation.as_ref().and_then(|navigation| navigation.prev.as_ref()),
+        next => payload.navigation.as_ref().and_then(|navigation| navigation.next.as_ref()),
+        breadcrumbs => payload.navigation.as_ref().map(|navigation| &navigation.breadcrumbs),
+        translations => payload.translations,
+        head => Value::from_safe_string(head),
+    })?;
+
+    Ok(page)
+}
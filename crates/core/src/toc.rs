@@ -0,0 +1,46 @@
+use crate::slug::Slugger;
+use pulldown_cmark::{Event, Tag};
+use serde_derive::{Deserialize, Serialize};
+
+/// A heading within a document, with the same slug `--heading-anchors` uses
+/// for its `id`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub level: u8,
+    pub text: String,
+    pub slug: String,
+}
+
+/// Collects every heading in `events`, in document order, slugged by
+/// `slugger`.
+pub fn collect(events: &[Event], mut slugger: Slugger) -> Vec<TocEntry> {
+    let mut entries = vec![];
+    let mut heading: Option<String> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading(..)) => heading = Some(String::new()),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(heading) = &mut heading {
+                    heading.push_str(text);
+                }
+            }
+            Event::End(Tag::Heading(level, id, _)) => {
+                if let Some(text) = heading.take() {
+                    let slug = match id {
+                        Some(id) => slugger.unique((*id).to_string()),
+                        None => slugger.slug(&text),
+                    };
+                    entries.push(TocEntry {
+                        level: *level as u8,
+                        text,
+                        slug,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    entries
+}
@@ -0,0 +1,217 @@
+use crate::{
+    abi,
+    plugin::{from_config, Plugin, Stage},
+};
+use anyhow::{anyhow, Context, Result};
+use core::ops::Range;
+use pulldown_cmark::Event;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, SystemTime},
+};
+use tracing::debug;
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// How often the epoch running modules are interrupted by goes up.
+const TICK: Duration = Duration::from_millis(10);
+
+#[derive(Deserialize)]
+struct WasmConfig {
+    wasm: String,
+    #[serde(default = "WasmConfig::default_timeout_ms")]
+    timeout_ms: u64,
+    #[serde(default = "WasmConfig::default_max_memory_mb")]
+    max_memory_mb: usize,
+}
+
+impl WasmConfig {
+    fn default_timeout_ms() -> u64 {
+        DEFAULT_TIMEOUT_MS
+    }
+
+    fn default_max_memory_mb() -> usize {
+        64
+    }
+}
+
+/// How long a module runs for each document without a `timeout_ms`.
+pub const DEFAULT_TIMEOUT_MS: u64 = 5000;
+
+/// The engine every module runs on, interrupting them once past their epoch
+/// deadline, counted in `TICK`s by a background thread.
+fn engine() -> Result<&'static Engine> {
+    static ENGINE: OnceLock<Result<Engine, String>> = OnceLock::new();
+    ENGINE
+        .get_or_init(|| {
+            let mut config = Config::new();
+            config.epoch_interruption(true);
+            let engine = Engine::new(&config).map_err(|error| error.to_string())?;
+            let ticker = engine.clone();
+            thread::spawn(move || loop {
+                thread::sleep(TICK);
+                ticker.increment_epoch();
+            });
+            Ok(engine)
+        })
+        .as_ref()
+        .map_err(|error| anyhow!(error.clone()))
+}
+
+/// Compiles `path`, reusing the module compiled by an earlier request while
+/// the file's modification time is the same.
+fn module(path: &Path) -> Result<Module> {
+    static MODULES: OnceLock<Mutex<HashMap<PathBuf, (Option<SystemTime>, Module)>>> =
+        OnceLock::new();
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut modules = MODULES
+        .get_or_init(Mutex::default)
+        .lock()
+        .map_err(|error| anyhow!(error.to_string()))?;
+
+    if let Some((_, module)) = modules.get(path).filter(|(at, _)| *at == modified) {
+        return Ok(module.clone());
+    }
+    debug!(?path, "Compiling wasm plugin.");
+    let module = Module::from_file(engine()?, path)?;
+    modules.insert(path.to_path_buf(), (modified, module.clone()));
+    Ok(module)
+}
+
+/// Runs a WebAssembly module over every event of a document.
+///
+/// Modules can't import anything and must export:
+/// - `memory`.
+/// - `alloc(len: i32) -> i32`, returning a pointer to `len` free bytes.
+/// - `transform(ptr: i32, len: i32) -> i64`, reading a json array of
+///   `abi::Event`s from `ptr` and returning the location of the transformed
+///   array, packed as `ptr << 32 | len`.
+///
+/// Fails the document when the module traps, returns invalid json, runs
+/// past its timeout or grows its memory past its limit.
+pub struct WasmPlugin {
+    path: PathBuf,
+    module: Module,
+    timeout: Duration,
+    max_memory: usize,
+}
+
+impl WasmPlugin {
+    /// Loads the module at `path`, stopping it after `timeout` & at
+    /// `max_memory` bytes of memory.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the module can't be read or compiled.
+    pub fn load(path: &Path, timeout: Duration, max_memory: usize) -> Result<WasmPlugin> {
+        Ok(WasmPlugin {
+            path: path.to_path_buf(),
+            module: module(path).with_context(|| format!("Unable to load {}.", path.display()))?,
+            timeout,
+            max_memory,
+        })
+    }
+
+    /// Builds the plugin from a config with a `wasm` path, optional
+    /// `timeout_ms` & `max_memory_mb`, for `PluginRegistry`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `wasm` is missing or the module can't be loaded.
+    pub fn construct(config: &Value) -> Result<Stage> {
+        let config: WasmConfig = from_config(config)?;
+        Ok(Stage::Window(Box::new(WasmPlugin::load(
+            Path::new(&config.wasm),
+            Duration::from_millis(config.timeout_ms),
+            config.max_memory_mb.saturating_mul(1024 * 1024),
+        )?)))
+    }
+
+    fn transform(&self, input: &[u8]) -> Result<Vec<u8>> {
+        // A fresh instance per document, so no state leaks between requests.
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory)
+            .instances(1)
+            .build();
+        let mut store: Store<StoreLimits> = Store::new(engine()?, limits);
+        store.limiter(|limits| limits);
+        let ticks = self.timeout.as_millis() / TICK.as_millis() + 1;
+        store.set_epoch_deadline(u64::try_from(ticks).unwrap_or(u64::MAX));
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow!("No `memory` export."))?;
+        let alloc = instance.get_typed_func::<u32, u32>(&mut store, "alloc")?;
+        let transform = instance.get_typed_func::<(u32, u32), u64>(&mut store, "transform")?;
+
+        let len = u32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, usize::try_from(ptr)?, input)?;
+        let packed = transform.call(&mut store, (ptr, len))?;
+
+        let start = usize::try_from(packed >> 32)?;
+        let end = start + usize::try_from(packed & 0xffff_ffff)?;
+        memory
+            .data(&store)
+            .get(start..end)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| anyhow!("Output {start}..{end} is out of bounds."))
+    }
+}
+
+impl Plugin for WasmPlugin {
+    fn window_size(&self) -> usize {
+        1
+    }
+
+    fn new_items(&self) -> usize {
+        0
+    }
+
+    fn check_slice(&mut self, _: &[(usize, Event)]) -> Result<Option<Range<usize>>> {
+        Ok(None)
+    }
+
+    /// Claims the whole document.
+    fn final_check(&mut self, pos: usize) -> Result<Option<Range<usize>>> {
+        Ok(Some(0..(pos + 1)))
+    }
+
+    fn replace_slice<'input>(
+        &self,
+        slice: &[(usize, Event<'input>)],
+    ) -> Result<Vec<Event<'input>>> {
+        abi::transform_slice(slice, |input| self.transform(&input))
+            .with_context(|| format!("Running {} failed.", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wasm_test_timeout() {
+        let path = std::env::temp_dir().join("serve_md_wasm_loop_test.wat");
+        fs::write(
+            &path,
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "transform") (param i32 i32) (result i64)
+                    (loop $forever (br $forever))
+                    i64.const 0))"#,
+        )
+        .unwrap();
+
+        let plugin = WasmPlugin::load(&path, Duration::from_millis(50), 1 << 20).unwrap();
+        assert!(plugin.transform(b"[]").is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+}
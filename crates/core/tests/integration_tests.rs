@@ -1,7 +1,7 @@
 use indoc::indoc;
 use serve_md_core::{
-    determine, formats::Matter, generate_payload_from_path, generate_payload_from_slice,
-    state::State,
+    determine, formats::Matter, generate_payload_from_path, generate_payload_from_reader,
+    generate_payload_from_slice, state::State,
 };
 use std::{path::{Path, PathBuf}, sync::Arc};
 
@@ -82,6 +82,37 @@ fn test_gen_payload_missing_file() {
     assert!(false, "Should have returned an error.")
 }
 
+#[test]
+fn test_gen_payload_over_max_file_size() {
+    use serve_md_core::error::RenderError;
+
+    let path:PathBuf = [env!("CARGO_MANIFEST_DIR"), "resources", "test.md"].iter().collect();
+    let mut state = State::default();
+    state.max_file_size = Some(8);
+    match generate_payload_from_path(Path::new(&path), Arc::new(state)) {
+        Ok(_) => assert!(false, "Should have returned an error."),
+        Err(error) => assert!(matches!(
+            error.downcast_ref::<RenderError>(),
+            Some(RenderError::TooLarge { limit: 8, .. })
+        )),
+    }
+}
+
+#[test]
+fn test_gen_payload_past_deadline() {
+    use serve_md_core::error::RenderError;
+
+    let mut state = State::default();
+    state.deadline = Some(std::time::Instant::now());
+    match generate_payload_from_slice(b"# Header\n", Arc::new(state)) {
+        Ok(_) => assert!(false, "Should have returned an error."),
+        Err(error) => assert!(matches!(
+            error.downcast_ref::<RenderError>(),
+            Some(RenderError::TimedOut)
+        )),
+    }
+}
+
 #[test]
 fn test_gen_payload_from_path() {
     use pretty_assertions::assert_eq;
@@ -128,7 +159,7 @@ fn test_gen_payload() {
         "key": [
           {
             "title": "title",
-            "uri": "/uri/path "
+            "uri": "/uri/path"
           },
           {
             "uri": "/dif/path"
@@ -157,3 +188,1702 @@ fn test_gen_payload() {
         }
     }
 }
+
+#[test]
+fn test_gen_payload_cbor() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::Payload;
+
+    let input = indoc! {r#"[key]: /uri/path "title"
+
+    # Header
+    some text.
+    "#};
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Refdef);
+    let payload = generate_payload_from_slice(input.as_bytes(), Arc::new(state));
+    match payload.and_then(|p| p.into_response_for(&serve_md_core::formats::Payload::Cbor)) {
+        Ok(vec) => {
+            let payload: Payload = ciborium::from_reader(&vec[..]).unwrap();
+            assert_eq!(payload.html, "<h1>Header</h1>\n<p>some text.</p>\n");
+            assert_eq!(payload.front_matter["key"][0]["title"], "title");
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_postcard() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::Payload;
+
+    let input = indoc! {r#"[key]: /uri/path "title"
+    [key]: /dif/path
+
+    # Header
+    some text.
+    "#};
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Refdef);
+    let payload = generate_payload_from_slice(input.as_bytes(), Arc::new(state));
+    match payload.and_then(|p| p.into_response_for(&serve_md_core::formats::Payload::Postcard)) {
+        Ok(vec) => {
+            let payload = Payload::from_postcard(&vec[..]).unwrap();
+            assert_eq!(payload.html, "<h1>Header</h1>\n<p>some text.</p>\n");
+            let key = &payload.front_matter["key"];
+            assert_eq!(key.as_array().map(Vec::len), Some(2));
+            assert_eq!(key[0]["title"], "title");
+            assert_eq!(key[1]["uri"], "/dif/path");
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_csv() {
+    use pretty_assertions::assert_eq;
+    let input = indoc! {r#"[key]: /uri/path "title"
+    [key]: /dif/path
+
+    # Header
+    some text.
+    "#};
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Refdef);
+    let expected_csv = indoc! {r#"key,value
+    key.0.title,title
+    key.0.uri,/uri/path
+    key.1.uri,/dif/path
+    "#};
+    let payload = generate_payload_from_slice(input.as_bytes(), Arc::new(state));
+    match payload.and_then(|p| p.into_response_for(&serve_md_core::formats::Payload::Csv)) {
+        Ok(vec) => {
+            assert_eq!(std::str::from_utf8(&vec).unwrap(), expected_csv)
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_write_response_for_reader() {
+    use pretty_assertions::assert_eq;
+    let input = indoc! {r#"[key]: /uri/path "title"
+
+    # Header
+    some text.
+    "#};
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Refdef);
+    let state = Arc::new(state);
+    let expected_csv = indoc! {r#"key,value
+    key.0.title,title
+    key.0.uri,/uri/path
+    "#};
+    let payload = generate_payload_from_reader(input.as_bytes(), state);
+    let written = payload.and_then(|p| {
+        let mut csv = vec![];
+        p.write_response_for(&mut csv, &serve_md_core::formats::Payload::Csv)?;
+        let mut json = vec![];
+        p.write_response_for(&mut json, &serve_md_core::formats::Payload::Json)?;
+        Ok((csv, json, p.into_response_for(&serve_md_core::formats::Payload::Json)?))
+    });
+    match written {
+        Ok((csv, json, expected_json)) => {
+            assert_eq!(std::str::from_utf8(&csv).unwrap(), expected_csv);
+            assert_eq!(json, expected_json);
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_xml() {
+    let input = indoc! {r#"[key]: /dif/path
+
+    # Header
+    some text.
+    "#};
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Refdef);
+    let payload = generate_payload_from_slice(input.as_bytes(), Arc::new(state));
+    match payload.and_then(|p| p.into_response_for(&serve_md_core::formats::Payload::Xml)) {
+        Ok(vec) => {
+            let xml = std::str::from_utf8(&vec).unwrap();
+            dbg!(&xml);
+            assert!(xml.starts_with("<Payload><front_matter><key><uri>/dif/path</uri></key>"));
+            assert!(xml.contains("<html>&lt;h1&gt;Header&lt;/h1&gt;"));
+            assert!(xml.ends_with("</html></Payload>"));
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_xml_names() {
+    use serve_md_core::formats::{Part, Payload as PayloadFormats};
+
+    let input = indoc! {r#"---
+    my key: one
+    1st: two
+    nested:
+      - "a b": three
+    ---
+    See [the docs].
+
+    [the docs]: /docs
+    "#};
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    state.refdefs = true;
+    let payload = match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => payload,
+        Err(error) => return assert!(false, "Should NEVER return an error. Error was {error}."),
+    };
+    let mut buf = vec![];
+    match payload.write_response_for(&mut buf, &PayloadFormats::Xml) {
+        Ok(()) => {
+            let xml = std::str::from_utf8(&buf).unwrap();
+            assert!(xml.contains("<my_key>one</my_key>"), "{xml}");
+            assert!(xml.contains("<_1st>two</_1st>"), "{xml}");
+            assert!(xml.contains("<nested><a_b>three</a_b></nested>"), "{xml}");
+            assert!(xml.contains("<refdefs><the_docs><uri>/docs</uri></the_docs></refdefs>"), "{xml}");
+        }
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+    buf.clear();
+    match payload.write_part_for(&mut buf, Part::Matter, &PayloadFormats::Xml) {
+        Ok(()) => {
+            let xml = std::str::from_utf8(&buf).unwrap();
+            assert!(xml.starts_with("<front_matter><_1st>two</_1st><my_key>one</my_key>"), "{xml}");
+        }
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_rss_feed() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::{
+        collection,
+        feed::{self, Channel},
+        formats::Feed,
+    };
+
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "resources", "feed"]
+        .iter()
+        .collect();
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    let mut documents = collection::scan(&path, &Arc::new(state)).unwrap();
+    feed::sort(&mut documents);
+    assert_eq!(documents.len(), 2);
+    assert_eq!(documents[0].title(), "Second post");
+    assert_eq!(documents[1].title(), "First post");
+
+    let channel = Channel {
+        title: "feed".to_string(),
+        link: "http://localhost:8083".to_string(),
+    };
+    let rss = feed::generate(&Feed::Rss, &channel, &documents).unwrap();
+    let rss = std::str::from_utf8(&rss).unwrap();
+    dbg!(&rss);
+    assert!(rss.contains("<link>http://localhost:8083/second.html</link>"));
+    assert!(rss.contains("<pubDate>Thu, 14 Sep 2023 09:30:00 +0100</pubDate>"));
+    assert!(rss.contains("<pubDate>Fri, 1 Sep 2023 00:00:00 +0000</pubDate>"));
+    assert!(rss.contains("<description>&lt;h1&gt;First&lt;/h1&gt;"));
+
+    let atom = feed::generate(&Feed::Atom, &channel, &documents).unwrap();
+    let atom = std::str::from_utf8(&atom).unwrap();
+    assert!(atom.contains("<updated>2023-09-14T09:30:00+01:00</updated>"));
+
+    let json = feed::generate(&Feed::Json, &channel, &documents).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["version"], "https://jsonfeed.org/version/1.1");
+    assert_eq!(json["items"][0]["url"], "http://localhost:8083/second.html");
+    assert_eq!(
+        json["items"][0]["date_published"],
+        "2023-09-14T09:30:00+01:00"
+    );
+    assert_eq!(json["items"][1]["_front_matter"]["title"], "First post");
+}
+
+#[test]
+fn test_scan_drafts() {
+    use serve_md_core::collection;
+
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "resources", "feed"]
+        .iter()
+        .collect();
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    state.show_drafts = true;
+    let documents = collection::scan(&path, &Arc::new(state)).unwrap();
+    assert_eq!(documents.len(), 3);
+    assert!(documents
+        .iter()
+        .any(|document| document.title() == "Unfinished post"));
+
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    state.drafts_not_found = true;
+    let draft = path.join("unfinished.html");
+    match determine(&draft.to_string_lossy(), Arc::new(state)) {
+        Ok(_) => assert!(false, "Should have returned an error."),
+        Err(error) => assert_eq!(
+            error.downcast_ref::<std::io::Error>().map(std::io::Error::kind),
+            Some(std::io::ErrorKind::NotFound)
+        ),
+    }
+}
+
+#[test]
+fn test_list_hashes() {
+    use serve_md_core::collection;
+
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "resources", "feed"]
+        .iter()
+        .collect();
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    match collection::list(&path, &state) {
+        Ok(listings) => {
+            let paths: Vec<_> = listings.iter().map(|listing| &listing.path[..]).collect();
+            assert_eq!(paths, ["first.md", "second.md"]);
+            let source = std::fs::read(path.join("first.md")).unwrap();
+            assert_eq!(listings[0].hash, collection::content_hash(&source, &state));
+            assert_ne!(listings[0].hash, listings[1].hash);
+            // Rendered differently, so a copy rendered before is stale.
+            let mut toc = state.clone();
+            toc.toc = true;
+            assert_ne!(listings[0].hash, collection::content_hash(&source, &toc));
+        }
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_navigation() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::navigation::{navigate, NavLink};
+
+    let link = |title: &str, path: &str| NavLink {
+        title: title.to_string(),
+        path: path.to_string(),
+    };
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    // Relative to the package, as a path relative to the current directory.
+    let path = Path::new("resources/navigation/guide/configure.md");
+    match navigate(path, &state) {
+        Ok(navigation) => {
+            assert_eq!(
+                navigation.prev,
+                Some(link("Install", "resources/navigation/guide/install.html"))
+            );
+            // Unweighted documents come last, by file name.
+            assert_eq!(
+                navigation.next,
+                Some(link("Troubleshooting", "resources/navigation/guide/troubleshooting.html"))
+            );
+            assert_eq!(
+                navigation.breadcrumbs,
+                vec![
+                    link("Docs", "resources/navigation/index.html"),
+                    link("guide", "resources/navigation/guide/index.html"),
+                ]
+            );
+        }
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_translations() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::i18n::Translation;
+
+    let translation = |lang: &str, path: &str| Translation {
+        lang: lang.to_string(),
+        path: path.to_string(),
+    };
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    state.i18n = true;
+    // Relative to the package, as a path relative to the current directory.
+    let path = Path::new("resources/i18n/page.fr.md");
+    match generate_payload_from_path(path, Arc::new(state)) {
+        Ok(payload) => assert_eq!(
+            payload.translations,
+            vec![
+                translation("de", "resources/i18n/page.de.html"),
+                translation("en", "resources/i18n/page.html"),
+                translation("fr", "resources/i18n/page.fr.html"),
+            ]
+        ),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_ignored() {
+    use serve_md_core::collection::{find_markdown, is_ignored};
+
+    // Relative to the package, as a path relative to the current directory.
+    let dir = Path::new("resources/ignored");
+    let mut state = State::default();
+    let mut paths = vec![];
+    match find_markdown(dir, &["md"], &state, &mut paths) {
+        Ok(()) => assert_eq!(paths, vec![dir.join("visible.md")]),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+    assert!(is_ignored(&dir.join(".dotted.md"), &state));
+    assert!(is_ignored(&dir.join("private/secret.md"), &state));
+    assert!(!is_ignored(&dir.join("visible.md"), &state));
+
+    let secret: PathBuf = [env!("CARGO_MANIFEST_DIR"), "resources", "ignored", "private", "secret.html"]
+        .iter()
+        .collect();
+    match determine(&secret.to_string_lossy(), Arc::new(state.clone())) {
+        Ok(_) => assert!(false, "Should have returned an error."),
+        Err(error) => assert_eq!(
+            error.downcast_ref::<std::io::Error>().map(std::io::Error::kind),
+            Some(std::io::ErrorKind::NotFound)
+        ),
+    }
+
+    state.show_ignored = true;
+    let mut paths = vec![];
+    match find_markdown(dir, &["md"], &state, &mut paths) {
+        Ok(()) => assert_eq!(paths.len(), 3),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+    assert!(!is_ignored(&dir.join("private/secret.md"), &state));
+}
+
+#[test]
+fn test_check_links() {
+    use serve_md_core::links::{check, BrokenLink};
+
+    let dir = Path::new("resources/links");
+    let broken = |document: &str, target: &str| BrokenLink {
+        document: document.to_string(),
+        target: target.to_string(),
+        reason: String::from("Not found."),
+    };
+    match check(dir, Arc::new(State::default())) {
+        Ok(report) => {
+            assert_eq!(report.documents, 2);
+            assert_eq!(report.links, 10);
+            assert_eq!(report.broken, vec![
+                broken("guide/setup.md", "/gone.md"),
+                broken("index.md", "guide/missing.html"),
+                broken("index.md", "logo.png"),
+            ]);
+            assert_eq!(report.external.keys().collect::<Vec<_>>(), vec!["https://example.com/docs"]);
+        },
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_bundle() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::{bundle, collection};
+
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "resources", "feed"]
+        .iter()
+        .collect();
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    let documents = collection::scan(&path, &Arc::new(state)).unwrap();
+    let bundle = bundle::generate(&documents).unwrap();
+    let lines: Vec<serde_json::Value> = std::str::from_utf8(&bundle)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["path"], "first.md");
+    assert_eq!(lines[0]["front_matter"]["title"], "First post");
+    assert_eq!(lines[1]["html"], "<h1>Second</h1>\n<p>hello again</p>\n");
+}
+
+#[test]
+fn test_gen_payload_with_template() {
+    use pretty_assertions::assert_eq;
+    let input = indoc! {r#"---
+    title: Page title
+    ---
+    # Header
+    "#};
+    let template: PathBuf = [env!("CARGO_MANIFEST_DIR"), "resources", "template.html"]
+        .iter()
+        .collect();
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    state.template = template.to_str().map(ToString::to_string);
+    let expected_html = indoc! {r#"<!DOCTYPE html>
+    <html>
+    <head>
+    <meta charset="utf-8">
+    <title>Page title</title>
+    </head>
+    <body>
+    <h1>Header</h1>
+
+    </body>
+    </html>"#};
+    let state = Arc::new(state);
+    let payload = generate_payload_from_slice(input.as_bytes(), Arc::clone(&state));
+    match payload.and_then(|p| {
+        p.into_response_with_state(&serve_md_core::formats::Payload::Html, &state)
+    }) {
+        Ok(vec) => {
+            assert_eq!(std::str::from_utf8(&vec).unwrap(), expected_html)
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_noindex() {
+    use serve_md_core::template::{Theme, ROBOTS_NOINDEX};
+
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    state.theme = Some(Theme::Light);
+    let state = Arc::new(state);
+    for (input, noindex) in [
+        ("---\nnoindex: true\n---\n# Hidden\n", true),
+        ("---\nnoindex: false\n---\n# Shown\n", false),
+    ] {
+        let payload = generate_payload_from_slice(input.as_bytes(), Arc::clone(&state)).unwrap();
+        assert_eq!(payload.noindex(), noindex);
+        match payload.into_response_with_state(&serve_md_core::formats::Payload::Html, &state) {
+            Ok(vec) => {
+                let html = std::str::from_utf8(&vec).unwrap();
+                assert_eq!(html.contains(ROBOTS_NOINDEX), noindex, "{html}");
+            }
+            Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_social_meta() {
+    use pretty_assertions::assert_eq;
+
+    let input = indoc! {r#"---
+    title: Social
+    image: /cover.png
+    ---
+    # Header
+    "#};
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    state.social_meta = true;
+    state.social_meta_overrides = [("og:type", ""), ("og:site_name", "Docs")]
+        .into_iter()
+        .map(|(name, content)| (name.to_string(), content.to_string()))
+        .collect();
+    let state = Arc::new(state);
+    let expected_html = indoc! {r#"<meta property="og:title" content="Social">
+    <meta name="twitter:title" content="Social">
+    <meta property="og:image" content="/cover.png">
+    <meta name="twitter:image" content="/cover.png">
+    <meta name="twitter:card" content="summary_large_image">
+    <meta property="og:site_name" content="Docs">
+    <h1>Header</h1>
+    "#};
+    match generate_payload_from_slice(input.as_bytes(), Arc::clone(&state)).and_then(|p| {
+        p.into_response_with_state(&serve_md_core::formats::Payload::Html, &state)
+    }) {
+        Ok(vec) => assert_eq!(std::str::from_utf8(&vec).unwrap(), expected_html),
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_lint() {
+    use serve_md_core::lint::LintWarning;
+
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    state.lint = true;
+    // Lines count the front matter.
+    let source = "---\ntitle: Lint\n---\n# Lint\n\n### Skipped\n";
+    match generate_payload_from_slice(source.as_bytes(), Arc::new(state)) {
+        Ok(payload) => assert_eq!(payload.lint, vec![LintWarning {
+            rule: String::from("heading-increment"),
+            line: 6,
+            message: String::from("Heading level jumps from h1 to h3."),
+        }]),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_gen_payload_edit_link() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::generate_payload_from_source;
+
+    let mut state = State::default();
+    state.edit_base_url = Some("https://github.com/user/repo/edit/main/".to_string());
+    let state = Arc::new(state);
+    let expected_html = indoc! {r#"<h1>Header</h1>
+    <p class="edit-page"><a href="https://github.com/user/repo/edit/main/docs/guide.md">Edit this page</a></p>
+    "#};
+    match generate_payload_from_source(Path::new("./docs/guide.md"), b"# Header\n", Arc::clone(&state))
+        .and_then(|p| p.into_response_with_state(&serve_md_core::formats::Payload::Html, &state))
+    {
+        Ok(vec) => assert_eq!(std::str::from_utf8(&vec).unwrap(), expected_html),
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_with_theme() {
+    use serve_md_core::template::Theme;
+
+    let input = "# Header\n";
+    let mut state = State::default();
+    state.theme = Some(Theme::Dark);
+    state.stylesheet = Some("/custom.css".to_string());
+    let state = Arc::new(state);
+    let payload = generate_payload_from_slice(input.as_bytes(), Arc::clone(&state));
+    match payload.and_then(|p| {
+        p.into_response_with_state(&serve_md_core::formats::Payload::Html, &state)
+    }) {
+        Ok(vec) => {
+            let html = std::str::from_utf8(&vec).unwrap();
+            dbg!(&html);
+            assert!(html.starts_with("<!DOCTYPE html>"));
+            assert!(html.contains(r#"<link rel="stylesheet" href="/custom.css">"#));
+            assert!(html.contains(Theme::Dark.css()));
+            assert!(html.contains("<body>\n<h1>Header</h1>\n</body>"));
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_default_front_matter() {
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    let defaults = json!({
+        "author": "Site author",
+        "layout": { "sidebar": true, "width": "wide" },
+    });
+    for (input, expected) in [
+        (
+            indoc! {r#"---
+            author: Page author
+            layout:
+              width: narrow
+            ---
+            # Header
+            "#},
+            json!({
+                "author": "Page author",
+                "layout": { "sidebar": true, "width": "narrow" },
+            }),
+        ),
+        ("# Header\n", defaults.clone()),
+    ] {
+        let mut state = State::default();
+        state.front_matter = Some(Matter::Yaml);
+        state.default_front_matter = defaults.as_object().cloned().unwrap_or_default();
+        match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+            Ok(payload) => assert_eq!(payload.front_matter, expected),
+            Err(error) => {
+                dbg!(&error);
+                assert!(false, "Should NEVER return an error. Error was {error}.")
+            }
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_typed_front_matter() {
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use serve_md_core::formats::Payload as PayloadFormats;
+
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Toml);
+    state.default_front_matter = json!({ "author": "Site author" }).as_object().cloned().unwrap_or_default();
+    let input = indoc! {r#"---
+    title = "Dated"
+    published = 2023-10-01T10:00:00Z
+    ---
+    # Header
+    "#};
+    let toml = generate_payload_from_slice(input.as_bytes(), Arc::new(state))
+        .and_then(|payload| {
+            assert_eq!(payload.front_matter["published"], "2023-10-01T10:00:00Z");
+            payload.into_response_for(&PayloadFormats::Toml)
+        });
+    match toml.map(|buf| toml::from_str::<toml::Table>(&String::from_utf8_lossy(&buf))) {
+        Ok(Ok(output)) => {
+            let matter = &output["front_matter"];
+            let published = matter["published"].as_datetime().map(ToString::to_string);
+            assert_eq!(published.as_deref(), Some("2023-10-01T10:00:00Z"));
+            assert_eq!(matter["author"].as_str(), Some("Site author"));
+        }
+        error => assert!(false, "Should NEVER return an error. Error was {error:?}."),
+    }
+
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    let input = indoc! {r#"---
+    origin: !Point { x: 1, y: 2 }
+    ---
+    # Header
+    "#};
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state))
+        .and_then(|payload| payload.into_response_for(&PayloadFormats::Yaml))
+    {
+        Ok(buf) => {
+            let output: serde_yaml::Value = serde_yaml::from_slice(&buf).unwrap();
+            match &output["front_matter"]["origin"] {
+                serde_yaml::Value::Tagged(tagged) => {
+                    assert_eq!(tagged.tag, "Point");
+                    assert_eq!(tagged.value["x"], 1);
+                }
+                value => assert!(false, "Should keep the tag. Value was {value:?}."),
+            }
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_matter_in_html() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::template::MatterInHtml;
+
+    let input = indoc! {r#"---
+    title: A <b>title</b>
+    tags: [a, b]
+    draft: false
+    ---
+    # Header
+    "#};
+    for (mode, expected) in [
+        (
+            MatterInHtml::Meta,
+            indoc! {r#"<meta name="draft" content="false">
+            <meta name="tags" content="a, b">
+            <meta name="title" content="A &lt;b&gt;title&lt;/b&gt;">
+            <h1>Header</h1>
+            "#},
+        ),
+        (
+            MatterInHtml::JsonLd,
+            indoc! {r#"<script type="application/ld+json">{"draft":false,"tags":["a","b"],"title":"A <b>title<\/b>"}</script>
+            <h1>Header</h1>
+            "#},
+        ),
+        (
+            MatterInHtml::Table,
+            indoc! {r#"<table class="front-matter">
+            <tbody>
+            <tr><th>draft</th><td>false</td></tr>
+            <tr><th>tags</th><td>[&quot;a&quot;,&quot;b&quot;]</td></tr>
+            <tr><th>title</th><td>A &lt;b&gt;title&lt;/b&gt;</td></tr>
+            </tbody>
+            </table>
+            <h1>Header</h1>
+            "#},
+        ),
+    ] {
+        let mut state = State::default();
+        state.front_matter = Some(Matter::Yaml);
+        state.matter_in_html = Some(mode);
+        let state = Arc::new(state);
+        let payload = generate_payload_from_slice(input.as_bytes(), Arc::clone(&state));
+        match payload.and_then(|p| {
+            p.into_response_with_state(&serve_md_core::formats::Payload::Html, &state)
+        }) {
+            Ok(vec) => assert_eq!(std::str::from_utf8(&vec).unwrap(), expected),
+            Err(error) => {
+                dbg!(&error);
+                assert!(false, "Should NEVER return an error. Error was {error}.")
+            }
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_toc_with_heading_anchors() {
+    use pretty_assertions::assert_eq;
+
+    let input = indoc! {r#"# Intro
+    ## Setup `cargo`
+    ## Setup cargo
+    "#};
+    let mut state = State::default();
+    state.toc = true;
+    state.heading_anchors = true;
+    let payload = generate_payload_from_slice(input.as_bytes(), Arc::new(state));
+    match payload.and_then(|p| p.into_response_for(&serve_md_core::formats::Payload::Json)) {
+        Ok(vec) => {
+            let json: serde_json::Value = serde_json::from_slice(&vec).unwrap();
+            dbg!(&json);
+            assert_eq!(
+                json["toc"],
+                serde_json::json!([
+                    { "level": 1, "text": "Intro", "slug": "intro" },
+                    { "level": 2, "text": "Setup cargo", "slug": "setup-cargo" },
+                    { "level": 2, "text": "Setup cargo", "slug": "setup-cargo-1" },
+                ])
+            );
+            assert_eq!(
+                json["html"],
+                indoc! {r##"<h1 id="intro">Intro<a class="anchor" href="#intro" aria-hidden="true">#</a></h1>
+                <h2 id="setup-cargo">Setup <code>cargo</code><a class="anchor" href="#setup-cargo" aria-hidden="true">#</a></h2>
+                <h2 id="setup-cargo-1">Setup cargo<a class="anchor" href="#setup-cargo-1" aria-hidden="true">#</a></h2>
+                "##}
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_toc_with_slug_style() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::slug::SlugStyle;
+
+    let input = indoc! {r#"# Café & Crème
+    ## Café, Crème
+    "#};
+    let mut state = State::default();
+    state.toc = true;
+    state.heading_anchors = true;
+    state.slug_style = Some(SlugStyle::Ascii);
+    state.slug_duplicates = Some(String::from("_"));
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            let slugs: Vec<_> = payload.toc.iter().map(|entry| entry.slug.as_str()).collect();
+            assert_eq!(slugs, ["cafe-creme", "cafe-creme_1"]);
+            assert!(payload.html.contains(r#"<h2 id="cafe-creme_1">"#));
+        }
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_gen_payload_refdefs() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::LinkDefinition;
+
+    let input = indoc! {r#"# Header
+    Some [text][later].
+
+    [later]: /later/path 'A title'
+    [Other]: </other path>
+    "#};
+    let mut state = State::default();
+    state.refdefs = true;
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.refdefs.into_iter().collect::<Vec<_>>(),
+                vec![
+                    (
+                        "Other".to_string(),
+                        LinkDefinition {
+                            uri: "/other path".to_string(),
+                            title: None,
+                        }
+                    ),
+                    (
+                        "later".to_string(),
+                        LinkDefinition {
+                            uri: "/later/path".to_string(),
+                            title: Some("A title".to_string()),
+                        }
+                    ),
+                ]
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_sanitized() {
+    let input = indoc! {r#"# Header
+
+    <script>alert("hi")</script>
+
+    <p onclick="alert('hi')">some text.</p>
+    "#};
+    let mut state = State::default();
+    state.sanitize = true;
+    state.heading_anchors = true;
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            dbg!(&payload.html);
+            assert!(!payload.html.contains("<script"));
+            assert!(!payload.html.contains("onclick"));
+            assert!(payload.html.contains(r#"<h1 id="header">"#));
+            assert!(payload.html.contains("some text."));
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_no_inline_html() {
+    use pretty_assertions::assert_eq;
+    let input = indoc! {r#"<div onclick="alert('hi')">block</div>
+
+    some <b>inline</b> text.
+    "#};
+    let mut state = State::default();
+    state.no_inline_html = true;
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.html,
+                indoc! {r#"&lt;div onclick=&quot;alert('hi')&quot;&gt;block&lt;/div&gt;
+                <p>some &lt;b&gt;inline&lt;/b&gt; text.</p>
+                "#}
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_interactive_tasklists() {
+    use pretty_assertions::assert_eq;
+    let input = indoc! {r#"[home]: https://example.com
+
+    - [ ] one
+    - [x] two
+    "#};
+    let mut state = State::default();
+    state.tasklists = true;
+    state.interactive_tasklists = true;
+    state.front_matter = Some(Matter::Refdef);
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.html,
+                indoc! {r#"<ul>
+                <li><input type="checkbox" data-line="3"/>
+                one</li>
+                <li><input type="checkbox" data-line="4" checked=""/>
+                two</li>
+                </ul>
+                "#}
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_plugin_order() {
+    use pretty_assertions::assert_eq;
+    let input = indoc! {r#"```mermaid
+    graph TD
+    ```
+    "#};
+    for (plugins, prefix) in [
+        (["mermaid", "highlight"], r#"<pre class="mermaid">"#),
+        (["highlight", "mermaid"], r#"<pre class="code">"#),
+    ] {
+        let mut state = State::default();
+        state.plugins = Some(plugins.iter().map(ToString::to_string).collect());
+        match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+            Ok(payload) => {
+                dbg!(&payload.html);
+                assert!(payload.html.starts_with(prefix));
+            }
+            Err(error) => {
+                dbg!(&error);
+                assert!(false, "Should NEVER return an error. Error was {error}.")
+            }
+        }
+    }
+
+    let mut state = State::default();
+    state.plugins = Some(vec!["emoji".to_string(), "nope".to_string()]);
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(_) => assert!(false, "Should have returned an error."),
+        Err(error) => assert_eq!(error.to_string(), "Unknown plugin nope."),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_gen_payload_exec_plugin() {
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use serve_md_core::plugin::PluginError;
+
+    let input = "# Header\n";
+    let payload = |options| {
+        let mut state = State::default();
+        state.plugins = Some(vec!["shout".to_string()]);
+        state.plugin_options.insert("shout".to_string(), options);
+        generate_payload_from_slice(input.as_bytes(), Arc::new(state))
+    };
+
+    // Shouts every text event.
+    match payload(
+        json!({ "command": "sed", "args": ["s/\"text\":\"\\([^\"]*\\)\"/\"text\":\"\\1!\"/g"] }),
+    ) {
+        Ok(payload) => assert_eq!(payload.html, "<h1>Header!</h1>\n"),
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+
+    // Failures & timeouts fail the whole document.
+    for options in [
+        json!({ "command": "false" }),
+        json!({ "command": "sleep", "args": ["5"], "timeout_ms": 100 }),
+    ] {
+        match payload(options) {
+            Ok(_) => assert!(false, "Should have returned an error."),
+            Err(error) => {
+                assert_eq!(error.to_string(), "The shout plugin failed.");
+                assert!(error.downcast_ref::<PluginError>().is_some());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_emoji_map() {
+    use pretty_assertions::assert_eq;
+
+    let map: PathBuf = [env!("CARGO_MANIFEST_DIR"), "resources", "emoji.toml"]
+        .iter()
+        .collect();
+    let input = "Ship it :shipit: :ferris: :tada:\n";
+    let mut state = State::default();
+    state.emoji_map = Some(map.to_string_lossy().into_owned());
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.html,
+                "<p>Ship it 🐿️ <img class=\"emoji\" src=\"/images/ferris.png\" alt=\":ferris:\"> 🎉</p>\n"
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_emoji_map_read_on_load() {
+    use pretty_assertions::assert_eq;
+
+    let dir = std::env::temp_dir().join("serve_md_emoji_map_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let map = dir.join("emoji.toml");
+    std::fs::write(&map, "ok = \"👍\"\n").unwrap();
+
+    let path = map.to_string_lossy().to_string();
+    let mut state = State::try_from_args(["serve_md", "--emoji-map", &path]).unwrap();
+    if let Err(error) = state.try_load_config() {
+        assert!(false, "Should NEVER return an error. Error was {error}.")
+    }
+    // Renders use the map read as the options loaded, until they load again.
+    std::fs::write(&map, "ok = \"👌\"\n").unwrap();
+    match generate_payload_from_slice(":ok:".as_bytes(), Arc::new(state.clone())) {
+        Ok(payload) => assert_eq!(payload.html, "<p>👍</p>\n"),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+    if let Err(error) = state.try_load_config() {
+        assert!(false, "Should NEVER return an error. Error was {error}.")
+    }
+    match generate_payload_from_slice(":ok:".as_bytes(), Arc::new(state.clone())) {
+        Ok(payload) => assert_eq!(payload.html, "<p>👌</p>\n"),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+
+    std::fs::write(&map, "ok = [1, 2]\n").unwrap();
+    match state.try_load_config() {
+        Ok(_) => assert!(false, "Should reject an invalid emoji map."),
+        Err(error) => assert!(error.to_string().contains("emoji_map"), "{error}"),
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_gen_payload_collapsible_plain_headers() {
+    use pretty_assertions::assert_eq;
+
+    let input = indoc! {r#"## Notes on `main`
+    Some text.
+    ## Other
+    "#};
+    let mut state = State::default();
+    state.collapsible_headers = vec![(2, "Notes on main".to_string())];
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.html,
+                indoc! {r#"<details open>
+                <summary>Notes on <code>main</code></summary>
+                <p>Some text.</p>
+                </details>
+                <h2>Other</h2>
+                "#}
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_collapsible_section_ends_document() {
+    use pretty_assertions::assert_eq;
+
+    let input = indoc! {r#"## Other
+    ## Notes
+    Some text.
+    "#};
+    let mut state = State::default();
+    state.collapsible_headers = vec![(2, "Notes".to_string())];
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.html,
+                "<h2>Other</h2>\n<details open>\n<summary>Notes</summary>\n<p>Some text.</p>\n</details>"
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_abbreviations() {
+    use pretty_assertions::assert_eq;
+
+    let input = indoc! {r#"The HTML spec, by the W3C.
+
+    *[HTML]: HyperText Markup Language
+    *[W3C]:  World Wide Web Consortium
+    "#};
+    let mut state = State::default();
+    state.abbreviations = true;
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.html,
+                "<p>The <abbr title=\"HyperText Markup Language\">HTML</abbr> spec, by the <abbr title=\"World Wide Web Consortium\">W3C</abbr>.</p>\n"
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_typography() {
+    use pretty_assertions::assert_eq;
+
+    let input = indoc! {r#"Copyright (c) <- and -> but not `(c)`.
+    "#};
+    let mut state = State::default();
+    state.typography = true;
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.html,
+                "<p>Copyright © ← and → but not <code>(c)</code>.</p>\n"
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_figures() {
+    use pretty_assertions::assert_eq;
+
+    let input = indoc! {r#"![A cat](/cat.png?w=300&h=200 "Our cat")
+
+    Inline ![dog](/dog.png) image.
+    "#};
+    let mut state = State::default();
+    state.figures = true;
+    state.figure_sizes = true;
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.html,
+                indoc! {r#"<figure>
+                <img src="/cat.png" alt="A cat" title="Our cat" width="300" height="200" />
+                <figcaption>Our cat</figcaption>
+                </figure>
+                <p>Inline <img src="/dog.png" alt="dog" /> image.</p>
+                "#}
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_gfm() {
+    use pretty_assertions::assert_eq;
+
+    let input = indoc! {r#"> [!TIP]
+    > See www.example.com/a_b.
+
+    - [x] ~~done~~
+    "#};
+    let mut state = State::default();
+    state.gfm = true;
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.html,
+                indoc! {r#"<div class="markdown-alert markdown-alert-tip">
+                <p class="markdown-alert-title">Tip</p>
+                <p>See <a href="http://www.example.com/a_b">www.example.com/a_b</a>.</p>
+                </div>
+                <ul>
+                <li><input disabled="" type="checkbox" checked=""/>
+                <del>done</del></li>
+                </ul>
+                "#}
+            );
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_gen_payload_render_errors() {
+    use serve_md_core::error::RenderError;
+
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    let state = Arc::new(state);
+
+    let invalid = indoc! {r#"---
+    title: [unclosed
+    ---
+    # Header
+    "#};
+    match generate_payload_from_slice(invalid.as_bytes(), Arc::clone(&state)) {
+        Ok(_) => assert!(false, "Should have returned an error."),
+        Err(error) => assert!(matches!(
+            error.downcast_ref::<RenderError>(),
+            Some(RenderError::InvalidFrontMatter(Matter::Yaml))
+        )),
+    }
+
+    match generate_payload_from_slice(b"# \xff\n", Arc::clone(&state)) {
+        Ok(_) => assert!(false, "Should have returned an error."),
+        Err(error) => assert!(matches!(
+            error.downcast_ref::<RenderError>(),
+            Some(RenderError::InvalidUtf8)
+        )),
+    }
+
+    let written = generate_payload_from_slice(b"# Header\n", state).and_then(|payload| {
+        payload.into_response_for(&serve_md_core::formats::Payload::Markdown)
+    });
+    match written {
+        Ok(_) => assert!(false, "Should have returned an error."),
+        Err(error) => assert!(matches!(
+            error.downcast_ref::<RenderError>(),
+            Some(RenderError::UnsupportedFormat(_))
+        )),
+    }
+}
+
+#[test]
+fn test_gen_payload_parts() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::{
+        error::RenderError,
+        formats::{Part, Payload as PayloadFormats},
+    };
+
+    let mut state = State::default();
+    state.front_matter = Some(Matter::Yaml);
+    let input = indoc! {r#"---
+    title: Parts
+    ---
+    # Header
+    "#};
+    let payload = generate_payload_from_slice(input.as_bytes(), Arc::new(state)).unwrap();
+
+    match payload.into_part_for(Part::Matter, &PayloadFormats::Json) {
+        Ok(buf) => assert_eq!(
+            std::str::from_utf8(&buf).unwrap(),
+            "{\n  \"title\": \"Parts\"\n}"
+        ),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+    match payload.into_part_for(Part::Body, &PayloadFormats::Html) {
+        Ok(buf) => assert_eq!(std::str::from_utf8(&buf).unwrap(), "<h1>Header</h1>\n"),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+    match payload.into_part_for(Part::Matter, &PayloadFormats::Html) {
+        Ok(_) => assert!(false, "Should have returned an error."),
+        Err(error) => assert!(matches!(
+            error.downcast_ref::<RenderError>(),
+            Some(RenderError::UnsupportedPart { part: Part::Matter, .. })
+        )),
+    }
+}
+
+#[test]
+fn test_part_tables() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::{error::RenderError, formats::{Part, Payload as PayloadFormats}};
+
+    let input = indoc! {r#"
+    | Name | Size |
+    |------|------|
+    | `a`  | 1, 2 |
+
+    | Planet |
+    |--------|
+    | Mars   |
+    "#};
+    let mut state = State::default();
+    state.tables = true;
+    let state = Arc::new(state);
+    let part = |part| generate_payload_from_slice(input.as_bytes(), Arc::clone(&state))
+        .and_then(|payload| payload.into_part_for(part, &PayloadFormats::Csv));
+
+    match part(Part::Tables) {
+        Ok(vec) => assert_eq!(std::str::from_utf8(&vec).unwrap(), "Name,Size\na,\"1, 2\"\n\nPlanet\nMars\n"),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+    match part(Part::Table(2)) {
+        Ok(vec) => assert_eq!(std::str::from_utf8(&vec).unwrap(), "Planet\nMars\n"),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+    match part(Part::Table(3)) {
+        Ok(_) => assert!(false, "Should have returned an error."),
+        Err(error) => assert!(matches!(
+            error.downcast_ref::<RenderError>(),
+            Some(RenderError::NoSuchTable { number: 3, count: 2 })
+        )),
+    }
+}
+
+#[test]
+fn test_gen_payload_table_classes() {
+    use pretty_assertions::assert_eq;
+
+    let input = indoc! {r#"
+    | Name | Size |
+    |:-----|-----:|
+    | a    | 1    |
+    "#};
+    let mut state = State::default();
+    state.tables = true;
+    state.table_class = Some(String::from("data"));
+    state.sortable_tables = true;
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => assert_eq!(
+            payload.html,
+            concat!(
+                r#"<table class="data" data-sortable><thead><tr>"#,
+                r#"<th style="text-align: left" data-column="0" aria-sort="none">Name</th>"#,
+                r#"<th style="text-align: right" data-column="1" aria-sort="none">Size</th>"#,
+                "</tr></thead><tbody>\n",
+                r#"<tr><td style="text-align: left">a</td><td style="text-align: right">1</td></tr>"#,
+                "\n</tbody></table>\n",
+            )
+        ),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_gen_payload_locale_quotes() {
+    use pretty_assertions::assert_eq;
+
+    let mut state = State::default();
+    state.smart_punctuation = true;
+    state.locale = Some(String::from("de"));
+    match generate_payload_from_slice(r#""Sie sagte 'ja'.""#.as_bytes(), Arc::new(state)) {
+        Ok(payload) => assert_eq!(payload.html, "<p>„Sie sagte ‚ja‘.“</p>\n"),
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_gen_payload_code_blocks() {
+    use pretty_assertions::assert_eq;
+
+    let input = indoc! {r#"
+    ```rust title="main.rs"
+    fn main() {}
+    ```
+    "#};
+    let mut state = State::default();
+    state.code_titles = true;
+    state.code_copy = true;
+    let state = Arc::new(state);
+    match generate_payload_from_slice(input.as_bytes(), Arc::clone(&state)) {
+        Ok(payload) => {
+            assert_eq!(
+                payload.html,
+                concat!(
+                    r#"<div class="code-block"><div class="code-header">"#,
+                    r#"<span class="code-title">main.rs</span>"#,
+                    r#"<button class="code-copy" type="button" aria-label="Copy code">Copy</button></div>"#,
+                    "\n",
+                    r#"<pre><code class="language-rust">fn main() {}"#,
+                    "\n</code></pre>\n</div>\n",
+                )
+            );
+            match serve_md_core::template::render(&payload, &state) {
+                Ok(html) => assert!(html.ends_with(serve_md_core::plugin::CODE_COPY_SCRIPT)),
+                Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+            }
+        }
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_gen_payload_highlight_lines() {
+    let input = indoc! {r#"
+    ```rust {2} linenos
+    fn main() {
+        println!("Hello");
+    }
+    ```
+    "#};
+    let mut state = State::default();
+    state.highlight = true;
+    match generate_payload_from_slice(input.as_bytes(), Arc::new(state)) {
+        Ok(payload) => {
+            assert!(payload.html.starts_with(r#"<pre class="code"><code class="language-rust">"#));
+            assert!(payload.html.contains(r#"<span class="line" data-line="1">"#));
+            assert!(payload.html.contains(r#"<span class="line highlighted" data-line="2">"#));
+            assert!(payload.html.contains(r#"<span class="line" data-line="3">"#));
+            assert!(!payload.html.contains(r#"data-line="4""#));
+        }
+        Err(error) => assert!(false, "Should NEVER return an error. Error was {error}."),
+    }
+}
+
+#[test]
+fn test_determine_with_part_extension() {
+    use pretty_assertions::assert_eq;
+
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "resources", "hio.body.html"]
+        .iter()
+        .collect();
+    match determine(&path.to_string_lossy(), Arc::new(State::default())) {
+        Ok(vec) => {
+            let html = std::str::from_utf8(&vec).unwrap();
+            assert!(html.starts_with("<h1>Header 1!</h1>"));
+            assert_eq!(html.matches("<html").count(), 0);
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_determine_renders_markdown_for_other_extensions() {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "resources", "hio.json"]
+        .iter()
+        .collect();
+    match determine(&path.to_string_lossy(), Arc::new(State::default())) {
+        Ok(vec) => {
+            let payload: serde_json::Value = serde_json::from_slice(&vec).unwrap();
+            assert!(payload["html"].as_str().is_some_and(|html| !html.is_empty()));
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+}
+
+#[test]
+fn test_determine_with_markdown_extensions() {
+    use pretty_assertions::assert_eq;
+    use serve_md_core::resolve;
+
+    let dir = std::env::temp_dir().join("serve_md_markdown_extensions_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("long.markdown"), "# Long\n").unwrap();
+
+    let mut state = State::default();
+    state.markdown_extensions = Some(vec!["md".to_string(), "markdown".to_string()]);
+    let path = dir.join("long.html").to_string_lossy().to_string();
+    match resolve(&path, &state) {
+        Some((_, markdown)) => assert!(markdown.ends_with("long.markdown")),
+        None => assert!(false, "Should have resolved {path}."),
+    }
+    // Missing files fall back to the first extension.
+    let missing = dir.join("missing.html").to_string_lossy().to_string();
+    match resolve(&missing, &state) {
+        Some((_, markdown)) => assert!(markdown.ends_with("missing.md")),
+        None => assert!(false, "Should have resolved {missing}."),
+    }
+
+    match determine(&path, Arc::new(state)) {
+        Ok(vec) => assert_eq!(std::str::from_utf8(&vec).unwrap(), "<h1>Long</h1>\n"),
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_process_commonmark_tokens() {
+    use pretty_assertions::assert_eq;
+    use pulldown_cmark::{html, Event};
+    use serve_md_core::{
+        make_commonmark_parser, make_commonmark_plugins,
+        plugin::{Stage, StreamPlugin},
+        process_commonmark_tokens,
+    };
+
+    struct Shout;
+
+    impl StreamPlugin for Shout {
+        fn map<'input>(&mut self, event: Event<'input>) -> anyhow::Result<Event<'input>> {
+            Ok(match event {
+                Event::Text(text) => Event::Text(text.to_uppercase().into()),
+                event => event,
+            })
+        }
+    }
+
+    let mut state = State::default();
+    state.emoji_shortcodes = true;
+    let mut pipeline = match make_commonmark_plugins(&state) {
+        Ok(pipeline) => pipeline,
+        Err(error) => {
+            dbg!(&error);
+            panic!("Should NEVER return an error. Error was {error}.")
+        }
+    };
+    pipeline.push("shout", Stage::Stream(Box::new(Shout)));
+    assert_eq!(pipeline.names().collect::<Vec<_>>(), ["emoji", "shout"]);
+
+    let parser = make_commonmark_parser("# Hello :tada:\nworld", &state);
+    match process_commonmark_tokens(parser, pipeline, &state) {
+        Ok(events) => {
+            let mut output = String::new();
+            html::push_html(&mut output, events.into_iter());
+            assert_eq!(output, "<h1>HELLO 🎉</h1>\n<p>WORLD</p>\n");
+        }
+        Err(error) => {
+            dbg!(&error);
+            assert!(false, "Should NEVER return an error. Error was {error}.")
+        }
+    }
+    let empty = make_commonmark_parser("", &state);
+    assert!(matches!(process_commonmark_tokens(empty, Default::default(), &state), Ok(events) if events.is_empty()));
+}
+
+mod roundtrip {
+    use proptest::prelude::*;
+    use serde_json::{json, Value};
+    use serve_md_core::{formats::Payload as PayloadFormats, toc::TocEntry, LinkDefinition, Payload};
+
+    const EXTENSIONS: [&str; 10] = [
+        "html", "md", "json", "yaml", "toml", "csv", "pickle", "postcard", "cbor", "xml",
+    ];
+
+    /// Keys are kept to valid xml element names, which xml requires of every
+    /// map key.
+    const KEY: &str = "[a-z_][a-z0-9_-]{0,11}";
+
+    /// Front matter is always a map, of strings, integers, booleans & lists,
+    /// as read from a document's front matter.
+    fn front_matter() -> impl Strategy<Value = Value> {
+        let scalar = prop_oneof![
+            any::<String>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<bool>().prop_map(Value::from),
+        ];
+        prop::collection::btree_map(
+            KEY,
+            (scalar, prop::collection::vec(any::<String>(), 0..4)),
+            0..6,
+        )
+        .prop_map(|map| {
+            map.into_iter()
+                .enumerate()
+                .map(|(index, (key, (scalar, list)))| {
+                    let value = if index % 2 == 0 { scalar } else { json!(list) };
+                    (key, value)
+                })
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        })
+    }
+
+    fn payload() -> impl Strategy<Value = Payload> {
+        let toc = prop::collection::vec(
+            (1u8..7, any::<String>(), "[a-z0-9-]{0,16}")
+                .prop_map(|(level, text, slug)| TocEntry { level, text, slug }),
+            0..6,
+        );
+        let refdefs = prop::collection::btree_map(
+            KEY,
+            (any::<String>(), prop::option::of(any::<String>()))
+                .prop_map(|(uri, title)| LinkDefinition { uri, title }),
+            0..4,
+        );
+        (front_matter(), any::<String>(), toc, refdefs).prop_map(
+            |(front_matter, html, toc, refdefs)| Payload {
+                front_matter,
+                typed_front_matter: None,
+                html,
+                toc,
+                refdefs,
+                git: None,
+                navigation: None,
+                lint: vec![],
+                translations: vec![],
+                tables: vec![],
+                path: None,
+            },
+        )
+    }
+
+    fn render(payload: &Payload, extension: &str) -> Vec<u8> {
+        let mut buf = vec![];
+        let extension = PayloadFormats::try_from(extension).unwrap();
+        match payload.write_response_for(&mut buf, &extension) {
+            Ok(_) => buf,
+            Err(error) => {
+                dbg!(&error);
+                panic!("Should NEVER return an error. Error was {error}.")
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip_test_json(payload in payload()) {
+            let bytes = render(&payload, "json");
+            let value: Payload = serde_json::from_slice(&bytes).unwrap();
+            prop_assert_eq!(value, payload);
+        }
+
+        #[test]
+        fn roundtrip_test_cbor(payload in payload()) {
+            let bytes = render(&payload, "cbor");
+            let value: Payload = ciborium::from_reader(bytes.as_slice()).unwrap();
+            prop_assert_eq!(value, payload);
+        }
+
+        #[test]
+        fn roundtrip_test_pickle(payload in payload()) {
+            let bytes = render(&payload, "pickle");
+            let value: Payload = serde_pickle::from_slice(&bytes, Default::default()).unwrap();
+            prop_assert_eq!(value, payload);
+        }
+
+        #[test]
+        fn roundtrip_test_postcard(payload in payload()) {
+            let bytes = render(&payload, "postcard");
+            let value = Payload::from_postcard(&bytes).unwrap();
+            prop_assert_eq!(value, payload);
+        }
+
+        #[test]
+        fn roundtrip_test_every_extension(payload in payload(), extension in prop::sample::select(EXTENSIONS.to_vec())) {
+            let format = PayloadFormats::try_from(extension).unwrap();
+            match payload.into_response_for(&format) {
+                Ok(_) => prop_assert!(format != PayloadFormats::Markdown),
+                Err(error) => {
+                    // Markdown is the source document, which a payload no longer has.
+                    prop_assert_eq!(format, PayloadFormats::Markdown, "{}", error);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use napi::bindgen_prelude::Buffer;
+use napi_derive::napi;
+use serde_json::Value;
+use serve_md_core::{
+    formats::Payload as PayloadFormats, generate_payload_from_path, generate_payload_from_slice,
+    state::State,
+};
+use std::{path::Path, sync::Arc};
+
+/// Renders `markdown` into the format of `extension`, `html` by default,
+/// with `options`, an object of the same options as a config file, e.g.
+/// `{ emoji_shortcodes: true, toc: true }`. Html is wrapped in the page
+/// template, if one is set.
+#[napi]
+pub fn render(
+    markdown: String,
+    options: Option<Value>,
+    extension: Option<String>,
+) -> napi::Result<Buffer> {
+    let state = state(options)?;
+    let format = format(extension)?;
+    generate_payload_from_slice(markdown.as_bytes(), Arc::new(state.clone()))
+        .and_then(|payload| payload.into_response_with_state(&format, &state))
+        .map(Buffer::from)
+        .map_err(to_napi)
+}
+
+/// Renders `markdown` into just its html, with `options`, an object of the
+/// same options as a config file.
+#[napi]
+pub fn render_html(markdown: String, options: Option<Value>) -> napi::Result<String> {
+    let state = state(options)?;
+    generate_payload_from_slice(markdown.as_bytes(), Arc::new(state))
+        .map(|payload| payload.html)
+        .map_err(to_napi)
+}
+
+/// Renders the document at `path` into the format of `extension`, `html` by
+/// default, like `render`. Documents with a converter for their extension are
+/// rendered through it.
+#[napi]
+pub fn render_file(
+    path: String,
+    options: Option<Value>,
+    extension: Option<String>,
+) -> napi::Result<Buffer> {
+    let state = state(options)?;
+    let format = format(extension)?;
+    generate_payload_from_path(Path::new(&path), Arc::new(state.clone()))
+        .and_then(|payload| payload.into_response_with_state(&format, &state))
+        .map(Buffer::from)
+        .map_err(to_napi)
+}
+
+fn state(options: Option<Value>) -> napi::Result<State> {
+    let options = options
+        .filter(|options| !options.is_null())
+        .map(|options| options.to_string())
+        .unwrap_or_default();
+    State::try_from_options(&options).map_err(to_napi)
+}
+
+fn format(extension: Option<String>) -> napi::Result<PayloadFormats> {
+    PayloadFormats::try_from(extension.as_deref().unwrap_or("html")).map_err(to_napi)
+}
+
+fn to_napi(error: anyhow::Error) -> napi::Error {
+    napi::Error::from_reason(format!("{error:#}"))
+}
@@ -0,0 +1,41 @@
+use serve_md_core::{
+    formats::Payload as PayloadFormats, generate_payload_from_slice, state::State,
+};
+use std::sync::Arc;
+use wasm_bindgen::prelude::*;
+
+/// Renders `markdown` into the format of `extension`, e.g. `json` or `html`,
+/// with `options`, a json object of the same options as a config file. Html
+/// is wrapped in the page template, if one is set.
+///
+/// # Errors
+///
+/// Will return `Err` if the options are invalid, the extension isn't
+/// supported or the document fails to render.
+#[wasm_bindgen]
+pub fn render(markdown: &str, options: &str, extension: &str) -> Result<Vec<u8>, JsError> {
+    let state = State::try_from_options(options).map_err(to_js)?;
+    let format = PayloadFormats::try_from(extension).map_err(to_js)?;
+    generate_payload_from_slice(markdown.as_bytes(), Arc::new(state.clone()))
+        .and_then(|payload| payload.into_response_with_state(&format, &state))
+        .map_err(to_js)
+}
+
+/// Renders `markdown` into just its html, e.g. for a live preview, with
+/// `options`, a json object of the same options as a config file.
+///
+/// # Errors
+///
+/// Will return `Err` if the options are invalid or the document fails to
+/// render.
+#[wasm_bindgen(js_name = renderHtml)]
+pub fn render_html(markdown: &str, options: &str) -> Result<String, JsError> {
+    let state = State::try_from_options(options).map_err(to_js)?;
+    generate_payload_from_slice(markdown.as_bytes(), Arc::new(state))
+        .map(|payload| payload.html)
+        .map_err(to_js)
+}
+
+fn to_js(error: anyhow::Error) -> JsError {
+    JsError::new(&format!("{error:#}"))
+}